@@ -0,0 +1,95 @@
+extern crate preqc_pack;
+use fastq::Record;
+use preqc_pack::qc::fastqc::{AdapterContent, Limits, ModuleStatus, OverRepresentedSeqs};
+use preqc_pack::qc::module::{downcast, ModuleRegistry, QCModule, ReadRecord};
+use preqc_pack::qc::{FastQCConfig, MislabelingConfig, QCResults};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Count the reads which start with a poly-G, it's a common artifact of two-color chemistry.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct PolyGStart {
+    prefix_length: usize,
+    total_reads: usize,
+    poly_g_reads: usize,
+    #[serde(skip_serializing)]
+    status: ModuleStatus,
+}
+
+impl QCModule for PolyGStart {
+    fn key(&self) -> &str {
+        return "poly_g_start";
+    }
+
+    fn name(&self) -> &str {
+        return "Poly-G start";
+    }
+
+    fn process_sequence(&mut self, record: &ReadRecord) {
+        self.total_reads += 1;
+        let seq = record.seq();
+        if seq.len() >= self.prefix_length && seq[..self.prefix_length].iter().all(|b| *b == b'G') {
+            self.poly_g_reads += 1;
+        }
+    }
+
+    fn merge(&mut self, other: &dyn QCModule) {
+        let other = downcast::<PolyGStart>(other);
+        self.total_reads += other.total_reads;
+        self.poly_g_reads += other.poly_g_reads;
+    }
+
+    fn finish(&mut self, _phred_offset: usize) {}
+
+    fn calculate_status(&mut self, _limits: &Limits) {
+        let percentage = self.poly_g_reads as f64 / self.total_reads.max(1) as f64 * 100.0;
+        self.status = ModuleStatus::from_upper_limits(percentage, 1.0, 5.0);
+    }
+
+    fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
+
+fn main() {
+    let mut registry = ModuleRegistry::new();
+    registry.register("poly_g_start", || {
+        Box::new(PolyGStart {
+            prefix_length: 10,
+            total_reads: 0,
+            poly_g_reads: 0,
+            status: ModuleStatus::Pass,
+        })
+    });
+
+    let mut fastqc_config = FastQCConfig::new(
+        AdapterContent::read_adapter_file("data/adapter_list.txt"),
+        OverRepresentedSeqs::read_contaminants_file("data/contaminant_list.txt"),
+        "".to_string(),
+        None,
+        None,
+        None,
+        None,
+    );
+    fastqc_config.set_registry(registry);
+
+    let mislabeling_config = MislabelingConfig::new(HashMap::new(), vec![], 0);
+    let qc_results = QCResults::run_qc_par(
+        "examples/test.fastq.gz",
+        4,
+        Arc::new("fastqc".to_string()),
+        Arc::new(fastqc_config),
+        Arc::new(mislabeling_config),
+    );
+
+    let fastqc = qc_results.fastqc().as_ref().unwrap();
+    for module in fastqc.extra_modules() {
+        println!(
+            "{}: {:?} {}",
+            module.name(),
+            module.status(),
+            module.to_json()
+        );
+    }
+}
@@ -1,6 +1,7 @@
 extern crate preqc_pack;
 use fastq::parse_path;
 use preqc_pack::qc::fastqc::FastQC;
+use preqc_pack::qc::FastQCConfig;
 
 fn test_process_sequence() {
     let fastq_path = "examples/test.fastq.gz";
@@ -11,7 +12,8 @@ fn test_process_sequence() {
         preqc_pack::qc::fastqc::OverRepresentedSeqs::read_contaminants_file(contaminant_file);
     let adapters = preqc_pack::qc::fastqc::AdapterContent::read_adapter_file(adapter_file);
 
-    let mut qc = FastQC::new(&contaminants, &adapters, None, None, None, None);
+    let config = FastQCConfig::new(adapters, contaminants, "".to_string(), None, None, None, None);
+    let mut qc = FastQC::new(&config);
 
     parse_path(Some(fastq_path), |parser| {
         parser
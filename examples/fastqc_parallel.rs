@@ -22,8 +22,15 @@ fn test_process_sequence() {
         preqc_pack::qc::fastqc::OverRepresentedSeqs::read_contaminants_file(contaminant_file);
     let adapters = preqc_pack::qc::fastqc::AdapterContent::read_adapter_file(adapter_file);
 
-    let fastqc_config =
-        preqc_pack::qc::FastQCConfig::new(adapters, contaminants, None, None, None, None);
+    let fastqc_config = preqc_pack::qc::FastQCConfig::new(
+        adapters,
+        contaminants,
+        "".to_string(),
+        None,
+        None,
+        None,
+        None,
+    );
 
     let mislabeling_config = preqc_pack::qc::MislabelingConfig::new(patterns, count_vec, count);
 
@@ -56,8 +63,15 @@ fn test_process_sequence1() {
         preqc_pack::qc::fastqc::OverRepresentedSeqs::read_contaminants_file(contaminant_file);
     let adapters = preqc_pack::qc::fastqc::AdapterContent::read_adapter_file(adapter_file);
 
-    let fastqc_config =
-        preqc_pack::qc::FastQCConfig::new(adapters, contaminants, None, None, None, None);
+    let fastqc_config = preqc_pack::qc::FastQCConfig::new(
+        adapters,
+        contaminants,
+        "".to_string(),
+        None,
+        None,
+        None,
+        None,
+    );
 
     let mislabeling_config = preqc_pack::qc::MislabelingConfig::new(patterns, count_vec, count);
 
@@ -0,0 +1,62 @@
+use log::*;
+use preqc_pack::qc::taxonomy::{Taxonomy, TaxonomyDatabase};
+use structopt::StructOpt;
+
+/// Build a taxonomy database from FASTA files for `metrics --taxonomy-db`, like kraken2-build.
+#[derive(StructOpt, PartialEq, Debug)]
+#[structopt(setting=structopt::clap::AppSettings::ColoredHelp, name="PreQC Tool Suite - Build DB", author="Jingcheng Yang <yjcyxky@163.com>; Haonan Chen <haonanchen0815@163.com>")]
+pub struct Arguments {
+    /// FASTA files (<taxid>=<path> or <path>), the taxid in a header like Kraken (>seq1|kraken:taxid|562) takes precedence.
+    #[structopt(name = "FASTA", multiple = true, takes_value = true, required = true)]
+    inputs: Vec<String>,
+
+    /// Taxonomy table of taxid, parent taxid, rank and name separated by tabs, the parent of the root is itself or 0.
+    #[structopt(name = "taxonomy", long = "taxonomy")]
+    taxonomy: String,
+
+    /// Output database file.
+    #[structopt(name = "output", short = "o", long = "output")]
+    output: String,
+}
+
+pub fn run(args: &Arguments) {
+    let taxonomy = match Taxonomy::read_table(&args.taxonomy) {
+        Ok(taxonomy) => taxonomy,
+        Err(msg) => {
+            error!("{}", msg);
+            std::process::exit(1);
+        }
+    };
+
+    let mut database = TaxonomyDatabase::new(taxonomy);
+    for input in &args.inputs {
+        let (taxid, path) = match input.find('=') {
+            Some(i) => match input[..i].parse::<u32>() {
+                Ok(taxid) => (Some(taxid), &input[i + 1..]),
+                Err(_) => {
+                    error!("Invalid taxid in {}, it should be <taxid>=<path>.", input);
+                    std::process::exit(1);
+                }
+            },
+            None => (None, input.as_str()),
+        };
+
+        match database.add_fasta(taxid, path) {
+            Ok(records) => info!("Added {} records of {}", records, path),
+            Err(msg) => {
+                error!("{}", msg);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Err(msg) = database.write(&args.output) {
+        error!("Cannot write {} - {}", args.output, msg);
+        std::process::exit(1);
+    }
+    info!(
+        "Wrote {} minimizers into {}",
+        database.minimizers(),
+        args.output
+    );
+}
@@ -0,0 +1,37 @@
+use log::*;
+use preqc_pack::qc::screen::ScreenIndex;
+use structopt::StructOpt;
+
+/// Build the index of the genomes to screen for `metrics --screen-index`, so the genomes are not indexed on every run.
+#[derive(StructOpt, PartialEq, Debug)]
+#[structopt(setting=structopt::clap::AppSettings::ColoredHelp, name="PreQC Tool Suite - Build Screen", author="Jingcheng Yang <yjcyxky@163.com>; Haonan Chen <haonanchen0815@163.com>")]
+pub struct Arguments {
+    /// FASTA files of the genomes (<name>=<path> or <path>), the name is the file name without the extension by default.
+    #[structopt(name = "FASTA", multiple = true, takes_value = true, required = true)]
+    inputs: Vec<String>,
+
+    /// Output index file.
+    #[structopt(name = "output", short = "o", long = "output")]
+    output: String,
+}
+
+pub fn run(args: &Arguments) {
+    let index = match ScreenIndex::read_fasta_files(&args.inputs) {
+        Ok(index) => index,
+        Err(msg) => {
+            error!("{}", msg);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(msg) = index.write(&args.output) {
+        error!("Cannot write {} - {}", args.output, msg);
+        std::process::exit(1);
+    }
+    info!(
+        "Wrote {} minimizers of {} genomes into {}",
+        index.minimizers(),
+        index.genomes().len(),
+        args.output
+    );
+}
@@ -1,5 +1,6 @@
 use log::*;
-use preqc_pack::qc::{self, FastQCConfig, MislabelingConfig};
+use preqc_pack::qc::{self, fastqc, FastQCConfig, MislabelingConfig};
+use preqc_pack::report;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
@@ -25,6 +26,10 @@ pub struct Arguments {
     #[structopt(name = "FILE")]
     input: Vec<String>,
 
+    /// Treat the fastq files as R1/R2 pairs (R1_a R2_a R1_b R2_b ...). The mates are read in lockstep in one thread, and the results are named after R1.
+    #[structopt(name = "paired", long = "paired")]
+    paired: bool,
+
     /// Which module will be called.
     #[structopt(name="which", short="w", long="which", possible_values=&["checksum", "fastqc", "checkmate", "all"], default_value="all")]
     which: String,
@@ -68,6 +73,15 @@ pub struct Arguments {
     )]
     contaminant_file: String,
 
+    /// [fastqc] Limits file which decides the pass/warn/fail status of each module (format: txt, same as FastQC's limits.txt). The built-in limits are used for the modules which are not specified.
+    #[structopt(
+        name = "limits-file",
+        short = "L",
+        long = "limits-file",
+        default_value = ""
+    )]
+    limits_file: String,
+
     /// [fastqc] The number of different sequences we want to track in overrepresented module. it will be unlimit when you specify 0.
     #[structopt(
         name = "overrepresented-musc",
@@ -87,6 +101,154 @@ pub struct Arguments {
     /// [fastqc] Ignore one read out of  every a  specified number of reads when crossing boundaries in 'tile quality' module. it will be unlimit when you specify 0.
     #[structopt(name = "tile-isi", long = "tile-isi", default_value = "10")]
     tile_isi: usize,
+
+    /// [fastqc] The kmer size in 'kmer' module, a single size (e.g. 7) or an inclusive range (e.g. 5..8).
+    #[structopt(name = "kmer-size", long = "kmer-size", default_value = "7", parse(try_from_str = parse_kmer_size))]
+    kmer_size: (usize, usize),
+
+    /// [fastqc] Truncate reads to the specified length in 'duplication' and 'overrepresented' modules. FastQC truncates reads longer than 75bp to 50bp when you specify 0.
+    #[structopt(name = "dup-length", long = "dup-length", default_value = "0")]
+    dup_length: usize,
+
+    /// [fastqc] How 'duplication' module counts duplicates: fastqc (the same as FastQC, from the first 100000 unique sequences) or sketch (from hash sketches over all reads, the results don't depend on --nthreads).
+    #[structopt(name = "dup-engine", long = "dup-engine", default_value = "fastqc", possible_values = &qc::dup_sketch::DUP_ENGINES)]
+    dup_engine: qc::dup_sketch::DupEngine,
+
+    /// [fastqc] The mismatches allowed in a full adapter in 'adapter' module. FastQC only counts exact matches when you specify 0.
+    #[structopt(name = "adapter-mismatches", long = "adapter-mismatches", default_value = "0")]
+    adapter_mismatches: usize,
+
+    /// [fastqc] The shortest adapter prefix at the 3' end of a read which is counted as a partial hit in 'adapter' module. Partial hits are not counted when you specify 0.
+    #[structopt(name = "adapter-min-overlap", long = "adapter-min-overlap", default_value = "8")]
+    adapter_min_overlap: usize,
+
+    /// [fastqc] Infer the adapter from the specified number of reads at the beginning of the file (the mate overlaps or the 3' tails), and count it in 'adapter' module besides the known adapters. The detection is disabled when you specify 0.
+    #[structopt(name = "detect-adapter", long = "detect-adapter", default_value = "0")]
+    detect_adapter: usize,
+
+    /// [fastqc] The format of read headers, which gives the tile in 'tile quality' module. The built-in formats are tried in order when you specify auto.
+    #[structopt(name = "header-format", long = "header-format", default_value = "auto", possible_values = &qc::header::HEADER_FORMATS)]
+    header_format: qc::header::HeaderFormat,
+
+    /// [fastqc] A regex with named captures (instrument, run, flowcell, lane, tile, x, y, barcode) to parse read headers, it overrides --header-format. e.g. '^(?P<flowcell>\w+):(?P<lane>\d+):(?P<tile>\d+)'
+    #[structopt(name = "header-regex", long = "header-regex", default_value = "")]
+    header_regex: String,
+
+    /// [fastqc] The expected sample indexes in 'index' module, separated by commas, e.g. ACGTACGT+TTGCAGGA. A read with only one expected index is counted as index hopping.
+    #[structopt(name = "expected-index", long = "expected-index", use_delimiter = true)]
+    expected_index: Vec<qc::barcode::ExpectedIndex>,
+
+    /// [fastqc] An Illumina sample sheet which gives the expected indexes in 'index' module, the sample is matched by the name of each fastq file (all indexes are expected when no sample matches). --expected-index takes precedence.
+    #[structopt(name = "sample-sheet", long = "sample-sheet", default_value = "")]
+    sample_sheet: String,
+
+    /// [fastqc] Where the UMIs are in 'umi' module: header (after the last ':' or '_' of the read name), regex:<pattern> (the 'umi' named capture on the read header) or read:[<offset>:]<length> (the bases of R1). The module is skipped when you don't specify it.
+    #[structopt(name = "umi", long = "umi", default_value = "")]
+    umi: String,
+
+    /// [fastqc] The window size of the sliding window quality trimming in 'trimming' module, the same as SLIDINGWINDOW of Trimmomatic.
+    #[structopt(name = "trim-window", long = "trim-window", default_value = "4")]
+    trim_window: usize,
+
+    /// [fastqc] The mean quality required in each window in 'trimming' module. The quality trimming is disabled when you specify 0.
+    #[structopt(name = "trim-quality", long = "trim-quality", default_value = "20")]
+    trim_quality: usize,
+
+    /// [fastqc] The reads shorter than the specified length after trimming are dropped in 'trimming' module.
+    #[structopt(name = "trim-min-length", long = "trim-min-length", default_value = "36")]
+    trim_min_length: usize,
+
+    /// [fastqc] Don't trim the Ns at both ends of reads in 'trimming' module.
+    #[structopt(name = "no-trim-n", long = "no-trim-n")]
+    no_trim_n: bool,
+
+    /// [fastqc] Filter reads in the same pass like fastp, the reads which pass the filter are reported by a second fastqc besides all reads. A pair passes when both mates pass.
+    #[structopt(name = "filter", long = "filter")]
+    filter: bool,
+
+    /// [fastqc] The reads shorter than the specified length are filtered with --filter.
+    #[structopt(name = "filter-min-length", long = "filter-min-length", default_value = "15")]
+    filter_min_length: usize,
+
+    /// [fastqc] The reads with more Ns than the specified number are filtered with --filter.
+    #[structopt(name = "filter-max-n", long = "filter-max-n", default_value = "5")]
+    filter_max_n: usize,
+
+    /// [fastqc] The reads whose mean quality (Phred+33) is lower than the specified quality are filtered with --filter. It's disabled when you specify 0.
+    #[structopt(name = "filter-min-quality", long = "filter-min-quality", default_value = "0")]
+    filter_min_quality: usize,
+
+    /// [fastqc] Filter the low-complexity reads (DUST score above 7) with --filter.
+    #[structopt(name = "filter-low-complexity", long = "filter-low-complexity")]
+    filter_low_complexity: bool,
+
+    /// [fastqc] Filter the reads with any adapter (the same matching as 'adapter' module) with --filter.
+    #[structopt(name = "filter-adapter", long = "filter-adapter")]
+    filter_adapter: bool,
+
+    /// [fastqc] Write the reads which pass --filter into <output>/<name>.filtered.fq (uncompressed). The order of reads is only kept with one thread.
+    #[structopt(name = "filter-output", long = "filter-output")]
+    filter_output: bool,
+
+    /// [fastqc] Screen reads against the genomes of FASTA files (<name>=<path> or <path>), separated by commas, like FastQ Screen. The genomes are indexed before the qc, use --screen-index for large genomes.
+    #[structopt(name = "screen", long = "screen", use_delimiter = true)]
+    screen: Vec<String>,
+
+    /// [fastqc] Screen reads against the genomes of an index built by the build-screen subcommand.
+    #[structopt(name = "screen-index", long = "screen-index", default_value = "")]
+    screen_index: String,
+
+    /// [fastqc] Screen one out of the specified number of reads with --screen.
+    #[structopt(name = "screen-sample-interval", long = "screen-sample-interval", default_value = "10")]
+    screen_sample_interval: usize,
+
+    /// [fastqc] Align the first reads (pairs) to a bwa index (the prefix of <index>.bwt etc.), and report the mapping rate, MAPQs, proper pairs, insert sizes, mismatches per cycle and reads per chromosome.
+    #[structopt(name = "align-index", long = "align-index", default_value = "")]
+    align_index: String,
+
+    /// [fastqc] The number of reads (pairs) which are aligned with --align-index.
+    #[structopt(name = "align-reads", long = "align-reads", default_value = "10000")]
+    align_reads: usize,
+
+    /// [fastqc] Classify reads by a taxonomy database from 'build-db', like Kraken, and report the fractions of reads at each rank.
+    #[structopt(name = "taxonomy-db", long = "taxonomy-db", default_value = "")]
+    taxonomy_db: String,
+
+    /// [fastqc] Classify one out of the specified number of reads with --taxonomy-db.
+    #[structopt(name = "taxonomy-sample-interval", long = "taxonomy-sample-interval", default_value = "10")]
+    taxonomy_sample_interval: usize,
+
+    /// [fastqc] Infer the sex by the k-mers unique to chrX and chrY in the file (<kmer>\t<X or Y> per line, see data/README.md), and compare it with the expected sex.
+    #[structopt(name = "sex-kmers", long = "sex-kmers", default_value = "")]
+    sex_kmers: String,
+
+    /// [fastqc] The expected sex (male or female) with --sex-kmers. The 'Sex' column of --sample-sheet gives the expected sex of each sample, --expected-sex takes precedence.
+    #[structopt(name = "expected-sex", long = "expected-sex")]
+    expected_sex: Option<qc::sex::Sex>,
+
+    /// [fastqc] The modules to run, separated by commas. Basic statistics is always enabled. [default: the modules of FastQC, and the index, umi, screen, taxonomy and sex modules when their options are given]
+    #[structopt(name = "modules", long = "modules", use_delimiter = true, possible_values = &fastqc::MODULE_NAMES)]
+    modules: Vec<String>,
+
+    /// [fastqc] Use the long-read (Nanopore/PacBio) profile: skip the Illumina-only and per-position modules, and report N50/N90, log-scaled lengths, mean read qualities, yields and positions binned as fractions of read length. The modules can still be overridden by --modules.
+    #[structopt(name = "long-read", long = "long-read")]
+    long_read: bool,
+
+    /// [fastqc] Use the bisulfite (WGBS/EM-seq) profile: skip the status of per-base content and the theoretical GC distribution, and report the library type (directional, non-directional or PBAT), the unconverted reads and the relative M-bias per position. The modules can still be overridden by --modules. It cannot be used with --long-read.
+    #[structopt(name = "bisulfite", long = "bisulfite", conflicts_with = "long-read")]
+    bisulfite: bool,
+
+    /// [fastqc] A FASTA file of the unmethylated spike-in (e.g. lambda phage) for --bisulfite, the conversion rate is measured in its CHH context.
+    #[structopt(name = "bisulfite-spike-in", long = "bisulfite-spike-in", default_value = "")]
+    bisulfite_spike_in: String,
+
+    /// [fastqc] Write fastqc_data.txt and summary.txt into <output>/<name>_fastqc, the same layout as FastQC (for MultiQC etc.).
+    #[structopt(name = "fastqc-data", long = "fastqc-data")]
+    fastqc_data: bool,
+
+    /// Write MultiQC custom content files (<name>_*_mqc.tsv and <name>_*_mqc.json) into <output>.
+    #[structopt(name = "multiqc", long = "multiqc")]
+    multiqc: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -95,14 +257,20 @@ pub struct MetricsConfig {
     algorithm: String,
     nthreads: usize,
     fastqc_config: FastQCConfig,
-    mislabeling_config: MislabelingConfig
+    mislabeling_config: MislabelingConfig,
+    align_index: String,
+    align_reads: usize,
+    fastqc_data: bool,
+    multiqc: bool,
 }
 
 impl MetricsConfig {
-    pub fn new(which: &str, algorithm: &str, nthreads: usize, pattern_file: &str, contaminant_file: &str, adapter_file: &str, overrepresented_musc: usize, kmer_isi: usize, tile_csb: usize, tile_isi: usize) -> MetricsConfig {
+    /// Read the pattern, contaminant, adapter and limits files etc. of the arguments, and check the
+    /// options which depend on each other.
+    pub fn new(args: &Arguments) -> Result<MetricsConfig, String> {
         info!("Started reading patternfile");
-        let (patterns, indexes, count) = if pattern_file.len() > 0 {
-            qc::mislabeling::VAFMatrix::read_patterns(pattern_file)
+        let (patterns, indexes, count) = if args.pattern_file.len() > 0 {
+            qc::mislabeling::VAFMatrix::read_patterns(&args.pattern_file)
         } else {
             qc::mislabeling::VAFMatrix::read_patterns_with_reader(PATTERN_FILE)
         };
@@ -117,57 +285,220 @@ impl MetricsConfig {
         info!("Finished building count array");
 
         info!("Started reading contaminants file");
-        let contaminants = if contaminant_file.len() > 0 {
-            qc::fastqc::OverRepresentedSeqs::read_contaminants_file(contaminant_file)
+        let contaminants = if args.contaminant_file.len() > 0 {
+            qc::fastqc::OverRepresentedSeqs::read_contaminants_file(&args.contaminant_file)
         } else {
             qc::fastqc::OverRepresentedSeqs::read_contaminants_list(CONTAMINANT_LIST)
         };
         info!("Finished reading contaminants file");
 
         info!("Started reading adapter file");
-        let adapters = if adapter_file.len() > 0 {
-            qc::fastqc::AdapterContent::read_adapter_file(adapter_file)
+        let adapters = if args.adapter_file.len() > 0 {
+            qc::fastqc::AdapterContent::read_adapter_file(&args.adapter_file)
         } else {
             qc::fastqc::AdapterContent::read_adapter_list(ADAPTER_LIST)
         };
         info!("Finished reading adapter file");
 
-        let overrepresented_max_unique_seq_count = if overrepresented_musc == 0 {
+        let limits = if args.limits_file.len() > 0 {
+            qc::fastqc::Limits::read_limits_file(&args.limits_file)
+        } else {
+            String::new()
+        };
+
+        let overrepresented_max_unique_seq_count = if args.overrepresented_musc == 0 {
             Some(2 ^ 64 - 1)
         } else {
-            Some(overrepresented_musc)
+            Some(args.overrepresented_musc)
         };
 
-        let kmer_ignore_sampling_interval = if kmer_isi == 0 {
+        let kmer_ignore_sampling_interval = if args.kmer_isi == 0 {
             Some(2 ^ 64 - 1)
         } else {
-            Some(kmer_isi)
+            Some(args.kmer_isi)
         };
 
-        let tile_continuous_sampling_boundary = if tile_csb == 0 {
+        let tile_continuous_sampling_boundary = if args.tile_csb == 0 {
             Some(2 ^ 64 - 1)
         } else {
-            Some(tile_csb)
+            Some(args.tile_csb)
         };
 
-        let tile_ignore_sampling_interval = if tile_isi == 0 {
+        let tile_ignore_sampling_interval = if args.tile_isi == 0 {
             Some(2 ^ 64 - 1)
         } else {
-            Some(tile_isi)
+            Some(args.tile_isi)
         };
 
-        let fastqc_config = qc::FastQCConfig::new(
+        let mut fastqc_config = qc::FastQCConfig::new(
             adapters,
             contaminants,
+            limits,
             overrepresented_max_unique_seq_count,
             kmer_ignore_sampling_interval,
             tile_continuous_sampling_boundary,
             tile_ignore_sampling_interval,
         );
 
+        if args.modules.len() > 0 {
+            fastqc_config.set_modules(args.modules.clone())?;
+        } else {
+            let mut modules = if args.long_read {
+                fastqc::long_read_modules()
+            } else if args.bisulfite {
+                fastqc::bisulfite_modules()
+            } else {
+                fastqc::all_modules()
+            };
+
+            // The modules besides the ones of FastQC are run by their options
+            let options = [
+                ("index", args.expected_index.len() > 0 || args.sample_sheet.len() > 0),
+                ("umi", args.umi.len() > 0),
+                ("screen", args.screen.len() > 0 || args.screen_index.len() > 0),
+                ("taxonomy", args.taxonomy_db.len() > 0),
+                ("sex", args.sex_kmers.len() > 0),
+            ];
+            for (module, given) in options.iter() {
+                if *given {
+                    modules.push(module.to_string());
+                }
+            }
+            fastqc_config.set_modules(modules)?;
+        }
+
+        fastqc_config.set_kmer_size(args.kmer_size.0, args.kmer_size.1);
+        fastqc_config.set_dup_length(args.dup_length);
+        fastqc_config.set_dup_engine(args.dup_engine);
+        fastqc_config.set_adapter_matching(args.adapter_mismatches, args.adapter_min_overlap);
+        if args.detect_adapter > 0 {
+            fastqc_config.set_adapter_detection(args.detect_adapter);
+        }
+
+        if args.header_regex.len() > 0 {
+            match qc::header::HeaderParser::from_regex(&args.header_regex) {
+                Ok(header_parser) => fastqc_config.set_header_parser(header_parser),
+                Err(msg) => return Err(msg),
+            }
+        } else {
+            fastqc_config.set_header_parser(qc::header::HeaderParser::new(args.header_format));
+        }
+
+        if args.expected_index.len() > 0 {
+            fastqc_config.set_expected_indexes(args.expected_index.clone());
+        } else if args.sample_sheet.len() > 0 {
+            match qc::barcode::read_sample_sheet(&args.sample_sheet) {
+                Ok(expected_indexes) => fastqc_config.set_expected_indexes(expected_indexes),
+                Err(msg) => return Err(msg),
+            }
+        }
+
+        if args.umi.len() > 0 {
+            match args.umi.parse::<qc::umi::UmiSource>() {
+                Ok(umi_source) => fastqc_config.set_umi_source(umi_source),
+                Err(msg) => return Err(msg),
+            }
+        }
+
+        if args.trim_window == 0 {
+            return Err("The window size of --trim-window must be greater than 0.".to_string());
+        }
+        fastqc_config.set_trim_params(qc::trimming::TrimParams::new(args.trim_window, args.trim_quality, !args.no_trim_n, args.trim_min_length));
+
+        if args.filter {
+            fastqc_config.set_read_filter(qc::filter::FilterParams::new(
+                args.filter_min_length,
+                args.filter_max_n,
+                args.filter_min_quality,
+                args.filter_low_complexity,
+                args.filter_adapter,
+            ));
+            if args.filter_output {
+                fastqc_config.set_filter_output(get_outdir(&args.output).to_str().unwrap());
+            }
+        }
+
+        if args.screen.len() > 0 || args.screen_index.len() > 0 {
+            if args.screen.len() > 0 && args.screen_index.len() > 0 {
+                return Err("--screen and --screen-index cannot be used together.".to_string());
+            }
+            if args.screen_sample_interval == 0 {
+                return Err("The interval of --screen-sample-interval must be greater than 0.".to_string());
+            }
+
+
+            // Building the index of large genomes takes long, so it's skipped when nothing uses it
+            if !fastqc_config.modules().contains(&"screen".to_string()) {
+                warn!("The screen module is not selected by --modules, so the genomes are not screened.");
+            } else {
+                info!("Started reading the genomes to screen");
+                let index = if args.screen_index.len() > 0 {
+                    qc::screen::ScreenIndex::read(&args.screen_index)
+                } else {
+                    qc::screen::ScreenIndex::read_fasta_files(&args.screen)
+                };
+                match index {
+                    Ok(index) => fastqc_config.set_screen(index, args.screen_sample_interval),
+                    Err(msg) => return Err(msg),
+                }
+                info!("Finished reading the genomes to screen");
+            }
+        }
+
+        if args.taxonomy_db.len() > 0 {
+            if args.taxonomy_sample_interval == 0 {
+                return Err("The interval of --taxonomy-sample-interval must be greater than 0.".to_string());
+            }
+
+            match qc::taxonomy::TaxonomyDatabase::read(&args.taxonomy_db) {
+                Ok(database) => {
+                    info!("Loaded the taxonomy database {:?}", database);
+                    fastqc_config.set_taxonomy(database, args.taxonomy_sample_interval);
+                }
+                Err(msg) => return Err(msg),
+            }
+        }
+
+        if args.bisulfite_spike_in.len() > 0 {
+            if !args.bisulfite {
+                return Err("--bisulfite-spike-in needs --bisulfite.".to_string());
+            }
+
+            match qc::bisulfite::SpikeIn::read_fasta(&args.bisulfite_spike_in) {
+                Ok(spike_in) => {
+                    info!("Loaded the spike-in {:?}", spike_in.names());
+                    fastqc_config.set_bisulfite_spike_in(spike_in);
+                }
+                Err(msg) => return Err(msg),
+            }
+        }
+
+        if args.sex_kmers.len() > 0 {
+            let expected_sexes = match (args.expected_sex, args.sample_sheet.len() > 0) {
+                (Some(sex), _) => Ok(vec![qc::sex::ExpectedSex::new(None, sex)]),
+                (None, true) => qc::sex::read_expected_sexes(&args.sample_sheet),
+                (None, false) => Ok(vec![]),
+            };
+            match (qc::sex::SexKmers::read_table(&args.sex_kmers), expected_sexes) {
+                (Ok(kmers), Ok(expected_sexes)) => fastqc_config.set_sex_kmers(kmers, expected_sexes),
+                (Err(msg), _) | (_, Err(msg)) => return Err(msg),
+            }
+        }
+
+        if args.align_index.len() > 0 {
+            for extension in &["ann", "bwt"] {
+                if !Path::new(&format!("{}.{}", args.align_index, extension)).exists() {
+                    return Err(format!("Not found the bwa index {:?}, it's the prefix of {}.bwt etc.", args.align_index, args.align_index));
+                }
+            }
+            if args.align_reads == 0 {
+                return Err("The number of --align-reads must be greater than 0.".to_string());
+            }
+        }
+
         let mislabeling_config = qc::MislabelingConfig::new(patterns, count_vec, count);
 
-        MetricsConfig { nthreads, which: which.to_string(), algorithm: algorithm.to_string(), fastqc_config, mislabeling_config }
+        return Ok(MetricsConfig { nthreads: args.nthreads, which: args.which.to_string(), algorithm: args.algorithm.to_string(), fastqc_config, mislabeling_config, align_index: args.align_index.to_string(), align_reads: args.align_reads, fastqc_data: args.fastqc_data, multiqc: args.multiqc });
     
     }
 }
@@ -175,20 +506,40 @@ impl MetricsConfig {
 pub fn run(args: &Arguments) {
     info!("Run with {:?} threads", args.nthreads);
     if Path::new(&args.output).is_dir() || &args.output == "" {
-        let config = MetricsConfig::new(
-            &args.which, 
-            &args.algorithm, 
-            args.nthreads, 
-            &args.pattern_file, 
-            &args.contaminant_file, 
-            &args.adapter_file, 
-            args.overrepresented_musc, 
-            args.kmer_isi, 
-            args.tile_csb, 
-            args.tile_isi
-        );
+        let config = match MetricsConfig::new(args) {
+            Ok(config) => config,
+            Err(msg) => {
+                error!("{}", msg);
+                std::process::exit(1);
+            }
+        };
+
+        if args.paired {
+            if args.input.len() % 2 != 0 {
+                error!("The paired mode needs R1 and R2 for each sample, but {} files are given.", args.input.len());
+                std::process::exit(1);
+            }
+
+            if args.nthreads > 1 {
+                info!("The mates are read in lockstep, so only one thread is used for each pair.");
+            }
+
+            let mut handles = vec![];
+            for pair in args.input.chunks(2) {
+                let (r1, r2) = (pair[0].to_owned(), pair[1].to_owned());
+                let output = args.output.to_owned();
+                let config = config.clone();
+                handles.push(
+                    thread::spawn(move|| {
+                        run_paired_with_args(&r1, &r2, &output, &config);
+                    })
+                )
+            }
 
-        if args.input.len() > 1 {
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        } else if args.input.len() > 1 {
             let inputs = args.input.to_owned();
             let mut handles = vec![];
             let output_arc = Arc::new(args.output.to_owned());
@@ -215,6 +566,73 @@ pub fn run(args: &Arguments) {
     }
 }
 
+/// 7 -> (7, 7)
+/// 5..8 -> (5, 8)
+fn parse_kmer_size(value: &str) -> Result<(usize, usize), String> {
+    let sizes: Vec<&str> = value.splitn(2, "..").collect();
+    let parse = |size: &str| size.trim().parse::<usize>().map_err(|_| format!("Invalid kmer size: {}", value));
+    let min = parse(sizes[0])?;
+    let max = if sizes.len() == 2 { parse(sizes[1])? } else { min };
+
+    if min == 0 || min > max {
+        return Err(format!("Invalid kmer size range: {}, it should be 1 <= min <= max", value));
+    }
+
+    return Ok((min, max));
+}
+
+/// xxx.fq.gz/xxx.fastq.gz -> xxx
+/// xxx.fq/xxx.fastq -> xxx
+fn get_basename(input: &str) -> String {
+    let basename = Path::new(input).file_stem().unwrap();
+    let basename = Path::new(basename).file_stem().unwrap();
+    return basename.to_str().unwrap().to_string();
+}
+
+fn get_outdir(output: &str) -> &Path {
+    if output.len() > 0 {
+        Path::new(output)
+    } else {
+        Path::new(".")
+    }
+}
+
+/// Export the results into the formats of other tools besides our JSON.
+///
+/// For paired-end reads, `input` is R1 and the fastqc results of R2 are exported by `input_r2`.
+///
+fn export_results(qc: &qc::QCResults, input: &str, input_r2: Option<&str>, output: &str, config: &MetricsConfig) {
+    if config.fastqc_data {
+        if let Some(fastqc) = qc.fastqc() {
+            match report::fastqc_data::export(fastqc, get_outdir(output), &get_basename(input)) {
+                Ok(dir) => info!("Write fastqc_data.txt and summary.txt into {:?}", dir),
+                Err(msg) => error!("Cannot write fastqc_data.txt: {}", msg),
+            }
+        }
+
+        if let (Some(fastqc), Some(input_r2)) = (qc.fastqc_r2(), input_r2) {
+            match report::fastqc_data::export(fastqc, get_outdir(output), &get_basename(input_r2)) {
+                Ok(dir) => info!("Write fastqc_data.txt and summary.txt into {:?}", dir),
+                Err(msg) => error!("Cannot write fastqc_data.txt: {}", msg),
+            }
+        }
+    }
+
+    if config.multiqc {
+        match report::multiqc::export(qc, get_outdir(output), &get_basename(input)) {
+            Ok(files) => info!("Write {} MultiQC custom content files into {:?}", files.len(), get_outdir(output)),
+            Err(msg) => error!("Cannot write MultiQC custom content files: {}", msg),
+        }
+
+        if let (Some(fastqc), Some(input_r2)) = (qc.fastqc_r2(), input_r2) {
+            match report::multiqc::export_fastqc(fastqc, get_outdir(output), &get_basename(input_r2)) {
+                Ok(files) => info!("Write {} MultiQC custom content files into {:?}", files.len(), get_outdir(output)),
+                Err(msg) => error!("Cannot write MultiQC custom content files: {}", msg),
+            }
+        }
+    }
+}
+
 pub fn run_with_args(input: &str, output: &str, config: &MetricsConfig) {
     let results = if Path::new(input).exists() {
         // TODO: Multi threads?
@@ -253,29 +671,72 @@ pub fn run_with_args(input: &str, output: &str, config: &MetricsConfig) {
                 )
             };
 
-            if config.which != "all" {
-                format!("{}", serde_json::to_string(&qc).unwrap())
-            } else {
+            if config.which == "all" {
                 qc.set_filemeta(Some(qc::hasher::checksum(input, &config.algorithm)));
-                format!("{}", serde_json::to_string(&qc).unwrap())
             }
+
+            if config.align_index.len() > 0 && (config.which == "fastqc" || config.which == "all") {
+                info!("Align the first {} reads of {:?} to {:?}...", config.align_reads, input, config.align_index);
+                qc.set_alignment(Some(qc::align::AlignmentStats::align_reads(&config.align_index, input, config.align_reads)));
+            }
+
+            export_results(&qc, input, None, output, config);
+
+            format!("{}", serde_json::to_string(&qc).unwrap())
         }
     } else {
         error!("{} - Not Found: {:?}", module_path!(), input);
         std::process::exit(1);
     };
 
-    // xxx.fq.gz/xxx.fastq.gz -> xxx
-    // xxx.fq/xxx.fastq -> xxx
-    let basename = Path::new(input).file_stem().unwrap();
-    let basename = Path::new(basename).file_stem().unwrap();
+    let filepath = get_outdir(output).join(format!("{}.json", get_basename(input)));
+
+    let mut f = File::create(filepath).unwrap();
+    f.write(results.as_bytes()).unwrap();
+}
+
+pub fn run_paired_with_args(r1: &str, r2: &str, output: &str, config: &MetricsConfig) {
+    for input in &[r1, r2] {
+        if !Path::new(input).exists() {
+            error!("{} - Not Found: {:?}", module_path!(), input);
+            std::process::exit(1);
+        }
+    }
 
-    let filepath = if output.len() > 0 {
-        Path::new(output).join(format!("{}.json", basename.to_str().unwrap()))
+    let results = if config.which == "checksum" {
+        info!("Run checksum on {:?} and {:?}...", r1, r2);
+        let md5sums = vec![
+            qc::hasher::checksum(r1, &config.algorithm),
+            qc::hasher::checksum(r2, &config.algorithm),
+        ];
+        format!("{}", serde_json::to_string(&md5sums).unwrap())
     } else {
-        Path::new(".").join(format!("{}.json", basename.to_str().unwrap()))
+        info!("Run {} on the pair {:?} and {:?}...", config.which, r1, r2);
+        let mut qc = qc::QCResults::run_qc_paired(
+            r1,
+            r2,
+            &config.which,
+            &config.fastqc_config,
+            &config.mislabeling_config,
+        );
+
+        if config.which == "all" {
+            qc.set_filemeta(Some(qc::hasher::checksum(r1, &config.algorithm)));
+            qc.set_filemeta_r2(Some(qc::hasher::checksum(r2, &config.algorithm)));
+        }
+
+        if config.align_index.len() > 0 && (config.which == "fastqc" || config.which == "all") {
+            info!("Align the first {} pairs of {:?} and {:?} to {:?}...", config.align_reads, r1, r2, config.align_index);
+            qc.set_alignment(Some(qc::align::AlignmentStats::align_pairs(&config.align_index, r1, r2, config.align_reads)));
+        }
+
+        export_results(&qc, r1, Some(r2), output, config);
+
+        format!("{}", serde_json::to_string(&qc).unwrap())
     };
 
+    let filepath = get_outdir(output).join(format!("{}.json", get_basename(r1)));
+
     let mut f = File::create(filepath).unwrap();
     f.write(results.as_bytes()).unwrap();
 }
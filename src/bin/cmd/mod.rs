@@ -1,2 +1,5 @@
+pub mod build_db;
+pub mod build_screen;
 pub mod metrics;
-pub mod merge;
\ No newline at end of file
+pub mod merge;
+pub mod report;
\ No newline at end of file
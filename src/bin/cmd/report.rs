@@ -0,0 +1,56 @@
+use log::*;
+use preqc_pack::qc::QCResults;
+use preqc_pack::report;
+use std::fs;
+use std::path::Path;
+use structopt::StructOpt;
+
+/// Write a self-contained HTML report for each JSON file of the metrics, the qc is not run again.
+#[derive(StructOpt, PartialEq, Debug)]
+#[structopt(setting=structopt::clap::AppSettings::ColoredHelp, name="PreQC Tool Suite - Report", author="Jingcheng Yang <yjcyxky@163.com>; Haonan Chen <haonanchen0815@163.com>")]
+pub struct Arguments {
+    /// The JSON files written by the metrics, e.g. xxx.json for xxx.fq.gz
+    #[structopt(name = "FILE", multiple = true, takes_value = true)]
+    inputs: Vec<String>,
+
+    /// A directory for the html reports, the current directory by default.
+    #[structopt(name = "output", short = "o", long = "output", default_value = "")]
+    output: String,
+}
+
+pub fn run(args: &Arguments) {
+    let outdir = if args.output.len() > 0 { Path::new(&args.output) } else { Path::new(".") };
+    if !outdir.is_dir() {
+        error!("{} is not a directory.", args.output);
+        std::process::exit(1);
+    }
+
+    for input in &args.inputs {
+        let content = match fs::read_to_string(input) {
+            Ok(content) => content,
+            Err(msg) => {
+                error!("Cannot read {:?}: {}", input, msg);
+                std::process::exit(1);
+            }
+        };
+
+        // The checksum only results are not QCResults, so they cannot be reported
+        let results: QCResults = match serde_json::from_str(&content) {
+            Ok(results) => results,
+            Err(msg) => {
+                error!("{:?} is not the results of fastqc or checkmate: {}", input, msg);
+                std::process::exit(1);
+            }
+        };
+
+        // xxx.json -> xxx
+        let basename = Path::new(input).file_stem().unwrap().to_str().unwrap();
+        match report::html::export(&results, outdir, basename) {
+            Ok(filepath) => info!("Write the html report of {:?} into {:?}", input, filepath),
+            Err(msg) => {
+                error!("Cannot write the html report of {:?}: {}", input, msg);
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -4,8 +4,11 @@ extern crate structopt;
 
 mod cmd;
 
+use cmd::build_db;
+use cmd::build_screen;
 use cmd::merge;
 use cmd::metrics;
+use cmd::report;
 use structopt::StructOpt;
 
 /// A suite of qc programs for interacting with fastq/bam/vcf/exp file
@@ -35,6 +38,12 @@ enum SubCommands {
     Meta(metrics::Arguments),
     #[structopt(name = "merge")]
     Merge(merge::Arguments),
+    #[structopt(name = "report")]
+    Report(report::Arguments),
+    #[structopt(name = "build-db")]
+    BuildDb(build_db::Arguments),
+    #[structopt(name = "build-screen")]
+    BuildScreen(build_screen::Arguments),
 }
 
 fn main() {
@@ -56,5 +65,14 @@ fn main() {
         SubCommands::Merge(arguments) => {
             merge::run(&arguments);
         }
+        SubCommands::Report(arguments) => {
+            report::run(&arguments);
+        }
+        SubCommands::BuildDb(arguments) => {
+            build_db::run(&arguments);
+        }
+        SubCommands::BuildScreen(arguments) => {
+            build_screen::run(&arguments);
+        }
     }
 }
@@ -3,6 +3,7 @@
 extern crate lazy_static;
 
 pub mod qc;
+pub mod report;
 pub mod util;
 
 use regex::Regex;
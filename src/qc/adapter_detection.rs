@@ -0,0 +1,547 @@
+//! Infer the adapter of a library from a sample of reads, before the reads are processed.
+//!
+//! Reads whose insert is shorter than the read length run into the adapter, so all of them end
+//! with the same sequence which starts at a variable position. In paired mode the mate overlap
+//! tells where the insert ends, otherwise the adapter is seeded by the most frequent kmer and
+//! extended to the consensus of the 3' tails.
+use super::paired::PairedMetrics;
+use super::util::base_index;
+use fastq::{each_zipped, parse_path, Record};
+use serde::{Deserialize, Serialize};
+use std::cmp;
+
+// The seeds are counted in an array of 4^10 entries, the same size as fastp
+const SEED_SIZE: usize = 10;
+// The most frequent kmers which are tried as a seed
+const MAX_SEEDS: usize = 10;
+const MAX_ADAPTER_LENGTH: usize = 64;
+const MIN_ADAPTER_LENGTH: usize = 8;
+// A consensus base needs at least 10 reads and 70% of them
+const MIN_COVERAGE: usize = 10;
+const MIN_CONSENSUS_FRACTION: f64 = 0.7;
+// A tail supports the adapter when at most 10% of its bases differ from the consensus
+const MAX_TAIL_DIFF_FRACTION: f64 = 0.1;
+// An adapter needs the support of 10 reads and 0.1% of the sampled reads
+const MIN_SUPPORT: usize = 10;
+const MIN_SUPPORT_FRACTION: f64 = 0.001;
+// A sequence which always starts at the same few positions is a duplicated fragment or an
+// amplicon, not an adapter
+const MIN_DISTINCT_POSITIONS: usize = 5;
+// Reads run into poly-A or poly-G (no signal on two-color chemistry) after the adapter
+const MIN_POLY_TAIL: usize = 5;
+
+/// The adapter inferred from the sampled reads, `sequence` is None when no adapter is found.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AdapterDetection {
+    // overlap (paired-end) or tail
+    method: String,
+    sampled_reads: usize,
+    sequence: Option<String>,
+    // The sampled reads which end with the adapter
+    support: usize,
+    closest_adapter: Option<String>,
+    // The mismatches of the closest known adapter in the inferred sequence
+    closest_mismatches: Option<usize>,
+}
+
+impl AdapterDetection {
+    pub fn method(&self) -> &str {
+        return &self.method;
+    }
+
+    pub fn sampled_reads(&self) -> usize {
+        return self.sampled_reads;
+    }
+
+    pub fn sequence(&self) -> &Option<String> {
+        return &self.sequence;
+    }
+
+    pub fn support(&self) -> usize {
+        return self.support;
+    }
+
+    pub fn percent_support(&self) -> f64 {
+        if self.sampled_reads == 0 {
+            return 0.0;
+        }
+
+        return self.support as f64 / self.sampled_reads as f64 * 100.0;
+    }
+
+    pub fn closest_adapter(&self) -> &Option<String> {
+        return &self.closest_adapter;
+    }
+
+    pub fn closest_mismatches(&self) -> Option<usize> {
+        return self.closest_mismatches;
+    }
+
+    /// A known adapter is found without mismatches in the inferred sequence, so the adapter
+    /// content has already counted it.
+    pub fn is_known(&self) -> bool {
+        return self.closest_mismatches == Some(0);
+    }
+}
+
+fn min_support(sampled_reads: usize) -> usize {
+    return cmp::max(
+        MIN_SUPPORT,
+        (sampled_reads as f64 * MIN_SUPPORT_FRACTION) as usize,
+    );
+}
+
+/// The majority base of a column, None when the coverage or the majority is too low.
+fn consensus_base<'a, I: Iterator<Item = &'a u8>>(bases: I) -> Option<u8> {
+    let mut counts = [0; 4];
+    let mut coverage = 0;
+    for base in bases {
+        coverage += 1;
+        if let Some(index) = base_index(*base) {
+            counts[index] += 1;
+        }
+    }
+
+    let (index, count) = counts.iter().enumerate().max_by_key(|(_, c)| **c).unwrap();
+    if coverage < MIN_COVERAGE || (*count as f64) < coverage as f64 * MIN_CONSENSUS_FRACTION {
+        return None;
+    }
+
+    return Some(b"ACGT"[index]);
+}
+
+/// The tail matches the adapter over their common length.
+fn supports(tail: &[u8], adapter: &[u8]) -> bool {
+    let len = cmp::min(tail.len(), adapter.len());
+    if len == 0 {
+        return false;
+    }
+
+    let diff = (0..len).filter(|&i| tail[i] != adapter[i]).count();
+    return diff as f64 <= len as f64 * MAX_TAIL_DIFF_FRACTION;
+}
+
+fn is_low_complexity(kmer: &[u8]) -> bool {
+    let mut seen = [false; 4];
+    for base in kmer {
+        match base_index(*base) {
+            Some(index) => seen[index] = true,
+            None => return true,
+        }
+    }
+
+    return seen.iter().filter(|s| **s).count() < 3;
+}
+
+fn encode_kmer(kmer: &[u8]) -> Option<usize> {
+    let mut index = 0;
+    for base in kmer {
+        index = (index << 2) | base_index(*base)?;
+    }
+
+    return Some(index);
+}
+
+fn decode_kmer(mut index: usize, size: usize) -> Vec<u8> {
+    let mut kmer = vec![b'A'; size];
+    for i in (0..size).rev() {
+        kmer[i] = b"ACGT"[index & 3];
+        index >>= 2;
+    }
+
+    return kmer;
+}
+
+fn find_first(seq: &[u8], kmer: &[u8]) -> Option<usize> {
+    if seq.len() < kmer.len() {
+        return None;
+    }
+
+    return (0..(seq.len() - kmer.len() + 1)).find(|&i| &seq[i..i + kmer.len()] == kmer);
+}
+
+/// Extend a seed to the consensus of the reads containing it, and count the supporting reads.
+fn extend_seed(reads: &Vec<Vec<u8>>, seed: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let hits: Vec<(&Vec<u8>, usize)> = reads
+        .iter()
+        .filter_map(|read| find_first(read, seed).map(|position| (read, position)))
+        .collect();
+
+    // To the left until the bases before the adapter (the inserts) disagree
+    let mut prefix = vec![];
+    loop {
+        let offset = prefix.len() + 1;
+        let column = hits
+            .iter()
+            .filter(|(_, position)| *position >= offset)
+            .map(|(read, position)| &read[position - offset]);
+        match consensus_base(column) {
+            Some(base) if prefix.len() + seed.len() < MAX_ADAPTER_LENGTH => prefix.push(base),
+            _ => break,
+        }
+    }
+    prefix.reverse();
+    let start_offset = prefix.len();
+
+    let mut adapter = prefix;
+    adapter.extend_from_slice(seed);
+    while adapter.len() < MAX_ADAPTER_LENGTH {
+        let offset = adapter.len() - start_offset;
+        let column = hits
+            .iter()
+            .filter(|(read, position)| position + offset < read.len())
+            .map(|(read, position)| &read[position + offset]);
+        match consensus_base(column) {
+            Some(base) => adapter.push(base),
+            None => break,
+        }
+    }
+
+    // The supporting reads end with the adapter, from where it starts to the 3' end
+    let mut positions: Vec<usize> = vec![];
+    let mut support = 0;
+    for (read, position) in hits.iter() {
+        if *position < start_offset {
+            continue;
+        }
+
+        let start = position - start_offset;
+        if supports(&read[start..], &adapter) {
+            support += 1;
+            positions.push(start);
+        }
+    }
+
+    positions.sort();
+    positions.dedup();
+    if positions.len() < MIN_DISTINCT_POSITIONS {
+        return None;
+    }
+
+    return Some((adapter, support));
+}
+
+/// The consensus of the tails which follow the inserts.
+fn tails_consensus(tails: &Vec<&[u8]>) -> Vec<u8> {
+    let mut adapter = vec![];
+    while adapter.len() < MAX_ADAPTER_LENGTH {
+        let offset = adapter.len();
+        let column = tails
+            .iter()
+            .filter(|tail| offset < tail.len())
+            .map(|tail| &tail[offset]);
+        match consensus_base(column) {
+            Some(base) => adapter.push(base),
+            None => break,
+        }
+    }
+
+    return adapter;
+}
+
+/// The known adapter which has the fewest mismatches at any position of the sequence.
+fn closest_adapter(sequence: &[u8], adapters_content: &String) -> Option<(String, usize)> {
+    let mut closest: Option<(String, usize)> = None;
+    for line in adapters_content.lines() {
+        if line.starts_with("#") || line.trim().len() == 0 {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split("\t").filter(|f| f.len() > 0).collect();
+        if fields.len() < 2 {
+            continue;
+        }
+
+        let known = fields[1].trim().as_bytes();
+        if known.len() == 0 {
+            continue;
+        }
+
+        let (longer, shorter) = if known.len() > sequence.len() {
+            (known, sequence)
+        } else {
+            (sequence, known)
+        };
+
+        for position in 0..(longer.len() - shorter.len() + 1) {
+            let mismatches = (0..shorter.len())
+                .filter(|&i| longer[position + i] != shorter[i])
+                .count();
+            if closest.as_ref().map_or(true, |(_, m)| mismatches < *m) {
+                closest = Some((fields[0].trim().to_string(), mismatches));
+            }
+        }
+    }
+
+    return closest;
+}
+
+/// Remove the trailing run of one base, it's not a part of the adapter.
+fn trim_poly_tail(mut adapter: Vec<u8>) -> Vec<u8> {
+    if let Some(&last) = adapter.last() {
+        let run = adapter
+            .iter()
+            .rev()
+            .take_while(|&&base| base == last)
+            .count();
+        if run >= MIN_POLY_TAIL {
+            adapter.truncate(adapter.len() - run);
+        }
+    }
+
+    return adapter;
+}
+
+fn make_detection(
+    method: &str,
+    sampled_reads: usize,
+    adapter: Option<(Vec<u8>, usize)>,
+    adapters_content: &String,
+) -> AdapterDetection {
+    let adapter = adapter
+        .map(|(sequence, support)| (trim_poly_tail(sequence), support))
+        .filter(|(sequence, support)| {
+            sequence.len() >= MIN_ADAPTER_LENGTH && *support >= min_support(sampled_reads)
+        });
+
+    return match adapter {
+        Some((sequence, support)) => {
+            let closest = closest_adapter(&sequence, adapters_content);
+            AdapterDetection {
+                method: method.to_string(),
+                sampled_reads: sampled_reads,
+                sequence: Some(String::from_utf8(sequence).unwrap()),
+                support: support,
+                closest_adapter: closest.as_ref().map(|(name, _)| name.clone()),
+                closest_mismatches: closest.map(|(_, mismatches)| mismatches),
+            }
+        }
+        None => AdapterDetection {
+            method: method.to_string(),
+            sampled_reads: sampled_reads,
+            sequence: None,
+            support: 0,
+            closest_adapter: None,
+            closest_mismatches: None,
+        },
+    };
+}
+
+/// Infer the adapter from the overrepresented 3' tails of single reads.
+///
+/// The most frequent kmers are tried as seeds, the first one which extends to an adapter
+/// with enough support wins.
+///
+pub fn detect_from_reads(reads: &Vec<Vec<u8>>, adapters_content: &String) -> AdapterDetection {
+    let mut counts: Vec<u32> = vec![0; 1 << (2 * SEED_SIZE)];
+    for read in reads {
+        if read.len() < SEED_SIZE {
+            continue;
+        }
+
+        for i in 0..(read.len() - SEED_SIZE + 1) {
+            if let Some(index) = encode_kmer(&read[i..i + SEED_SIZE]) {
+                counts[index] += 1;
+            }
+        }
+    }
+
+    let mut seeds: Vec<(Vec<u8>, u32)> = counts
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count as usize >= min_support(reads.len()))
+        .map(|(index, count)| (decode_kmer(index, SEED_SIZE), *count))
+        .filter(|(kmer, _)| !is_low_complexity(kmer))
+        .collect();
+    // The sort is stable, so the ties are ordered by the kmers
+    seeds.sort_by(|a, b| b.1.cmp(&a.1));
+
+    let adapter = seeds
+        .iter()
+        .take(MAX_SEEDS)
+        .filter_map(|(seed, _)| extend_seed(reads, seed))
+        .filter(|(adapter, support)| {
+            adapter.len() >= MIN_ADAPTER_LENGTH && *support >= min_support(reads.len())
+        })
+        .next();
+
+    return make_detection("tail", reads.len(), adapter, adapters_content);
+}
+
+/// Infer the adapters of R1 and R2 from the bases after the inserts, which are told by the
+/// mate overlaps. Fall back to the 3' tails when there are not enough short inserts.
+pub fn detect_from_pairs(
+    pairs: &Vec<(Vec<u8>, Vec<u8>)>,
+    adapters_content: &String,
+) -> (AdapterDetection, AdapterDetection) {
+    let mut tails_r1: Vec<&[u8]> = vec![];
+    let mut tails_r2: Vec<&[u8]> = vec![];
+    for (seq1, seq2) in pairs {
+        if let Some(insert_size) = PairedMetrics::insert_size(seq1, seq2) {
+            if insert_size < seq1.len() {
+                tails_r1.push(&seq1[insert_size..]);
+            }
+            if insert_size < seq2.len() {
+                tails_r2.push(&seq2[insert_size..]);
+            }
+        }
+    }
+
+    let detect = |tails: &Vec<&[u8]>, reads: Vec<Vec<u8>>| {
+        let adapter = tails_consensus(tails);
+        let support = tails.iter().filter(|tail| supports(tail, &adapter)).count();
+        let detection = make_detection(
+            "overlap",
+            pairs.len(),
+            Some((adapter, support)),
+            adapters_content,
+        );
+        if detection.sequence().is_some() {
+            return detection;
+        }
+
+        return detect_from_reads(&reads, adapters_content);
+    };
+
+    return (
+        detect(
+            &tails_r1,
+            pairs.iter().map(|(seq1, _)| seq1.clone()).collect(),
+        ),
+        detect(
+            &tails_r2,
+            pairs.iter().map(|(_, seq2)| seq2.clone()).collect(),
+        ),
+    );
+}
+
+/// The sequences of the first `n` reads.
+pub fn sample_reads(fastq_path: &str, n: usize) -> Vec<Vec<u8>> {
+    let mut reads = vec![];
+    match parse_path(Some(fastq_path), |parser| {
+        parser.each(|record| {
+            if reads.len() >= n {
+                return false;
+            }
+
+            reads.push(record.seq().to_vec());
+            return true;
+        })
+    }) {
+        Ok(Ok(_)) => {}
+        Err(msg) | Ok(Err(msg)) => panic!("Cannot parse fastq file: {}", msg),
+    }
+
+    return reads;
+}
+
+/// The sequences of the first `n` pairs, the unpaired reads are ignored.
+pub fn sample_pairs(r1_path: &str, r2_path: &str, n: usize) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut pairs = vec![];
+    let result = parse_path(Some(r1_path), |parser1| {
+        parse_path(Some(r2_path), |parser2| {
+            each_zipped(parser1, parser2, |r1, r2| {
+                if pairs.len() >= n {
+                    return (false, false);
+                }
+
+                if let (Some(r1), Some(r2)) = (&r1, &r2) {
+                    pairs.push((r1.seq().to_vec(), r2.seq().to_vec()));
+                }
+                return (r1.is_some(), r2.is_some());
+            })
+        })
+    });
+
+    match result {
+        Ok(Ok(Ok(_))) => {}
+        Err(msg) | Ok(Err(msg)) | Ok(Ok(Err(msg))) => {
+            panic!("Cannot parse fastq files: {}", msg);
+        }
+    }
+
+    return pairs;
+}
+
+#[cfg(test)]
+mod adapter_detection_tests {
+    use super::*;
+    use crate::qc::util::reverse_complement;
+
+    const ADAPTER: &[u8] = b"CTGTAGGCACCATCAATCGTATGCCGTCTTCTGCTTG";
+    const ADAPTERS: &str =
+        "Illumina Universal Adapter\tAGATCGGAAGAG\nIllumina Small RNA 3' Adapter\tTGGAATTCTCGG\n";
+
+    // A deterministic pseudo-random sequence
+    fn random_seq(seed: usize, len: usize) -> Vec<u8> {
+        let mut state = seed as u64;
+        return (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                b"ACGT"[(state >> 33) as usize % 4]
+            })
+            .collect();
+    }
+
+    #[test]
+    fn test_detect_from_reads() {
+        let reads: Vec<Vec<u8>> = (0..1000)
+            .map(|i| {
+                let mut read = random_seq(i, 60);
+                if i % 5 == 0 {
+                    // The inserts are from 20bp to 39bp
+                    read.truncate(20 + i / 5 % 20);
+                    read.extend_from_slice(ADAPTER);
+                    read.truncate(60);
+                }
+                read
+            })
+            .collect();
+
+        let detection = detect_from_reads(&reads, &ADAPTERS.to_string());
+        let sequence = detection.sequence().as_ref().unwrap();
+        assert!(ADAPTER.starts_with(sequence.as_bytes()));
+        assert!(sequence.len() >= 20);
+        assert_eq!(detection.support(), 200);
+        assert!(detection.closest_adapter().is_some());
+        assert!(!detection.is_known());
+
+        let reads: Vec<Vec<u8>> = (0..1000).map(|i| random_seq(i, 60)).collect();
+        assert_eq!(
+            detect_from_reads(&reads, &ADAPTERS.to_string()).sequence(),
+            &None
+        );
+    }
+
+    #[test]
+    fn test_detect_from_pairs() {
+        let pairs: Vec<(Vec<u8>, Vec<u8>)> = (0..200)
+            .map(|i| {
+                let insert = random_seq(i, 40 + i % 20);
+                let mut r1 = insert.clone();
+                r1.extend_from_slice(ADAPTER);
+                r1.truncate(80);
+                let mut r2 = reverse_complement(&insert);
+                r2.extend_from_slice(b"AGATCGGAAGAGCGTCGTGTAGGGAAAGAGTGT");
+                r2.truncate(80);
+                (r1, r2)
+            })
+            .collect();
+
+        let (r1, r2) = detect_from_pairs(&pairs, &ADAPTERS.to_string());
+        assert_eq!(r1.method(), "overlap");
+        assert_eq!(r1.sequence(), &Some(from_utf8(ADAPTER).to_string()));
+        assert_eq!(r1.support(), 200);
+        assert_eq!(
+            r2.closest_adapter(),
+            &Some("Illumina Universal Adapter".to_string())
+        );
+        assert!(r2.is_known());
+    }
+
+    fn from_utf8(seq: &[u8]) -> &str {
+        return std::str::from_utf8(seq).unwrap();
+    }
+}
@@ -0,0 +1,516 @@
+//! Align a subsample of reads to a bwa index.
+//!
+//! The mapping rate, MAPQs, proper pairs, insert sizes, mismatches per cycle and the reads per
+//! chromosome are usually only known after the full alignment. Here the first reads (pairs) of
+//! the file are aligned with bwa mem before the qc to estimate them. Only the primary alignments
+//! are counted.
+use super::paired::PairedMetrics;
+use bwa::BwaAligner;
+use fastq::{each_zipped, parse_path, OwnedRecord, Record};
+use rust_htslib::bam;
+use rust_htslib::bam::record::{Aux, Cigar};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+// The max MAPQ of bwa mem
+const MAX_MAPQ: usize = 60;
+// The insert sizes above it are counted as it
+const MAX_INSERT_SIZE: usize = 1000;
+
+/// The contigs of a bwa index by the `.ann` file, in the order of the target ids.
+pub fn read_contigs(index: &str) -> std::io::Result<Vec<(String, usize)>> {
+    let reader = BufReader::new(File::open(format!("{}.ann", index))?);
+    let lines: Vec<String> = reader.lines().collect::<Result<_, _>>()?;
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid .ann file");
+
+    // The first line is the total length, the number of contigs and the seed, then two lines for
+    // each contig: "<gi> <name> <anno>" and "<offset> <length> <ambiguous bases>"
+    let n_contigs = lines
+        .first()
+        .and_then(|header| header.split_whitespace().nth(1))
+        .and_then(|n| n.parse::<usize>().ok())
+        .ok_or_else(invalid)?;
+    let mut contigs = vec![];
+    for pair in lines[1..].chunks(2) {
+        if pair.len() < 2 {
+            return Err(invalid());
+        }
+        let name = pair[0].split_whitespace().nth(1).ok_or_else(invalid)?;
+        let length = pair[1]
+            .split_whitespace()
+            .nth(1)
+            .and_then(|length| length.parse::<usize>().ok())
+            .ok_or_else(invalid)?;
+        contigs.push((name.to_string(), length));
+    }
+    if contigs.len() != n_contigs {
+        return Err(invalid());
+    }
+
+    return Ok(contigs);
+}
+
+/// The positions of the mismatches in the aligned part of a read from the CIGAR and the MD tag,
+/// 0-based in the orientation of the alignment, the clipped bases are counted.
+pub fn mismatch_positions(cigar: &[Cigar], md: &str) -> Vec<usize> {
+    // The offsets of the mismatched reference bases from the start of the alignment, the deleted
+    // bases (^ACG) are also in the MD tag
+    let mut ref_mismatches = vec![];
+    let (mut ref_offset, mut matches, mut deletion) = (0, 0, false);
+    for c in md.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            matches = matches * 10 + digit as usize;
+            deletion = false;
+            continue;
+        }
+
+        ref_offset += matches;
+        matches = 0;
+        if c == '^' {
+            deletion = true;
+            continue;
+        }
+
+        if !deletion {
+            ref_mismatches.push(ref_offset);
+        }
+        ref_offset += 1;
+    }
+
+    let mut positions = vec![];
+    let (mut query, mut reference) = (0, 0);
+    for op in cigar {
+        match op {
+            Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                let len = *len as usize;
+                for offset in &ref_mismatches {
+                    if *offset >= reference && *offset < reference + len {
+                        positions.push(query + offset - reference);
+                    }
+                }
+                query += len;
+                reference += len;
+            }
+            Cigar::Ins(len) | Cigar::SoftClip(len) | Cigar::HardClip(len) => query += *len as usize,
+            Cigar::Del(len) | Cigar::RefSkip(len) => reference += *len as usize,
+            Cigar::Pad(_) => {}
+        }
+    }
+
+    return positions;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContigCoverage {
+    name: String,
+    length: usize,
+    reads: usize,
+    // The percentage of the mapped reads, and the percentage expected by the length
+    percent_reads: f64,
+    percent_length: f64,
+}
+
+impl ContigCoverage {
+    pub fn name(&self) -> &String {
+        return &self.name;
+    }
+
+    pub fn length(&self) -> usize {
+        return self.length;
+    }
+
+    pub fn reads(&self) -> usize {
+        return self.reads;
+    }
+
+    pub fn percent_reads(&self) -> f64 {
+        return self.percent_reads;
+    }
+
+    pub fn percent_length(&self) -> f64 {
+        return self.percent_length;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AlignmentStats {
+    index: String,
+    total_reads: usize,
+    mapped_reads: usize,
+    percent_mapped: f64,
+    // The number of mapped reads by MAPQ, from 0 to `MAX_MAPQ`
+    mapq_distribution: Vec<usize>,
+    // Only for paired-end reads
+    total_pairs: usize,
+    proper_pairs: usize,
+    percent_proper_pairs: f64,
+    // insert_sizes[i] is the number of proper pairs whose insert size is i
+    insert_sizes: Vec<usize>,
+    mean_insert_size: f64,
+    median_insert_size: usize,
+    // By the cycle (the position in the read), only the aligned bases are counted
+    mismatches_per_cycle: Vec<usize>,
+    aligned_bases_per_cycle: Vec<usize>,
+    mismatch_rate_per_cycle: Vec<f64>,
+    mismatch_rate: f64,
+    contigs: Vec<ContigCoverage>,
+}
+
+impl AlignmentStats {
+    pub fn new(index: &str, contigs: &Vec<(String, usize)>) -> AlignmentStats {
+        return AlignmentStats {
+            index: index.to_string(),
+            total_reads: 0,
+            mapped_reads: 0,
+            percent_mapped: 0.0,
+            mapq_distribution: vec![0; MAX_MAPQ + 1],
+            total_pairs: 0,
+            proper_pairs: 0,
+            percent_proper_pairs: 0.0,
+            insert_sizes: vec![],
+            mean_insert_size: 0.0,
+            median_insert_size: 0,
+            mismatches_per_cycle: vec![],
+            aligned_bases_per_cycle: vec![],
+            mismatch_rate_per_cycle: vec![],
+            mismatch_rate: 0.0,
+            contigs: contigs
+                .iter()
+                .map(|(name, length)| ContigCoverage {
+                    name: name.clone(),
+                    length: *length,
+                    reads: 0,
+                    percent_reads: 0.0,
+                    percent_length: 0.0,
+                })
+                .collect(),
+        };
+    }
+
+    /// Align the first `n` reads of a fastq file.
+    pub fn align_reads(index: &str, fastq_path: &str, n: usize) -> AlignmentStats {
+        let (aligner, mut stats) = AlignmentStats::open(index);
+        for record in sample_records(fastq_path, n) {
+            let name = PairedMetrics::read_name(record.head());
+            let alignments = aligner.align_read(name, record.seq(), record.qual());
+            stats.add_read(&alignments, record.seq().len());
+        }
+
+        stats.finish();
+        return stats;
+    }
+
+    /// Align the first `n` pairs of paired-end fastq files, the unpaired reads are ignored.
+    pub fn align_pairs(index: &str, r1_path: &str, r2_path: &str, n: usize) -> AlignmentStats {
+        let (aligner, mut stats) = AlignmentStats::open(index);
+        for (r1, r2) in sample_pairs(r1_path, r2_path, n) {
+            let name = PairedMetrics::read_name(r1.head());
+            let (alignments1, alignments2) =
+                aligner.align_read_pair(name, r1.seq(), r1.qual(), r2.seq(), r2.qual());
+            stats.add_pair(&alignments1);
+            stats.add_read(&alignments1, r1.seq().len());
+            stats.add_read(&alignments2, r2.seq().len());
+        }
+
+        stats.finish();
+        return stats;
+    }
+
+    fn open(index: &str) -> (BwaAligner, AlignmentStats) {
+        let contigs = match read_contigs(index) {
+            Ok(contigs) => contigs,
+            Err(msg) => panic!(
+                "Cannot read the contigs of the bwa index {} - {}",
+                index, msg
+            ),
+        };
+        let aligner = match BwaAligner::from_path(&index) {
+            Ok(aligner) => aligner,
+            Err(msg) => panic!("Cannot open the bwa index {} - {:?}", index, msg),
+        };
+
+        return (aligner, AlignmentStats::new(index, &contigs));
+    }
+
+    fn primary(alignments: &Vec<bam::Record>) -> Option<&bam::Record> {
+        return alignments
+            .iter()
+            .find(|record| !record.is_secondary() && !record.is_supplementary());
+    }
+
+    fn add_read(&mut self, alignments: &Vec<bam::Record>, read_length: usize) {
+        self.total_reads += 1;
+        let record = match AlignmentStats::primary(alignments) {
+            Some(record) if !record.is_unmapped() => record,
+            _ => return,
+        };
+
+        self.mapped_reads += 1;
+        self.mapq_distribution[(record.mapq() as usize).min(MAX_MAPQ)] += 1;
+        if let Some(contig) = self.contigs.get_mut(record.tid() as usize) {
+            contig.reads += 1;
+        }
+
+        if self.aligned_bases_per_cycle.len() < read_length {
+            self.aligned_bases_per_cycle.resize(read_length, 0);
+            self.mismatches_per_cycle.resize(read_length, 0);
+        }
+        // The cycle of a position in the alignment, the reverse strand is reverse complemented
+        let cycle = |position: usize| {
+            if record.is_reverse() {
+                read_length.saturating_sub(position + 1)
+            } else {
+                position.min(read_length.saturating_sub(1))
+            }
+        };
+
+        let cigar = record.cigar();
+        let mut query = 0;
+        for op in cigar.iter() {
+            match op {
+                Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                    for position in query..query + *len as usize {
+                        self.aligned_bases_per_cycle[cycle(position)] += 1;
+                    }
+                    query += *len as usize;
+                }
+                Cigar::Ins(len) | Cigar::SoftClip(len) | Cigar::HardClip(len) => {
+                    query += *len as usize
+                }
+                _ => {}
+            }
+        }
+
+        if let Ok(Aux::String(md)) = record.aux(b"MD") {
+            for position in mismatch_positions(&cigar, md) {
+                self.mismatches_per_cycle[cycle(position)] += 1;
+            }
+        }
+    }
+
+    fn add_pair(&mut self, alignments1: &Vec<bam::Record>) {
+        self.total_pairs += 1;
+        let record = match AlignmentStats::primary(alignments1) {
+            Some(record) if record.is_proper_pair() => record,
+            _ => return,
+        };
+
+        self.proper_pairs += 1;
+        let insert_size = (record.insert_size().abs() as usize).min(MAX_INSERT_SIZE);
+        if self.insert_sizes.len() <= insert_size {
+            self.insert_sizes.resize(insert_size + 1, 0);
+        }
+        self.insert_sizes[insert_size] += 1;
+    }
+
+    fn finish(&mut self) {
+        if self.total_reads == 0 {
+            return;
+        }
+
+        self.percent_mapped = self.mapped_reads as f64 / self.total_reads as f64 * 100.0;
+        if self.total_pairs > 0 {
+            self.percent_proper_pairs = self.proper_pairs as f64 / self.total_pairs as f64 * 100.0;
+        }
+
+        let total: usize = self.insert_sizes.iter().sum();
+        if total > 0 {
+            let mut sum = 0;
+            let mut cumulative = 0;
+            let mut median = None;
+            for (insert_size, count) in self.insert_sizes.iter().enumerate() {
+                sum += insert_size * count;
+                cumulative += count;
+                if median.is_none() && cumulative * 2 >= total {
+                    median = Some(insert_size);
+                }
+            }
+
+            self.mean_insert_size = sum as f64 / total as f64;
+            self.median_insert_size = median.unwrap();
+        }
+
+        self.mismatch_rate_per_cycle = self
+            .mismatches_per_cycle
+            .iter()
+            .zip(self.aligned_bases_per_cycle.iter())
+            .map(|(mismatches, bases)| {
+                if *bases > 0 {
+                    *mismatches as f64 / *bases as f64 * 100.0
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let aligned_bases: usize = self.aligned_bases_per_cycle.iter().sum();
+        if aligned_bases > 0 {
+            let mismatches: usize = self.mismatches_per_cycle.iter().sum();
+            self.mismatch_rate = mismatches as f64 / aligned_bases as f64 * 100.0;
+        }
+
+        let genome_length: usize = self.contigs.iter().map(|contig| contig.length).sum();
+        for contig in self.contigs.iter_mut() {
+            if self.mapped_reads > 0 {
+                contig.percent_reads = contig.reads as f64 / self.mapped_reads as f64 * 100.0;
+            }
+            if genome_length > 0 {
+                contig.percent_length = contig.length as f64 / genome_length as f64 * 100.0;
+            }
+        }
+    }
+
+    pub fn index(&self) -> &String {
+        return &self.index;
+    }
+
+    pub fn total_reads(&self) -> usize {
+        return self.total_reads;
+    }
+
+    pub fn mapped_reads(&self) -> usize {
+        return self.mapped_reads;
+    }
+
+    pub fn percent_mapped(&self) -> f64 {
+        return self.percent_mapped;
+    }
+
+    pub fn mapq_distribution(&self) -> &Vec<usize> {
+        return &self.mapq_distribution;
+    }
+
+    pub fn total_pairs(&self) -> usize {
+        return self.total_pairs;
+    }
+
+    pub fn proper_pairs(&self) -> usize {
+        return self.proper_pairs;
+    }
+
+    pub fn percent_proper_pairs(&self) -> f64 {
+        return self.percent_proper_pairs;
+    }
+
+    pub fn insert_sizes(&self) -> &Vec<usize> {
+        return &self.insert_sizes;
+    }
+
+    pub fn mean_insert_size(&self) -> f64 {
+        return self.mean_insert_size;
+    }
+
+    pub fn median_insert_size(&self) -> usize {
+        return self.median_insert_size;
+    }
+
+    /// The percentage of the aligned bases which are mismatches, by the cycle.
+    pub fn mismatch_rate_per_cycle(&self) -> &Vec<f64> {
+        return &self.mismatch_rate_per_cycle;
+    }
+
+    pub fn mismatch_rate(&self) -> f64 {
+        return self.mismatch_rate;
+    }
+
+    pub fn contigs(&self) -> &Vec<ContigCoverage> {
+        return &self.contigs;
+    }
+}
+
+fn sample_records(fastq_path: &str, n: usize) -> Vec<OwnedRecord> {
+    let mut records = vec![];
+    match parse_path(Some(fastq_path), |parser| {
+        parser.each(|record| {
+            if records.len() >= n {
+                return false;
+            }
+
+            records.push(record.to_owned_record());
+            return true;
+        })
+    }) {
+        Ok(Ok(_)) => {}
+        Err(msg) | Ok(Err(msg)) => panic!("Cannot parse fastq file: {}", msg),
+    }
+
+    return records;
+}
+
+fn sample_pairs(r1_path: &str, r2_path: &str, n: usize) -> Vec<(OwnedRecord, OwnedRecord)> {
+    let mut pairs = vec![];
+    let result = parse_path(Some(r1_path), |parser1| {
+        parse_path(Some(r2_path), |parser2| {
+            each_zipped(parser1, parser2, |r1, r2| {
+                if pairs.len() >= n {
+                    return (false, false);
+                }
+
+                if let (Some(r1), Some(r2)) = (&r1, &r2) {
+                    pairs.push((r1.to_owned_record(), r2.to_owned_record()));
+                }
+                return (r1.is_some(), r2.is_some());
+            })
+        })
+    });
+
+    match result {
+        Ok(Ok(Ok(_))) => {}
+        Err(msg) | Ok(Err(msg)) | Ok(Ok(Err(msg))) => {
+            panic!("Cannot parse fastq files: {}", msg);
+        }
+    }
+
+    return pairs;
+}
+
+#[cfg(test)]
+mod align_tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_mismatch_positions() {
+        // 2S 5M 1I 4M 2D 3M: the mismatches are at the 2nd base, the 2nd base after the
+        // insertion and the 1st base after the deletion
+        let cigar = vec![
+            Cigar::SoftClip(2),
+            Cigar::Match(5),
+            Cigar::Ins(1),
+            Cigar::Match(4),
+            Cigar::Del(2),
+            Cigar::Match(3),
+        ];
+        assert_eq!(mismatch_positions(&cigar, "1A4C2^TT0G2"), vec![3, 9, 12]);
+        assert_eq!(
+            mismatch_positions(&[Cigar::Match(10)], "10"),
+            Vec::<usize>::new()
+        );
+    }
+
+    #[test]
+    fn test_read_contigs() {
+        let index = std::env::temp_dir().join("preqc_align_tests.fa");
+        let mut ann = File::create(format!("{}.ann", index.display())).unwrap();
+        write!(
+            ann,
+            "6000 2 11\n0 chr1 (null)\n0 5000 0\n0 chrM (null)\n5000 1000 0\n"
+        )
+        .unwrap();
+
+        let contigs = read_contigs(index.to_str().unwrap()).unwrap();
+        assert_eq!(
+            contigs,
+            vec![("chr1".to_string(), 5000), ("chrM".to_string(), 1000)]
+        );
+        assert!(read_contigs("/nonexistent/genome.fa").is_err());
+
+        // An empty or truncated .ann file
+        for content in &["", "6000 2 11\n0 chr1 (null)\n0 5000 0\n"] {
+            std::fs::write(format!("{}.ann", index.display()), content).unwrap();
+            let error = read_contigs(index.to_str().unwrap()).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        }
+        std::fs::remove_file(format!("{}.ann", index.display())).unwrap();
+    }
+}
@@ -0,0 +1,626 @@
+//! Tally the sample indexes (i7/i5) in read headers and estimate index hopping.
+//!
+//! The observed combinations are compared with the expected indexes of the sample, which are
+//! given on the command line or read from an Illumina sample sheet. A read with one expected
+//! and one unexpected index is counted as hopped: on patterned flowcells a free adapter can
+//! swap one index of a molecule, but rarely both.
+use super::fastqc::{Limits, ModuleStatus};
+use super::header::HeaderParser;
+use super::module::{downcast, QCModule, ReadRecord};
+use super::util::reverse_complement;
+use fastq::Record;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+// The same as the default of bcl2fastq
+const MAX_INDEX_MISMATCHES: usize = 1;
+// The number of barcodes in the report
+const TOP_BARCODES: usize = 20;
+// Stop tallying new barcodes beyond this, the counts of the known ones are still exact
+const MAX_DISTINCT_BARCODES: usize = 100000;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ExpectedIndex {
+    // The sample in the sample sheet, None for an index from the command line
+    sample: Option<String>,
+    index: String,
+    index2: Option<String>,
+}
+
+impl ExpectedIndex {
+    pub fn new(sample: Option<String>, index: &str, index2: Option<&str>) -> ExpectedIndex {
+        return ExpectedIndex {
+            sample: sample,
+            index: index.to_uppercase(),
+            index2: index2
+                .filter(|index2| index2.len() > 0)
+                .map(|index2| index2.to_uppercase()),
+        };
+    }
+
+    pub fn sample(&self) -> &Option<String> {
+        return &self.sample;
+    }
+
+    pub fn index(&self) -> &String {
+        return &self.index;
+    }
+
+    pub fn index2(&self) -> &Option<String> {
+        return &self.index2;
+    }
+
+    fn matches_i7(&self, i7: &str) -> bool {
+        return index_matches(self.index.as_bytes(), i7.as_bytes());
+    }
+
+    /// The i5 in the header is the reverse complement of the sample sheet on some instruments,
+    /// so both orientations are accepted.
+    fn matches_i5(&self, i5: Option<&str>) -> bool {
+        return match (&self.index2, i5) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(expected), Some(i5)) => {
+                index_matches(expected.as_bytes(), i5.as_bytes())
+                    || index_matches(&reverse_complement(expected.as_bytes()), i5.as_bytes())
+            }
+        };
+    }
+}
+
+/// An index in the form of `I7` or `I7+I5`, the same as in Illumina read headers.
+impl FromStr for ExpectedIndex {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut indexes = s.splitn(2, '+');
+        let index = indexes.next().unwrap_or("");
+        let index2 = indexes.next();
+        if !is_index(index) || !index2.map_or(true, is_index) {
+            return Err(format!("Invalid index: {}", s));
+        }
+
+        return Ok(ExpectedIndex::new(None, index, index2));
+    }
+}
+
+fn is_index(index: &str) -> bool {
+    return index.len() > 0 && index.bytes().all(|b| b"ACGTNacgtn".contains(&b));
+}
+
+/// Whether the observed index matches the expected one with at most `MAX_INDEX_MISMATCHES`
+/// mismatches, the observed index may be longer (e.g. 10bp reads of 8bp indexes).
+fn index_matches(expected: &[u8], observed: &[u8]) -> bool {
+    if observed.len() < expected.len() {
+        return false;
+    }
+
+    let mismatches = expected
+        .iter()
+        .zip(observed.iter())
+        .filter(|(e, o)| e != o)
+        .count();
+    return mismatches <= MAX_INDEX_MISMATCHES;
+}
+
+/// Read the rows of the `[Data]` section of an Illumina sample sheet by the lowercase column
+/// names, the `[BCLConvert_Data]` section of the v2 format is also supported. The empty values
+/// are left out.
+pub fn read_sample_sheet_rows(path: &str) -> Result<Vec<HashMap<String, String>>, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(msg) => return Err(format!("Cannot read the sample sheet {}: {}", path, msg)),
+    };
+
+    let mut rows = vec![];
+    let mut in_data = false;
+    let mut columns: Option<Vec<String>> = None;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            // e.g. [Cloud_Data] of the v2 format has no indexes
+            let section = line.split(']').next().unwrap_or("").to_lowercase();
+            in_data = section == "[data" || section == "[bclconvert_data";
+            columns = None;
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        if !in_data || fields.iter().all(|f| f.len() == 0) {
+            continue;
+        }
+
+        let header = match &columns {
+            Some(header) => header,
+            None => {
+                columns = Some(fields.iter().map(|f| f.to_lowercase()).collect());
+                continue;
+            }
+        };
+
+        let row: HashMap<String, String> = header
+            .iter()
+            .zip(fields.iter())
+            .filter(|(_, value)| value.len() > 0)
+            .map(|(column, value)| (column.clone(), value.to_string()))
+            .collect();
+        rows.push(row);
+    }
+
+    if rows.len() == 0 {
+        return Err(format!("No sample found in the sample sheet {}", path));
+    }
+
+    return Ok(rows);
+}
+
+/// The name of the sample in a row of the sample sheet.
+pub fn sample_sheet_sample(row: &HashMap<String, String>) -> Option<String> {
+    return row
+        .get("sample_name")
+        .or(row.get("sample_id"))
+        .map(|sample| sample.clone());
+}
+
+/// Read the indexes of all samples from an Illumina sample sheet, see `read_sample_sheet_rows`.
+/// No indexes are expected when the sheet has no index column, e.g. it only gives the sex.
+pub fn read_sample_sheet(path: &str) -> Result<Vec<ExpectedIndex>, String> {
+    let rows = read_sample_sheet_rows(path)?;
+    if rows.iter().all(|row| !row.contains_key("index")) {
+        return Ok(vec![]);
+    }
+
+    let mut expected = vec![];
+    for row in &rows {
+        let index = match row.get("index") {
+            Some(index) => index,
+            None => {
+                return Err(format!(
+                    "No index found in the sample sheet {}: {:?}",
+                    path, row
+                ))
+            }
+        };
+        expected.push(ExpectedIndex::new(
+            sample_sheet_sample(row),
+            index,
+            row.get("index2").map(|index2| index2.as_str()),
+        ));
+    }
+
+    return Ok(expected);
+}
+
+/// Whether the fastq file belongs to the sample, e.g. `Sample1_S1_L001_R1_001.fastq.gz` of
+/// `Sample1`.
+pub fn matches_sample(sample: &str, fastq_path: &str) -> bool {
+    return match Path::new(fastq_path).file_name() {
+        Some(filename) => {
+            let filename = filename.to_string_lossy();
+            filename.starts_with(&format!("{}_", sample))
+                || filename.starts_with(&format!("{}.", sample))
+        }
+        None => false,
+    };
+}
+
+/// Keep the indexes of the sample which the fastq file belongs to, see `matches_sample`.
+///
+/// All indexes are kept when no sample matches, e.g. for the Undetermined file, so the reads
+/// which swapped indexes between the samples are still counted as hopped.
+pub fn select_expected(expected: &Vec<ExpectedIndex>, fastq_path: &str) -> Vec<ExpectedIndex> {
+    let selected: Vec<ExpectedIndex> = expected
+        .iter()
+        .filter(|index| match &index.sample {
+            Some(sample) => matches_sample(sample, fastq_path),
+            None => false,
+        })
+        .cloned()
+        .collect();
+
+    if selected.len() > 0 {
+        return selected;
+    } else {
+        return expected.clone();
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BarcodeCount {
+    barcode: String,
+    count: usize,
+    percent: f64,
+    expected: bool,
+}
+
+impl BarcodeCount {
+    pub fn barcode(&self) -> &String {
+        return &self.barcode;
+    }
+
+    pub fn count(&self) -> usize {
+        return self.count;
+    }
+
+    pub fn percent(&self) -> f64 {
+        return self.percent;
+    }
+
+    pub fn expected(&self) -> bool {
+        return self.expected;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IndexStats {
+    expected_indexes: Vec<ExpectedIndex>,
+    reads_with_index: usize,
+    // The reads without an index in the header, or with a sample number instead of the index
+    unparsed_reads: usize,
+    reads_with_n: usize,
+    expected_reads: usize,
+    hopped_reads: usize,
+    unexpected_reads: usize,
+    // The percentages of the reads with an index, None when no index is expected
+    percent_expected: Option<f64>,
+    percent_hopped: Option<f64>,
+    percent_with_n: f64,
+    distinct_barcodes: usize,
+    top_barcodes: Vec<BarcodeCount>,
+    #[serde(skip)]
+    header_parser: HeaderParser,
+    #[serde(skip)]
+    barcode_counts: HashMap<String, usize>,
+    status: ModuleStatus,
+}
+
+impl IndexStats {
+    pub fn new(header_parser: HeaderParser) -> IndexStats {
+        return IndexStats {
+            expected_indexes: vec![],
+            reads_with_index: 0,
+            unparsed_reads: 0,
+            reads_with_n: 0,
+            expected_reads: 0,
+            hopped_reads: 0,
+            unexpected_reads: 0,
+            percent_expected: None,
+            percent_hopped: None,
+            percent_with_n: 0.0,
+            distinct_barcodes: 0,
+            top_barcodes: vec![],
+            header_parser: header_parser,
+            barcode_counts: HashMap::new(),
+            status: ModuleStatus::Pass,
+        };
+    }
+
+    /// Must be called before processing any read.
+    pub fn set_expected_indexes(&mut self, expected_indexes: Vec<ExpectedIndex>) {
+        self.expected_indexes = expected_indexes;
+    }
+
+    pub fn expected_indexes(&self) -> &Vec<ExpectedIndex> {
+        return &self.expected_indexes;
+    }
+
+    pub fn reads_with_index(&self) -> usize {
+        return self.reads_with_index;
+    }
+
+    pub fn unparsed_reads(&self) -> usize {
+        return self.unparsed_reads;
+    }
+
+    pub fn reads_with_n(&self) -> usize {
+        return self.reads_with_n;
+    }
+
+    pub fn expected_reads(&self) -> usize {
+        return self.expected_reads;
+    }
+
+    pub fn hopped_reads(&self) -> usize {
+        return self.hopped_reads;
+    }
+
+    pub fn unexpected_reads(&self) -> usize {
+        return self.unexpected_reads;
+    }
+
+    pub fn percent_expected(&self) -> Option<f64> {
+        return self.percent_expected;
+    }
+
+    pub fn percent_hopped(&self) -> Option<f64> {
+        return self.percent_hopped;
+    }
+
+    pub fn percent_with_n(&self) -> f64 {
+        return self.percent_with_n;
+    }
+
+    pub fn distinct_barcodes(&self) -> usize {
+        return self.distinct_barcodes;
+    }
+
+    pub fn top_barcodes(&self) -> &Vec<BarcodeCount> {
+        return &self.top_barcodes;
+    }
+
+    fn is_expected(&self, i7: &str, i5: Option<&str>) -> bool {
+        return self
+            .expected_indexes
+            .iter()
+            .any(|index| index.matches_i7(i7) && index.matches_i5(i5));
+    }
+
+    /// Only one index of the read belongs to an expected combination.
+    fn is_hopped(&self, i7: &str, i5: Option<&str>) -> bool {
+        let i7_expected = self
+            .expected_indexes
+            .iter()
+            .any(|index| index.matches_i7(i7));
+        let i5_expected = i5.is_some()
+            && self
+                .expected_indexes
+                .iter()
+                .any(|index| index.index2.is_some() && index.matches_i5(i5));
+        return i7_expected || i5_expected;
+    }
+
+    fn count_barcode(&mut self, barcode: &str) {
+        match self.barcode_counts.get_mut(barcode) {
+            Some(count) => *count += 1,
+            None => {
+                if self.barcode_counts.len() < MAX_DISTINCT_BARCODES {
+                    self.barcode_counts.insert(barcode.to_string(), 1);
+                }
+            }
+        }
+    }
+}
+
+impl QCModule for IndexStats {
+    fn key(&self) -> &str {
+        return "index_stats";
+    }
+
+    fn name(&self) -> &str {
+        return "Index composition";
+    }
+
+    fn process_sequence(&mut self, record: &ReadRecord) {
+        let header = self.header_parser.parse(record.head());
+        let barcode = match header.barcode() {
+            Some(barcode) if barcode.split('+').all(is_index) => barcode.to_uppercase(),
+            _ => {
+                self.unparsed_reads += 1;
+                return;
+            }
+        };
+
+        self.reads_with_index += 1;
+        if barcode.contains('N') {
+            self.reads_with_n += 1;
+        }
+
+        let mut indexes = barcode.splitn(2, '+');
+        let i7 = indexes.next().unwrap();
+        let i5 = indexes.next();
+        if self.expected_indexes.len() > 0 {
+            if self.is_expected(i7, i5) {
+                self.expected_reads += 1;
+            } else if self.is_hopped(i7, i5) {
+                self.hopped_reads += 1;
+            } else {
+                self.unexpected_reads += 1;
+            }
+        }
+
+        self.count_barcode(&barcode);
+    }
+
+    fn merge(&mut self, other: &dyn QCModule) {
+        let other = downcast::<IndexStats>(other);
+        if self.expected_indexes != other.expected_indexes {
+            panic!("Cannot merge the index stats with different expected indexes");
+        }
+
+        self.reads_with_index += other.reads_with_index;
+        self.unparsed_reads += other.unparsed_reads;
+        self.reads_with_n += other.reads_with_n;
+        self.expected_reads += other.expected_reads;
+        self.hopped_reads += other.hopped_reads;
+        self.unexpected_reads += other.unexpected_reads;
+        for (barcode, count) in &other.barcode_counts {
+            match self.barcode_counts.get_mut(barcode) {
+                Some(total) => *total += count,
+                None => {
+                    if self.barcode_counts.len() < MAX_DISTINCT_BARCODES {
+                        self.barcode_counts.insert(barcode.clone(), *count);
+                    }
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self, _phred_offset: usize) {
+        if self.reads_with_index == 0 {
+            return;
+        }
+
+        let total = self.reads_with_index as f64;
+        self.percent_with_n = self.reads_with_n as f64 / total * 100.0;
+        if self.expected_indexes.len() > 0 {
+            self.percent_expected = Some(self.expected_reads as f64 / total * 100.0);
+            self.percent_hopped = Some(self.hopped_reads as f64 / total * 100.0);
+        }
+
+        let mut counts: Vec<(&String, &usize)> = self.barcode_counts.iter().collect();
+        // Ties are ordered by the barcode, so the report is the same for any number of threads
+        counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        self.distinct_barcodes = counts.len();
+        self.top_barcodes = counts
+            .iter()
+            .take(TOP_BARCODES)
+            .map(|(barcode, count)| {
+                let mut indexes = barcode.splitn(2, '+');
+                let i7 = indexes.next().unwrap();
+                let i5 = indexes.next();
+                BarcodeCount {
+                    barcode: barcode.to_string(),
+                    count: **count,
+                    percent: **count as f64 / total * 100.0,
+                    expected: self.is_expected(i7, i5),
+                }
+            })
+            .collect();
+    }
+
+    /// Warn or fail on a low fraction of the expected index or a high hopping rate, the module
+    /// is ignored when no read has an index.
+    fn calculate_status(&mut self, limits: &Limits) {
+        if self.reads_with_index == 0 || limits.ignore("index") {
+            self.status = ModuleStatus::Ignore;
+            return;
+        }
+
+        self.status = ModuleStatus::Pass;
+        if let Some(percent_expected) = self.percent_expected {
+            self.status = self
+                .status
+                .max(limits.lower_status("index", percent_expected));
+        }
+
+        if let Some(percent_hopped) = self.percent_hopped {
+            self.status = self
+                .status
+                .max(limits.upper_status("index_hopping", percent_hopped));
+        }
+    }
+
+    fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
+
+#[cfg(test)]
+mod barcode_tests {
+    use super::*;
+    use fastq::OwnedRecord;
+
+    fn process(module: &mut IndexStats, barcode: &str) {
+        let record = OwnedRecord {
+            head: format!("A00123:8:FC1:1:1101:1000:2000 1:N:0:{}", barcode).into_bytes(),
+            seq: b"ACGTACGTAC".to_vec(),
+            qual: b"IIIIIIIIII".to_vec(),
+            sep: None,
+        };
+        module.process_sequence(&ReadRecord::new(&record));
+    }
+
+    #[test]
+    fn test_index_stats() {
+        let expected = vec![ExpectedIndex::from_str("ACGTACGT+TTGCAGGA").unwrap()];
+        let mut module = IndexStats::new(HeaderParser::default());
+        let mut other = IndexStats::new(HeaderParser::default());
+        module.set_expected_indexes(expected.clone());
+        other.set_expected_indexes(expected);
+        for _ in 0..90 {
+            process(&mut module, "ACGTACGT+TTGCAGGA");
+        }
+        // One mismatch and the reverse complement of i5
+        process(&mut module, "ACGTACGA+TCCTGCAA");
+        for _ in 0..5 {
+            process(&mut other, "ACGTACGT+GGGGGGGG");
+        }
+        process(&mut other, "CCCCCCCC+TTGCAGGA");
+        process(&mut other, "NNNNNNNN+NNNNNNNN");
+        process(&mut other, "CCCCCCCC+GGGGGGGG");
+        process(&mut other, "CCCCCCCC+GGGGGGGG");
+        other.process_sequence(&ReadRecord::new(&OwnedRecord {
+            head: b"read1".to_vec(),
+            seq: b"ACGT".to_vec(),
+            qual: b"IIII".to_vec(),
+            sep: None,
+        }));
+        module.merge(&other);
+        module.finish(33);
+        module.calculate_status(&Limits::default());
+
+        assert_eq!(module.reads_with_index(), 100);
+        assert_eq!(module.unparsed_reads(), 1);
+        assert_eq!(module.reads_with_n(), 1);
+        assert_eq!(module.expected_reads(), 91);
+        assert_eq!(module.hopped_reads(), 6);
+        assert_eq!(module.unexpected_reads(), 3);
+        assert_eq!(module.percent_hopped(), Some(6.0));
+        assert_eq!(module.distinct_barcodes(), 6);
+
+        let top = &module.top_barcodes()[0];
+        assert_eq!(top.barcode(), "ACGTACGT+TTGCAGGA");
+        assert_eq!(top.count(), 90);
+        assert!(top.expected());
+        assert_eq!(module.top_barcodes()[1].barcode(), "ACGTACGT+GGGGGGGG");
+        assert!(!module.top_barcodes()[1].expected());
+        assert_eq!(module.status(), ModuleStatus::Fail);
+    }
+
+    #[test]
+    fn test_select_expected() {
+        let expected = vec![
+            ExpectedIndex::new(Some("S1".to_string()), "ACGTACGT", Some("TTGCAGGA")),
+            ExpectedIndex::new(Some("S10".to_string()), "GGGGGGGG", None),
+        ];
+        let selected = select_expected(&expected, "/data/S1_S1_L001_R1_001.fastq.gz");
+        assert_eq!(selected, vec![expected[0].clone()]);
+        let selected = select_expected(&expected, "Undetermined_S0_L001_R1_001.fastq.gz");
+        assert_eq!(selected, expected);
+
+        assert!(ExpectedIndex::from_str("ACGT+").is_err());
+        assert!(ExpectedIndex::from_str("ACGX").is_err());
+        assert_eq!(
+            ExpectedIndex::from_str("acgt").unwrap().index(),
+            &"ACGT".to_string()
+        );
+    }
+
+    #[test]
+    fn test_read_sample_sheet() {
+        let path = std::env::temp_dir().join("preqc_barcode_tests.csv");
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "[Header]\nDate,2024-01-01\n\n[Data]\nSample_ID,index,index2\nS1,ACGTACGT,TTGCAGGA\n",
+        )
+        .unwrap();
+        let expected = read_sample_sheet(path).unwrap();
+        assert_eq!(expected[0].index(), &"ACGTACGT".to_string());
+
+        // A sheet which only gives the sex has no indexes to expect
+        std::fs::write(path, "[Data]\nSample_ID,Sex\nS1,F\n").unwrap();
+        assert_eq!(read_sample_sheet(path).unwrap(), vec![]);
+
+        // Only the [BCLConvert_Data] section of a v2 sheet is read, not [Cloud_Data]
+        std::fs::write(
+            path,
+            "[Header]\nFileFormatVersion,2\n\n[BCLConvert_Data]\nSample_ID,Index,Index2\n\
+             S1,ACGTACGT,TTGCAGGA\n\n[Cloud_Data]\nSample_ID,ProjectName,LibraryName\n\
+             S1,P1,L1\n",
+        )
+        .unwrap();
+        let rows = read_sample_sheet_rows(path).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get("index"), Some(&"ACGTACGT".to_string()));
+        assert!(!rows[0].contains_key("projectname"));
+
+        std::fs::write(path, "[Header]\nDate,2024-01-01\n").unwrap();
+        assert!(read_sample_sheet(path).is_err());
+        std::fs::remove_file(path).unwrap();
+        assert!(read_sample_sheet("/nonexistent/SampleSheet.csv").is_err());
+    }
+}
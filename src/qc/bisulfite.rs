@@ -0,0 +1,677 @@
+//! Bisulfite / EM-seq metrics for the bisulfite profile.
+//!
+//! The conversion turns the unmethylated C into T, so a read of a converted strand (OT/OB) has
+//! almost no C and a read of its complement (CTOT/CTOB) has almost no G. The reads are classified
+//! by their C and G, which tells the library type: R1 of a directional library is from OT/OB,
+//! R1 of a PBAT library is from CTOT/CTOB, and a non-directional library has both. A read with
+//! both C and G is unconverted.
+//!
+//! The M-bias is the CpG level per read position, by the CG and TG (CA and CG for the CTOT/CTOB
+//! reads) in the reads, relative to the level of all positions. It's not aligned, so the genomic
+//! TG is a background and the level is not the methylation, but the background is the same at all
+//! positions, so the ends with a different level (e.g. by the end-repair) still deviate from 1.
+//!
+//! The conversion rate is measured on the CHH context of a spike-in (lambda, pUC19 etc.) which
+//! is given as FASTA: the reads are placed on the converted spike-in by k-mers, and each C of the
+//! spike-in in CHH context is converted when the read has T.
+//!
+//! Without a spike-in, the conversion rate is estimated by the T in the C and T followed by two
+//! non-G (CHH) bases of the OT/OB reads. The genomic T in that context can't be told from a
+//! converted C without alignment, so the estimate is an upper bound of the conversion rate.
+use super::fastqc::{Limits, ModuleStatus};
+use super::module::{downcast, QCModule, ReadRecord};
+use super::util::{complement, for_each_fasta_record, hash_seq, reverse_complement};
+use fastq::Record;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+// Fewer C and G can't tell the strand of a read
+const MIN_CG_BASES: usize = 10;
+// The fraction of C in the C and G of a converted read is about 0.05 for human
+const MAX_CONVERTED_C_FRACTION: f64 = 0.2;
+// The fraction of the OT/OB reads of a directional library
+const DIRECTIONAL_FRACTION: f64 = 0.8;
+const MIN_LIBRARY_READS: usize = 100;
+const SPIKE_IN_KMER_SIZE: usize = 25;
+// The k-mers of a read are looked up at this step
+const SPIKE_IN_KMER_STEP: usize = 8;
+const MIN_SPIKE_IN_HITS: usize = 2;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LibraryType {
+    Directional,
+    NonDirectional,
+    Pbat,
+    Undetermined,
+}
+
+impl fmt::Display for LibraryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LibraryType::Directional => "directional",
+            LibraryType::NonDirectional => "non-directional",
+            LibraryType::Pbat => "PBAT",
+            LibraryType::Undetermined => "undetermined",
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReadStrand {
+    // OT/OB, C to T
+    Converted,
+    // CTOT/CTOB, G to A
+    Complementary,
+    Unconverted,
+}
+
+/// The strand of a read by its C and G, None for too few of them.
+fn read_strand(seq: &[u8]) -> Option<ReadStrand> {
+    let c = seq.iter().filter(|b| **b == b'C' || **b == b'c').count();
+    let g = seq.iter().filter(|b| **b == b'G' || **b == b'g').count();
+    if c + g < MIN_CG_BASES {
+        return None;
+    }
+
+    let c_fraction = c as f64 / (c + g) as f64;
+    if c_fraction <= MAX_CONVERTED_C_FRACTION {
+        return Some(ReadStrand::Converted);
+    } else if c_fraction >= 1.0 - MAX_CONVERTED_C_FRACTION {
+        return Some(ReadStrand::Complementary);
+    } else {
+        return Some(ReadStrand::Unconverted);
+    }
+}
+
+/// The strands of the spike-in sequences, and their k-mers after the C to T conversion.
+#[derive(Default)]
+pub struct SpikeIn {
+    names: Vec<String>,
+    // The forward and reverse strands of each sequence, uppercase
+    strands: Vec<Vec<u8>>,
+    // The k-mer in only one place, or None for the repeated k-mers
+    kmers: HashMap<u64, Option<(usize, usize)>>,
+}
+
+impl fmt::Debug for SpikeIn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return f
+            .debug_struct("SpikeIn")
+            .field("names", &self.names)
+            .field("kmers", &self.kmers.len())
+            .finish();
+    }
+}
+
+fn convert_c_to_t(seq: &[u8]) -> Vec<u8> {
+    return seq
+        .iter()
+        .map(|base| match base.to_ascii_uppercase() {
+            b'C' => b'T',
+            base => base,
+        })
+        .collect();
+}
+
+impl SpikeIn {
+    /// Read the sequences of the spike-in, e.g. lambda phage (NC_001416.1).
+    pub fn read_fasta(path: &str) -> Result<SpikeIn, String> {
+        let mut spike_in = SpikeIn::default();
+        let result = for_each_fasta_record(path, |header, seq| {
+            let name = String::from_utf8_lossy(header);
+            spike_in.add_sequence(name.split_whitespace().next().unwrap_or(""), seq);
+        });
+
+        return match result {
+            Err(msg) => Err(format!("Cannot read the spike-in {} - {}", path, msg)),
+            Ok(_) if spike_in.kmers.len() == 0 => {
+                Err(format!("No sequence found in the spike-in {}", path))
+            }
+            Ok(_) => Ok(spike_in),
+        };
+    }
+
+    pub fn add_sequence(&mut self, name: &str, seq: &[u8]) {
+        self.names.push(name.to_string());
+        let forward = seq.to_ascii_uppercase();
+        let reverse = reverse_complement(&forward);
+        for strand in vec![forward, reverse] {
+            let converted = convert_c_to_t(&strand);
+            let index = self.strands.len();
+            for (position, kmer) in converted.windows(SPIKE_IN_KMER_SIZE).enumerate() {
+                self.kmers
+                    .entry(hash_seq(kmer))
+                    .and_modify(|place| *place = None)
+                    .or_insert(Some((index, position)));
+            }
+            self.strands.push(strand);
+        }
+    }
+
+    pub fn names(&self) -> &Vec<String> {
+        return &self.names;
+    }
+
+    /// The strand and the offset of the read on it by the most k-mer hits, the read has been
+    /// oriented as a converted strand. `votes` is a buffer kept over the reads, a read has only a
+    /// few places so it's searched linearly.
+    fn place(
+        &self,
+        read: &[u8],
+        votes: &mut Vec<((usize, isize), usize)>,
+    ) -> Option<(usize, isize)> {
+        if read.len() < SPIKE_IN_KMER_SIZE {
+            return None;
+        }
+
+        votes.clear();
+        let mut kmer = [0u8; SPIKE_IN_KMER_SIZE];
+        for i in (0..read.len() - SPIKE_IN_KMER_SIZE + 1).step_by(SPIKE_IN_KMER_STEP) {
+            for (converted, base) in kmer.iter_mut().zip(&read[i..i + SPIKE_IN_KMER_SIZE]) {
+                *converted = match base.to_ascii_uppercase() {
+                    b'C' => b'T',
+                    base => base,
+                };
+            }
+            if let Some(Some((strand, position))) = self.kmers.get(&hash_seq(&kmer)) {
+                let place = (*strand, *position as isize - i as isize);
+                match votes.iter_mut().find(|(voted, _)| *voted == place) {
+                    Some((_, hits)) => *hits += 1,
+                    None => votes.push((place, 1)),
+                }
+            }
+        }
+
+        return votes
+            .iter()
+            .filter(|(_, hits)| *hits >= MIN_SPIKE_IN_HITS)
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+            .map(|(place, _)| *place);
+    }
+
+    /// The converted and unconverted C of the spike-in in CHH context, None when the read is not
+    /// from the spike-in.
+    fn count_chh(
+        &self,
+        read: &[u8],
+        votes: &mut Vec<((usize, isize), usize)>,
+    ) -> Option<(usize, usize)> {
+        let (strand, offset) = self.place(read, votes)?;
+        let strand = &self.strands[strand];
+        let is_h = |position: usize| match strand.get(position) {
+            Some(b'A') | Some(b'C') | Some(b'T') => true,
+            _ => false,
+        };
+
+        let (mut converted, mut unconverted) = (0, 0);
+        for (i, base) in read.iter().enumerate() {
+            let position = offset + i as isize;
+            if position < 0 {
+                continue;
+            }
+
+            let position = position as usize;
+            if strand.get(position) != Some(&b'C') || !is_h(position + 1) || !is_h(position + 2) {
+                continue;
+            }
+            match base.to_ascii_uppercase() {
+                b'T' => converted += 1,
+                b'C' => unconverted += 1,
+                _ => {}
+            }
+        }
+
+        return Some((converted, unconverted));
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BisulfiteStats {
+    total_reads: usize,
+    // OT/OB reads
+    converted_reads: usize,
+    // CTOT/CTOB reads
+    complementary_reads: usize,
+    unconverted_reads: usize,
+    // Of the reads with enough C and G
+    percent_unconverted_reads: f64,
+    library_type: LibraryType,
+    // The CG and TG per read position, see the module doc
+    m_bias_methylated: Vec<usize>,
+    m_bias_unmethylated: Vec<usize>,
+    // The CpG level per read position divided by the level of all positions
+    m_bias_relative: Vec<f64>,
+    spike_in_reads: usize,
+    chh_converted: usize,
+    chh_unconverted: usize,
+    // The percentage of the converted C in CHH context of the spike-in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conversion_rate: Option<f64>,
+    // The C and T in CHH context of the OT/OB reads
+    read_chh_c: usize,
+    read_chh_t: usize,
+    // The percentage of T of them without a spike-in, see the module doc
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conversion_rate_upper_bound: Option<f64>,
+    #[serde(skip)]
+    spike_in: Option<Arc<SpikeIn>>,
+    // The buffers of the spike-in, kept over the reads
+    #[serde(skip)]
+    votes: Vec<((usize, isize), usize)>,
+    #[serde(skip)]
+    reverse: Vec<u8>,
+    status: ModuleStatus,
+}
+
+impl BisulfiteStats {
+    pub fn new(spike_in: Option<Arc<SpikeIn>>) -> BisulfiteStats {
+        return BisulfiteStats {
+            total_reads: 0,
+            converted_reads: 0,
+            complementary_reads: 0,
+            unconverted_reads: 0,
+            percent_unconverted_reads: 0.0,
+            library_type: LibraryType::Undetermined,
+            m_bias_methylated: vec![],
+            m_bias_unmethylated: vec![],
+            m_bias_relative: vec![],
+            spike_in_reads: 0,
+            chh_converted: 0,
+            chh_unconverted: 0,
+            conversion_rate: None,
+            read_chh_c: 0,
+            read_chh_t: 0,
+            conversion_rate_upper_bound: None,
+            spike_in: spike_in,
+            votes: vec![],
+            reverse: vec![],
+            status: ModuleStatus::Pass,
+        };
+    }
+
+    fn count_m_bias(&mut self, seq: &[u8], strand: ReadStrand) {
+        if self.m_bias_methylated.len() < seq.len() {
+            self.m_bias_methylated.resize(seq.len(), 0);
+            self.m_bias_unmethylated.resize(seq.len(), 0);
+        }
+
+        for i in 0..seq.len().saturating_sub(1) {
+            // The position of the C of a CpG on the converted strand, or the G on its complement
+            let dinucleotide = (seq[i].to_ascii_uppercase(), seq[i + 1].to_ascii_uppercase());
+            let (position, methylated) = match (strand, dinucleotide.0, dinucleotide.1) {
+                (ReadStrand::Converted, b'C', b'G') => (i, true),
+                (ReadStrand::Converted, b'T', b'G') => (i, false),
+                (ReadStrand::Complementary, b'C', b'G') => (i + 1, true),
+                (ReadStrand::Complementary, b'C', b'A') => (i + 1, false),
+                _ => continue,
+            };
+            if methylated {
+                self.m_bias_methylated[position] += 1;
+            } else {
+                self.m_bias_unmethylated[position] += 1;
+            }
+        }
+    }
+
+    /// Count the C and T followed by two non-G bases of an OT/OB read.
+    fn count_read_chh(&mut self, seq: &[u8]) {
+        let is_h = |base: u8| match base.to_ascii_uppercase() {
+            b'A' | b'C' | b'T' => true,
+            _ => false,
+        };
+        for context in seq.windows(3) {
+            if !is_h(context[1]) || !is_h(context[2]) {
+                continue;
+            }
+            match context[0].to_ascii_uppercase() {
+                b'C' => self.read_chh_c += 1,
+                b'T' => self.read_chh_t += 1,
+                _ => {}
+            }
+        }
+    }
+
+    pub fn total_reads(&self) -> usize {
+        return self.total_reads;
+    }
+
+    pub fn converted_reads(&self) -> usize {
+        return self.converted_reads;
+    }
+
+    pub fn complementary_reads(&self) -> usize {
+        return self.complementary_reads;
+    }
+
+    pub fn unconverted_reads(&self) -> usize {
+        return self.unconverted_reads;
+    }
+
+    pub fn percent_unconverted_reads(&self) -> f64 {
+        return self.percent_unconverted_reads;
+    }
+
+    pub fn library_type(&self) -> LibraryType {
+        return self.library_type;
+    }
+
+    pub fn m_bias_relative(&self) -> &Vec<f64> {
+        return &self.m_bias_relative;
+    }
+
+    pub fn spike_in_reads(&self) -> usize {
+        return self.spike_in_reads;
+    }
+
+    pub fn chh_converted(&self) -> usize {
+        return self.chh_converted;
+    }
+
+    pub fn chh_unconverted(&self) -> usize {
+        return self.chh_unconverted;
+    }
+
+    pub fn conversion_rate(&self) -> Option<f64> {
+        return self.conversion_rate;
+    }
+
+    pub fn conversion_rate_upper_bound(&self) -> Option<f64> {
+        return self.conversion_rate_upper_bound;
+    }
+
+    pub fn spike_in(&self) -> &Option<Arc<SpikeIn>> {
+        return &self.spike_in;
+    }
+}
+
+impl QCModule for BisulfiteStats {
+    fn key(&self) -> &str {
+        return "bisulfite";
+    }
+
+    fn name(&self) -> &str {
+        return "Bisulfite conversion";
+    }
+
+    fn process_sequence(&mut self, record: &ReadRecord) {
+        self.total_reads += 1;
+        let seq = record.seq();
+        match read_strand(seq) {
+            Some(ReadStrand::Converted) => {
+                self.converted_reads += 1;
+                self.count_m_bias(seq, ReadStrand::Converted);
+                self.count_read_chh(seq);
+            }
+            Some(ReadStrand::Complementary) => {
+                self.complementary_reads += 1;
+                self.count_m_bias(seq, ReadStrand::Complementary);
+            }
+            Some(ReadStrand::Unconverted) => self.unconverted_reads += 1,
+            None => {}
+        }
+
+        if let Some(spike_in) = &self.spike_in {
+            // A CTOT/CTOB read is the reverse complement of a converted strand
+            let (votes, reverse) = (&mut self.votes, &mut self.reverse);
+            let counts = spike_in.count_chh(seq, votes).or_else(|| {
+                reverse.clear();
+                reverse.extend(seq.iter().rev().map(|base| complement(*base)));
+                return spike_in.count_chh(reverse, votes);
+            });
+            if let Some((converted, unconverted)) = counts {
+                self.spike_in_reads += 1;
+                self.chh_converted += converted;
+                self.chh_unconverted += unconverted;
+            }
+        }
+    }
+
+    fn merge(&mut self, other: &dyn QCModule) {
+        let other = downcast::<BisulfiteStats>(other);
+        self.total_reads += other.total_reads;
+        self.converted_reads += other.converted_reads;
+        self.complementary_reads += other.complementary_reads;
+        self.unconverted_reads += other.unconverted_reads;
+        if self.m_bias_methylated.len() < other.m_bias_methylated.len() {
+            self.m_bias_methylated
+                .resize(other.m_bias_methylated.len(), 0);
+            self.m_bias_unmethylated
+                .resize(other.m_bias_unmethylated.len(), 0);
+        }
+        for (i, count) in other.m_bias_methylated.iter().enumerate() {
+            self.m_bias_methylated[i] += count;
+        }
+        for (i, count) in other.m_bias_unmethylated.iter().enumerate() {
+            self.m_bias_unmethylated[i] += count;
+        }
+        self.spike_in_reads += other.spike_in_reads;
+        self.chh_converted += other.chh_converted;
+        self.chh_unconverted += other.chh_unconverted;
+        self.read_chh_c += other.read_chh_c;
+        self.read_chh_t += other.read_chh_t;
+    }
+
+    fn finish(&mut self, _phred_offset: usize) {
+        let classified_reads = self.converted_reads + self.complementary_reads;
+        let strand_reads = classified_reads + self.unconverted_reads;
+        if strand_reads > 0 {
+            self.percent_unconverted_reads =
+                self.unconverted_reads as f64 / strand_reads as f64 * 100.0;
+        }
+
+        if classified_reads >= MIN_LIBRARY_READS {
+            let fraction = self.converted_reads as f64 / classified_reads as f64;
+            self.library_type = if fraction >= DIRECTIONAL_FRACTION {
+                LibraryType::Directional
+            } else if fraction <= 1.0 - DIRECTIONAL_FRACTION {
+                LibraryType::Pbat
+            } else {
+                LibraryType::NonDirectional
+            };
+        }
+
+        let methylated: usize = self.m_bias_methylated.iter().sum();
+        let unmethylated: usize = self.m_bias_unmethylated.iter().sum();
+        if methylated > 0 {
+            let level = methylated as f64 / (methylated + unmethylated) as f64;
+            self.m_bias_relative = self
+                .m_bias_methylated
+                .iter()
+                .zip(self.m_bias_unmethylated.iter())
+                .map(
+                    |(methylated, unmethylated)| match methylated + unmethylated {
+                        0 => 0.0,
+                        total => *methylated as f64 / total as f64 / level,
+                    },
+                )
+                .collect();
+        }
+
+        let chh = self.chh_converted + self.chh_unconverted;
+        if chh > 0 {
+            self.conversion_rate = Some(self.chh_converted as f64 / chh as f64 * 100.0);
+        }
+
+        let read_chh = self.read_chh_c + self.read_chh_t;
+        if self.spike_in.is_none() && read_chh > 0 {
+            self.conversion_rate_upper_bound =
+                Some(self.read_chh_t as f64 / read_chh as f64 * 100.0);
+        }
+    }
+
+    /// Grade the conversion rate of the spike-in, it passes without a spike-in.
+    fn calculate_status(&mut self, limits: &Limits) {
+        if self.total_reads == 0 || limits.ignore("bisulfite") {
+            self.status = ModuleStatus::Ignore;
+            return;
+        }
+
+        self.status = match self.conversion_rate {
+            Some(rate) => limits.lower_status("bisulfite", rate),
+            None => ModuleStatus::Pass,
+        };
+    }
+
+    fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
+
+#[cfg(test)]
+mod bisulfite_tests {
+    use super::*;
+    use crate::qc::fastqc::{bisulfite_modules, FastQC};
+    use crate::qc::util::mix64;
+    use crate::qc::FastQCConfig;
+    use fastq::OwnedRecord;
+
+    // A pseudo-random spike-in
+    fn genome(seed: u64, length: usize) -> Vec<u8> {
+        return (0..length as u64)
+            .map(|i| b"ACGT"[(mix64(seed ^ i) % 4) as usize])
+            .collect();
+    }
+
+    // Convert all C but a quarter of the CpG which are methylated, the random genome has more CpG
+    // than a real one
+    fn bisulfite(seq: &[u8]) -> Vec<u8> {
+        return (0..seq.len())
+            .map(|i| match (seq[i], seq.get(i + 1)) {
+                (b'C', Some(b'G')) if i % 4 == 0 => b'C',
+                (b'C', _) => b'T',
+                (base, _) => base,
+            })
+            .collect();
+    }
+
+    fn process(module: &mut BisulfiteStats, seq: &[u8]) {
+        let record = OwnedRecord {
+            head: b"read".to_vec(),
+            seq: seq.to_vec(),
+            qual: vec![b'I'; seq.len()],
+            sep: None,
+        };
+        module.process_sequence(&ReadRecord::new(&record));
+    }
+
+    #[test]
+    fn test_bisulfite_stats() {
+        let lambda = genome(1, 5000);
+        let mut spike_in = SpikeIn::default();
+        spike_in.add_sequence("lambda", &lambda);
+        let spike_in = Arc::new(spike_in);
+
+        let mut module = BisulfiteStats::new(Some(spike_in.clone()));
+        let mut other = BisulfiteStats::new(Some(spike_in));
+        for i in 0..100 {
+            let read = &lambda[i * 40..i * 40 + 100];
+            let module = if i % 2 == 0 { &mut module } else { &mut other };
+            // OT reads, and one CTOT read from the converted reverse strand
+            if i == 99 {
+                process(
+                    module,
+                    &reverse_complement(&bisulfite(&reverse_complement(read))),
+                );
+            } else {
+                process(module, &bisulfite(read));
+            }
+        }
+        process(&mut module, &lambda[200..300]);
+        module.merge(&other);
+        module.finish(33);
+        module.calculate_status(&Limits::default());
+
+        assert_eq!(module.total_reads(), 101);
+        assert_eq!(
+            (module.converted_reads(), module.complementary_reads()),
+            (99, 1)
+        );
+        assert_eq!(module.unconverted_reads(), 1);
+        assert_eq!(module.library_type(), LibraryType::Directional);
+        assert_eq!(module.m_bias_relative().len(), 100);
+        // Only the CpG at every 4th position are methylated
+        let m_bias = module.m_bias_relative();
+        assert!(m_bias.iter().step_by(4).all(|p| *p > 1.0));
+        assert!(m_bias.iter().skip(1).step_by(4).all(|p| *p == 0.0));
+
+        // The unconverted read lowers the conversion rate
+        assert_eq!(module.spike_in_reads(), 101);
+        let rate = module.conversion_rate().unwrap();
+        assert!(rate < 100.0 && rate > 95.0);
+        assert!(module.chh_unconverted() > 0);
+        assert_eq!(module.conversion_rate_upper_bound(), None);
+
+        let mut unrelated = BisulfiteStats::new(Some(Arc::new(SpikeIn::default())));
+        process(&mut unrelated, &bisulfite(&genome(2, 100)));
+        unrelated.finish(33);
+        assert_eq!(unrelated.spike_in_reads(), 0);
+        assert_eq!(unrelated.conversion_rate(), None);
+        assert_eq!(unrelated.library_type(), LibraryType::Undetermined);
+    }
+
+    #[test]
+    fn test_conversion_rate_upper_bound() {
+        let genome = genome(3, 5000);
+        let mut module = BisulfiteStats::new(None);
+        for i in 0..40 {
+            let read = &genome[i * 100..i * 100 + 100];
+            // Leave some C unconverted
+            let mut converted = bisulfite(read);
+            for j in (1..read.len()).step_by(40) {
+                converted[j] = read[j];
+            }
+            process(&mut module, &converted);
+        }
+        module.finish(33);
+
+        assert_eq!(module.converted_reads(), 40);
+        assert_eq!(module.conversion_rate(), None);
+        let rate = module.conversion_rate_upper_bound().unwrap();
+        assert!(rate < 100.0 && rate > 90.0);
+
+        let mut converted = BisulfiteStats::new(None);
+        process(&mut converted, &bisulfite(&genome[..100]));
+        converted.finish(33);
+        assert_eq!(converted.conversion_rate_upper_bound(), Some(100.0));
+    }
+
+    #[test]
+    fn test_bisulfite_limits() {
+        // The profile overrides the limits file, even the built-in limits given explicitly
+        let mut config = FastQCConfig::new(
+            include_str!("../../data/adapter_list.txt").to_string(),
+            include_str!("../../data/contaminant_list.txt").to_string(),
+            include_str!("../../data/limits.txt").to_string(),
+            None,
+            None,
+            None,
+            None,
+        );
+        config.set_modules(bisulfite_modules()).unwrap();
+        let mut qc = FastQC::new(&config);
+        let lambda = genome(1, 5000);
+        for i in 0..40 {
+            let record = OwnedRecord {
+                head: format!("read{}", i).into_bytes(),
+                seq: bisulfite(&lambda[i * 100..i * 100 + 100]),
+                qual: vec![b'I'; 100],
+                sep: None,
+            };
+            qc.process_sequence(&record);
+        }
+        qc.finish();
+
+        assert_eq!(
+            qc.per_base_seq_content.as_ref().map(|m| m.status()),
+            Some(ModuleStatus::Ignore)
+        );
+        assert_eq!(
+            qc.per_seq_gc_content.as_ref().map(|m| m.status()),
+            Some(ModuleStatus::Ignore)
+        );
+    }
+}
@@ -0,0 +1,362 @@
+//! Extrapolate the number of distinct reads at a deeper sequencing, like `preseq lc_extrap`.
+//!
+//! The duplicate count histogram (`n_j` sequences seen `j` times) gives the Good–Toulmin power
+//! series of the new distinct reads from `t` more units of sequencing. The series diverges for
+//! `t > 1`, so it is turned into a continued fraction (a rational function), which stays valid
+//! far beyond the current depth as long as the curve is increasing and concave.
+use super::fastqc::{Limits, ModuleStatus};
+use super::module::{downcast, QCModule, ReadRecord};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// The same defaults as preseq
+const MAX_TERMS: usize = 100;
+const MIN_TERMS: usize = 4;
+// Extrapolate from 1x to 10x the current depth
+const MAX_FOLD: usize = 10;
+// The points between two folds where the curve is checked
+const STABILITY_STEPS: usize = 10;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ComplexityPoint {
+    fold: usize,
+    reads: usize,
+    distinct_reads: f64,
+}
+
+impl ComplexityPoint {
+    pub fn fold(&self) -> usize {
+        return self.fold;
+    }
+
+    pub fn reads(&self) -> usize {
+        return self.reads;
+    }
+
+    pub fn distinct_reads(&self) -> f64 {
+        return self.distinct_reads;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LibraryComplexity {
+    // The reads in the histogram, the depths of the extrapolation are relative to them
+    total_reads: usize,
+    distinct_reads: usize,
+    // All reads of the input, the histogram of the overrepresented module only covers the reads
+    // before its unique sequence limit
+    input_reads: usize,
+    // rational_function, or good_toulmin (only up to 2x) when no continued fraction is stable
+    method: String,
+    // The number of terms of the continued fraction
+    terms: usize,
+    extrapolation: Vec<ComplexityPoint>,
+    // The Chao1 lower bound of the distinct molecules in the library
+    estimated_library_size: f64,
+    status: ModuleStatus,
+}
+
+impl LibraryComplexity {
+    /// `histogram` maps the number of copies to the number of distinct sequences with that many
+    /// copies, it may only cover a sample of the `input_reads`.
+    pub fn new(histogram: &BTreeMap<usize, usize>, input_reads: usize) -> LibraryComplexity {
+        let total_reads: usize = histogram.iter().map(|(copies, n)| copies * n).sum();
+        let mut complexity = LibraryComplexity {
+            total_reads: total_reads,
+            distinct_reads: histogram.values().sum(),
+            input_reads: input_reads.max(total_reads),
+            method: String::new(),
+            terms: 0,
+            extrapolation: vec![],
+            estimated_library_size: 0.0,
+            status: ModuleStatus::Pass,
+        };
+        if total_reads == 0 {
+            return complexity;
+        }
+
+        let count = |copies: usize| *histogram.get(&copies).unwrap_or(&0) as f64;
+        let (n1, n2) = (count(1), count(2));
+        complexity.estimated_library_size =
+            complexity.distinct_reads as f64 + n1 * (n1 - 1.0).max(0.0) / (2.0 * (n2 + 1.0));
+
+        // The series stops at the first missing copy number
+        let max_terms = (1..MAX_TERMS + 1)
+            .take_while(|copies| count(*copies) > 0.0)
+            .count();
+        // The coefficient of t^(j-1) is (-1)^(j+1) n_j
+        let coeffs: Vec<f64> = (1..max_terms + 1)
+            .map(|j| if j % 2 == 1 { count(j) } else { -count(j) })
+            .collect();
+
+        let distinct = complexity.distinct_reads as f64;
+        let cf = continued_fraction(&coeffs);
+        // An even number of terms gives the same degree of the numerator and the denominator, so
+        // the curve can saturate
+        let stable = (MIN_TERMS..cf.len() + 1)
+            .rev()
+            .filter(|terms| terms % 2 == 0)
+            .find(|terms| is_stable(&cf[..*terms], distinct));
+        let (method, folds) = match stable {
+            Some(terms) => {
+                complexity.terms = terms;
+                ("rational_function", MAX_FOLD)
+            }
+            // Good–Toulmin is only reliable up to twice the depth
+            None => ("good_toulmin", 2),
+        };
+
+        complexity.method = method.to_string();
+        complexity.extrapolation = (1..folds + 1)
+            .map(|fold| {
+                let t = (fold - 1) as f64;
+                let new_reads = match stable {
+                    Some(terms) => t * evaluate(&cf[..terms], t),
+                    None => coeffs
+                        .iter()
+                        .enumerate()
+                        .map(|(i, c)| c * t.powi(i as i32 + 1))
+                        .sum(),
+                };
+                ComplexityPoint {
+                    fold: fold,
+                    reads: total_reads * fold,
+                    distinct_reads: distinct + new_reads,
+                }
+            })
+            .collect();
+
+        return complexity;
+    }
+
+    pub fn total_reads(&self) -> usize {
+        return self.total_reads;
+    }
+
+    pub fn distinct_reads(&self) -> usize {
+        return self.distinct_reads;
+    }
+
+    pub fn input_reads(&self) -> usize {
+        return self.input_reads;
+    }
+
+    pub fn method(&self) -> &String {
+        return &self.method;
+    }
+
+    pub fn terms(&self) -> usize {
+        return self.terms;
+    }
+
+    pub fn extrapolation(&self) -> &Vec<ComplexityPoint> {
+        return &self.extrapolation;
+    }
+
+    pub fn estimated_library_size(&self) -> f64 {
+        return self.estimated_library_size;
+    }
+}
+
+/// The coefficients `a` of the continued fraction
+/// `a0 / (1 + a1 t / (1 + a2 t / (1 + ...)))` which has the same power series as `coeffs`, by
+/// the quotient-difference algorithm.
+///
+/// The fraction is cut at the first coefficient which can't be computed, e.g. when the series
+/// is a rational function of a lower degree.
+///
+fn continued_fraction(coeffs: &Vec<f64>) -> Vec<f64> {
+    if coeffs.len() == 0 {
+        return vec![];
+    }
+
+    let mut cf = vec![coeffs[0]];
+    let mut q: Vec<f64> = (0..coeffs.len() - 1)
+        .map(|k| coeffs[k + 1] / coeffs[k])
+        .collect();
+    let mut e_prev = vec![0.0; coeffs.len()];
+    while q.len() > 0 && q[0].is_finite() {
+        cf.push(-q[0]);
+        let e: Vec<f64> = (0..q.len() - 1)
+            .map(|k| q[k + 1] - q[k] + e_prev[k + 1])
+            .collect();
+        if e.len() == 0 || !e[0].is_finite() || e[0] == 0.0 {
+            break;
+        }
+
+        cf.push(-e[0]);
+        q = (0..e.len() - 1)
+            .map(|k| q[k + 1] * e[k + 1] / e[k])
+            .collect();
+        e_prev = e;
+    }
+
+    return cf;
+}
+
+fn evaluate(cf: &[f64], t: f64) -> f64 {
+    let mut value = 1.0;
+    for a in cf[1..].iter().rev() {
+        value = 1.0 + a * t / value;
+    }
+    return cf[0] / value;
+}
+
+/// The extrapolated distinct reads must be finite, increasing and concave up to `MAX_FOLD`.
+fn is_stable(cf: &[f64], distinct: f64) -> bool {
+    let mut previous = distinct;
+    let mut previous_step = f64::INFINITY;
+    for i in 1..(MAX_FOLD - 1) * STABILITY_STEPS + 1 {
+        let t = i as f64 / STABILITY_STEPS as f64;
+        let value = distinct + t * evaluate(cf, t);
+        let step = value - previous;
+        if !value.is_finite() || step < 0.0 || step > previous_step * (1.0 + 1e-9) {
+            return false;
+        }
+
+        previous = value;
+        previous_step = step;
+    }
+
+    return true;
+}
+
+impl QCModule for LibraryComplexity {
+    fn key(&self) -> &str {
+        return "library_complexity";
+    }
+
+    fn name(&self) -> &str {
+        return "Library complexity";
+    }
+
+    // It is built from the data of overrepresented sequences when finishing
+    fn process_sequence(&mut self, _record: &ReadRecord) {}
+
+    fn merge(&mut self, other: &dyn QCModule) {
+        downcast::<LibraryComplexity>(other);
+    }
+
+    fn finish(&mut self, _phred_offset: usize) {}
+
+    fn calculate_status(&mut self, _limits: &Limits) {
+        if self.total_reads == 0 {
+            self.status = ModuleStatus::Ignore;
+        }
+    }
+
+    fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
+
+#[cfg(test)]
+mod complexity_tests {
+    use super::*;
+    use crate::qc::fastqc::FastQC;
+    use crate::qc::FastQCConfig;
+    use fastq::OwnedRecord;
+
+    #[test]
+    fn test_continued_fraction() {
+        // 1 / (1 - t) = 1 + t + t^2 + ...
+        let cf = continued_fraction(&vec![1.0; 6]);
+        assert_eq!(cf, vec![1.0, -1.0]);
+        assert_eq!(evaluate(&cf, 0.5), 2.0);
+    }
+
+    #[test]
+    fn test_library_complexity() {
+        // Sample 20000 reads from 20000 molecules of exponentially distributed abundances
+        let molecules = 20000;
+        let mut state: u64 = 42;
+        let mut random = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            return ((state >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+        };
+        let weights: Vec<f64> = (0..molecules).map(|_| -random().ln()).collect();
+        let total: f64 = weights.iter().sum();
+        let mut cumulative = vec![];
+        let mut sum = 0.0;
+        for w in &weights {
+            sum += w;
+            cumulative.push(sum);
+        }
+
+        let mut copies = vec![0; molecules];
+        for _ in 0..molecules {
+            let x = random() * total;
+            let i = match cumulative.binary_search_by(|c| c.partial_cmp(&x).unwrap()) {
+                Ok(i) | Err(i) => i.min(molecules - 1),
+            };
+            copies[i] += 1;
+        }
+
+        let mut histogram = BTreeMap::new();
+        for c in copies.iter().filter(|c| **c > 0) {
+            *histogram.entry(*c).or_insert(0) += 1;
+        }
+        let complexity = LibraryComplexity::new(&histogram, molecules);
+
+        assert_eq!(complexity.method(), "rational_function");
+        assert_eq!(complexity.total_reads(), molecules);
+        let points = complexity.extrapolation();
+        assert_eq!(points.len(), 10);
+        assert_eq!(
+            points[0].distinct_reads(),
+            complexity.distinct_reads() as f64
+        );
+        for point in points {
+            let expected: f64 = weights
+                .iter()
+                .map(|w| 1.0 - (-((point.fold() * molecules) as f64) * w / total).exp())
+                .sum();
+            assert!((point.distinct_reads() - expected).abs() / expected < 0.1);
+        }
+
+        // Every read is distinct, the series is too short for a continued fraction
+        let histogram: BTreeMap<usize, usize> = [(1, 100)].iter().cloned().collect();
+        let complexity = LibraryComplexity::new(&histogram, 150);
+        assert_eq!(complexity.method(), "good_toulmin");
+        assert_eq!(complexity.total_reads(), 100);
+        assert_eq!(complexity.input_reads(), 150);
+        assert_eq!(complexity.extrapolation()[1].distinct_reads(), 200.0);
+    }
+
+    #[test]
+    fn test_unique_limit() {
+        let contaminants = include_str!("../../data/contaminant_list.txt").to_string();
+        let mut config = FastQCConfig::new(
+            String::new(),
+            contaminants,
+            String::new(),
+            Some(100),
+            None,
+            None,
+            None,
+        );
+        config.set_modules(vec!["complexity".to_string()]).unwrap();
+        let mut qc = FastQC::new(&config);
+        let seq = |i: usize| -> Vec<u8> { (0..20).map(|j| b"ACGT"[(i >> (2 * j)) % 4]).collect() };
+        // 50 sequences are duplicated, then every read is distinct after the unique limit
+        let reads: Vec<usize> = (0..50).flat_map(|i| vec![i, i]).chain(50..1050).collect();
+        for i in reads {
+            qc.process_sequence(&OwnedRecord {
+                head: b"read".to_vec(),
+                seq: seq(i),
+                qual: vec![b'I'; 20],
+                sep: None,
+            });
+        }
+        qc.finish();
+
+        // Only the 150 reads before the limit are in the histogram, not 950 more singletons
+        let complexity = qc.library_complexity.unwrap();
+        assert_eq!(complexity.total_reads(), 150);
+        assert_eq!(complexity.input_reads(), 1100);
+        assert_eq!(complexity.distinct_reads(), 100);
+        assert_eq!(complexity.extrapolation()[0].reads(), 150);
+    }
+}
@@ -0,0 +1,301 @@
+//! Estimate the duplication over the whole file in bounded memory.
+//!
+//! `OverRepresentedSeqs` stops tracking new sequences after its unique limit, so the duplication
+//! is extrapolated from the beginning of the file, which is a different beginning in each thread.
+//! Here every read is hashed instead:
+//!
+//! * a HyperLogLog counts the distinct reads;
+//! * the sequences whose hash has at least `level` trailing zero bits are counted exactly, the
+//!   level is raised whenever more than `SAMPLE_CAPACITY` sequences are kept. The kept sequences
+//!   are a uniform sample of the distinct sequences, and the sample only depends on the hashes,
+//!   so the results are the same for any number of threads.
+use super::complexity::LibraryComplexity;
+use super::fastqc::{
+    duplication_labels, duplication_slot, truncate_for_duplication, Limits, ModuleStatus,
+};
+use super::module::{downcast, QCModule, ReadRecord};
+use super::util::hash_seq;
+use fastq::Record;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+// 2^14 registers, the standard error is 1.04 / sqrt(2^14) = 0.8%
+const HLL_PRECISION: u32 = 14;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+// The max number of distinct sequences which are counted exactly
+const SAMPLE_CAPACITY: usize = 1 << 17;
+
+pub const DUP_ENGINES: [&str; 2] = ["fastqc", "sketch"];
+
+/// How sequence duplication levels are computed.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DupEngine {
+    /// From the data of overrepresented sequences, the same as FastQC.
+    FastQC,
+    /// From sketches over all reads, see `SketchDuplicationLevel`.
+    Sketch,
+}
+
+impl FromStr for DupEngine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DupEngine, String> {
+        return match s {
+            "fastqc" => Ok(DupEngine::FastQC),
+            "sketch" => Ok(DupEngine::Sketch),
+            _ => Err(format!(
+                "Unknown duplication engine {}, it should be one of {}",
+                s,
+                DUP_ENGINES.join(",")
+            )),
+        };
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SketchDuplicationLevel {
+    total_reads: usize,
+    // The distinct reads by the HyperLogLog
+    estimated_distinct_reads: f64,
+    // Only the sequences whose hash has `sample_level` trailing zero bits are sampled, i.e. one
+    // out of 2^sample_level
+    sample_level: u32,
+    sampled_distinct_reads: usize,
+    sampled_reads: usize,
+    dedup_percentages: Vec<f64>,
+    total_percentages: Vec<f64>,
+    percent_diff_seq: f64,
+    labels: Vec<String>,
+    dup_length: usize,
+    #[serde(skip)]
+    registers: Vec<u8>,
+    #[serde(skip)]
+    sample: HashMap<u64, usize>,
+    status: ModuleStatus,
+}
+
+impl SketchDuplicationLevel {
+    pub fn new(dup_length: usize) -> SketchDuplicationLevel {
+        return SketchDuplicationLevel {
+            total_reads: 0,
+            estimated_distinct_reads: 0.0,
+            sample_level: 0,
+            sampled_distinct_reads: 0,
+            sampled_reads: 0,
+            dedup_percentages: vec![],
+            total_percentages: vec![],
+            percent_diff_seq: 100.0,
+            labels: vec![],
+            dup_length: dup_length,
+            registers: vec![0; HLL_REGISTERS],
+            sample: HashMap::new(),
+            status: ModuleStatus::Pass,
+        };
+    }
+
+    pub fn total_reads(&self) -> usize {
+        return self.total_reads;
+    }
+
+    pub fn estimated_distinct_reads(&self) -> f64 {
+        return self.estimated_distinct_reads;
+    }
+
+    pub fn sample_level(&self) -> u32 {
+        return self.sample_level;
+    }
+
+    /// The percentage of sequences which would remain after deduplication.
+    pub fn percent_diff_seq(&self) -> f64 {
+        return self.percent_diff_seq;
+    }
+
+    pub fn labels(&self) -> &Vec<String> {
+        return &self.labels;
+    }
+
+    pub fn dedup_percentages(&self) -> &Vec<f64> {
+        return &self.dedup_percentages;
+    }
+
+    pub fn total_percentages(&self) -> &Vec<f64> {
+        return &self.total_percentages;
+    }
+
+    pub fn dup_length(&self) -> usize {
+        return self.dup_length;
+    }
+
+    /// Library complexity from the sampled copies, scaled up by the sampling rate.
+    pub fn library_complexity(&self) -> LibraryComplexity {
+        let scale = 1usize << self.sample_level;
+        let mut histogram: BTreeMap<usize, usize> = BTreeMap::new();
+        for copies in self.sample.values() {
+            *histogram.entry(*copies).or_insert(0) += scale;
+        }
+
+        let sampled_reads: usize = histogram.iter().map(|(copies, n)| copies * n).sum();
+        return LibraryComplexity::new(&histogram, sampled_reads);
+    }
+
+    fn is_sampled(&self, hash: u64) -> bool {
+        return hash.trailing_zeros() >= self.sample_level;
+    }
+
+    fn shrink_sample(&mut self) {
+        while self.sample.len() > SAMPLE_CAPACITY {
+            self.sample_level += 1;
+            let level = self.sample_level;
+            self.sample.retain(|hash, _| hash.trailing_zeros() >= level);
+        }
+    }
+
+    fn estimate_distinct(&self) -> f64 {
+        let m = HLL_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+
+        // Linear counting is more accurate for small cardinalities
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            return m * (m / zeros as f64).ln();
+        }
+
+        return estimate;
+    }
+}
+
+impl QCModule for SketchDuplicationLevel {
+    fn key(&self) -> &str {
+        return "sketch_duplication_level";
+    }
+
+    fn name(&self) -> &str {
+        return "Sequence Duplication Levels (sketch)";
+    }
+
+    fn process_sequence(&mut self, record: &ReadRecord) {
+        self.total_reads += 1;
+        let hash = hash_seq(truncate_for_duplication(record.seq(), self.dup_length));
+
+        let register = (hash >> (64 - HLL_PRECISION)) as usize;
+        let rank = ((hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1))).leading_zeros() + 1;
+        self.registers[register] = self.registers[register].max(rank as u8);
+
+        if self.is_sampled(hash) {
+            *self.sample.entry(hash).or_insert(0) += 1;
+            self.shrink_sample();
+        }
+    }
+
+    fn merge(&mut self, other: &dyn QCModule) {
+        let other = downcast::<SketchDuplicationLevel>(other);
+        if self.dup_length != other.dup_length {
+            panic!(
+                "Cannot merge duplication sketches with different duplication lengths: {} and {}",
+                self.dup_length, other.dup_length
+            );
+        }
+
+        self.total_reads += other.total_reads;
+        for (register, rank) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *register = (*register).max(*rank);
+        }
+
+        self.sample_level = self.sample_level.max(other.sample_level);
+        let level = self.sample_level;
+        self.sample.retain(|hash, _| hash.trailing_zeros() >= level);
+        for (hash, count) in &other.sample {
+            if hash.trailing_zeros() >= level {
+                *self.sample.entry(*hash).or_insert(0) += count;
+            }
+        }
+        self.shrink_sample();
+    }
+
+    fn finish(&mut self, _phred_offset: usize) {
+        self.estimated_distinct_reads = self.estimate_distinct();
+        self.sampled_distinct_reads = self.sample.len();
+        self.sampled_reads = self.sample.values().sum();
+        self.labels = duplication_labels();
+        self.dedup_percentages = vec![0.0; self.labels.len()];
+        self.total_percentages = vec![0.0; self.labels.len()];
+        if self.sampled_reads == 0 {
+            return;
+        }
+
+        for copies in self.sample.values() {
+            let slot = duplication_slot(*copies);
+            self.dedup_percentages[slot] += 1.0;
+            self.total_percentages[slot] += *copies as f64;
+        }
+
+        for i in 0..self.labels.len() {
+            self.dedup_percentages[i] *= 100.0 / self.sampled_distinct_reads as f64;
+            self.total_percentages[i] *= 100.0 / self.sampled_reads as f64;
+        }
+        self.percent_diff_seq =
+            self.sampled_distinct_reads as f64 / self.sampled_reads as f64 * 100.0;
+    }
+
+    /// Fail if the percentage of sequences remaining after deduplication is lower than the limits.
+    fn calculate_status(&mut self, limits: &Limits) {
+        self.status = limits.lower_status("duplication", self.percent_diff_seq);
+    }
+
+    fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
+
+#[cfg(test)]
+mod dup_sketch_tests {
+    use super::*;
+    use fastq::OwnedRecord;
+
+    fn process(module: &mut SketchDuplicationLevel, i: usize) {
+        let record = OwnedRecord {
+            head: b"read".to_vec(),
+            seq: format!("ACGT{:08}", i).into_bytes(),
+            qual: vec![b'I'; 12],
+            sep: None,
+        };
+        module.process_sequence(&ReadRecord::new(&record));
+    }
+
+    #[test]
+    fn test_sketch_duplication() {
+        // 300000 distinct sequences, the first 100000 are seen twice
+        let mut whole = SketchDuplicationLevel::new(0);
+        let mut parts = vec![SketchDuplicationLevel::new(0); 3];
+        for i in 0..400000 {
+            let seq = if i < 200000 { i / 2 } else { i - 100000 };
+            process(&mut whole, seq);
+            process(&mut parts[i % 3], seq);
+        }
+
+        let mut merged = parts[0].clone();
+        merged.merge(&parts[1]);
+        merged.merge(&parts[2]);
+        whole.finish(33);
+        merged.finish(33);
+
+        // The sample only depends on the hashes
+        assert!(whole.sample_level() > 0);
+        assert_eq!(whole.sample_level(), merged.sample_level());
+        assert_eq!(whole.sample, merged.sample);
+        assert_eq!(whole.registers, merged.registers);
+        assert_eq!(
+            whole.estimated_distinct_reads(),
+            merged.estimated_distinct_reads()
+        );
+
+        assert!((whole.estimated_distinct_reads() - 300000.0).abs() < 300000.0 * 0.03);
+        assert!((whole.percent_diff_seq() - 75.0).abs() < 2.0);
+        assert!((whole.total_percentages()[1] - 50.0).abs() < 2.0);
+        assert_eq!(whole.labels().len(), 16);
+    }
+}
@@ -1,13 +1,29 @@
+use super::adapter_detection::AdapterDetection;
+use super::barcode::IndexStats;
+use super::bisulfite::BisulfiteStats;
+use super::complexity::LibraryComplexity;
+use super::dup_sketch::SketchDuplicationLevel;
+use super::header::HeaderParser;
+use super::FastQCConfig;
+use super::lane::PerLaneStats;
+use super::long_read::LongReadStats;
+use super::low_complexity::LowComplexity;
+use super::module::{downcast, QCModule, ReadRecord};
+use super::screen::ContaminationScreen;
+use super::sex::SexCheck;
+use super::taxonomy::TaxonomicProfile;
+use super::trimming::TrimSimulation;
+use super::umi::UmiStats;
 use fastq::Record;
 use log::*;
 use probability::prelude::*;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use std::cmp::Ordering;
 use std::io::Read;
 use std::{
     cmp,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     f64::consts::{E, PI},
     str::from_utf8,
     vec,
@@ -22,11 +38,239 @@ const ILLUMINA_1_5: &str = "Illumina 1.5";
 const FORWARD_TYPE: usize = 1;
 const REVERSE_TYPE: usize = 2;
 
-const FASTQC_CONFIG_DUP_LENGTH: usize = 0;
-const FASTQC_CONFIG_KMER_SIZE: usize = 0;
+const DEFAULT_LIMITS: &str = include_str!("../../data/limits.txt");
+
+// The detected adapter is counted by its first 12 bases, the same length as the built-in adapters
+pub const DETECTED_ADAPTER_NAME: &str = "Detected Adapter";
+const DETECTED_ADAPTER_LENGTH: usize = 12;
+
+// The shortest adapter prefix at the 3' end which is counted as a partial hit. A random 3' end
+// matches an 8bp prefix once in 65536 reads (4^8), while 5bp would flag ~0.5% of the reads with
+// the built-in adapters, and the trimming simulation and read filter act on the same hits.
+pub const DEFAULT_ADAPTER_MIN_OVERLAP: usize = 8;
+
+/// The verdict of a module, it is decided by the limits after all sequences have been processed.
+///
+/// NOTE: The order of the variants matters, a worse status is always greater than a better one.
+///
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ModuleStatus {
+    Ignore,
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl ModuleStatus {
+    /// The status of a metric which is worse when it is higher, such as the N content.
+    pub fn from_upper_limits(value: f64, warn: f64, error: f64) -> ModuleStatus {
+        if value > error {
+            return ModuleStatus::Fail;
+        } else if value > warn {
+            return ModuleStatus::Warn;
+        }
+
+        return ModuleStatus::Pass;
+    }
+
+    /// The status of a metric which is worse when it is lower, such as the median quality.
+    pub fn from_lower_limits(value: f64, warn: f64, error: f64) -> ModuleStatus {
+        if value < error {
+            return ModuleStatus::Fail;
+        } else if value < warn {
+            return ModuleStatus::Warn;
+        }
+
+        return ModuleStatus::Pass;
+    }
+
+    pub fn name(&self) -> String {
+        return match self {
+            ModuleStatus::Ignore => "ignore".to_string(),
+            ModuleStatus::Pass => "pass".to_string(),
+            ModuleStatus::Warn => "warn".to_string(),
+            ModuleStatus::Fail => "fail".to_string(),
+        };
+    }
+}
+
+/// Modules start with pass before their status is calculated.
+impl Default for ModuleStatus {
+    fn default() -> ModuleStatus {
+        return ModuleStatus::Pass;
+    }
+}
+
+/// Thresholds of all modules, it is equivalent to the limits.txt of FastQC.
+///
+/// Each line contains a module name, a level (ignore, warn or error) and a value, e.g. `adapter	warn	5`.
+///
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Limits {
+    limits: HashMap<String, HashMap<String, f64>>,
+}
+
+impl Limits {
+    /// The built-in limits are always loaded first, so `limits` only needs to contain the values you want to override.
+    pub fn new(limits: &String) -> Limits {
+        let mut l = Limits {
+            limits: HashMap::new(),
+        };
+
+        l.update(&DEFAULT_LIMITS.to_string());
+        l.update(limits);
+
+        return l;
+    }
+
+    pub fn read_limits_file(limits_file: &str) -> String {
+        let f = match std::fs::File::open(limits_file) {
+            Ok(f) => f,
+            Err(msg) => panic!("Cannot open {} - {}", limits_file, msg),
+        };
+
+        return Limits::read_limits_list(f);
+    }
+
+    pub fn read_limits_list<R: Read>(mut reader: R) -> String {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).unwrap();
+        return contents;
+    }
+
+    fn update(&mut self, limits: &String) {
+        let r = Regex::new("\\s+").unwrap();
+        for s in limits.lines() {
+            if s.starts_with('#') {
+                continue;
+            }
+            if s.trim().len() == 0 {
+                continue;
+            }
+
+            let sections: Vec<&str> = r.split(s.trim()).collect();
+            if sections.len() != 3 {
+                panic!("Config line '{}' didn't contain the 3 required sections", s);
+            }
+
+            let value = match sections[2].parse::<f64>() {
+                Ok(v) => v,
+                Err(_) => panic!("Value {} didn't look like a number", sections[2]),
+            };
+
+            self.limits
+                .entry(sections[0].to_string())
+                .or_insert(HashMap::new())
+                .insert(sections[1].to_string(), value);
+        }
+    }
+
+    pub fn get(&self, module: &str, level: &str) -> f64 {
+        return match self.limits.get(module).and_then(|m| m.get(level)) {
+            Some(value) => *value,
+            None => panic!("No limit found for {} {}", module, level),
+        };
+    }
+
+    /// Whether the module has been switched off in the limits.
+    pub fn ignore(&self, module: &str) -> bool {
+        return match self.limits.get(module).and_then(|m| m.get("ignore")) {
+            Some(value) => *value > 0.0,
+            None => false,
+        };
+    }
+
+    /// Grade a metric which is worse when it is higher.
+    pub fn upper_status(&self, module: &str, value: f64) -> ModuleStatus {
+        if self.ignore(module) {
+            return ModuleStatus::Ignore;
+        }
+
+        return ModuleStatus::from_upper_limits(
+            value,
+            self.get(module, "warn"),
+            self.get(module, "error"),
+        );
+    }
+
+    /// Grade a metric which is worse when it is lower.
+    pub fn lower_status(&self, module: &str, value: f64) -> ModuleStatus {
+        if self.ignore(module) {
+            return ModuleStatus::Ignore;
+        }
+
+        return ModuleStatus::from_lower_limits(
+            value,
+            self.get(module, "warn"),
+            self.get(module, "error"),
+        );
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        return Limits::new(&"".to_string());
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModuleSummary {
+    module: String,
+    status: ModuleStatus,
+}
+
+impl ModuleSummary {
+    pub fn new(module: &str, status: ModuleStatus) -> ModuleSummary {
+        return ModuleSummary {
+            module: module.to_string(),
+            status: status,
+        };
+    }
+
+    pub fn module(&self) -> String {
+        return self.module.clone();
+    }
+
+    pub fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
 
-const INDICATOR_CONFIG_TILE_IGNORE: usize = 0;
-const INDICATOR_CONFIG_OVERREPESENTED_WARN: f64 = 0.1;
+#[cfg(test)]
+mod limits_tests {
+    use super::*;
+
+    #[test]
+    fn test_default_limits() {
+        let limits = Limits::default();
+        assert_eq!(limits.get("adapter", "warn"), 5.0);
+        assert_eq!(limits.get("overrepresented", "warn"), 0.1);
+        assert!(!limits.ignore("tile"));
+    }
+
+    #[test]
+    fn test_override_limits() {
+        let limits = Limits::new(&"# comment\nadapter\twarn\t1\ntile ignore 1\n".to_string());
+        assert_eq!(limits.get("adapter", "warn"), 1.0);
+        assert_eq!(limits.get("adapter", "error"), 10.0);
+        assert!(limits.ignore("tile"));
+        assert_eq!(limits.upper_status("tile", 100.0), ModuleStatus::Ignore);
+    }
+
+    #[test]
+    fn test_module_status() {
+        assert_eq!(
+            ModuleStatus::from_upper_limits(6.0, 5.0, 10.0),
+            ModuleStatus::Warn
+        );
+        assert_eq!(
+            ModuleStatus::from_lower_limits(19.0, 25.0, 20.0),
+            ModuleStatus::Fail
+        );
+        assert!(ModuleStatus::Fail > ModuleStatus::Warn);
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct QualityCount {
@@ -380,9 +624,8 @@ mod phred_encoding_tests {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PerBaseSeqQuality {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     quality_counts: Vec<QualityCount>,
-    #[serde(skip_serializing)]
     xlabels: Vec<String>,
     base_pos: Vec<usize>,
     mean: Vec<f64>,
@@ -391,6 +634,7 @@ pub struct PerBaseSeqQuality {
     upper_quartile: Vec<f64>,
     lowest: Vec<f64>,
     highest: Vec<f64>,
+    status: ModuleStatus,
 }
 
 impl PerBaseSeqQuality {
@@ -405,6 +649,7 @@ impl PerBaseSeqQuality {
             lowest: vec![],
             highest: vec![],
             xlabels: vec![],
+            status: ModuleStatus::Pass,
         };
     }
 
@@ -511,6 +756,57 @@ impl PerBaseSeqQuality {
         self.get_percentages(offset);
     }
 
+    /// Fail if the lower quartile or the median of any base is lower than the limits.
+    fn calculate_status(&mut self, limits: &Limits) {
+        if limits.ignore("quality_base") {
+            self.status = ModuleStatus::Ignore;
+            return;
+        }
+
+        let mut status = ModuleStatus::Pass;
+        for i in 0..self.median.len() {
+            status = status
+                .max(limits.lower_status("quality_base_lower", self.lower_quartile[i]))
+                .max(limits.lower_status("quality_base_median", self.median[i]));
+        }
+
+        self.status = status;
+    }
+
+    pub fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+
+    pub fn xlabels(&self) -> &Vec<String> {
+        return &self.xlabels;
+    }
+
+    pub fn mean(&self) -> &Vec<f64> {
+        return &self.mean;
+    }
+
+    pub fn median(&self) -> &Vec<f64> {
+        return &self.median;
+    }
+
+    pub fn lower_quartile(&self) -> &Vec<f64> {
+        return &self.lower_quartile;
+    }
+
+    pub fn upper_quartile(&self) -> &Vec<f64> {
+        return &self.upper_quartile;
+    }
+
+    /// The 10th percentile of each base group
+    pub fn lowest(&self) -> &Vec<f64> {
+        return &self.lowest;
+    }
+
+    /// The 90th percentile of each base group
+    pub fn highest(&self) -> &Vec<f64> {
+        return &self.highest;
+    }
+
     pub fn merge(&mut self, other: &PerBaseSeqQuality) {
         self.add_quality_counts(&other.quality_counts);
     }
@@ -538,6 +834,7 @@ pub struct BasicStats {
     lowest_char: usize,
     highest_char: usize,
     quality_count: QualityCount,
+    status: ModuleStatus,
 }
 
 impl BasicStats {
@@ -559,11 +856,12 @@ impl BasicStats {
             lowest_char: 126,
             highest_char: 0,
             file_type: "".to_string(),
-            // We guess that the length of a sequence is impossible to be greater than 1000
-            min_length: 1000,
+            // It's set by the first read
+            min_length: 0,
             max_length: 0,
             phred: PhredEncoding::new("", 0),
             quality_count: QualityCount::new(),
+            status: ModuleStatus::Pass,
         };
     }
 
@@ -640,6 +938,41 @@ impl BasicStats {
         return self.total_reads;
     }
 
+    /// The number of bases whose quality is at least Q20.
+    pub fn q20_bases(&self) -> usize {
+        return self.q20_bases;
+    }
+
+    /// The number of bases whose quality is at least Q30.
+    pub fn q30_bases(&self) -> usize {
+        return self.q30_bases;
+    }
+
+    pub fn file_name(&self) -> String {
+        return self.file_name.clone();
+    }
+
+    pub fn file_type(&self) -> String {
+        return self.file_type.clone();
+    }
+
+    pub fn phred(&self) -> &PhredEncoding {
+        return &self.phred;
+    }
+
+    pub fn min_length(&self) -> usize {
+        return self.min_length;
+    }
+
+    pub fn max_length(&self) -> usize {
+        return self.max_length;
+    }
+
+    /// The fraction of G and C in all bases, it ranges from 0 to 1.
+    pub fn gc_percentage(&self) -> f64 {
+        return self.gc_percentage;
+    }
+
     fn add_total_reads(&mut self, total_reads: usize) {
         self.total_reads += total_reads;
     }
@@ -717,6 +1050,15 @@ impl BasicStats {
         self.set_q_score();
     }
 
+    /// Basic statistics never raise a warning or an error.
+    fn calculate_status(&mut self, _limits: &Limits) {
+        self.status = ModuleStatus::Pass;
+    }
+
+    pub fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+
     pub fn merge(&mut self, other: &BasicStats) {
         self.quality_count.add_quality_count(&other.quality_count);
         self.total_reads += other.total_reads;
@@ -726,8 +1068,15 @@ impl BasicStats {
         self.a_count += other.a_count;
         self.g_count += other.g_count;
         self.n_count += other.n_count;
-        self.min_length = self.min_length.min(other.min_length);
-        self.max_length = self.max_length.max(other.max_length);
+        // The lengths of a peer without reads are meaningless, so are ours when all reads are the peer's
+        if other.total_reads > 0 {
+            if self.total_reads == other.total_reads {
+                self.min_length = other.min_length;
+            } else {
+                self.min_length = self.min_length.min(other.min_length);
+            }
+            self.max_length = self.max_length.max(other.max_length);
+        }
         self.lowest_char = self.lowest_char.min(other.lowest_char);
         self.highest_char = self.highest_char.max(other.highest_char);
     }
@@ -735,15 +1084,16 @@ impl BasicStats {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PerSeqQualityScore {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     average_score_counts: HashMap<usize, usize>,
     x_category_quality: Vec<usize>,
     y_category_count: Vec<usize>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     max_counts: usize,
     most_frequent_score: usize,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     lowest_char: usize,
+    status: ModuleStatus,
 }
 
 impl PerSeqQualityScore {
@@ -755,6 +1105,7 @@ impl PerSeqQualityScore {
             max_counts: 0,
             most_frequent_score: 0,
             lowest_char: 126,
+            status: ModuleStatus::Pass,
         };
     }
 
@@ -816,6 +1167,23 @@ impl PerSeqQualityScore {
         self.calculate_distribution();
     }
 
+    /// Fail if the most frequent mean quality of sequences is lower than the limits.
+    fn calculate_status(&mut self, limits: &Limits) {
+        self.status = limits.lower_status("quality_sequence", self.most_frequent_score as f64);
+    }
+
+    pub fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+
+    pub fn x_category_quality(&self) -> &Vec<usize> {
+        return &self.x_category_quality;
+    }
+
+    pub fn y_category_count(&self) -> &Vec<usize> {
+        return &self.y_category_count;
+    }
+
     pub fn merge(&mut self, other: &PerSeqQualityScore) {
         self.lowest_char = self.lowest_char.min(other.lowest_char);
 
@@ -840,6 +1208,7 @@ pub struct PerBaseSeqContent {
     a_counts: Vec<usize>,
     t_counts: Vec<usize>,
     percentages: Vec<Vec<f64>>,
+    status: ModuleStatus,
 }
 
 impl PerBaseSeqContent {
@@ -851,6 +1220,7 @@ impl PerBaseSeqContent {
             t_counts: vec![],
             percentages: vec![],
             x_category: vec![],
+            status: ModuleStatus::Pass,
         };
     }
 
@@ -937,6 +1307,37 @@ impl PerBaseSeqContent {
         self.get_percentages();
     }
 
+    /// Fail if the difference between A and T, or G and C is greater than the limits at any position.
+    fn calculate_status(&mut self, limits: &Limits) {
+        if limits.ignore("sequence") {
+            self.status = ModuleStatus::Ignore;
+            return;
+        }
+
+        // percentages: [T, C, A, G]
+        let mut max_diff: f64 = 0.0;
+        for i in 0..self.x_category.len() {
+            let gc_diff = (self.percentages[3][i] - self.percentages[1][i]).abs();
+            let at_diff = (self.percentages[2][i] - self.percentages[0][i]).abs();
+            max_diff = max_diff.max(gc_diff).max(at_diff);
+        }
+
+        self.status = limits.upper_status("sequence", max_diff);
+    }
+
+    pub fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+
+    pub fn x_category(&self) -> &Vec<String> {
+        return &self.x_category;
+    }
+
+    /// The percentages of T, C, A and G at each base group, in that order.
+    pub fn percentages(&self) -> &Vec<Vec<f64>> {
+        return &self.percentages;
+    }
+
     pub fn merge(&mut self, other: &PerBaseSeqContent) {
         let this_len = self.g_counts.len();
         let other_len = other.g_counts.len();
@@ -1094,11 +1495,12 @@ pub struct PerSeqGCContent {
     x_category: Vec<usize>,
     y_gc_distribution: Vec<f64>,
     y_theo_distribution: Vec<f64>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     max: f64,
     deviation_percent: f64,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     cached_models: Vec<GCModel>,
+    status: ModuleStatus,
 }
 
 impl PerSeqGCContent {
@@ -1110,9 +1512,35 @@ impl PerSeqGCContent {
             y_gc_distribution: vec![0.0; 101],
             y_theo_distribution: vec![0.0; 101],
             cached_models: Vec::with_capacity(200),
+            status: ModuleStatus::Pass,
         };
     }
 
+    /// Fail if the sum of the deviations from the theoretical distribution is greater than the limits.
+    fn calculate_status(&mut self, limits: &Limits) {
+        self.status = limits.upper_status("gc_sequence", self.deviation_percent);
+    }
+
+    pub fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+
+    pub fn x_category(&self) -> &Vec<usize> {
+        return &self.x_category;
+    }
+
+    pub fn y_gc_distribution(&self) -> &Vec<f64> {
+        return &self.y_gc_distribution;
+    }
+
+    pub fn y_theo_distribution(&self) -> &Vec<f64> {
+        return &self.y_theo_distribution;
+    }
+
+    pub fn deviation_percent(&self) -> f64 {
+        return self.deviation_percent;
+    }
+
     pub fn merge(&mut self, other: &PerSeqGCContent) {
         // update y_gc_distribution
         for i in 0..101 {
@@ -1287,6 +1715,7 @@ pub struct PerBaseNContent {
     n_counts: Vec<usize>,
     not_n_counts: Vec<usize>,
     percentages: Vec<f64>,
+    status: ModuleStatus,
 }
 
 impl PerBaseNContent {
@@ -1296,6 +1725,7 @@ impl PerBaseNContent {
             not_n_counts: vec![],
             percentages: vec![],
             x_categories: vec![],
+            status: ModuleStatus::Pass,
         };
     }
 
@@ -1352,6 +1782,28 @@ impl PerBaseNContent {
         self.get_percentages();
     }
 
+    /// Fail if the percentage of N at any position is greater than the limits.
+    fn calculate_status(&mut self, limits: &Limits) {
+        let mut max_percentage: f64 = 0.0;
+        for p in &self.percentages {
+            max_percentage = max_percentage.max(*p);
+        }
+
+        self.status = limits.upper_status("n_content", max_percentage);
+    }
+
+    pub fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+
+    pub fn x_categories(&self) -> &Vec<String> {
+        return &self.x_categories;
+    }
+
+    pub fn percentages(&self) -> &Vec<f64> {
+        return &self.percentages;
+    }
+
     pub fn merge(&mut self, other: &PerBaseNContent) {
         let this_len = self.n_counts.len();
         let other_len = other.n_counts.len();
@@ -1372,12 +1824,13 @@ impl PerBaseNContent {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SeqLenDistribution {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     len_counts: Vec<usize>,
     x_categories: Vec<String>,
     graph_counts: Vec<f64>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     max: usize,
+    status: ModuleStatus,
 }
 
 impl SeqLenDistribution {
@@ -1387,6 +1840,7 @@ impl SeqLenDistribution {
             graph_counts: vec![],
             x_categories: vec![],
             max: 0,
+            status: ModuleStatus::Pass,
         };
     }
 
@@ -1502,6 +1956,37 @@ impl SeqLenDistribution {
         self.calculate_distribution();
     }
 
+    /// The tests are binary: fail if there are sequences of zero length, warn if the sequences have different lengths.
+    fn calculate_status(&mut self, limits: &Limits) {
+        if limits.ignore("sequence_length") {
+            self.status = ModuleStatus::Ignore;
+            return;
+        }
+
+        let zero_length = self.len_counts.len() > 0 && self.len_counts[0] > 0;
+        let lengths = self.len_counts.iter().filter(|c| **c > 0).count();
+
+        self.status = if limits.get("sequence_length", "error") != 0.0 && zero_length {
+            ModuleStatus::Fail
+        } else if limits.get("sequence_length", "warn") != 0.0 && lengths > 1 {
+            ModuleStatus::Warn
+        } else {
+            ModuleStatus::Pass
+        };
+    }
+
+    pub fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+
+    pub fn x_categories(&self) -> &Vec<String> {
+        return &self.x_categories;
+    }
+
+    pub fn graph_counts(&self) -> &Vec<f64> {
+        return &self.graph_counts;
+    }
+
     pub fn merge(&mut self, other: &SeqLenDistribution) {
         let this_len = self.len_counts.len();
         let other_len = other.len_counts.len();
@@ -1789,8 +2274,9 @@ impl OverRepresentedSeq {
         sequences: String,
         contaminants: &Vec<Contaminant>,
     ) -> Option<ContaminantHit> {
+        // e.g. the UMI + sequence keys of the umi module are not searched
         if contaminants.is_empty() {
-            panic!("No any contaminants");
+            return None;
         }
 
         let mut best_hit: Option<ContaminantHit> = None;
@@ -1833,39 +2319,74 @@ impl OverRepresentedSeq {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Truncate a read before the duplication analysis, 0 means the default of FastQC (reads
+/// longer than 75bp are truncated to 50bp).
+pub fn truncate_for_duplication(seq: &[u8], dup_length: usize) -> &[u8] {
+    if dup_length != 0 {
+        if seq.len() > dup_length {
+            return &seq[0..dup_length];
+        }
+    } else if seq.len() > 75 {
+        return &seq[0..50];
+    }
+
+    return seq;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct OverRepresentedSeqs {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     sequences: HashMap<String, usize>,
     count: usize,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     frozen: bool,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     duplication_module: Option<Box<SeqDuplicationLevel>>,
     // default 100000
     observation_cut_off: usize,
     unique_seq_count: usize,
     count_at_unique_limit: usize,
+    // Reads are truncated to this length before counting, 0 means the default of FastQC
+    // (reads longer than 75bp are truncated to 50bp)
+    dup_length: usize,
     overrepresented_seqs: Vec<OverRepresentedSeq>,
-    #[serde(skip_serializing)]
+    // The number of distinct sequences by their copies, it's kept after the sequences are cleared
+    #[serde(skip_serializing, default)]
+    count_histogram: BTreeMap<usize, usize>,
+    // The histogram when the unique limit is reached, only the tracked sequences are counted
+    // after that, so their copies are not a sample of the reads any more
+    #[serde(skip_serializing, default)]
+    sample_histogram: Option<BTreeMap<usize, usize>>,
+    #[serde(skip_serializing, default)]
     contaminants: Vec<Contaminant>,
+    // The percentage a sequence must reach to be reported, it's the warn value in the limits
+    #[serde(skip_serializing, default)]
+    report_threshold: f64,
+    status: ModuleStatus,
 }
 
 impl OverRepresentedSeqs {
     pub fn new(
         contaminants: &String,
         overrepresented_max_unique_seq_count: usize,
+        dup_length: usize,
+        limits: &Limits,
     ) -> OverRepresentedSeqs {
         return OverRepresentedSeqs {
             sequences: HashMap::new(),
             count: 0,
             overrepresented_seqs: vec![],
+            count_histogram: BTreeMap::new(),
+            sample_histogram: None,
             frozen: false,
             duplication_module: None,
             observation_cut_off: overrepresented_max_unique_seq_count,
             unique_seq_count: 0,
             count_at_unique_limit: 0,
+            dup_length: dup_length,
             contaminants: OverRepresentedSeqs::make_contaminants_list(contaminants),
+            report_threshold: limits.get("overrepresented", "warn"),
+            status: ModuleStatus::Pass,
         };
     }
 
@@ -1924,6 +2445,33 @@ impl OverRepresentedSeqs {
         return self.count;
     }
 
+    /// The number of distinct sequences by their copies, only available after finishing.
+    pub fn count_histogram(&self) -> &BTreeMap<usize, usize> {
+        return &self.count_histogram;
+    }
+
+    /// The histogram of the reads before the unique limit, it's the same as `count_histogram`
+    /// when the limit is never reached.
+    ///
+    /// NOTE: The samples of several threads are added up, a sequence in two samples is counted
+    /// as two distinct sequences.
+    ///
+    pub fn sample_histogram(&self) -> &BTreeMap<usize, usize> {
+        return match &self.sample_histogram {
+            Some(histogram) => histogram,
+            None => &self.count_histogram,
+        };
+    }
+
+    fn histogram(&self) -> BTreeMap<usize, usize> {
+        let mut histogram: BTreeMap<usize, usize> = BTreeMap::new();
+        for count in self.sequences.values() {
+            *histogram.entry(*count).or_insert(0) += 1;
+        }
+
+        return histogram;
+    }
+
     fn calculate_overrepresented_seq(&mut self) {
         // If the duplication module hasn't already done
         // its calculation it needs to do it now before
@@ -1935,7 +2483,7 @@ impl OverRepresentedSeqs {
 
         for (seq, seq_count) in self.sequences.clone() {
             let percentage: f64 = seq_count as f64 / self.count as f64 * 100.0;
-            if percentage > INDICATOR_CONFIG_OVERREPESENTED_WARN {
+            if percentage > self.report_threshold {
                 let os: OverRepresentedSeq =
                     OverRepresentedSeq::new(seq.clone(), seq_count, percentage, &self.contaminants);
                 self.overrepresented_seqs.push(os);
@@ -1950,13 +2498,7 @@ impl OverRepresentedSeqs {
 
     pub fn process_sequence(&mut self, record: &impl Record) {
         self.count += 1;
-        let mut seq = record.seq();
-
-        if FASTQC_CONFIG_DUP_LENGTH != 0 {
-            seq = &seq[0..FASTQC_CONFIG_DUP_LENGTH];
-        } else if seq.len() > 75 {
-            seq = &seq[0..50];
-        }
+        let seq = truncate_for_duplication(record.seq(), self.dup_length);
 
         let seq_string: String = from_utf8(seq).unwrap().to_string();
         if self.sequences.contains_key(&seq_string) {
@@ -1973,6 +2515,7 @@ impl OverRepresentedSeqs {
                 self.count_at_unique_limit = self.count;
                 if self.unique_seq_count == self.observation_cut_off {
                     self.frozen = true;
+                    self.sample_histogram = Some(self.histogram());
                 }
             }
         }
@@ -1982,11 +2525,52 @@ impl OverRepresentedSeqs {
     fn finish(&mut self) {
         // update duplication_module when self has been updated
         self.duplication_module = Some(Box::new(SeqDuplicationLevel::new(self)));
+        self.count_histogram = self.histogram();
 
         self.calculate_overrepresented_seq();
     }
 
+    /// Fail if any sequence accounts for more than the error limit, every reported sequence raises a warning.
+    fn calculate_status(&mut self, limits: &Limits) {
+        let mut max_percentage: f64 = 0.0;
+        for os in &self.overrepresented_seqs {
+            max_percentage = max_percentage.max(os.percentage());
+        }
+
+        self.status = limits.upper_status("overrepresented", max_percentage);
+    }
+
+    pub fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+
+    pub fn overrepresented_seqs(&self) -> &Vec<OverRepresentedSeq> {
+        return &self.overrepresented_seqs;
+    }
+
+    pub fn dup_length(&self) -> usize {
+        return self.dup_length;
+    }
+
     pub fn merge(&mut self, other: &OverRepresentedSeqs) {
+        // The counts of differently truncated sequences can't be added up
+        if self.dup_length != other.dup_length {
+            panic!(
+                "Cannot merge overrepresented sequences with different duplication lengths: {} and {}",
+                self.dup_length, other.dup_length
+            );
+        }
+
+        // The sequences of both sides are samples until either is frozen
+        let mut sample_histogram = self
+            .sample_histogram
+            .clone()
+            .unwrap_or_else(|| self.histogram());
+        let other_sample_histogram = other
+            .sample_histogram
+            .clone()
+            .unwrap_or_else(|| other.histogram());
+
         let mut copy_count = self.count;
         self.count += other.count;
 
@@ -2014,19 +2598,59 @@ impl OverRepresentedSeqs {
                 }
             }
         }
+
+        if self.frozen || other.frozen {
+            for (copies, n) in other_sample_histogram {
+                *sample_histogram.entry(copies).or_insert(0) += n;
+            }
+            self.sample_histogram = Some(sample_histogram);
+        }
+    }
+}
+
+/// The slot of a duplication level in the 16 bins of FastQC: 1 to 9, >10, >50, >100, >500,
+/// >1k, >5k and >10k.
+pub fn duplication_slot(dup_level: usize) -> usize {
+    let dup_slot = dup_level - 1;
+    if dup_slot > 9999 {
+        return 15;
+    } else if dup_slot > 4999 {
+        return 14;
+    } else if dup_slot > 999 {
+        return 13;
+    } else if dup_slot > 499 {
+        return 12;
+    } else if dup_slot > 99 {
+        return 11;
+    } else if dup_slot > 49 {
+        return 10;
+    } else if dup_slot > 9 {
+        return 9;
+    }
+
+    return dup_slot;
+}
+
+pub fn duplication_labels() -> Vec<String> {
+    let mut labels: Vec<String> = (1..10).map(|i| i.to_string()).collect();
+    for label in &[">10", ">50", ">100", ">500", ">1k", ">5k", ">10k"] {
+        labels.push(label.to_string());
     }
+
+    return labels;
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SeqDuplicationLevel {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     overrepresented_module: Box<OverRepresentedSeqs>,
     dedup_percentages: Vec<f64>,
     total_percentages: Vec<f64>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     max_count: f64,
     percent_diff_seq: f64,
     labels: Vec<String>,
+    status: ModuleStatus,
 }
 
 impl SeqDuplicationLevel {
@@ -2038,6 +2662,7 @@ impl SeqDuplicationLevel {
             max_count: 0.0,
             percent_diff_seq: 0.0,
             labels: vec![],
+            status: ModuleStatus::Pass,
         };
     }
 
@@ -2116,48 +2741,13 @@ impl SeqDuplicationLevel {
             dedup_total += corrected_count;
             raw_total += corrected_count * (*dup_level) as f64;
 
-            let mut dup_slot = dup_level - 1;
-
-            if dup_slot > 9999 {
-                dup_slot = 15;
-            } else if dup_slot > 4999 {
-                dup_slot = 14;
-            } else if dup_slot > 999 {
-                dup_slot = 13;
-            } else if dup_slot > 499 {
-                dup_slot = 12;
-            } else if dup_slot > 99 {
-                dup_slot = 11;
-            } else if dup_slot > 49 {
-                dup_slot = 10;
-            } else if dup_slot > 9 {
-                dup_slot = 9;
-            }
-
+            let dup_slot = duplication_slot(*dup_level);
             self.dedup_percentages[dup_slot] += corrected_count;
             self.total_percentages[dup_slot] += corrected_count * (*dup_level) as f64;
         }
 
-        self.labels = vec!["".to_string(); 16];
+        self.labels = duplication_labels();
         for i in 0..self.dedup_percentages.len() {
-            if i < 9 {
-                self.labels[i] = (i + 1).to_string();
-            } else if i == 9 {
-                self.labels[i] = ">10".to_string();
-            } else if i == 10 {
-                self.labels[i] = ">50".to_string();
-            } else if i == 11 {
-                self.labels[i] = ">100".to_string();
-            } else if i == 12 {
-                self.labels[i] = ">500".to_string();
-            } else if i == 13 {
-                self.labels[i] = ">1k".to_string();
-            } else if i == 14 {
-                self.labels[i] = ">5k".to_string();
-            } else if i == 15 {
-                self.labels[i] = ">10k".to_string();
-            }
-
             self.dedup_percentages[i] /= dedup_total;
             self.total_percentages[i] /= raw_total;
             self.dedup_percentages[i] *= 100.0;
@@ -2181,19 +2771,96 @@ impl SeqDuplicationLevel {
         // it has been finished in over-represented modules
     }
 
-    pub fn merge(&mut self, other: &SeqDuplicationLevel) {
-        // We don't need to do anything since we use
-        // the data structure from the overrepresented sequences
-        // module.
+    /// Fail if the percentage of sequences remaining after deduplication is lower than the limits.
+    fn calculate_status(&mut self, limits: &Limits) {
+        self.status = limits.lower_status("duplication", self.percent_diff_seq);
     }
-}
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Adapter {
-    name: String,
-    sequence: String,
-    positions: Vec<usize>,
-}
+    pub fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+
+    /// The percentage of sequences which would remain after deduplication.
+    pub fn percent_diff_seq(&self) -> f64 {
+        return self.percent_diff_seq;
+    }
+
+    pub fn labels(&self) -> &Vec<String> {
+        return &self.labels;
+    }
+
+    pub fn dedup_percentages(&self) -> &Vec<f64> {
+        return &self.dedup_percentages;
+    }
+
+    pub fn total_percentages(&self) -> &Vec<f64> {
+        return &self.total_percentages;
+    }
+
+    pub fn merge(&mut self, other: &SeqDuplicationLevel) {
+        // We don't need to do anything since we use
+        // the data structure from the overrepresented sequences
+        // module.
+    }
+}
+
+/// Where an adapter is found in a read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdapterMatch {
+    /// The whole adapter starts at the position.
+    Full { position: usize, mismatches: usize },
+    /// Only a prefix of the adapter is at the 3' end of the read, it starts at the position.
+    Partial { position: usize, overlap: usize },
+}
+
+impl AdapterMatch {
+    pub fn position(&self) -> usize {
+        return match self {
+            AdapterMatch::Full { position, .. } => *position,
+            AdapterMatch::Partial { position, .. } => *position,
+        };
+    }
+}
+
+/// The number of reads in which an adapter is found, by the kind of hit.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AdapterHits {
+    name: String,
+    // The whole adapter is found, with at most max_mismatches mismatches
+    full: usize,
+    // The subset of full hits with at least one mismatch
+    mismatched: usize,
+    // Only a prefix of the adapter is found at the 3' end
+    partial: usize,
+}
+
+impl AdapterHits {
+    pub fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    pub fn full(&self) -> usize {
+        return self.full;
+    }
+
+    pub fn mismatched(&self) -> usize {
+        return self.mismatched;
+    }
+
+    pub fn partial(&self) -> usize {
+        return self.partial;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Adapter {
+    name: String,
+    sequence: String,
+    positions: Vec<usize>,
+    full_hits: usize,
+    mismatched_hits: usize,
+    partial_hits: usize,
+}
 
 impl Adapter {
     pub fn new(name: String, sequence: String) -> Adapter {
@@ -2201,14 +2868,123 @@ impl Adapter {
             name: name,
             sequence: sequence,
             positions: vec![0],
+            full_hits: 0,
+            mismatched_hits: 0,
+            partial_hits: 0,
         };
     }
 
+    /// Parse the adapter list, the same format as the adapter_list.txt of FastQC (name and
+    /// sequence separated by tabs).
+    pub fn parse_adapters(adapters_content: &String) -> Vec<Adapter> {
+        let mut adapters: Vec<Adapter> = Vec::new();
+        let r = Regex::new("\\t+").unwrap();
+        for s in adapters_content.lines() {
+            if s.starts_with('#') {
+                continue;
+            }
+            if s.trim().len() == 0 {
+                continue;
+            }
+
+            let sections: Vec<&str> = r.split(s).collect();
+            adapters.push(Adapter::new(
+                sections[0].trim().to_string(),
+                sections[1].trim().to_string(),
+            ));
+        }
+
+        return adapters;
+    }
+
     pub fn merge(&mut self, other: &Adapter) {
         let other_len = other.positions.len();
         for i in 0..other_len {
             self.positions[i] += other.positions[i];
         }
+
+        self.full_hits += other.full_hits;
+        self.mismatched_hits += other.mismatched_hits;
+        self.partial_hits += other.partial_hits;
+    }
+
+    fn count_mismatches(seq: &[u8], adapter: &[u8], max_mismatches: usize) -> Option<usize> {
+        let mut mismatches = 0;
+        for i in 0..adapter.len() {
+            if seq[i] != adapter[i] {
+                mismatches += 1;
+                if mismatches > max_mismatches {
+                    return None;
+                }
+            }
+        }
+
+        return Some(mismatches);
+    }
+
+    /// Find the adapter in a sequence, the leftmost full match wins over a partial match.
+    ///
+    /// A full match allows at most `max_mismatches` mismatches. A partial match is a prefix of
+    /// the adapter (at least `min_overlap` bases, 0 disables it) at the 3' end, the mismatches
+    /// allowed are scaled down by the overlap.
+    ///
+    pub fn find(&self, seq: &[u8], max_mismatches: usize, min_overlap: usize) -> Option<AdapterMatch> {
+        let adapter = self.sequence.as_bytes();
+        if adapter.len() == 0 {
+            return None;
+        }
+
+        if seq.len() >= adapter.len() {
+            if max_mismatches == 0 {
+                // The same as FastQC, and it's much faster
+                if let Some(position) = from_utf8(seq).ok().and_then(|s| s.find(&self.sequence)) {
+                    return Some(AdapterMatch::Full {
+                        position: position,
+                        mismatches: 0,
+                    });
+                }
+            } else {
+                for position in 0..(seq.len() - adapter.len() + 1) {
+                    if let Some(mismatches) =
+                        Adapter::count_mismatches(&seq[position..], adapter, max_mismatches)
+                    {
+                        return Some(AdapterMatch::Full {
+                            position: position,
+                            mismatches: mismatches,
+                        });
+                    }
+                }
+            }
+        }
+
+        if min_overlap == 0 {
+            return None;
+        }
+
+        let longest_overlap = cmp::min(adapter.len() - 1, seq.len());
+        let mut overlap = longest_overlap;
+        while overlap >= min_overlap && overlap > 0 {
+            let allowed = max_mismatches * overlap / adapter.len();
+            let position = seq.len() - overlap;
+            if Adapter::count_mismatches(&seq[position..], &adapter[..overlap], allowed).is_some() {
+                return Some(AdapterMatch::Partial {
+                    position: position,
+                    overlap: overlap,
+                });
+            }
+            overlap -= 1;
+        }
+
+        return None;
+    }
+
+    fn hits(&self) -> AdapterHits {
+        return AdapterHits {
+            name: self.name.clone(),
+            full: self.full_hits,
+            mismatched: self.mismatched_hits,
+            partial: self.partial_hits,
+        };
     }
 
     pub fn increment_count(&mut self, position: usize) {
@@ -2232,30 +3008,29 @@ impl Adapter {
         }
     }
 
-    pub fn positions(&mut self) -> Vec<usize> {
+    pub fn positions(&self) -> Vec<usize> {
         return self.positions.clone();
     }
 
-    pub fn sequence(&mut self) -> String {
+    pub fn sequence(&self) -> String {
         return self.sequence.clone();
     }
 
-    pub fn name(&mut self) -> String {
+    pub fn name(&self) -> String {
         return self.name.clone();
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AdapterContent {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     longest_sequence: usize,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     longest_adapter: usize,
-    #[serde(skip_serializing)]
     total_count: usize,
 
     // This is the full set of Kmers to be reported
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     adapters: Vec<Adapter>,
 
     labels: Vec<String>,
@@ -2263,36 +3038,30 @@ pub struct AdapterContent {
 
     // This is the data for the Kmers which are going to be placed on the graph
     enrichments: Vec<Vec<f64>>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     groups: Vec<BaseGroup>,
+    // The mismatches allowed in a full adapter, 0 is the exact matching of FastQC
+    max_mismatches: usize,
+    // The shortest adapter prefix at the 3' end which is counted as a partial hit, 0 disables it
+    min_overlap: usize,
+    // Only full hits are plotted in the enrichments, partial hits are counted here
+    hits: Vec<AdapterHits>,
+    // The reads with any adapter, full or partial
+    reads_with_adapter: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detection: Option<AdapterDetection>,
+    status: ModuleStatus,
 }
 
 impl AdapterContent {
-    pub fn new(adapters_content: &String) -> AdapterContent {
-        let mut adapters: Vec<Adapter> = Vec::new();
-        let mut labels: Vec<String> = Vec::new();
-        let mut longest_adapter = 0;
-
-        for s in adapters_content.lines() {
-            if s.starts_with('#') {
-                continue;
-            }
-            if s.trim().len() == 0 {
-                continue;
-            }
-
-            let r = Regex::new("\\t+").unwrap();
-            let sections: Vec<&str> = r.split(s).collect();
-            let mut adapter = Adapter::new(
-                sections[0].trim().to_string(),
-                sections[1].trim().to_string(),
-            );
-            adapters.push(adapter.clone());
-            labels.push(adapter.name());
-            if adapter.sequence().len() > longest_adapter {
-                longest_adapter = adapter.sequence().len();
-            }
-        }
+    pub fn new(adapters_content: &String, max_mismatches: usize, min_overlap: usize) -> AdapterContent {
+        let adapters = Adapter::parse_adapters(adapters_content);
+        let labels: Vec<String> = adapters.iter().map(|adapter| adapter.name()).collect();
+        let longest_adapter = adapters
+            .iter()
+            .map(|adapter| adapter.sequence().len())
+            .max()
+            .unwrap_or(0);
 
         return AdapterContent {
             longest_sequence: 0,
@@ -2303,9 +3072,38 @@ impl AdapterContent {
             labels: labels,
             x_labels: vec![],
             groups: vec![],
+            max_mismatches: max_mismatches,
+            min_overlap: min_overlap,
+            hits: vec![],
+            reads_with_adapter: 0,
+            detection: None,
+            status: ModuleStatus::Pass,
         };
     }
 
+    /// Record the adapter detected before the qc, and count it like a known adapter unless a
+    /// known adapter is found in it. It must be called before any sequence is processed.
+    pub fn add_detection(&mut self, detection: AdapterDetection) {
+        if let Some(sequence) = detection.sequence() {
+            if !detection.is_known() {
+                let probe_length = cmp::min(sequence.len(), DETECTED_ADAPTER_LENGTH);
+                let adapter = Adapter::new(
+                    DETECTED_ADAPTER_NAME.to_string(),
+                    sequence[..probe_length].to_string(),
+                );
+                self.labels.push(adapter.name());
+                self.longest_adapter = cmp::max(self.longest_adapter, probe_length);
+                self.adapters.push(adapter);
+            }
+        }
+
+        self.detection = Some(detection);
+    }
+
+    pub fn detection(&self) -> &Option<AdapterDetection> {
+        return &self.detection;
+    }
+
     pub fn read_adapter_file(adapter_filepath: &str) -> String {
         let f = match std::fs::File::open(adapter_filepath) {
             Ok(f) => f,
@@ -2339,21 +3137,35 @@ impl AdapterContent {
         }
 
         // Now we go through all of the Adapters to see where they occur
-
+        let mut found = false;
         for a in 0..self.adapters.len() {
-            let index_option = from_utf8(record.seq())
-                .unwrap()
-                .find(&self.adapters[a].sequence());
-            match index_option {
-                Some(index) => {
+            let adapter_match =
+                self.adapters[a].find(record.seq(), self.max_mismatches, self.min_overlap);
+            match adapter_match {
+                Some(AdapterMatch::Full {
+                    position: index,
+                    mismatches,
+                }) => {
+                    found = true;
+                    self.adapters[a].full_hits += 1;
+                    if mismatches > 0 {
+                        self.adapters[a].mismatched_hits += 1;
+                    }
                     for i in index..(self.longest_sequence - self.longest_adapter + 1) {
                         self.adapters[a].increment_count(i);
                     }
                 }
-
+                Some(AdapterMatch::Partial { .. }) => {
+                    found = true;
+                    self.adapters[a].partial_hits += 1;
+                }
                 None => {}
             }
         }
+
+        if found {
+            self.reads_with_adapter += 1;
+        }
     }
 
     pub fn calculate_enrichment(&mut self) {
@@ -2394,10 +3206,74 @@ impl AdapterContent {
     /// Some data values should be calculated after all sequences have been processed
     fn finish(&mut self) {
         self.calculate_enrichment();
+        self.hits = self.adapters.iter().map(|adapter| adapter.hits()).collect();
+    }
+
+    /// Fail if any adapter has been seen in more than the error limit of the library at any position.
+    fn calculate_status(&mut self, limits: &Limits) {
+        let mut max_enrichment: f64 = 0.0;
+        for a in 0..self.enrichments.len() {
+            for e in &self.enrichments[a] {
+                max_enrichment = max_enrichment.max(*e);
+            }
+        }
+
+        self.status = limits.upper_status("adapter", max_enrichment);
+    }
+
+    pub fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+
+    /// The names of adapters, in the same order as the enrichments.
+    pub fn labels(&self) -> &Vec<String> {
+        return &self.labels;
+    }
+
+    pub fn x_labels(&self) -> &Vec<String> {
+        return &self.x_labels;
+    }
+
+    pub fn enrichments(&self) -> &Vec<Vec<f64>> {
+        return &self.enrichments;
+    }
+
+    pub fn hits(&self) -> &Vec<AdapterHits> {
+        return &self.hits;
+    }
+
+    pub fn reads_with_adapter(&self) -> usize {
+        return self.reads_with_adapter;
+    }
+
+    pub fn total_count(&self) -> usize {
+        return self.total_count;
+    }
+
+    pub fn max_mismatches(&self) -> usize {
+        return self.max_mismatches;
+    }
+
+    pub fn min_overlap(&self) -> usize {
+        return self.min_overlap;
     }
 
     pub fn merge(&mut self, other: &AdapterContent) {
+        // A hit means different things with different settings
+        if self.max_mismatches != other.max_mismatches || self.min_overlap != other.min_overlap {
+            panic!(
+                "Cannot merge adapter content with different matching settings: {}/{} and {}/{}",
+                self.max_mismatches, self.min_overlap, other.max_mismatches, other.min_overlap
+            );
+        }
+
+        // The detected adapter is one more adapter to count
+        if self.detection != other.detection {
+            panic!("Cannot merge adapter content with different detected adapters");
+        }
+
         self.total_count += other.total_count;
+        self.reads_with_adapter += other.reads_with_adapter;
 
         let this_longest_len = self.longest_sequence;
         let other_longest_len = other.longest_sequence;
@@ -2416,14 +3292,58 @@ impl AdapterContent {
     }
 }
 
+#[cfg(test)]
+mod adapter_tests {
+    use super::*;
+
+    const ILLUMINA: &str = "AGATCGGAAGAG";
+
+    #[test]
+    fn test_find_adapter() {
+        let adapter = Adapter::new("Illumina Universal Adapter".to_string(), ILLUMINA.to_string());
+
+        let seq = b"ACGTACGTACAGATCGGAAGAGCACACG";
+        assert_eq!(
+            adapter.find(seq, 0, 5),
+            Some(AdapterMatch::Full {
+                position: 10,
+                mismatches: 0
+            })
+        );
+
+        // One sequencing error in the adapter
+        let seq = b"ACGTACGTACAGATCGCAAGAGCACACG";
+        assert_eq!(adapter.find(seq, 0, 5), None);
+        assert_eq!(
+            adapter.find(seq, 1, 5),
+            Some(AdapterMatch::Full {
+                position: 10,
+                mismatches: 1
+            })
+        );
+
+        // The read ends in the adapter
+        let seq = b"ACGTACGTACGTACGTAGATCGG";
+        assert_eq!(
+            adapter.find(seq, 0, 5),
+            Some(AdapterMatch::Partial {
+                position: 16,
+                overlap: 7
+            })
+        );
+        // A random 3' end matches such a short prefix too often to count it by default
+        assert_eq!(adapter.find(seq, 0, DEFAULT_ADAPTER_MIN_OVERLAP), None);
+        assert_eq!(adapter.find(seq, 0, 0), None);
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Kmer {
     sequence: String,
     count: usize,
     lowest_pvalue: f64,
-    #[serde(skip_serializing)]
     obs_exp_position: Vec<f64>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     positions: Vec<usize>,
 }
 
@@ -2453,15 +3373,15 @@ impl Kmer {
         self.count += other.count;
     }
 
-    pub fn sequence(&mut self) -> String {
+    pub fn sequence(&self) -> String {
         return self.sequence.clone();
     }
 
-    pub fn count(&mut self) -> usize {
+    pub fn count(&self) -> usize {
         return self.count;
     }
 
-    pub fn positions(&mut self) -> Vec<usize> {
+    pub fn positions(&self) -> Vec<usize> {
         return self.positions.clone();
     }
 
@@ -2510,6 +3430,10 @@ impl Kmer {
         self.lowest_pvalue = pvalue;
     }
 
+    pub fn lowest_pvalue(&self) -> f64 {
+        return self.lowest_pvalue;
+    }
+
     pub fn obs_exp_position(&self) -> Vec<f64> {
         return self.obs_exp_position.clone();
     }
@@ -2517,46 +3441,51 @@ impl Kmer {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct KmerContent {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     kmers: HashMap<String, Kmer>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     longest_sequence: usize,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     total_kmer_counts: Vec<Vec<usize>>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     skip_count: usize,
-    #[serde(skip_serializing)]
     min_kmer_size: usize,
-    #[serde(skip_serializing)]
     max_kmer_size: usize,
     // This is the full set of Kmers to be reported
     enriched_kmers: Vec<Kmer>,
     // This is the data for the Kmers which are going to be placed on the graph
     enrichments: Vec<Vec<f64>>,
     // For the graph we also need to know the scale we need to use on the axes.
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     min_gragh_value: f64,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     max_gragh_value: f64,
 
     // One sample is ignored every skip_count samples,default 50
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     ignore_smapling_interval: usize,
 
     x_categories: Vec<String>,
     x_labels: Vec<String>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     groups: Vec<BaseGroup>,
+    status: ModuleStatus,
 }
 
 impl KmerContent {
-    pub fn new(kmer_ignore_smapling_interval: usize) -> KmerContent {
-        let mut min_kmer_size = 7;
-        let mut max_kmer_size = 7;
-        if FASTQC_CONFIG_KMER_SIZE != 0 {
-            min_kmer_size = FASTQC_CONFIG_KMER_SIZE;
-            max_kmer_size = FASTQC_CONFIG_KMER_SIZE;
+    /// Count all kmers from `min_kmer_size` to `max_kmer_size` (inclusive), FastQC uses 7-mers.
+    pub fn new(
+        kmer_ignore_smapling_interval: usize,
+        min_kmer_size: usize,
+        max_kmer_size: usize,
+    ) -> KmerContent {
+        if min_kmer_size == 0 || min_kmer_size > max_kmer_size {
+            panic!(
+                "Invalid kmer size range {}..{}, it should be 1 <= min <= max",
+                min_kmer_size, max_kmer_size
+            );
         }
+
         return KmerContent {
             kmers: HashMap::new(),
             longest_sequence: 0,
@@ -2572,6 +3501,7 @@ impl KmerContent {
             x_labels: vec![],
             groups: vec![],
             ignore_smapling_interval: kmer_ignore_smapling_interval,
+            status: ModuleStatus::Pass,
         };
     }
 
@@ -2597,7 +3527,9 @@ impl KmerContent {
          * compared to its average coverage over all positions.
          */
 
-        self.groups = BaseGroup::make_base_groups(self.longest_sequence - self.min_kmer_size + 1);
+        // No group when all sampled reads are shorter than the kmers
+        self.groups =
+            BaseGroup::make_base_groups((self.longest_sequence + 1).saturating_sub(self.min_kmer_size));
 
         let mut uneven_kemers: Vec<Kmer> = vec![];
         let mut kmers_vec = self.kmers.values().collect::<Vec<_>>();
@@ -2762,6 +3694,10 @@ impl KmerContent {
 
         // Now we go through all of the Kmers to count these
         for kmer_size in self.min_kmer_size..(self.max_kmer_size + 1) {
+            if seq.len() < kmer_size {
+                break;
+            }
+
             for i in 0..(seq.len() - kmer_size + 1) {
                 let kmer: String = seq[i..(i + kmer_size)].to_string();
 
@@ -2798,7 +3734,61 @@ impl KmerContent {
         self.calculate_enrichment();
     }
 
+    /// Fail if the -log10 of the lowest p-value over all enriched kmers is greater than the limits.
+    fn calculate_status(&mut self, limits: &Limits) {
+        // The enriched kmers are sorted by the obs/exp, not by the p-value
+        let score = if self.enriched_kmers.len() > 0 {
+            let lowest_pvalue = self
+                .enriched_kmers
+                .iter()
+                .map(|kmer| kmer.lowest_pvalue)
+                .fold(f64::INFINITY, f64::min);
+            0.0 - lowest_pvalue.log10()
+        } else {
+            0.0
+        };
+
+        self.status = limits.upper_status("kmer", score);
+    }
+
+    pub fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+
+    pub fn enriched_kmers(&self) -> &Vec<Kmer> {
+        return &self.enriched_kmers;
+    }
+
+    pub fn x_categories(&self) -> &Vec<String> {
+        return &self.x_categories;
+    }
+
+    /// The enrichments of the top 6 kmers, the x_labels are their sequences.
+    pub fn enrichments(&self) -> &Vec<Vec<f64>> {
+        return &self.enrichments;
+    }
+
+    pub fn x_labels(&self) -> &Vec<String> {
+        return &self.x_labels;
+    }
+
+    pub fn min_kmer_size(&self) -> usize {
+        return self.min_kmer_size;
+    }
+
+    pub fn max_kmer_size(&self) -> usize {
+        return self.max_kmer_size;
+    }
+
     pub fn merge(&mut self, other: &KmerContent) {
+        // The positions of kmers are counted per size, so the sizes must be the same
+        if self.min_kmer_size != other.min_kmer_size || self.max_kmer_size != other.max_kmer_size {
+            panic!(
+                "Cannot merge kmer content with different kmer sizes: {}..{} and {}..{}",
+                self.min_kmer_size, self.max_kmer_size, other.min_kmer_size, other.max_kmer_size
+            );
+        }
+
         // merge longest_sequence
         self.longest_sequence = self.longest_sequence.max(other.longest_sequence);
 
@@ -2835,37 +3825,94 @@ impl KmerContent {
     }
 }
 
+#[cfg(test)]
+mod kmer_content_tests {
+    use super::*;
+    use fastq::OwnedRecord;
+
+    fn make_kmer_content(min_kmer_size: usize, max_kmer_size: usize) -> KmerContent {
+        let mut module = KmerContent::new(1, min_kmer_size, max_kmer_size);
+        module.process_sequence(&OwnedRecord {
+            head: b"read1".to_vec(),
+            seq: b"ACGTACGTAC".to_vec(),
+            qual: b"IIIIIIIIII".to_vec(),
+            sep: None,
+        });
+        return module;
+    }
+
+    #[test]
+    fn test_kmer_size_range() {
+        let module = make_kmer_content(5, 8);
+        assert_eq!(module.kmers.get("ACGTA").unwrap().count(), 2);
+        assert_eq!(module.kmers.get("ACGTACGT").unwrap().count(), 1);
+        assert!(module.kmers.keys().all(|k| k.len() >= 5 && k.len() <= 8));
+
+        let results = serde_json::to_value(&module).unwrap();
+        assert_eq!(results["min_kmer_size"], 5);
+        assert_eq!(results["max_kmer_size"], 8);
+    }
+
+    #[test]
+    fn test_status_by_lowest_pvalue() {
+        let mut module = make_kmer_content(7, 7);
+        let mut kmers = vec![];
+        for (sequence, lowest_pvalue) in &[("ACGTACG", 0.001), ("CGTACGT", 1e-30)] {
+            let mut kmer = Kmer::new(sequence.to_string(), 0, 4);
+            kmer.lowest_pvalue = *lowest_pvalue;
+            kmers.push(kmer);
+        }
+        module.enriched_kmers = kmers;
+
+        // The first kmer only warns, the second one fails
+        module.calculate_status(&Limits::new(&"kmer\tignore\t0\n".to_string()));
+        assert_eq!(module.status(), ModuleStatus::Fail);
+    }
+
+    #[test]
+    #[should_panic(expected = "different kmer sizes")]
+    fn test_merge_different_kmer_sizes() {
+        let mut module = make_kmer_content(7, 7);
+        module.merge(&make_kmer_content(5, 8));
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PerTileQualityScore {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     per_tile_quality_counts: HashMap<usize, Vec<QualityCount>>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     current_length: usize,
     x_labels: Vec<String>,
     tiles: Vec<usize>,
     means: Vec<Vec<f64>>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     high: usize,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     total_count: usize,
-    #[serde(skip_serializing)]
-    split_position: isize,
-    #[serde(skip_serializing)]
+    #[serde(skip)]
+    header_parser: HeaderParser,
+    // The sampled reads whose tile is not available in the header
+    unparsed_reads: usize,
+    #[serde(skip_serializing, default)]
     max_deviation: f64,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     ignore_in_report: bool,
     // default 10000
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     continuous_sampling_boundary: usize,
     // One sample is ignored every interval samples, default 10
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     ignore_smapling_interval: usize,
+    status: ModuleStatus,
 }
 
 impl PerTileQualityScore {
     pub fn new(
         tile_continuous_sampling_boundary: usize,
         tile_ignore_smapling_interval: usize,
+        header_parser: HeaderParser,
+        limits: &Limits,
     ) -> PerTileQualityScore {
         return PerTileQualityScore {
             per_tile_quality_counts: HashMap::new(),
@@ -2875,11 +3922,14 @@ impl PerTileQualityScore {
             tiles: vec![],
             high: 0,
             total_count: 0,
-            split_position: -1,
+            header_parser: header_parser,
+            unparsed_reads: 0,
             max_deviation: 0.0,
-            ignore_in_report: false,
+            // Don't waste time calculating this if the module has been switched off in the limits
+            ignore_in_report: limits.ignore("tile"),
             continuous_sampling_boundary: tile_continuous_sampling_boundary,
             ignore_smapling_interval: tile_ignore_smapling_interval,
+            status: ModuleStatus::Pass,
         };
     }
 
@@ -2903,13 +3953,6 @@ impl PerTileQualityScore {
     }
 
     pub fn process_sequence(&mut self, record: &impl Record) {
-        // Check if we can skip counting because the module is being ignored anyway
-        if self.total_count == 0 {
-            if INDICATOR_CONFIG_TILE_IGNORE > 0 {
-                self.ignore_in_report = true;
-            }
-        }
-
         // Don't waste time calculating this if we're not going to use it anyway
         if self.ignore_in_report {
             return;
@@ -2928,32 +3971,14 @@ impl PerTileQualityScore {
             return;
         }
 
-        // First try to split the id by :
-        let mut tile: usize = 0;
-        let id_string = from_utf8(record.head()).unwrap().to_string();
-        let split_id_array: Vec<&str> = id_string.split(":").collect();
-
-        // analysis tile id
-        if self.split_position >= 0 {
-            if split_id_array.len() <= self.split_position as usize {
-                println!("Can't extract a number - not enough data");
-                self.ignore_in_report = true;
+        // The read is skipped when the tile is not available, the module is ignored if no read has a tile
+        let tile = match self.header_parser.parse(record.head()).tile() {
+            Some(tile) => tile,
+            None => {
+                self.unparsed_reads += 1;
                 return;
             }
-            tile = split_id_array[self.split_position as usize]
-                .parse::<usize>()
-                .unwrap();
-        } else if split_id_array.len() >= 7 {
-            self.split_position = 4;
-            tile = split_id_array[4].parse::<usize>().unwrap();
-        } else if split_id_array.len() >= 5 {
-            self.split_position = 2;
-            tile = split_id_array[2].parse::<usize>().unwrap();
-        } else {
-            // We're not going to get a tile out of this
-            self.ignore_in_report = true;
-            return;
-        }
+        };
 
         let qual = record.qual();
 
@@ -3021,6 +4046,12 @@ impl PerTileQualityScore {
     }
 
     fn get_percentages(&mut self, offset: usize) {
+        // No tile means there is no quality to calculate the offset
+        if self.per_tile_quality_counts.len() == 0 {
+            self.ignore_in_report = true;
+            return;
+        }
+
         let range = self.calculate_offset();
         self.high = range[1] as usize - offset;
 
@@ -3091,8 +4122,41 @@ impl PerTileQualityScore {
         self.get_percentages(offset);
     }
 
+    /// Fail if any tile deviates from the average quality more than the limits.
+    fn calculate_status(&mut self, limits: &Limits) {
+        if self.ignore_in_report {
+            self.status = ModuleStatus::Ignore;
+            return;
+        }
+
+        self.status = limits.upper_status("tile", self.max_deviation);
+    }
+
+    pub fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+
+    pub fn x_labels(&self) -> &Vec<String> {
+        return &self.x_labels;
+    }
+
+    pub fn tiles(&self) -> &Vec<usize> {
+        return &self.tiles;
+    }
+
+    /// The deviations of the mean quality of each tile from the average of all tiles.
+    pub fn means(&self) -> &Vec<Vec<f64>> {
+        return &self.means;
+    }
+
+    /// The sampled reads whose tile is not available in the header.
+    pub fn unparsed_reads(&self) -> usize {
+        return self.unparsed_reads;
+    }
+
     pub fn merge(&mut self, other: &PerTileQualityScore) {
         self.total_count += other.total_count;
+        self.unparsed_reads += other.unparsed_reads;
         if self.total_count > self.continuous_sampling_boundary
             && self.total_count % self.ignore_smapling_interval != 0
         {
@@ -3137,50 +4201,426 @@ impl PerTileQualityScore {
     }
 }
 
+/// Implement `QCModule` for a module of FastQC by its inherent methods, `phred_offset` is for the
+/// modules whose finish needs the phred offset guessed by basic statistics.
+macro_rules! impl_qc_module {
+    ($module:ident, $key:expr, $name:expr) => {
+        impl_qc_module!(@impl $module, $key, $name, fn finish(&mut self, _phred_offset: usize) {
+            $module::finish(self);
+        });
+    };
+    ($module:ident, $key:expr, $name:expr, phred_offset) => {
+        impl_qc_module!(@impl $module, $key, $name, fn finish(&mut self, phred_offset: usize) {
+            $module::finish(self, phred_offset);
+        });
+    };
+    (@impl $module:ident, $key:expr, $name:expr, $($finish:tt)*) => {
+        impl QCModule for $module {
+            fn key(&self) -> &str {
+                return $key;
+            }
+
+            fn name(&self) -> &str {
+                return $name;
+            }
+
+            fn process_sequence(&mut self, record: &ReadRecord) {
+                $module::process_sequence(self, record);
+            }
+
+            fn merge(&mut self, other: &dyn QCModule) {
+                $module::merge(self, downcast::<$module>(other));
+            }
+
+            $($finish)*
+
+            fn calculate_status(&mut self, limits: &Limits) {
+                $module::calculate_status(self, limits);
+            }
+
+            fn status(&self) -> ModuleStatus {
+                return $module::status(self);
+            }
+        }
+    };
+}
+
+impl_qc_module!(BasicStats, "basic_stats", "Basic Statistics");
+impl_qc_module!(
+    PerBaseSeqQuality,
+    "per_base_seq_quality",
+    "Per base sequence quality",
+    phred_offset
+);
+impl_qc_module!(
+    PerTileQualityScore,
+    "per_tile_quality_score",
+    "Per tile sequence quality",
+    phred_offset
+);
+impl_qc_module!(PerSeqQualityScore, "per_seq_quality_score", "Per sequence quality scores");
+impl_qc_module!(PerBaseSeqContent, "per_base_seq_content", "Per base sequence content");
+impl_qc_module!(PerSeqGCContent, "per_seq_gc_content", "Per sequence GC content");
+impl_qc_module!(PerBaseNContent, "per_base_n_content", "Per base N content");
+impl_qc_module!(SeqLenDistribution, "seq_len_distribution", "Sequence Length Distribution");
+impl_qc_module!(SeqDuplicationLevel, "seq_duplication_level", "Sequence Duplication Levels");
+impl_qc_module!(OverRepresentedSeqs, "overrepresented_seqs", "Overrepresented sequences");
+impl_qc_module!(AdapterContent, "adapter_content", "Adapter Content");
+impl_qc_module!(KmerContent, "kmer_content", "Kmer Content");
+
+/// The names of the built-in modules which can be selected, e.g. `--modules basic,gc,adapter`.
+///
+/// NOTE: Basic statistics is always enabled because other modules depend on the phred encoding it guesses,
+/// and duplication and complexity are computed from the data of overrepresented sequences, which is only reported when it's selected too.
+///
+pub const MODULE_NAMES: [&str; 23] = [
+    "basic",
+    "per_base_quality",
+    "per_tile_quality",
+    "per_seq_quality",
+    "per_base_content",
+    "gc",
+    "n_content",
+    "length",
+    "duplication",
+    "overrepresented",
+    "adapter",
+    "kmer",
+    "long_read",
+    "lane",
+    "index",
+    "umi",
+    "complexity",
+    "low_complexity",
+    "trimming",
+    "screen",
+    "taxonomy",
+    "sex",
+    "bisulfite",
+];
+
+/// The modules of the long-read profile.
+///
+/// The per-position modules and the gc module allocate memory by the read length, and the tile
+/// and adapter modules only make sense for Illumina reads, so they are skipped.
+///
+pub const LONG_READ_MODULES: [&str; 5] = [
+    "basic",
+    "per_seq_quality",
+    "duplication",
+    "overrepresented",
+    "long_read",
+];
+
+/// The limits of the bisulfite profile, they override the limits file when the bisulfite module
+/// is selected.
+///
+/// The conversion depletes C (G for the complementary strands), so the per-base content and the
+/// theoretical GC distribution of the gc module don't apply.
+///
+const BISULFITE_LIMITS: &str = "sequence\tignore\t1\ngc_sequence\tignore\t1\n";
+
+/// The 12 modules of FastQC, they are the first of `MODULE_NAMES`.
+const FASTQC_MODULE_COUNT: usize = 12;
+
+/// The modules of FastQC, it's the default of the selected modules (the short-read profile).
+///
+/// The other modules are only run when they are selected.
+///
+pub fn all_modules() -> Vec<String> {
+    return MODULE_NAMES[..FASTQC_MODULE_COUNT]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+}
+
+/// The modules of the short-read profile and the bisulfite module.
+pub fn bisulfite_modules() -> Vec<String> {
+    let mut modules = all_modules();
+    modules.push("bisulfite".to_string());
+    return modules;
+}
+
+pub fn long_read_modules() -> Vec<String> {
+    return LONG_READ_MODULES.iter().map(|name| name.to_string()).collect();
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FastQC {
     pub basic_stats: BasicStats,
-    pub per_base_seq_quality: PerBaseSeqQuality,
-    pub per_seq_quality_score: PerSeqQualityScore,
-    pub per_base_seq_content: PerBaseSeqContent,
-    pub per_seq_gc_content: PerSeqGCContent,
-    pub per_base_n_content: PerBaseNContent,
-    pub seq_len_distribution: SeqLenDistribution,
-    pub overrepresented_seqs: OverRepresentedSeqs,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_base_seq_quality: Option<PerBaseSeqQuality>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_seq_quality_score: Option<PerSeqQualityScore>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_base_seq_content: Option<PerBaseSeqContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_seq_gc_content: Option<PerSeqGCContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_base_n_content: Option<PerBaseNContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq_len_distribution: Option<SeqLenDistribution>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overrepresented_seqs: Option<OverRepresentedSeqs>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub seq_duplication_level: Option<SeqDuplicationLevel>,
-    pub adapter_content: AdapterContent,
-    pub kmer_content: KmerContent,
-    pub per_tile_quality_score: PerTileQualityScore,
+    /// Replaces `seq_duplication_level` when the sketch engine is used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sketch_duplication_level: Option<SketchDuplicationLevel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub library_complexity: Option<LibraryComplexity>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adapter_content: Option<AdapterContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kmer_content: Option<KmerContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_tile_quality_score: Option<PerTileQualityScore>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub long_read_stats: Option<LongReadStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_lane_stats: Option<PerLaneStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index_stats: Option<IndexStats>,
+    /// Only built when a UMI source is given, see `FastQCConfig::set_umi_source`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub umi_stats: Option<UmiStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub low_complexity: Option<LowComplexity>,
+    /// Built with the trimming parameters, see `FastQCConfig::set_trim_params`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trim_simulation: Option<TrimSimulation>,
+    /// Only built with the genomes to screen, see `FastQCConfig::set_screen`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screen: Option<ContaminationScreen>,
+    /// Only built with a taxonomy database, see `FastQCConfig::set_taxonomy`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub taxonomy: Option<TaxonomicProfile>,
+    /// Only built with the sex k-mers, see `FastQCConfig::set_sex_kmers`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sex_check: Option<SexCheck>,
+    /// Built in the bisulfite profile, see `FastQCConfig::set_bisulfite_spike_in`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bisulfite: Option<BisulfiteStats>,
+    /// The modules from the registry, they are serialized as a map from key to results.
+    #[serde(
+        serialize_with = "serialize_modules",
+        skip_serializing_if = "Vec::is_empty",
+        skip_deserializing
+    )]
+    extra_modules: Vec<Box<dyn QCModule>>,
+    /// Sequence duplication levels is computed from overrepresented sequences when finishing.
+    #[serde(skip_serializing, default)]
+    duplication_enabled: bool,
+    /// Library complexity is also computed from overrepresented sequences when finishing.
+    #[serde(skip_serializing, default)]
+    complexity_enabled: bool,
+    /// Overrepresented sequences may be built only for the modules above, it's dropped when
+    /// finishing unless it's selected.
+    #[serde(skip_serializing, default)]
+    overrepresented_enabled: bool,
+    #[serde(skip_serializing, default)]
+    limits: Limits,
+}
+
+fn serialize_modules<S: Serializer>(
+    modules: &Vec<Box<dyn QCModule>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    return serializer.collect_map(modules.iter().map(|m| (m.key().to_string(), m.to_json())));
+}
+
+/// Run the body on each enabled module of a FastQC, in the same order as the report of FastQC
+/// and the extra modules at the end.
+///
+/// The built-in modules are only listed here, so a new module is a field plus a line, and the
+/// per-read loop doesn't collect the modules into a Vec.
+///
+macro_rules! for_each_module {
+    ($fastqc:expr, |$module:ident| $body:block) => {
+        for_each_module!(@visit $fastqc, (), $module, $body)
+    };
+    ($fastqc:expr, mut |$module:ident| $body:block) => {
+        for_each_module!(@visit $fastqc, (mut), $module, $body)
+    };
+    (@visit $fastqc:expr, ($($mutability:tt)?), $module:ident, $body:block) => {{
+        {
+            let $module: &$($mutability)? dyn QCModule = &$($mutability)? $fastqc.basic_stats;
+            $body
+        }
+        for_each_module!(
+            @optional $fastqc,
+            ($($mutability)?),
+            $module,
+            $body,
+            per_base_seq_quality,
+            per_tile_quality_score,
+            per_seq_quality_score,
+            per_base_seq_content,
+            per_seq_gc_content,
+            per_base_n_content,
+            seq_len_distribution,
+            seq_duplication_level,
+            sketch_duplication_level,
+            library_complexity,
+            overrepresented_seqs,
+            adapter_content,
+            kmer_content,
+            long_read_stats,
+            per_lane_stats,
+            index_stats,
+            umi_stats,
+            low_complexity,
+            trim_simulation,
+            screen,
+            taxonomy,
+            sex_check,
+            bisulfite
+        );
+        for $module in &$($mutability)? $fastqc.extra_modules {
+            let $module: &$($mutability)? dyn QCModule = &$($mutability)? **$module;
+            $body
+        }
+    }};
+    (@optional $fastqc:expr, ($($mutability:tt)?), $module:ident, $body:block, $field:ident $(, $rest:ident)*) => {
+        if let Some($module) = &$($mutability)? $fastqc.$field {
+            let $module: &$($mutability)? dyn QCModule = $module;
+            $body
+        }
+        for_each_module!(@optional $fastqc, ($($mutability)?), $module, $body $(, $rest)*);
+    };
+    (@optional $fastqc:expr, ($($mutability:tt)?), $module:ident, $body:block) => {};
 }
 
 impl FastQC {
-    pub fn new(
-        contaminants: &String,
-        adapters: &String,
-        overrepresented_max_unique_seq_count: Option<usize>,
-        kmer_ignore_smapling_interval: Option<usize>,
-        tile_continuous_sampling_boundary: Option<usize>,
-        tile_ignore_smapling_interval: Option<usize>,
-    ) -> FastQC {
+    /// Build the modules selected in the config, with its parameters.
+    ///
+    /// The modules which need more than the parameters (e.g. the screen index or the expected
+    /// indexes of a fastq file) are added by `FastQCConfig` for each fastq file.
+    ///
+    pub fn new(config: &FastQCConfig) -> FastQC {
+        let modules = config.modules();
+        let contaminants = config.contaminants();
+        let adapters = config.adapters();
+        let overrepresented_max_unique_seq_count = config.overrepresented_max_unique_seq_count();
+        let dup_length = config.dup_length();
+        let (adapter_mismatches, adapter_min_overlap) = config.adapter_matching();
+        let header_parser = config.header_parser();
+        let enabled = |name: &str| modules.iter().any(|m| m == name);
+        let duplication_enabled = enabled("duplication");
+        let complexity_enabled = enabled("complexity");
+        let overrepresented_enabled = enabled("overrepresented");
+        let mut limits = Limits::new(config.limits());
+        if enabled("bisulfite") {
+            limits.update(&BISULFITE_LIMITS.to_string());
+        }
         return FastQC {
             basic_stats: BasicStats::new(),
-            per_base_seq_quality: PerBaseSeqQuality::new(),
-            per_seq_quality_score: PerSeqQualityScore::new(),
-            per_base_seq_content: PerBaseSeqContent::new(),
-            per_seq_gc_content: PerSeqGCContent::new(),
-            per_base_n_content: PerBaseNContent::new(),
-            seq_len_distribution: SeqLenDistribution::new(),
-            overrepresented_seqs: OverRepresentedSeqs::new(
-                contaminants,
-                overrepresented_max_unique_seq_count.unwrap_or(100000),
-            ),
+            per_base_seq_quality: if enabled("per_base_quality") {
+                Some(PerBaseSeqQuality::new())
+            } else {
+                None
+            },
+            per_seq_quality_score: if enabled("per_seq_quality") {
+                Some(PerSeqQualityScore::new())
+            } else {
+                None
+            },
+            per_base_seq_content: if enabled("per_base_content") {
+                Some(PerBaseSeqContent::new())
+            } else {
+                None
+            },
+            per_seq_gc_content: if enabled("gc") {
+                Some(PerSeqGCContent::new())
+            } else {
+                None
+            },
+            per_base_n_content: if enabled("n_content") {
+                Some(PerBaseNContent::new())
+            } else {
+                None
+            },
+            seq_len_distribution: if enabled("length") {
+                Some(SeqLenDistribution::new())
+            } else {
+                None
+            },
+            overrepresented_seqs: if overrepresented_enabled
+                || duplication_enabled
+                || complexity_enabled
+            {
+                Some(OverRepresentedSeqs::new(
+                    contaminants,
+                    overrepresented_max_unique_seq_count.unwrap_or(100000),
+                    dup_length.unwrap_or(0),
+                    &limits,
+                ))
+            } else {
+                None
+            },
             seq_duplication_level: None,
-            adapter_content: AdapterContent::new(adapters),
-            kmer_content: KmerContent::new(kmer_ignore_smapling_interval.unwrap_or(50)),
-            per_tile_quality_score: PerTileQualityScore::new(
-                tile_continuous_sampling_boundary.unwrap_or(10000),
-                tile_ignore_smapling_interval.unwrap_or(10),
-            ),
+            sketch_duplication_level: None,
+            library_complexity: None,
+            adapter_content: if enabled("adapter") {
+                Some(AdapterContent::new(
+                    adapters,
+                    adapter_mismatches.unwrap_or(0),
+                    adapter_min_overlap.unwrap_or(DEFAULT_ADAPTER_MIN_OVERLAP),
+                ))
+            } else {
+                None
+            },
+            kmer_content: if enabled("kmer") {
+                let (min_kmer_size, max_kmer_size) = config.kmer_size().unwrap_or((7, 7));
+                Some(KmerContent::new(
+                    config.kmer_ignore_smapling_interval().unwrap_or(50),
+                    min_kmer_size,
+                    max_kmer_size,
+                ))
+            } else {
+                None
+            },
+            per_tile_quality_score: if enabled("per_tile_quality") {
+                Some(PerTileQualityScore::new(
+                    config.tile_continuous_sampling_boundary().unwrap_or(10000),
+                    config.tile_ignore_smapling_interval().unwrap_or(10),
+                    header_parser.clone(),
+                    &limits,
+                ))
+            } else {
+                None
+            },
+            long_read_stats: if enabled("long_read") {
+                Some(LongReadStats::new())
+            } else {
+                None
+            },
+            per_lane_stats: if enabled("lane") {
+                Some(PerLaneStats::new(header_parser.clone()))
+            } else {
+                None
+            },
+            index_stats: if enabled("index") {
+                Some(IndexStats::new(header_parser.clone()))
+            } else {
+                None
+            },
+            umi_stats: None,
+            low_complexity: if enabled("low_complexity") {
+                Some(LowComplexity::new())
+            } else {
+                None
+            },
+            trim_simulation: None,
+            screen: None,
+            taxonomy: None,
+            sex_check: None,
+            bisulfite: None,
+            extra_modules: vec![],
+            duplication_enabled: duplication_enabled,
+            complexity_enabled: complexity_enabled,
+            overrepresented_enabled: overrepresented_enabled,
+            limits: limits,
         };
     }
 
@@ -3189,37 +4629,114 @@ impl FastQC {
         self
     }
 
-    /// Finish method is crucial, don't forget it.
-    pub fn finish(&mut self) {
-        self.basic_stats.finish();
+    /// Add a module besides the built-in modules, e.g. a module from the registry.
+    pub fn add_module(&mut self, module: Box<dyn QCModule>) {
+        if self.modules().iter().any(|m| m.key() == module.key()) {
+            panic!("The module {} already exists", module.key());
+        }
 
-        self.per_base_seq_quality
-            .finish(self.basic_stats.phred.offset);
+        self.extra_modules.push(module);
+    }
 
-        self.per_seq_quality_score.finish();
+    /// Compute the duplication from sketches over all reads instead of the data of overrepresented
+    /// sequences, so it covers the whole file and doesn't depend on the number of threads.
+    ///
+    /// It does nothing when the duplication module is not selected.
+    ///
+    pub fn use_sketch_duplication(&mut self, dup_length: usize) {
+        if self.duplication_enabled {
+            self.duplication_enabled = false;
+            self.sketch_duplication_level = Some(SketchDuplicationLevel::new(dup_length));
+            // Library complexity is also computed from the sketches then
+            if !self.overrepresented_enabled {
+                self.overrepresented_seqs = None;
+            }
+        }
+    }
 
-        self.per_base_seq_content.finish();
+    pub fn extra_modules(&self) -> &Vec<Box<dyn QCModule>> {
+        return &self.extra_modules;
+    }
 
-        self.per_seq_gc_content.finish();
+    /// All modules in the same order as the report of FastQC, the extra modules are at the end.
+    ///
+    /// NOTE: Sequence duplication levels is only available after finishing.
+    ///
+    pub fn modules(&self) -> Vec<&dyn QCModule> {
+        let mut modules: Vec<&dyn QCModule> = vec![];
+        for_each_module!(self, |module| {
+            modules.push(module);
+        });
 
-        self.per_base_n_content.finish();
+        return modules;
+    }
 
-        self.seq_len_distribution.finish();
+    pub fn modules_mut(&mut self) -> Vec<&mut dyn QCModule> {
+        let mut modules: Vec<&mut dyn QCModule> = vec![];
+        for_each_module!(self, mut |module| {
+            modules.push(module);
+        });
 
-        self.overrepresented_seqs.finish();
+        return modules;
+    }
 
-        let mut seq_duplication_level = *self
-            .overrepresented_seqs
-            .duplication_level_module()
-            .unwrap();
-        self.seq_duplication_level = Some(seq_duplication_level);
+    /// Finish method is crucial, don't forget it.
+    pub fn finish(&mut self) {
+        // The phred encoding is guessed by basic statistics, other modules depend on it.
+        self.basic_stats.finish();
+        let phred_offset = self.basic_stats.phred.offset;
+
+        for_each_module!(self, mut |module| {
+            if module.key() != "basic_stats" {
+                module.finish(phred_offset);
+            }
+        });
+
+        // Sequence duplication levels is computed from the data of overrepresented sequences
+        if self.duplication_enabled {
+            if let Some(overrepresented_seqs) = &mut self.overrepresented_seqs {
+                self.seq_duplication_level = overrepresented_seqs
+                    .duplication_level_module()
+                    .map(|module| *module);
+            }
+        }
+
+        if self.complexity_enabled {
+            if let Some(sketch) = &self.sketch_duplication_level {
+                self.library_complexity = Some(sketch.library_complexity());
+            } else if let Some(overrepresented_seqs) = &self.overrepresented_seqs {
+                self.library_complexity = Some(LibraryComplexity::new(
+                    overrepresented_seqs.sample_histogram(),
+                    overrepresented_seqs.count(),
+                ));
+            }
+        }
+
+        if !self.overrepresented_enabled {
+            self.overrepresented_seqs = None;
+        }
 
-        self.adapter_content.finish();
+        self.calculate_status();
+    }
 
-        self.kmer_content.finish();
+    fn calculate_status(&mut self) {
+        let limits = &self.limits;
+        for_each_module!(self, mut |module| {
+            module.calculate_status(limits);
+        });
+    }
 
-        self.per_tile_quality_score
-            .finish(self.basic_stats.phred.offset);
+    /// The verdict of each module, in the same order as the summary.txt of FastQC.
+    ///
+    /// NOTE: Modules which are ignored in the limits are not listed.
+    ///
+    pub fn summary(&self) -> Vec<ModuleSummary> {
+        return self
+            .modules()
+            .into_iter()
+            .filter(|module| module.status() != ModuleStatus::Ignore)
+            .map(|module| ModuleSummary::new(module.name(), module.status()))
+            .collect();
     }
 
     /// Process sequence one by one, and update the statistics data.
@@ -3233,6 +4750,7 @@ impl FastQC {
     /// ```
     /// extern crate preqc_pack;
     /// use preqc_pack::qc::fastqc::FastQC;
+    /// use preqc_pack::qc::FastQCConfig;
     /// use fastq::OwnedRecord;
     ///
     /// let read1 = OwnedRecord {
@@ -3242,7 +4760,12 @@ impl FastQC {
     ///   sep: None,
     /// };
     ///
-    /// let mut qc = FastQC::new();
+    /// let contaminants = "Illumina Single End Adapter 1\tGATCGGAAGAGCTCGTATGCCGTCTTCTGCTTG";
+    /// let config = FastQCConfig::new(
+    ///   String::new(), contaminants.to_string(), String::new(), None, None, None, None,
+    /// );
+    ///
+    /// let mut qc = FastQC::new(&config);
     /// qc.process_sequence(&read1);
     ///
     /// assert_eq!(qc.basic_stats.total_bases(), 68);
@@ -3255,30 +4778,13 @@ impl FastQC {
     /// ```
     ///
     pub fn process_sequence(&mut self, record: &impl Record) {
-        self.basic_stats.process_sequence(record);
-
-        self.per_base_seq_quality.process_sequence(record);
-
-        self.per_seq_quality_score.process_sequence(record);
-
-        self.per_base_seq_content.process_sequence(record);
-
-        self.per_seq_gc_content.process_sequence(record);
+        let record = ReadRecord::new(record);
 
-        self.per_base_n_content.process_sequence(record);
-
-        self.seq_len_distribution.process_sequence(record);
-
-        self.overrepresented_seqs.process_sequence(record);
-
-        // For seq_duplication_level sharing the same data structure with overrepresented_seqs
-        // we don't call seq_duplication_level' function of process_sequence which is empty.
-
-        self.adapter_content.process_sequence(record);
-
-        self.kmer_content.process_sequence(record);
-
-        self.per_tile_quality_score.process_sequence(record);
+        // For seq_duplication_level sharing the same data structure with overrepresented_seqs,
+        // it's not in the modules until finishing.
+        for_each_module!(self, mut |module| {
+            module.process_sequence(&record);
+        });
     }
 
     /// Merge several FastQC instances.
@@ -3292,6 +4798,7 @@ impl FastQC {
     /// ```
     /// extern crate preqc_pack;
     /// use preqc_pack::qc::fastqc::FastQC;
+    /// use preqc_pack::qc::FastQCConfig;
     /// use fastq::OwnedRecord;
     ///
     /// let read1 = OwnedRecord {
@@ -3301,10 +4808,15 @@ impl FastQC {
     ///   sep: None,
     /// };
     ///
-    /// let mut qc = FastQC::new();
+    /// let contaminants = "Illumina Single End Adapter 1\tGATCGGAAGAGCTCGTATGCCGTCTTCTGCTTG";
+    /// let config = FastQCConfig::new(
+    ///   String::new(), contaminants.to_string(), String::new(), None, None, None, None,
+    /// );
+    ///
+    /// let mut qc = FastQC::new(&config);
     /// qc.process_sequence(&read1);
     ///
-    /// let mut qc2 = FastQC::new();
+    /// let mut qc2 = FastQC::new(&config);
     /// qc2.process_sequence(&read1);
     ///
     /// qc.merge(&[qc2]);
@@ -3313,19 +4825,13 @@ impl FastQC {
     ///
     pub fn merge(&mut self, fastqc_vec: &[FastQC]) {
         for i in fastqc_vec {
-            self.basic_stats.merge(&i.basic_stats);
-            self.per_base_seq_quality.merge(&i.per_base_seq_quality);
-            self.per_seq_quality_score.merge(&i.per_seq_quality_score);
-            self.per_base_seq_content.merge(&i.per_base_seq_content);
-            self.per_seq_gc_content.merge(&i.per_seq_gc_content);
-            self.per_base_n_content.merge(&i.per_base_n_content);
-            self.seq_len_distribution.merge(&i.seq_len_distribution);
-            self.overrepresented_seqs.merge(&i.overrepresented_seqs);
-            // For seq_duplication_level sharing the same data structure with overrepresented_seqs
-            // we don't call seq_duplication_level' function of merge which is empty.
-            self.adapter_content.merge(&i.adapter_content);
-            self.kmer_content.merge(&i.kmer_content);
-            self.per_tile_quality_score.merge(&i.per_tile_quality_score);
+            let others = i.modules();
+            for module in self.modules_mut() {
+                match others.iter().find(|other| other.key() == module.key()) {
+                    Some(other) => module.merge(*other),
+                    None => warn!("The module {} is not found in the peer", module.key()),
+                }
+            }
         }
 
         // Finish method is crucial, don't forget it.
@@ -0,0 +1,381 @@
+//! Filter reads in the same pass as the qc, like fastp.
+//!
+//! All reads feed the qc as usual, and the reads which pass the filter also feed a second
+//! `fastqc::FilteredFastQC`, so the report can show the data before and after filtering. A read is
+//! counted by the first filter it fails, in the order of `FilterReason`.
+use super::fastqc::{Adapter, FilteredFastQC};
+use super::low_complexity::{dust_score, DUST_THRESHOLD};
+use fastq::Record;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+// The qualities are assumed to be Phred+33 like fastp, the encoding is only guessed after all
+// reads
+const FILTER_PHRED_OFFSET: usize = 33;
+
+/// Which reads are filtered, the defaults of the length and N limits are the same as fastp.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FilterParams {
+    min_length: usize,
+    max_n: usize,
+    // 0 disables the mean quality filter
+    min_mean_quality: usize,
+    low_complexity: bool,
+    adapter: bool,
+}
+
+impl Default for FilterParams {
+    fn default() -> FilterParams {
+        return FilterParams::new(15, 5, 0, false, false);
+    }
+}
+
+impl FilterParams {
+    pub fn new(
+        min_length: usize,
+        max_n: usize,
+        min_mean_quality: usize,
+        low_complexity: bool,
+        adapter: bool,
+    ) -> FilterParams {
+        return FilterParams {
+            min_length: min_length,
+            max_n: max_n,
+            min_mean_quality: min_mean_quality,
+            low_complexity: low_complexity,
+            adapter: adapter,
+        };
+    }
+
+    pub fn min_length(&self) -> usize {
+        return self.min_length;
+    }
+
+    pub fn max_n(&self) -> usize {
+        return self.max_n;
+    }
+
+    pub fn min_mean_quality(&self) -> usize {
+        return self.min_mean_quality;
+    }
+
+    pub fn low_complexity(&self) -> bool {
+        return self.low_complexity;
+    }
+
+    pub fn adapter(&self) -> bool {
+        return self.adapter;
+    }
+}
+
+/// Why a read is filtered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterReason {
+    TooShort,
+    TooManyN,
+    LowQuality,
+    LowComplexity,
+    Adapter,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReadFilter {
+    params: FilterParams,
+    adapters: Vec<Adapter>,
+    // The same adapter matching as the adapter module
+    max_mismatches: usize,
+    min_overlap: usize,
+}
+
+impl ReadFilter {
+    pub fn new(
+        params: &FilterParams,
+        adapters_content: &String,
+        max_mismatches: usize,
+        min_overlap: usize,
+    ) -> ReadFilter {
+        return ReadFilter {
+            params: params.clone(),
+            adapters: Adapter::parse_adapters(adapters_content),
+            max_mismatches: max_mismatches,
+            min_overlap: min_overlap,
+        };
+    }
+
+    /// Filter the reads with an adapter besides the known adapters, e.g. the adapter detected
+    /// before the qc.
+    pub fn add_adapter(&mut self, name: &str, sequence: &str) {
+        self.adapters
+            .push(Adapter::new(name.to_string(), sequence.to_string()));
+    }
+
+    pub fn params(&self) -> &FilterParams {
+        return &self.params;
+    }
+
+    /// The first filter which the read fails, None when it passes.
+    pub fn check(&self, record: &impl Record) -> Option<FilterReason> {
+        let (seq, qual) = (record.seq(), record.qual());
+        if seq.len() < self.params.min_length {
+            return Some(FilterReason::TooShort);
+        }
+
+        let n_count = seq.iter().filter(|b| **b == b'N' || **b == b'n').count();
+        if n_count > self.params.max_n {
+            return Some(FilterReason::TooManyN);
+        }
+
+        if self.params.min_mean_quality > 0 {
+            let total: usize = qual
+                .iter()
+                .map(|c| (*c as usize).saturating_sub(FILTER_PHRED_OFFSET))
+                .sum();
+            if qual.len() == 0 || total < self.params.min_mean_quality * qual.len() {
+                return Some(FilterReason::LowQuality);
+            }
+        }
+
+        if self.params.low_complexity && dust_score(seq) > DUST_THRESHOLD as f64 {
+            return Some(FilterReason::LowComplexity);
+        }
+
+        if self.params.adapter
+            && self.adapters.iter().any(|adapter| {
+                adapter
+                    .find(seq, self.max_mismatches, self.min_overlap)
+                    .is_some()
+            })
+        {
+            return Some(FilterReason::Adapter);
+        }
+
+        return None;
+    }
+}
+
+/// The number of reads (pairs in paired mode) which fail each filter.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FilterStats {
+    params: FilterParams,
+    total_reads: usize,
+    passed_reads: usize,
+    too_short: usize,
+    too_many_n: usize,
+    low_quality: usize,
+    low_complexity: usize,
+    with_adapter: usize,
+    percent_passed: f64,
+}
+
+impl FilterStats {
+    pub fn new(params: &FilterParams) -> FilterStats {
+        return FilterStats {
+            params: params.clone(),
+            total_reads: 0,
+            passed_reads: 0,
+            too_short: 0,
+            too_many_n: 0,
+            low_quality: 0,
+            low_complexity: 0,
+            with_adapter: 0,
+            percent_passed: 0.0,
+        };
+    }
+
+    pub fn count(&mut self, reason: Option<FilterReason>) {
+        self.total_reads += 1;
+        match reason {
+            None => self.passed_reads += 1,
+            Some(FilterReason::TooShort) => self.too_short += 1,
+            Some(FilterReason::TooManyN) => self.too_many_n += 1,
+            Some(FilterReason::LowQuality) => self.low_quality += 1,
+            Some(FilterReason::LowComplexity) => self.low_complexity += 1,
+            Some(FilterReason::Adapter) => self.with_adapter += 1,
+        }
+    }
+
+    pub fn merge(&mut self, other: &FilterStats) {
+        self.total_reads += other.total_reads;
+        self.passed_reads += other.passed_reads;
+        self.too_short += other.too_short;
+        self.too_many_n += other.too_many_n;
+        self.low_quality += other.low_quality;
+        self.low_complexity += other.low_complexity;
+        self.with_adapter += other.with_adapter;
+    }
+
+    pub fn finish(&mut self) {
+        if self.total_reads > 0 {
+            self.percent_passed = self.passed_reads as f64 / self.total_reads as f64 * 100.0;
+        }
+    }
+
+    pub fn params(&self) -> &FilterParams {
+        return &self.params;
+    }
+
+    pub fn total_reads(&self) -> usize {
+        return self.total_reads;
+    }
+
+    pub fn passed_reads(&self) -> usize {
+        return self.passed_reads;
+    }
+
+    pub fn too_short(&self) -> usize {
+        return self.too_short;
+    }
+
+    pub fn too_many_n(&self) -> usize {
+        return self.too_many_n;
+    }
+
+    pub fn low_quality(&self) -> usize {
+        return self.low_quality;
+    }
+
+    pub fn low_complexity(&self) -> usize {
+        return self.low_complexity;
+    }
+
+    pub fn with_adapter(&self) -> usize {
+        return self.with_adapter;
+    }
+
+    pub fn percent_passed(&self) -> f64 {
+        return self.percent_passed;
+    }
+}
+
+/// A read filter and the qc of the reads which pass it.
+#[derive(Debug, Clone)]
+pub struct FilteredQC {
+    filter: ReadFilter,
+    fastqc: FilteredFastQC,
+    stats: FilterStats,
+}
+
+impl FilteredQC {
+    pub fn new(filter: ReadFilter, fastqc: FilteredFastQC) -> FilteredQC {
+        let stats = FilterStats::new(filter.params());
+        return FilteredQC {
+            filter: filter,
+            fastqc: fastqc,
+            stats: stats,
+        };
+    }
+
+    /// Count the read by the filter, and process it when it passes the filter.
+    pub fn process_sequence(&mut self, record: &impl Record) -> bool {
+        let reason = self.filter.check(record);
+        self.stats.count(reason);
+        if reason.is_some() {
+            return false;
+        }
+
+        self.fastqc.process_sequence(record);
+        return true;
+    }
+
+    /// The same as `process_sequence` for a pair, the pair passes when both mates pass. The pairs
+    /// are counted by the filter of R1.
+    pub fn process_pair(
+        &mut self,
+        mate: &mut FilteredQC,
+        r1: &impl Record,
+        r2: &impl Record,
+    ) -> bool {
+        let reason = self.filter.check(r1).or_else(|| mate.filter.check(r2));
+        self.stats.count(reason);
+        if reason.is_some() {
+            return false;
+        }
+
+        self.fastqc.process_sequence(r1);
+        mate.fastqc.process_sequence(r2);
+        return true;
+    }
+
+    /// Skip the umi module in the filtered qc, e.g. for R2 when the UMIs are in the bases of R1.
+    pub fn disable_umi(&mut self) {
+        self.fastqc.umi_stats = None;
+    }
+
+    pub fn into_parts(self) -> (FilteredFastQC, FilterStats) {
+        return (self.fastqc, self.stats);
+    }
+}
+
+/// `<outdir>/<name>.filtered.fq` for `<name>.fq.gz`, `<name>.fastq` etc., the passing reads are
+/// written uncompressed.
+pub fn filtered_path(outdir: &str, fastq_path: &str) -> PathBuf {
+    let mut name = Path::new(fastq_path)
+        .file_name()
+        .unwrap_or(OsStr::new("reads"))
+        .to_string_lossy()
+        .to_string();
+    for extension in &[".gz", ".fastq", ".fq"] {
+        if name.ends_with(extension) {
+            name.truncate(name.len() - extension.len());
+        }
+    }
+
+    return Path::new(outdir).join(format!("{}.filtered.fq", name));
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use super::*;
+    use fastq::OwnedRecord;
+
+    fn record(seq: &[u8], quality: u8) -> OwnedRecord {
+        return OwnedRecord {
+            head: b"read".to_vec(),
+            seq: seq.to_vec(),
+            qual: vec![quality; seq.len()],
+            sep: None,
+        };
+    }
+
+    #[test]
+    fn test_read_filter() {
+        let params = FilterParams::new(20, 2, 20, true, true);
+        let adapters = "Illumina Universal Adapter\tAGATCGGAAGAG\n".to_string();
+        let filter = ReadFilter::new(&params, &adapters, 0, 5);
+        let insert = b"GTCGCACTGATCTGGGTTAGGCGCGGAGCCGAGGGTTGCACCATTTTTCA";
+
+        let mut stats = FilterStats::new(&params);
+        let reads = vec![
+            (record(insert, b'I'), None),
+            (record(&insert[..10], b'I'), Some(FilterReason::TooShort)),
+            (
+                record(&[&insert[..], b"NNN"].concat(), b'I'),
+                Some(FilterReason::TooManyN),
+            ),
+            (record(insert, b'+'), Some(FilterReason::LowQuality)),
+            (record(&[b'G'; 50], b'I'), Some(FilterReason::LowComplexity)),
+            (
+                record(&[&insert[..], b"AGATCGGAAGAG"].concat(), b'I'),
+                Some(FilterReason::Adapter),
+            ),
+        ];
+        for (read, reason) in &reads {
+            assert_eq!(filter.check(read), *reason);
+            stats.count(filter.check(read));
+        }
+
+        let mut other = FilterStats::new(&params);
+        other.count(None);
+        stats.merge(&other);
+        stats.finish();
+        assert_eq!(stats.total_reads(), 7);
+        assert_eq!(stats.passed_reads(), 2);
+        assert_eq!(stats.with_adapter(), 1);
+
+        assert_eq!(
+            filtered_path("out", "/data/sample_R1.fastq.gz"),
+            Path::new("out/sample_R1.filtered.fq")
+        );
+    }
+}
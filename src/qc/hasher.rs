@@ -20,6 +20,14 @@ impl Meta {
       filesize: filesize,
     }
   }
+
+  pub fn md5sum(&self) -> String {
+    return self.md5sum.clone();
+  }
+
+  pub fn filesize(&self) -> usize {
+    return self.filesize;
+  }
 }
 
 pub fn init_meta() -> Meta {
@@ -0,0 +1,373 @@
+//! Parse the instrument, flowcell, lane, tile etc. from read headers.
+//!
+//! Every field is optional, a header which can't be parsed gives an empty `ReadHeader`, so the
+//! modules depending on it report "not available" instead of failing the run.
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::str::{from_utf8, FromStr};
+
+lazy_static! {
+    // @<flowcell>L<lane>C<column>R<row><read number>, e.g. @V300012345L1C001R0010000001/1
+    static ref MGI_HEADER: Regex =
+        Regex::new(r"^(?P<flowcell>[A-Za-z0-9]+?)L(?P<lane>\d{1,2})C(?P<column>\d{3})R(?P<row>\d{3})_?\d+")
+            .unwrap();
+    // @SRR1234567.1, the original header may be kept in the comment
+    static ref SRA_HEADER: Regex = Regex::new(r"^(?P<run>[SED]RR\d+)\.\d+").unwrap();
+}
+
+pub const HEADER_FORMATS: [&str; 5] = ["auto", "casava", "illumina", "mgi", "sra"];
+
+// The fields which a user regex can capture
+const CAPTURE_NAMES: [&str; 8] = [
+    "instrument",
+    "run",
+    "flowcell",
+    "lane",
+    "tile",
+    "x",
+    "y",
+    "barcode",
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderFormat {
+    /// Try Casava 1.8+, old Illumina, SRA and MGI in order.
+    Auto,
+    /// @<instrument>:<run>:<flowcell>:<lane>:<tile>:<x>:<y> <read>:<filtered>:<control>:<barcode>
+    Casava,
+    /// @<instrument>:<lane>:<tile>:<x>:<y>#<barcode>/<read>
+    Illumina,
+    /// @<flowcell>L<lane>C<column>R<row><read number>/<read>
+    Mgi,
+    /// @<accession>.<number> [<original header>], the original header is parsed if it's kept.
+    Sra,
+    /// A user regex with named captures.
+    Regex,
+}
+
+impl FromStr for HeaderFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HeaderFormat, String> {
+        return match s {
+            "auto" => Ok(HeaderFormat::Auto),
+            "casava" => Ok(HeaderFormat::Casava),
+            "illumina" => Ok(HeaderFormat::Illumina),
+            "mgi" => Ok(HeaderFormat::Mgi),
+            "sra" => Ok(HeaderFormat::Sra),
+            _ => Err(format!(
+                "Unknown header format {}, it should be one of {}",
+                s,
+                HEADER_FORMATS.join(",")
+            )),
+        };
+    }
+}
+
+/// The fields of a read header, None means not available.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ReadHeader {
+    instrument: Option<String>,
+    run: Option<String>,
+    flowcell: Option<String>,
+    lane: Option<usize>,
+    // The tile of MGI is <column> * 1000 + <row>, e.g. C001R002 is 1002
+    tile: Option<usize>,
+    x: Option<usize>,
+    y: Option<usize>,
+    barcode: Option<String>,
+}
+
+impl ReadHeader {
+    pub fn instrument(&self) -> &Option<String> {
+        return &self.instrument;
+    }
+
+    pub fn run(&self) -> &Option<String> {
+        return &self.run;
+    }
+
+    pub fn flowcell(&self) -> &Option<String> {
+        return &self.flowcell;
+    }
+
+    pub fn lane(&self) -> Option<usize> {
+        return self.lane;
+    }
+
+    pub fn tile(&self) -> Option<usize> {
+        return self.tile;
+    }
+
+    pub fn x(&self) -> Option<usize> {
+        return self.x;
+    }
+
+    pub fn y(&self) -> Option<usize> {
+        return self.y;
+    }
+
+    pub fn barcode(&self) -> &Option<String> {
+        return &self.barcode;
+    }
+}
+
+fn to_string(field: &str) -> Option<String> {
+    if field.len() == 0 {
+        return None;
+    }
+
+    return Some(field.to_string());
+}
+
+fn to_number(field: &str) -> Option<usize> {
+    return field.parse::<usize>().ok();
+}
+
+#[derive(Debug, Clone)]
+pub struct HeaderParser {
+    format: HeaderFormat,
+    regex: Option<Regex>,
+}
+
+impl Default for HeaderParser {
+    fn default() -> HeaderParser {
+        return HeaderParser::new(HeaderFormat::Auto);
+    }
+}
+
+impl HeaderParser {
+    pub fn new(format: HeaderFormat) -> HeaderParser {
+        if format == HeaderFormat::Regex {
+            panic!("A regex header parser should be built by HeaderParser::from_regex");
+        }
+
+        return HeaderParser {
+            format: format,
+            regex: None,
+        };
+    }
+
+    /// A parser which takes the fields from the named captures of a regex, see `CAPTURE_NAMES`.
+    pub fn from_regex(pattern: &str) -> Result<HeaderParser, String> {
+        let regex = match Regex::new(pattern) {
+            Ok(regex) => regex,
+            Err(msg) => return Err(format!("Invalid header regex {}: {}", pattern, msg)),
+        };
+
+        let names: Vec<&str> = regex.capture_names().filter_map(|name| name).collect();
+        if let Some(name) = names.iter().find(|name| !CAPTURE_NAMES.contains(name)) {
+            return Err(format!(
+                "Unknown capture {} in the header regex, it should be one of {}",
+                name,
+                CAPTURE_NAMES.join(",")
+            ));
+        }
+
+        if names.len() == 0 {
+            return Err(format!("No named capture in the header regex {}", pattern));
+        }
+
+        return Ok(HeaderParser {
+            format: HeaderFormat::Regex,
+            regex: Some(regex),
+        });
+    }
+
+    pub fn format(&self) -> HeaderFormat {
+        return self.format;
+    }
+
+    /// Parse a header without the leading @, the fields which can't be parsed are None.
+    pub fn parse(&self, head: &[u8]) -> ReadHeader {
+        let head = match from_utf8(head) {
+            Ok(head) => head,
+            Err(_) => return ReadHeader::default(),
+        };
+
+        let header = match self.format {
+            HeaderFormat::Auto => HeaderParser::parse_casava(head)
+                .or_else(|| HeaderParser::parse_illumina(head))
+                .or_else(|| HeaderParser::parse_sra(head))
+                .or_else(|| HeaderParser::parse_mgi(head)),
+            HeaderFormat::Casava => HeaderParser::parse_casava(head),
+            HeaderFormat::Illumina => HeaderParser::parse_illumina(head),
+            HeaderFormat::Mgi => HeaderParser::parse_mgi(head),
+            HeaderFormat::Sra => HeaderParser::parse_sra(head),
+            HeaderFormat::Regex => self.parse_regex(head),
+        };
+
+        return header.unwrap_or_default();
+    }
+
+    fn split_comment(head: &str) -> (&str, &str) {
+        return match head.find(char::is_whitespace) {
+            Some(i) => (&head[..i], head[i..].trim_start()),
+            None => (head, ""),
+        };
+    }
+
+    fn parse_casava(head: &str) -> Option<ReadHeader> {
+        let (name, comment) = HeaderParser::split_comment(head);
+        let fields: Vec<&str> = name.split(':').collect();
+        if fields.len() < 7 {
+            return None;
+        }
+
+        // <read>:<filtered>:<control>:<barcode>
+        let barcode = comment
+            .split(':')
+            .nth(3)
+            .and_then(|barcode| to_string(barcode.trim()));
+        return Some(ReadHeader {
+            instrument: to_string(fields[0]),
+            run: to_string(fields[1]),
+            flowcell: to_string(fields[2]),
+            lane: Some(to_number(fields[3])?),
+            tile: Some(to_number(fields[4])?),
+            x: to_number(fields[5]),
+            y: to_number(fields[6]),
+            barcode: barcode,
+        });
+    }
+
+    fn parse_illumina(head: &str) -> Option<ReadHeader> {
+        let (name, _) = HeaderParser::split_comment(head);
+        let fields: Vec<&str> = name.split(':').collect();
+        if fields.len() < 5 || fields.len() >= 7 {
+            return None;
+        }
+
+        // <y>#<barcode>/<read>
+        let last = fields[fields.len() - 1];
+        let last = match last.rfind('/') {
+            Some(i) => &last[..i],
+            None => last,
+        };
+        let (y, barcode) = match last.find('#') {
+            Some(i) => (&last[..i], to_string(&last[i + 1..])),
+            None => (last, None),
+        };
+
+        return Some(ReadHeader {
+            instrument: to_string(fields[0]),
+            run: None,
+            flowcell: None,
+            lane: Some(to_number(fields[1])?),
+            tile: Some(to_number(fields[2])?),
+            x: to_number(fields[3]),
+            y: to_number(y),
+            // A barcode of 0 means no index
+            barcode: barcode.filter(|barcode| barcode != "0"),
+        });
+    }
+
+    fn parse_mgi(head: &str) -> Option<ReadHeader> {
+        let captures = MGI_HEADER.captures(head)?;
+        let column = to_number(&captures["column"])?;
+        let row = to_number(&captures["row"])?;
+        return Some(ReadHeader {
+            instrument: None,
+            run: None,
+            flowcell: to_string(&captures["flowcell"]),
+            lane: to_number(&captures["lane"]),
+            tile: Some(column * 1000 + row),
+            x: None,
+            y: None,
+            barcode: None,
+        });
+    }
+
+    fn parse_sra(head: &str) -> Option<ReadHeader> {
+        let captures = SRA_HEADER.captures(head)?;
+        let (_, comment) = HeaderParser::split_comment(head);
+        let mut header = HeaderParser::parse_casava(comment)
+            .or_else(|| HeaderParser::parse_illumina(comment))
+            .unwrap_or_default();
+        header.run = to_string(&captures["run"]);
+        return Some(header);
+    }
+
+    fn parse_regex(&self, head: &str) -> Option<ReadHeader> {
+        let captures = self.regex.as_ref()?.captures(head)?;
+        let text = |captures: &Captures, name: &str| {
+            captures.name(name).and_then(|m| to_string(m.as_str()))
+        };
+        let number = |captures: &Captures, name: &str| {
+            captures.name(name).and_then(|m| to_number(m.as_str()))
+        };
+
+        return Some(ReadHeader {
+            instrument: text(&captures, "instrument"),
+            run: text(&captures, "run"),
+            flowcell: text(&captures, "flowcell"),
+            lane: number(&captures, "lane"),
+            tile: number(&captures, "tile"),
+            x: number(&captures, "x"),
+            y: number(&captures, "y"),
+            barcode: text(&captures, "barcode"),
+        });
+    }
+}
+
+#[cfg(test)]
+mod header_tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_formats() {
+        let parser = HeaderParser::default();
+
+        let header = parser.parse(b"A00123:8:H5KJ2DSXY:1:1101:1000:2000 1:N:0:ACGTACGT+TTGCAGGA");
+        assert_eq!(header.flowcell(), &Some("H5KJ2DSXY".to_string()));
+        assert_eq!(header.lane(), Some(1));
+        assert_eq!(header.tile(), Some(1101));
+        assert_eq!(header.y(), Some(2000));
+        assert_eq!(header.barcode(), &Some("ACGTACGT+TTGCAGGA".to_string()));
+
+        let header = parser.parse(b"HWUSI-EAS100R:6:73:941:1973#ATCACG/1");
+        assert_eq!(header.lane(), Some(6));
+        assert_eq!(header.tile(), Some(73));
+        assert_eq!(header.y(), Some(1973));
+        assert_eq!(header.barcode(), &Some("ATCACG".to_string()));
+
+        let header = parser.parse(b"V300012345L2C003R0120000001/1");
+        assert_eq!(header.flowcell(), &Some("V300012345".to_string()));
+        assert_eq!(header.lane(), Some(2));
+        assert_eq!(header.tile(), Some(3012));
+
+        let header = parser.parse(b"SRR1234567.1 HWI-ST1234:8:1101:1000:2000 length=100");
+        assert_eq!(header.run(), &Some("SRR1234567".to_string()));
+        assert_eq!(header.tile(), Some(1101));
+        let header = parser.parse(b"SRR1234567.1 1 length=100");
+        assert_eq!(header.run(), &Some("SRR1234567".to_string()));
+        assert_eq!(header.tile(), None);
+
+        // Not available instead of a panic
+        assert_eq!(
+            parser.parse(b"A00123:8:H5KJ2DSXY:1:tile:1000:2000"),
+            ReadHeader::default()
+        );
+        assert_eq!(parser.parse(b"read1"), ReadHeader::default());
+        assert_eq!(
+            HeaderParser::new(HeaderFormat::Mgi).parse(b"A00123:8:H5KJ2DSXY:1:1101:1000:2000"),
+            ReadHeader::default()
+        );
+    }
+
+    #[test]
+    fn test_regex_format() {
+        let parser =
+            HeaderParser::from_regex(r"^(?P<flowcell>\w+)_(?P<lane>\d+)_(?P<tile>\w+)").unwrap();
+        let header = parser.parse(b"FC01_3_1101 extra");
+        assert_eq!(header.flowcell(), &Some("FC01".to_string()));
+        assert_eq!(header.lane(), Some(3));
+        assert_eq!(header.tile(), Some(1101));
+        assert_eq!(parser.parse(b"FC01_3_T1").tile(), None);
+
+        assert!(HeaderParser::from_regex(r"(?P<lane>\d+").is_err());
+        assert!(HeaderParser::from_regex(r"(?P<surface>\d+)").is_err());
+        assert!(HeaderParser::from_regex(r"(\d+)").is_err());
+    }
+}
@@ -0,0 +1,371 @@
+//! Split the core metrics of a file by (instrument, run, flowcell, lane).
+//!
+//! A file may be concatenated from lanes of different flowcells, so a bad lane is hidden in
+//! the file-wide numbers. Each lane is compared with the whole file, and the lanes which
+//! deviate more than the limits are flagged.
+use super::fastqc::{Limits, ModuleStatus};
+use super::header::HeaderParser;
+use super::module::{downcast, QCModule, ReadRecord};
+use fastq::Record;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Raw quality chars are at most 126 ('~')
+const MAX_QUALITY_CHAR: usize = 127;
+
+// (instrument, run, flowcell, lane)
+type LaneKey = (Option<String>, Option<String>, Option<String>, usize);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LaneStats {
+    instrument: Option<String>,
+    run: Option<String>,
+    flowcell: Option<String>,
+    lane: usize,
+    reads: usize,
+    bases: usize,
+    percent_q30: f64,
+    percent_gc: f64,
+    percent_n: f64,
+    mean_quality: f64,
+    // The metrics which deviate from the whole file more than the warn limits
+    flags: Vec<String>,
+    status: ModuleStatus,
+    #[serde(skip_serializing, default)]
+    gc_bases: usize,
+    #[serde(skip_serializing, default)]
+    n_bases: usize,
+    // Indexed by the raw quality char, the phred offset is applied when finishing
+    #[serde(skip_serializing, default)]
+    qualities: Vec<usize>,
+}
+
+impl LaneStats {
+    fn new(key: &LaneKey) -> LaneStats {
+        return LaneStats {
+            instrument: key.0.clone(),
+            run: key.1.clone(),
+            flowcell: key.2.clone(),
+            lane: key.3,
+            reads: 0,
+            bases: 0,
+            percent_q30: 0.0,
+            percent_gc: 0.0,
+            percent_n: 0.0,
+            mean_quality: 0.0,
+            flags: vec![],
+            status: ModuleStatus::Pass,
+            gc_bases: 0,
+            n_bases: 0,
+            qualities: vec![0; MAX_QUALITY_CHAR + 1],
+        };
+    }
+
+    fn add(&mut self, other: &LaneStats) {
+        self.reads += other.reads;
+        self.bases += other.bases;
+        self.gc_bases += other.gc_bases;
+        self.n_bases += other.n_bases;
+        for c in 0..MAX_QUALITY_CHAR + 1 {
+            self.qualities[c] += other.qualities[c];
+        }
+    }
+
+    fn finish(&mut self, phred_offset: usize) {
+        if self.bases == 0 {
+            return;
+        }
+
+        let mut quality_bases = 0;
+        let mut quality_sum = 0;
+        let mut q30_bases = 0;
+        for (c, count) in self.qualities.iter().enumerate() {
+            let quality = c.saturating_sub(phred_offset);
+            quality_bases += count;
+            quality_sum += quality * count;
+            if quality >= 30 {
+                q30_bases += count;
+            }
+        }
+
+        if quality_bases > 0 {
+            self.percent_q30 = q30_bases as f64 / quality_bases as f64 * 100.0;
+            self.mean_quality = quality_sum as f64 / quality_bases as f64;
+        }
+        self.percent_gc = self.gc_bases as f64 / self.bases as f64 * 100.0;
+        self.percent_n = self.n_bases as f64 / self.bases as f64 * 100.0;
+    }
+
+    pub fn instrument(&self) -> &Option<String> {
+        return &self.instrument;
+    }
+
+    pub fn run(&self) -> &Option<String> {
+        return &self.run;
+    }
+
+    pub fn flowcell(&self) -> &Option<String> {
+        return &self.flowcell;
+    }
+
+    pub fn lane(&self) -> usize {
+        return self.lane;
+    }
+
+    pub fn reads(&self) -> usize {
+        return self.reads;
+    }
+
+    pub fn bases(&self) -> usize {
+        return self.bases;
+    }
+
+    pub fn percent_q30(&self) -> f64 {
+        return self.percent_q30;
+    }
+
+    pub fn percent_gc(&self) -> f64 {
+        return self.percent_gc;
+    }
+
+    pub fn percent_n(&self) -> f64 {
+        return self.percent_n;
+    }
+
+    pub fn mean_quality(&self) -> f64 {
+        return self.mean_quality;
+    }
+
+    pub fn flags(&self) -> &Vec<String> {
+        return &self.flags;
+    }
+
+    pub fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PerLaneStats {
+    // Sorted by the lane key
+    lanes: Vec<LaneStats>,
+    // All lanes together, the lanes are compared with it
+    overall: Option<LaneStats>,
+    // The reads whose lane is not available in the header
+    unparsed_reads: usize,
+    #[serde(skip)]
+    header_parser: HeaderParser,
+    #[serde(skip)]
+    lane_stats: BTreeMap<LaneKey, LaneStats>,
+    status: ModuleStatus,
+}
+
+impl PerLaneStats {
+    pub fn new(header_parser: HeaderParser) -> PerLaneStats {
+        return PerLaneStats {
+            lanes: vec![],
+            overall: None,
+            unparsed_reads: 0,
+            header_parser: header_parser,
+            lane_stats: BTreeMap::new(),
+            status: ModuleStatus::Pass,
+        };
+    }
+
+    pub fn lanes(&self) -> &Vec<LaneStats> {
+        return &self.lanes;
+    }
+
+    pub fn overall(&self) -> &Option<LaneStats> {
+        return &self.overall;
+    }
+
+    pub fn unparsed_reads(&self) -> usize {
+        return self.unparsed_reads;
+    }
+
+    fn flag(lane: &mut LaneStats, overall: &LaneStats, limits: &Limits) {
+        let deviations = [
+            ("q30", "lane_q30", lane.percent_q30 - overall.percent_q30),
+            ("gc", "lane_gc", lane.percent_gc - overall.percent_gc),
+            ("n", "lane_n", lane.percent_n - overall.percent_n),
+            (
+                "quality",
+                "lane_quality",
+                lane.mean_quality - overall.mean_quality,
+            ),
+        ];
+
+        lane.flags = vec![];
+        lane.status = ModuleStatus::Pass;
+        for (flag, limit, deviation) in deviations.iter() {
+            let status = limits.upper_status(limit, deviation.abs());
+            if status > ModuleStatus::Pass {
+                lane.flags.push(flag.to_string());
+            }
+            lane.status = lane.status.max(status);
+        }
+    }
+}
+
+impl QCModule for PerLaneStats {
+    fn key(&self) -> &str {
+        return "per_lane_stats";
+    }
+
+    fn name(&self) -> &str {
+        return "Per lane statistics";
+    }
+
+    fn process_sequence(&mut self, record: &ReadRecord) {
+        let header = self.header_parser.parse(record.head());
+        let lane = match header.lane() {
+            Some(lane) => lane,
+            None => {
+                self.unparsed_reads += 1;
+                return;
+            }
+        };
+
+        let key = (
+            header.instrument().clone(),
+            header.run().clone(),
+            header.flowcell().clone(),
+            lane,
+        );
+        let stats = self
+            .lane_stats
+            .entry(key)
+            .or_insert_with_key(|key| LaneStats::new(key));
+
+        stats.reads += 1;
+        stats.bases += record.seq().len();
+        for base in record.seq() {
+            match base {
+                b'G' | b'C' | b'g' | b'c' => stats.gc_bases += 1,
+                b'N' | b'n' => stats.n_bases += 1,
+                _ => {}
+            }
+        }
+
+        for c in record.qual() {
+            stats.qualities[(*c as usize).min(MAX_QUALITY_CHAR)] += 1;
+        }
+    }
+
+    fn merge(&mut self, other: &dyn QCModule) {
+        let other = downcast::<PerLaneStats>(other);
+        self.unparsed_reads += other.unparsed_reads;
+        for (key, stats) in &other.lane_stats {
+            self.lane_stats
+                .entry(key.clone())
+                .or_insert_with_key(|key| LaneStats::new(key))
+                .add(stats);
+        }
+    }
+
+    fn finish(&mut self, phred_offset: usize) {
+        let mut overall = LaneStats::new(&(None, None, None, 0));
+        self.lanes = vec![];
+        for stats in self.lane_stats.values() {
+            let mut stats = stats.clone();
+            stats.finish(phred_offset);
+            overall.add(&stats);
+            self.lanes.push(stats);
+        }
+
+        if self.lanes.len() > 0 {
+            overall.finish(phred_offset);
+            self.overall = Some(overall);
+        }
+    }
+
+    /// Warn or fail if any lane deviates from the whole file more than the limits, the module is
+    /// ignored when no read has a lane.
+    fn calculate_status(&mut self, limits: &Limits) {
+        let overall = match &self.overall {
+            Some(overall) => overall.clone(),
+            None => {
+                self.status = ModuleStatus::Ignore;
+                return;
+            }
+        };
+
+        if limits.ignore("lane") {
+            self.status = ModuleStatus::Ignore;
+            return;
+        }
+
+        self.status = ModuleStatus::Pass;
+        for lane in self.lanes.iter_mut() {
+            PerLaneStats::flag(lane, &overall, limits);
+            self.status = self.status.max(lane.status);
+        }
+    }
+
+    fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
+
+#[cfg(test)]
+mod lane_tests {
+    use super::*;
+    use fastq::OwnedRecord;
+
+    fn process(module: &mut PerLaneStats, head: &str, seq: &[u8], quality: u8) {
+        let record = OwnedRecord {
+            head: head.as_bytes().to_vec(),
+            seq: seq.to_vec(),
+            qual: vec![quality; seq.len()],
+            sep: None,
+        };
+        module.process_sequence(&ReadRecord::new(&record));
+    }
+
+    #[test]
+    fn test_per_lane_stats() {
+        let mut module = PerLaneStats::new(HeaderParser::default());
+        let mut other = PerLaneStats::new(HeaderParser::default());
+        for _ in 0..10 {
+            // Q40 and Q10 in Sanger encoding
+            process(
+                &mut module,
+                "A00123:8:FC1:1:1101:1000:2000",
+                b"ACGTACGTAC",
+                b'I',
+            );
+            process(
+                &mut other,
+                "A00123:8:FC1:2:1101:1000:2000",
+                b"ACGTACGTAC",
+                b'I',
+            );
+            process(
+                &mut other,
+                "A00123:9:FC2:1:1101:1000:2000",
+                b"NNNNGCGCGC",
+                b'+',
+            );
+        }
+        process(&mut module, "read1", b"ACGTACGTAC", b'I');
+        module.merge(&other);
+        module.finish(33);
+        module.calculate_status(&Limits::default());
+
+        assert_eq!(module.unparsed_reads(), 1);
+        let lanes = module.lanes();
+        assert_eq!(lanes.len(), 3);
+        assert_eq!(lanes[0].lane(), 1);
+        assert_eq!(lanes[1].lane(), 2);
+        assert_eq!(lanes[1].reads(), 10);
+        assert_eq!(lanes[2].flowcell(), &Some("FC2".to_string()));
+        assert_eq!(lanes[2].percent_q30(), 0.0);
+        assert_eq!(lanes[2].percent_n(), 40.0);
+        assert_eq!(lanes[2].mean_quality(), 10.0);
+        assert_eq!(module.overall().as_ref().unwrap().reads(), 30);
+
+        assert_eq!(lanes[2].flags(), &vec!["q30", "gc", "n", "quality"]);
+        assert_eq!(module.status(), ModuleStatus::Fail);
+    }
+}
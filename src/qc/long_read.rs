@@ -0,0 +1,443 @@
+//! Statistics of long reads (Nanopore/PacBio), used by the long-read profile.
+//!
+//! The per-position modules of FastQC allocate arrays as long as the longest read, so the
+//! positions are binned as fractions of the read length here instead, and the memory doesn't
+//! grow with the read length.
+use super::fastqc::{Limits, ModuleStatus};
+use super::module::{downcast, QCModule, ReadRecord};
+use fastq::Record;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// 5% of the read length per bin
+const POSITION_BINS: usize = 20;
+const LENGTH_BINS_PER_DECADE: usize = 10;
+// Raw quality chars are at most 126 ('~')
+const MAX_QUALITY_CHAR: usize = 127;
+const LENGTH_CUTOFFS: [usize; 6] = [1000, 5000, 10000, 20000, 50000, 100000];
+const QUALITY_CUTOFFS: [usize; 5] = [7, 10, 12, 15, 20];
+
+lazy_static! {
+    // The error probability of each raw quality char, the phred offset is applied when finishing
+    static ref ERROR_PROBABILITIES: Vec<f64> = (0..MAX_QUALITY_CHAR + 1)
+        .map(|c| 10_f64.powf(-(c as f64) / 10.0))
+        .collect();
+}
+
+/// The number of reads and bases which reach a length or quality cutoff.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Yield {
+    cutoff: usize,
+    reads: usize,
+    bases: usize,
+}
+
+impl Yield {
+    pub fn cutoff(&self) -> usize {
+        return self.cutoff;
+    }
+
+    pub fn reads(&self) -> usize {
+        return self.reads;
+    }
+
+    pub fn bases(&self) -> usize {
+        return self.bases;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LongReadStats {
+    total_reads: usize,
+    total_bases: usize,
+    mean_length: f64,
+    longest: usize,
+    n50: usize,
+    n90: usize,
+    // Log-scaled, length_bins are the lower bounds of the bins
+    length_bins: Vec<usize>,
+    length_counts: Vec<usize>,
+    // The mean quality of a read is computed from the mean error probability, the same as NanoPlot
+    mean_quality_bins: Vec<usize>,
+    mean_quality_counts: Vec<usize>,
+    length_yield: Vec<Yield>,
+    quality_yield: Vec<Yield>,
+    position_labels: Vec<String>,
+    position_mean_quality: Vec<f64>,
+    position_gc: Vec<f64>,
+    position_n: Vec<f64>,
+    #[serde(skip_serializing, default)]
+    lengths: BTreeMap<usize, usize>,
+    // Indexed by the raw char of the mean quality
+    #[serde(skip_serializing, default)]
+    quality_reads: Vec<usize>,
+    #[serde(skip_serializing, default)]
+    quality_bases: Vec<usize>,
+    #[serde(skip_serializing, default)]
+    position_quality_sums: Vec<usize>,
+    #[serde(skip_serializing, default)]
+    position_quality_counts: Vec<usize>,
+    #[serde(skip_serializing, default)]
+    position_base_counts: Vec<usize>,
+    #[serde(skip_serializing, default)]
+    position_gc_counts: Vec<usize>,
+    #[serde(skip_serializing, default)]
+    position_n_counts: Vec<usize>,
+    status: ModuleStatus,
+}
+
+impl LongReadStats {
+    pub fn new() -> LongReadStats {
+        return LongReadStats {
+            total_reads: 0,
+            total_bases: 0,
+            mean_length: 0.0,
+            longest: 0,
+            n50: 0,
+            n90: 0,
+            length_bins: vec![],
+            length_counts: vec![],
+            mean_quality_bins: vec![],
+            mean_quality_counts: vec![],
+            length_yield: vec![],
+            quality_yield: vec![],
+            position_labels: vec![],
+            position_mean_quality: vec![],
+            position_gc: vec![],
+            position_n: vec![],
+            lengths: BTreeMap::new(),
+            quality_reads: vec![0; MAX_QUALITY_CHAR + 1],
+            quality_bases: vec![0; MAX_QUALITY_CHAR + 1],
+            position_quality_sums: vec![0; POSITION_BINS],
+            position_quality_counts: vec![0; POSITION_BINS],
+            position_base_counts: vec![0; POSITION_BINS],
+            position_gc_counts: vec![0; POSITION_BINS],
+            position_n_counts: vec![0; POSITION_BINS],
+            status: ModuleStatus::Pass,
+        };
+    }
+
+    fn length_bin(length: usize) -> usize {
+        if length <= 1 {
+            return 0;
+        }
+
+        return ((length as f64).log10() * LENGTH_BINS_PER_DECADE as f64).floor() as usize;
+    }
+
+    /// The length where the reads at least as long contain `fraction` of all bases, e.g. N50.
+    fn nx(&self, fraction: f64) -> usize {
+        let mut bases = 0;
+        for (length, count) in self.lengths.iter().rev() {
+            bases += length * count;
+            if bases as f64 >= self.total_bases as f64 * fraction {
+                return *length;
+            }
+        }
+
+        return 0;
+    }
+
+    fn calculate_lengths(&mut self) {
+        self.mean_length = if self.total_reads > 0 {
+            self.total_bases as f64 / self.total_reads as f64
+        } else {
+            0.0
+        };
+        self.longest = self.lengths.keys().last().copied().unwrap_or(0);
+        self.n50 = self.nx(0.5);
+        self.n90 = self.nx(0.9);
+
+        self.length_bins = vec![];
+        self.length_counts = vec![];
+        if let (Some(shortest), Some(longest)) =
+            (self.lengths.keys().next(), self.lengths.keys().last())
+        {
+            let first_bin = LongReadStats::length_bin(*shortest);
+            let last_bin = LongReadStats::length_bin(*longest);
+            for bin in first_bin..last_bin + 1 {
+                let lower_bound = 10_f64
+                    .powf(bin as f64 / LENGTH_BINS_PER_DECADE as f64)
+                    .ceil();
+                self.length_bins.push(lower_bound as usize);
+                self.length_counts.push(0);
+            }
+            for (length, count) in &self.lengths {
+                self.length_counts[LongReadStats::length_bin(*length) - first_bin] += count;
+            }
+        }
+
+        self.length_yield = LENGTH_CUTOFFS
+            .iter()
+            .map(|cutoff| {
+                let mut reads = 0;
+                let mut bases = 0;
+                for (length, count) in self.lengths.range(cutoff..) {
+                    reads += count;
+                    bases += length * count;
+                }
+                Yield {
+                    cutoff: *cutoff,
+                    reads: reads,
+                    bases: bases,
+                }
+            })
+            .collect();
+    }
+
+    fn calculate_qualities(&mut self, phred_offset: usize) {
+        self.mean_quality_bins = vec![];
+        self.mean_quality_counts = vec![];
+        let observed: Vec<usize> = (0..self.quality_reads.len())
+            .filter(|c| self.quality_reads[*c] > 0)
+            .collect();
+        if let (Some(lowest), Some(highest)) = (observed.first(), observed.last()) {
+            for c in *lowest..*highest + 1 {
+                self.mean_quality_bins.push(c.saturating_sub(phred_offset));
+                self.mean_quality_counts.push(self.quality_reads[c]);
+            }
+        }
+
+        self.quality_yield = QUALITY_CUTOFFS
+            .iter()
+            .map(|cutoff| {
+                let first_char = (cutoff + phred_offset).min(MAX_QUALITY_CHAR + 1);
+                Yield {
+                    cutoff: *cutoff,
+                    reads: self.quality_reads[first_char..].iter().sum(),
+                    bases: self.quality_bases[first_char..].iter().sum(),
+                }
+            })
+            .collect();
+
+        self.position_labels = (0..POSITION_BINS)
+            .map(|i| {
+                format!(
+                    "{}-{}%",
+                    i * 100 / POSITION_BINS,
+                    (i + 1) * 100 / POSITION_BINS
+                )
+            })
+            .collect();
+        let percentage = |count: usize, total: usize| {
+            if total > 0 {
+                count as f64 / total as f64 * 100.0
+            } else {
+                0.0
+            }
+        };
+        self.position_mean_quality = (0..POSITION_BINS)
+            .map(|i| {
+                if self.position_quality_counts[i] > 0 {
+                    self.position_quality_sums[i] as f64 / self.position_quality_counts[i] as f64
+                        - phred_offset as f64
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        self.position_gc = (0..POSITION_BINS)
+            .map(|i| percentage(self.position_gc_counts[i], self.position_base_counts[i]))
+            .collect();
+        self.position_n = (0..POSITION_BINS)
+            .map(|i| percentage(self.position_n_counts[i], self.position_base_counts[i]))
+            .collect();
+    }
+
+    pub fn total_reads(&self) -> usize {
+        return self.total_reads;
+    }
+
+    pub fn total_bases(&self) -> usize {
+        return self.total_bases;
+    }
+
+    pub fn mean_length(&self) -> f64 {
+        return self.mean_length;
+    }
+
+    pub fn longest(&self) -> usize {
+        return self.longest;
+    }
+
+    pub fn n50(&self) -> usize {
+        return self.n50;
+    }
+
+    pub fn n90(&self) -> usize {
+        return self.n90;
+    }
+
+    pub fn length_bins(&self) -> &Vec<usize> {
+        return &self.length_bins;
+    }
+
+    pub fn length_counts(&self) -> &Vec<usize> {
+        return &self.length_counts;
+    }
+
+    pub fn mean_quality_bins(&self) -> &Vec<usize> {
+        return &self.mean_quality_bins;
+    }
+
+    pub fn mean_quality_counts(&self) -> &Vec<usize> {
+        return &self.mean_quality_counts;
+    }
+
+    pub fn length_yield(&self) -> &Vec<Yield> {
+        return &self.length_yield;
+    }
+
+    pub fn quality_yield(&self) -> &Vec<Yield> {
+        return &self.quality_yield;
+    }
+
+    pub fn position_labels(&self) -> &Vec<String> {
+        return &self.position_labels;
+    }
+
+    pub fn position_mean_quality(&self) -> &Vec<f64> {
+        return &self.position_mean_quality;
+    }
+
+    pub fn position_gc(&self) -> &Vec<f64> {
+        return &self.position_gc;
+    }
+
+    pub fn position_n(&self) -> &Vec<f64> {
+        return &self.position_n;
+    }
+}
+
+impl QCModule for LongReadStats {
+    fn key(&self) -> &str {
+        return "long_read_stats";
+    }
+
+    fn name(&self) -> &str {
+        return "Long read statistics";
+    }
+
+    fn process_sequence(&mut self, record: &ReadRecord) {
+        let seq = record.seq();
+        let qual = record.qual();
+        let length = seq.len();
+
+        self.total_reads += 1;
+        self.total_bases += length;
+        *self.lengths.entry(length).or_insert(0) += 1;
+
+        for (i, base) in seq.iter().enumerate() {
+            let bin = i * POSITION_BINS / length;
+            self.position_base_counts[bin] += 1;
+            match base {
+                b'G' | b'C' | b'g' | b'c' => self.position_gc_counts[bin] += 1,
+                b'N' | b'n' => self.position_n_counts[bin] += 1,
+                _ => {}
+            }
+        }
+
+        if qual.len() == 0 {
+            return;
+        }
+
+        let mut error_probability = 0.0;
+        for (i, c) in qual.iter().enumerate() {
+            let c = (*c as usize).min(MAX_QUALITY_CHAR);
+            error_probability += ERROR_PROBABILITIES[c];
+
+            let bin = i * POSITION_BINS / qual.len();
+            self.position_quality_sums[bin] += c;
+            self.position_quality_counts[bin] += 1;
+        }
+
+        // The epsilon keeps the rounding error of an exact quality from flooring it down
+        let mean_quality = -10.0 * (error_probability / qual.len() as f64).log10() + 1e-9;
+        let mean_quality = (mean_quality.floor() as usize).min(MAX_QUALITY_CHAR);
+        self.quality_reads[mean_quality] += 1;
+        self.quality_bases[mean_quality] += length;
+    }
+
+    fn merge(&mut self, other: &dyn QCModule) {
+        let other = downcast::<LongReadStats>(other);
+        self.total_reads += other.total_reads;
+        self.total_bases += other.total_bases;
+        for (length, count) in &other.lengths {
+            *self.lengths.entry(*length).or_insert(0) += count;
+        }
+
+        for c in 0..MAX_QUALITY_CHAR + 1 {
+            self.quality_reads[c] += other.quality_reads[c];
+            self.quality_bases[c] += other.quality_bases[c];
+        }
+
+        for i in 0..POSITION_BINS {
+            self.position_quality_sums[i] += other.position_quality_sums[i];
+            self.position_quality_counts[i] += other.position_quality_counts[i];
+            self.position_base_counts[i] += other.position_base_counts[i];
+            self.position_gc_counts[i] += other.position_gc_counts[i];
+            self.position_n_counts[i] += other.position_n_counts[i];
+        }
+    }
+
+    fn finish(&mut self, phred_offset: usize) {
+        self.calculate_lengths();
+        self.calculate_qualities(phred_offset);
+    }
+
+    /// Long read statistics never raise a warning or an error.
+    fn calculate_status(&mut self, _limits: &Limits) {
+        self.status = ModuleStatus::Pass;
+    }
+
+    fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
+
+#[cfg(test)]
+mod long_read_tests {
+    use super::*;
+    use fastq::OwnedRecord;
+
+    fn process(module: &mut LongReadStats, length: usize, quality: u8) {
+        let record = OwnedRecord {
+            head: b"read".to_vec(),
+            seq: vec![b'G'; length],
+            qual: vec![quality; length],
+            sep: None,
+        };
+        module.process_sequence(&ReadRecord::new(&record));
+    }
+
+    #[test]
+    fn test_long_read_stats() {
+        let mut module = LongReadStats::new();
+        // Q10 and Q20 in Sanger encoding
+        process(&mut module, 100000, b'+');
+        process(&mut module, 20000, b'5');
+        process(&mut module, 5000, b'5');
+
+        let mut other = LongReadStats::new();
+        process(&mut other, 1000, b'5');
+        module.merge(&other);
+        module.finish(33);
+
+        assert_eq!(module.total_reads(), 4);
+        assert_eq!(module.n50(), 100000);
+        assert_eq!(module.n90(), 20000);
+        assert_eq!(module.length_bins().first(), Some(&1000));
+        assert_eq!(module.length_counts().iter().sum::<usize>(), 4);
+        assert_eq!(
+            module.mean_quality_bins(),
+            &vec![10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20]
+        );
+
+        let yield_10k = &module.length_yield()[2];
+        assert_eq!((yield_10k.cutoff(), yield_10k.reads()), (10000, 2));
+        let yield_q15 = &module.quality_yield()[3];
+        assert_eq!((yield_q15.cutoff(), yield_q15.bases()), (15, 26000));
+
+        assert_eq!(module.position_gc(), &vec![100.0; POSITION_BINS]);
+    }
+}
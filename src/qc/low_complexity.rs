@@ -0,0 +1,328 @@
+//! Find low-complexity reads and 3' poly-X tails.
+//!
+//! Poly-G reads (no signal on the two-color chemistry), poly-A and dinucleotide repeats are
+//! only caught by the overrepresented sequences when they are identical. Here every read gets
+//! a DUST score, and the length of the run of the same base at the 3' end is counted per base.
+use super::fastqc::{Limits, ModuleStatus};
+use super::module::{downcast, QCModule, ReadRecord};
+use super::util::base_index;
+use fastq::Record;
+use serde::{Deserialize, Serialize};
+
+// The same window and step as prinseq
+const DUST_WINDOW: usize = 64;
+const DUST_STEP: usize = 32;
+// A read is low-complexity if the DUST score of any window is above it, the same default as
+// prinseq
+pub const DUST_THRESHOLD: usize = 7;
+// A tail is counted as a poly-X tail from this length, the same default as fastp
+const POLY_X_MIN_LENGTH: usize = 10;
+
+const BASES: [char; 4] = ['A', 'C', 'G', 'T'];
+
+/// The DUST score of a read in 0-100, it's the max score of the windows.
+///
+/// The score of a window is the probability that two triplets are the same, scaled to 100, e.g.
+/// 100 for a homopolymer, 50 for a dinucleotide repeat and about 1.6 for a random sequence.
+/// The triplets with any base other than ACGT are skipped.
+///
+pub fn dust_score(seq: &[u8]) -> f64 {
+    let triplets: Vec<Option<usize>> = seq
+        .windows(3)
+        .map(|bases| {
+            Some(base_index(bases[0])? * 16 + base_index(bases[1])? * 4 + base_index(bases[2])?)
+        })
+        .collect();
+
+    // The last window is aligned to the end of the read, so all windows are full
+    let window = (DUST_WINDOW - 2).min(triplets.len());
+    let mut max_score = 0.0;
+    let mut start = 0;
+    while start + window <= triplets.len() {
+        let end = start + window;
+        let mut counts = [0usize; 64];
+        let mut total = 0;
+        for triplet in triplets[start..end].iter().flatten() {
+            counts[*triplet] += 1;
+            total += 1;
+        }
+
+        if total > 1 {
+            let same: usize = counts.iter().map(|c| c * c.saturating_sub(1)).sum();
+            let score = same as f64 / (total * (total - 1)) as f64 * 100.0;
+            if score > max_score {
+                max_score = score;
+            }
+        }
+
+        if end == triplets.len() {
+            break;
+        }
+        start = (start + DUST_STEP).min(triplets.len() - window);
+    }
+
+    return max_score;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PolyXTail {
+    base: char,
+    // The number of reads by the length of the tail, from 0
+    lengths: Vec<usize>,
+    // The reads whose tail is at least `POLY_X_MIN_LENGTH`
+    reads: usize,
+    percentage: f64,
+    mean_length: f64,
+}
+
+impl PolyXTail {
+    fn new(base: char) -> PolyXTail {
+        return PolyXTail {
+            base: base,
+            lengths: vec![],
+            reads: 0,
+            percentage: 0.0,
+            mean_length: 0.0,
+        };
+    }
+
+    fn add_length(&mut self, length: usize, count: usize) {
+        if self.lengths.len() <= length {
+            self.lengths.resize(length + 1, 0);
+        }
+        self.lengths[length] += count;
+    }
+
+    pub fn base(&self) -> char {
+        return self.base;
+    }
+
+    pub fn lengths(&self) -> &Vec<usize> {
+        return &self.lengths;
+    }
+
+    pub fn reads(&self) -> usize {
+        return self.reads;
+    }
+
+    pub fn percentage(&self) -> f64 {
+        return self.percentage;
+    }
+
+    /// The mean length of the tails which are at least `POLY_X_MIN_LENGTH`.
+    pub fn mean_length(&self) -> f64 {
+        return self.mean_length;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LowComplexity {
+    total_reads: usize,
+    low_complexity_reads: usize,
+    percent_low_complexity: f64,
+    dust_threshold: usize,
+    // The number of reads by the DUST score rounded down, from 0 to 100
+    dust_distribution: Vec<usize>,
+    poly_x_min_length: usize,
+    poly_x_tails: Vec<PolyXTail>,
+    // The reads with a poly-X tail of any base
+    percent_poly_x: f64,
+    status: ModuleStatus,
+}
+
+impl LowComplexity {
+    pub fn new() -> LowComplexity {
+        return LowComplexity {
+            total_reads: 0,
+            low_complexity_reads: 0,
+            percent_low_complexity: 0.0,
+            dust_threshold: DUST_THRESHOLD,
+            dust_distribution: vec![0; 101],
+            poly_x_min_length: POLY_X_MIN_LENGTH,
+            poly_x_tails: BASES.iter().map(|base| PolyXTail::new(*base)).collect(),
+            percent_poly_x: 0.0,
+            status: ModuleStatus::Pass,
+        };
+    }
+
+    pub fn total_reads(&self) -> usize {
+        return self.total_reads;
+    }
+
+    pub fn low_complexity_reads(&self) -> usize {
+        return self.low_complexity_reads;
+    }
+
+    pub fn percent_low_complexity(&self) -> f64 {
+        return self.percent_low_complexity;
+    }
+
+    pub fn dust_threshold(&self) -> usize {
+        return self.dust_threshold;
+    }
+
+    pub fn dust_distribution(&self) -> &Vec<usize> {
+        return &self.dust_distribution;
+    }
+
+    pub fn poly_x_min_length(&self) -> usize {
+        return self.poly_x_min_length;
+    }
+
+    pub fn poly_x_tails(&self) -> &Vec<PolyXTail> {
+        return &self.poly_x_tails;
+    }
+
+    pub fn percent_poly_x(&self) -> f64 {
+        return self.percent_poly_x;
+    }
+}
+
+impl QCModule for LowComplexity {
+    fn key(&self) -> &str {
+        return "low_complexity";
+    }
+
+    fn name(&self) -> &str {
+        return "Low complexity sequences";
+    }
+
+    fn process_sequence(&mut self, record: &ReadRecord) {
+        let seq = record.seq();
+        self.total_reads += 1;
+
+        let score = dust_score(seq);
+        self.dust_distribution[(score as usize).min(100)] += 1;
+        if score > self.dust_threshold as f64 {
+            self.low_complexity_reads += 1;
+        }
+
+        let last = match seq.last().and_then(|base| base_index(*base)) {
+            Some(index) => index,
+            None => return,
+        };
+        let length = seq
+            .iter()
+            .rev()
+            .take_while(|base| base_index(**base) == Some(last))
+            .count();
+        self.poly_x_tails[last].add_length(length, 1);
+    }
+
+    fn merge(&mut self, other: &dyn QCModule) {
+        let other = downcast::<LowComplexity>(other);
+        self.total_reads += other.total_reads;
+        self.low_complexity_reads += other.low_complexity_reads;
+        for (count, other_count) in self
+            .dust_distribution
+            .iter_mut()
+            .zip(other.dust_distribution.iter())
+        {
+            *count += other_count;
+        }
+
+        for (tail, other_tail) in self.poly_x_tails.iter_mut().zip(other.poly_x_tails.iter()) {
+            for (length, count) in other_tail.lengths.iter().enumerate() {
+                if *count > 0 {
+                    tail.add_length(length, *count);
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self, _phred_offset: usize) {
+        if self.total_reads == 0 {
+            return;
+        }
+
+        let total_reads = self.total_reads as f64;
+        self.percent_low_complexity = self.low_complexity_reads as f64 / total_reads * 100.0;
+
+        let mut poly_x_reads = 0;
+        for tail in self.poly_x_tails.iter_mut() {
+            let mut bases = 0;
+            tail.reads = 0;
+            for (length, count) in tail.lengths.iter().enumerate().skip(self.poly_x_min_length) {
+                tail.reads += count;
+                bases += length * count;
+            }
+
+            tail.percentage = tail.reads as f64 / total_reads * 100.0;
+            if tail.reads > 0 {
+                tail.mean_length = bases as f64 / tail.reads as f64;
+            }
+            poly_x_reads += tail.reads;
+        }
+        self.percent_poly_x = poly_x_reads as f64 / total_reads * 100.0;
+    }
+
+    /// Warn or fail if the percentage of low-complexity reads or reads with a poly-X tail is higher
+    /// than the limits.
+    fn calculate_status(&mut self, limits: &Limits) {
+        if self.total_reads == 0 || limits.ignore("low_complexity") {
+            self.status = ModuleStatus::Ignore;
+            return;
+        }
+
+        self.status = limits
+            .upper_status("low_complexity", self.percent_low_complexity)
+            .max(limits.upper_status("poly_x", self.percent_poly_x));
+    }
+
+    fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
+
+#[cfg(test)]
+mod low_complexity_tests {
+    use super::*;
+    use fastq::OwnedRecord;
+
+    fn process(module: &mut LowComplexity, seq: &[u8]) {
+        let record = OwnedRecord {
+            head: b"read".to_vec(),
+            seq: seq.to_vec(),
+            qual: vec![b'I'; seq.len()],
+            sep: None,
+        };
+        module.process_sequence(&ReadRecord::new(&record));
+    }
+
+    #[test]
+    fn test_dust_score() {
+        assert_eq!(dust_score(&[b'G'; 150]), 100.0);
+        assert!((dust_score(&b"AC".repeat(75)) - 50.0).abs() < 1.0);
+        assert!(dust_score(b"GTCGCACTGATCTGGGTTAGGCGCGGAGCCGAGGGTTGCACCATTTTTCATTATTGAATGC") < 7.0);
+        assert_eq!(dust_score(b"AC"), 0.0);
+        assert_eq!(dust_score(b"NNNNNNNN"), 0.0);
+    }
+
+    #[test]
+    fn test_low_complexity() {
+        let random = b"GTCGCACTGATCTGGGTTAGGCGCGGAGCCGAGGGTTGCACCATTTTTCATTATTGAATGC";
+        let mut module = LowComplexity::new();
+        let mut other = LowComplexity::new();
+        process(&mut module, &[b'G'; 100]);
+        process(&mut module, random);
+        process(&mut other, &[&random[..], &[b'A'; 12]].concat());
+        process(&mut other, &b"AC".repeat(50));
+        module.merge(&other);
+        module.finish(33);
+        module.calculate_status(&Limits::default());
+
+        assert_eq!(module.total_reads(), 4);
+        assert_eq!(module.low_complexity_reads(), 2);
+        assert_eq!(module.dust_distribution()[100], 1);
+
+        let tails = module.poly_x_tails();
+        assert_eq!(tails[0].lengths()[12], 1);
+        assert_eq!(tails[1].lengths()[1], 2);
+        assert_eq!(tails[2].lengths()[100], 1);
+        assert_eq!(tails[2].reads(), 1);
+        assert_eq!(tails[3].reads(), 0);
+        assert_eq!(tails[0].mean_length(), 12.0);
+        assert_eq!(module.percent_poly_x(), 50.0);
+        assert_eq!(module.status(), ModuleStatus::Fail);
+    }
+}
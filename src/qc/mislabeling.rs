@@ -20,15 +20,15 @@ const PATTERN_LENGTH: usize = 21;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct VAFMatrix {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     length: usize,
     indexes: Vec<usize>,
     reference: Vec<Option<usize>>,
     alternative: Vec<Option<usize>>,
     vaf: Vec<Option<f32>>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     seq_ref_hited: Vec<usize>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     seq_alt_hited: Vec<usize>,
 }
 
@@ -130,6 +130,19 @@ impl VAFMatrix {
         );
     }
 
+    pub fn reference(&self) -> &Vec<Option<usize>> {
+        return &self.reference;
+    }
+
+    pub fn alternative(&self) -> &Vec<Option<usize>> {
+        return &self.alternative;
+    }
+
+    /// It's empty until the finish method has been called.
+    pub fn vaf(&self) -> &Vec<Option<f32>> {
+        return &self.vaf;
+    }
+
     fn reset_seq_hited(&mut self) {
         self.seq_alt_hited = vec![];
         self.seq_ref_hited = vec![];
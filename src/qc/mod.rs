@@ -1,22 +1,65 @@
+pub mod adapter_detection;
+pub mod align;
+pub mod barcode;
+pub mod bisulfite;
+pub mod complexity;
+pub mod dup_sketch;
 pub mod fastqc;
+pub mod filter;
 pub mod hasher;
+pub mod header;
+pub mod lane;
+pub mod long_read;
+pub mod low_complexity;
 pub mod mislabeling;
+pub mod module;
+pub mod paired;
+pub mod screen;
+pub mod sex;
+pub mod taxonomy;
+pub mod trimming;
+pub mod umi;
 pub mod util;
 
 use serde::{Deserialize, Serialize};
 
-use fastq::parse_path;
+use fastq::{each_zipped, parse_path, Record};
 use std::collections::HashMap;
 // use hashbrown::HashMap;
-use std::io::Error;
+use std::fs::File;
+use std::io::{BufWriter, Error, Write};
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct QCResults {
     filemeta: Option<hasher::Meta>,
     fastqc: Option<fastqc::FastQC>,
+    summary: Option<Vec<fastqc::ModuleSummary>>,
     vaf_matrix: Option<mislabeling::VAFMatrix>,
+    // Only for paired-end reads, `filemeta`, `fastqc` and `summary` are the results of R1 then
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filemeta_r2: Option<hasher::Meta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fastqc_r2: Option<fastqc::FastQC>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary_r2: Option<Vec<fastqc::ModuleSummary>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    paired: Option<paired::PairedMetrics>,
+    // Only with the read filter, the qc of the reads (pairs in paired mode) which pass it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filtered_fastqc: Option<fastqc::FilteredFastQC>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filtered_summary: Option<Vec<fastqc::ModuleSummary>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filtered_fastqc_r2: Option<fastqc::FilteredFastQC>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filtered_summary_r2: Option<Vec<fastqc::ModuleSummary>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filter_stats: Option<filter::FilterStats>,
+    // Only with a bwa index, the alignment of the first reads (pairs)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alignment: Option<align::AlignmentStats>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -25,14 +68,51 @@ pub struct FastQCConfig {
     kmer_ignore_smapling_interval: Option<usize>,
     tile_continuous_sampling_boundary: Option<usize>,
     tile_ignore_smapling_interval: Option<usize>,
+    kmer_size: Option<(usize, usize)>,
+    dup_length: Option<usize>,
+    dup_engine: dup_sketch::DupEngine,
+    adapter_mismatches: Option<usize>,
+    adapter_min_overlap: Option<usize>,
+    // The number of reads sampled to detect the adapter, None disables the detection
+    adapter_detection_reads: Option<usize>,
+    // The indexes of all samples, the ones of each fastq file are selected by its name
+    expected_indexes: Vec<barcode::ExpectedIndex>,
+    trim_params: trimming::TrimParams,
+    // None disables the read filter
+    read_filter: Option<filter::FilterParams>,
+    // Where the reads which pass the filter are written, None disables the output
+    filter_output: Option<String>,
     adapters: String,
     contaminants: String,
+    limits: String,
+    modules: Vec<String>,
+    #[serde(skip)]
+    header_parser: header::HeaderParser,
+    #[serde(skip)]
+    umi_source: Option<umi::UmiSource>,
+    // None disables the screen module
+    #[serde(skip)]
+    screen_index: Option<Arc<screen::ScreenIndex>>,
+    screen_sample_interval: usize,
+    // None disables the taxonomy module
+    #[serde(skip)]
+    taxonomy_db: Option<Arc<taxonomy::TaxonomyDatabase>>,
+    taxonomy_sample_interval: usize,
+    // None disables the sex module
+    #[serde(skip)]
+    sex_kmers: Option<Arc<sex::SexKmers>>,
+    expected_sexes: Vec<sex::ExpectedSex>,
+    #[serde(skip)]
+    bisulfite_spike_in: Option<Arc<bisulfite::SpikeIn>>,
+    #[serde(skip)]
+    registry: module::ModuleRegistry,
 }
 
 impl FastQCConfig {
     pub fn new(
         adapters: String,
         contaminants: String,
+        limits: String,
         overrepresented_max_unique_seq_count: Option<usize>,
         kmer_ignore_smapling_interval: Option<usize>,
         tile_continuous_sampling_boundary: Option<usize>,
@@ -43,10 +123,403 @@ impl FastQCConfig {
             kmer_ignore_smapling_interval,
             tile_continuous_sampling_boundary,
             tile_ignore_smapling_interval,
+            kmer_size: None,
+            dup_length: None,
+            dup_engine: dup_sketch::DupEngine::FastQC,
+            adapter_mismatches: None,
+            adapter_min_overlap: None,
+            adapter_detection_reads: None,
+            expected_indexes: vec![],
+            trim_params: trimming::TrimParams::default(),
+            read_filter: None,
+            filter_output: None,
             adapters,
             contaminants,
+            limits,
+            modules: fastqc::all_modules(),
+            header_parser: header::HeaderParser::default(),
+            umi_source: None,
+            screen_index: None,
+            screen_sample_interval: screen::DEFAULT_SAMPLE_INTERVAL,
+            taxonomy_db: None,
+            taxonomy_sample_interval: taxonomy::DEFAULT_SAMPLE_INTERVAL,
+            sex_kmers: None,
+            expected_sexes: vec![],
+            bisulfite_spike_in: None,
+            registry: module::ModuleRegistry::new(),
         };
     }
+
+    /// Only run the selected built-in modules, see `fastqc::MODULE_NAMES` for the names.
+    pub fn set_modules(&mut self, modules: Vec<String>) -> Result<(), String> {
+        for name in &modules {
+            if !fastqc::MODULE_NAMES.contains(&name.as_str()) {
+                return Err(format!(
+                    "Unknown module {}, it should be one of {}",
+                    name,
+                    fastqc::MODULE_NAMES.join(",")
+                ));
+            }
+        }
+
+        self.modules = modules;
+        return Ok(());
+    }
+
+    pub fn modules(&self) -> &Vec<String> {
+        return &self.modules;
+    }
+
+    pub fn adapters(&self) -> &String {
+        return &self.adapters;
+    }
+
+    pub fn contaminants(&self) -> &String {
+        return &self.contaminants;
+    }
+
+    pub fn limits(&self) -> &String {
+        return &self.limits;
+    }
+
+    pub fn overrepresented_max_unique_seq_count(&self) -> Option<usize> {
+        return self.overrepresented_max_unique_seq_count;
+    }
+
+    pub fn kmer_ignore_smapling_interval(&self) -> Option<usize> {
+        return self.kmer_ignore_smapling_interval;
+    }
+
+    pub fn tile_continuous_sampling_boundary(&self) -> Option<usize> {
+        return self.tile_continuous_sampling_boundary;
+    }
+
+    pub fn tile_ignore_smapling_interval(&self) -> Option<usize> {
+        return self.tile_ignore_smapling_interval;
+    }
+
+    /// Count all kmers from `min` to `max` (inclusive) in the kmer module, 7-mers by default.
+    pub fn set_kmer_size(&mut self, min: usize, max: usize) {
+        self.kmer_size = Some((min, max));
+    }
+
+    pub fn kmer_size(&self) -> Option<(usize, usize)> {
+        return self.kmer_size;
+    }
+
+    /// Truncate reads to `dup_length` before the duplication analysis, 0 means the default of
+    /// FastQC (reads longer than 75bp are truncated to 50bp).
+    pub fn set_dup_length(&mut self, dup_length: usize) {
+        self.dup_length = Some(dup_length);
+    }
+
+    pub fn dup_length(&self) -> Option<usize> {
+        return self.dup_length;
+    }
+
+    /// How the duplication is computed, from overrepresented sequences (like FastQC) by default.
+    pub fn set_dup_engine(&mut self, dup_engine: dup_sketch::DupEngine) {
+        self.dup_engine = dup_engine;
+    }
+
+    pub fn dup_engine(&self) -> dup_sketch::DupEngine {
+        return self.dup_engine;
+    }
+
+    /// Allow `max_mismatches` mismatches in a full adapter (0 by default, the same as FastQC), and
+    /// count an adapter prefix of at least `min_overlap` bases at the 3' end as a partial hit
+    /// (8 by default, 0 disables it).
+    pub fn set_adapter_matching(&mut self, max_mismatches: usize, min_overlap: usize) {
+        self.adapter_mismatches = Some(max_mismatches);
+        self.adapter_min_overlap = Some(min_overlap);
+    }
+
+    pub fn adapter_matching(&self) -> (Option<usize>, Option<usize>) {
+        return (self.adapter_mismatches, self.adapter_min_overlap);
+    }
+
+    /// Infer the adapter from the first `sample_reads` reads before the qc, the inferred adapter
+    /// is counted in the adapter module besides the known adapters.
+    pub fn set_adapter_detection(&mut self, sample_reads: usize) {
+        self.adapter_detection_reads = Some(sample_reads);
+    }
+
+    pub fn adapter_detection_reads(&self) -> Option<usize> {
+        return self.adapter_detection_reads;
+    }
+
+    fn detect_adapter(&self, fastq_path: &str) -> Option<adapter_detection::AdapterDetection> {
+        if !self.modules.iter().any(|m| m == "adapter") {
+            return None;
+        }
+
+        return self.adapter_detection_reads.map(|n| {
+            let reads = adapter_detection::sample_reads(fastq_path, n);
+            adapter_detection::detect_from_reads(&reads, &self.adapters)
+        });
+    }
+
+    fn detect_adapters_paired(
+        &self,
+        r1_path: &str,
+        r2_path: &str,
+    ) -> (
+        Option<adapter_detection::AdapterDetection>,
+        Option<adapter_detection::AdapterDetection>,
+    ) {
+        if !self.modules.iter().any(|m| m == "adapter") {
+            return (None, None);
+        }
+
+        return match self.adapter_detection_reads {
+            Some(n) => {
+                let pairs = adapter_detection::sample_pairs(r1_path, r2_path, n);
+                let (r1, r2) = adapter_detection::detect_from_pairs(&pairs, &self.adapters);
+                (Some(r1), Some(r2))
+            }
+            None => (None, None),
+        };
+    }
+
+    /// How to parse the tile etc. from read headers, the built-in formats are tried by default.
+    pub fn set_header_parser(&mut self, header_parser: header::HeaderParser) {
+        self.header_parser = header_parser;
+    }
+
+    pub fn header_parser(&self) -> &header::HeaderParser {
+        return &self.header_parser;
+    }
+
+    /// The expected sample indexes, from the command line or a sample sheet, see
+    /// `barcode::select_expected` for how they are matched with the fastq files.
+    pub fn set_expected_indexes(&mut self, expected_indexes: Vec<barcode::ExpectedIndex>) {
+        self.expected_indexes = expected_indexes;
+    }
+
+    pub fn expected_indexes(&self) -> &Vec<barcode::ExpectedIndex> {
+        return &self.expected_indexes;
+    }
+
+    /// Where the UMIs are, the umi module is skipped without a source.
+    pub fn set_umi_source(&mut self, umi_source: umi::UmiSource) {
+        self.umi_source = Some(umi_source);
+    }
+
+    pub fn umi_source(&self) -> &Option<umi::UmiSource> {
+        return &self.umi_source;
+    }
+
+    /// The parameters of the simulated trimmer in the trimming module.
+    pub fn set_trim_params(&mut self, trim_params: trimming::TrimParams) {
+        self.trim_params = trim_params;
+    }
+
+    pub fn trim_params(&self) -> &trimming::TrimParams {
+        return &self.trim_params;
+    }
+
+    /// Filter reads in the same pass, the reads which pass the filter are also processed by a
+    /// second qc.
+    pub fn set_read_filter(&mut self, params: filter::FilterParams) {
+        self.read_filter = Some(params);
+    }
+
+    pub fn read_filter(&self) -> &Option<filter::FilterParams> {
+        return &self.read_filter;
+    }
+
+    /// Write the reads which pass the filter into `outdir`, see `filter::filtered_path`.
+    pub fn set_filter_output(&mut self, outdir: &str) {
+        self.filter_output = Some(outdir.to_string());
+    }
+
+    pub fn filter_output(&self) -> &Option<String> {
+        return &self.filter_output;
+    }
+
+    fn new_read_filter(
+        &self,
+        detection: &Option<adapter_detection::AdapterDetection>,
+    ) -> Option<filter::ReadFilter> {
+        return self.read_filter.as_ref().map(|params| {
+            let mut read_filter = filter::ReadFilter::new(
+                params,
+                &self.adapters,
+                self.adapter_mismatches.unwrap_or(0),
+                self.adapter_min_overlap.unwrap_or(fastqc::DEFAULT_ADAPTER_MIN_OVERLAP),
+            );
+            if let Some(detection) = detection {
+                if let (Some(sequence), false) = (detection.sequence(), detection.is_known()) {
+                    read_filter.add_adapter(fastqc::DETECTED_ADAPTER_NAME, sequence);
+                }
+            }
+            read_filter
+        });
+    }
+
+    fn new_filtered_qc(
+        &self,
+        fastq_path: &str,
+        detection: &Option<adapter_detection::AdapterDetection>,
+    ) -> Option<filter::FilteredQC> {
+        return self.new_read_filter(detection).map(|read_filter| {
+            filter::FilteredQC::new(read_filter, self.new_fastqc(fastq_path, detection))
+        });
+    }
+
+    fn filtered_writer(&self, fastq_path: &str) -> Option<BufWriter<File>> {
+        if self.read_filter.is_none() {
+            return None;
+        }
+
+        return self.filter_output.as_ref().map(|outdir| {
+            let filepath = filter::filtered_path(outdir, fastq_path);
+            match File::create(&filepath) {
+                Ok(f) => BufWriter::new(f),
+                Err(msg) => panic!("Cannot create {:?} - {}", filepath, msg),
+            }
+        });
+    }
+
+    /// Screen one out of `sample_interval` reads against the genomes of the index, the screen
+    /// module is skipped without an index.
+    pub fn set_screen(&mut self, index: screen::ScreenIndex, sample_interval: usize) {
+        self.screen_index = Some(Arc::new(index));
+        self.screen_sample_interval = sample_interval;
+    }
+
+    pub fn screen_index(&self) -> &Option<Arc<screen::ScreenIndex>> {
+        return &self.screen_index;
+    }
+
+    /// Classify one out of `sample_interval` reads by the taxonomy database, the taxonomy module
+    /// is skipped without a database.
+    pub fn set_taxonomy(&mut self, database: taxonomy::TaxonomyDatabase, sample_interval: usize) {
+        self.taxonomy_db = Some(Arc::new(database));
+        self.taxonomy_sample_interval = sample_interval;
+    }
+
+    pub fn taxonomy_db(&self) -> &Option<Arc<taxonomy::TaxonomyDatabase>> {
+        return &self.taxonomy_db;
+    }
+
+    /// Infer the sex by the k-mers of chrX and chrY, and compare it with the expected sex of each
+    /// fastq file, see `sex::select_expected_sex`. The sex module is skipped without k-mers.
+    pub fn set_sex_kmers(&mut self, kmers: sex::SexKmers, expected_sexes: Vec<sex::ExpectedSex>) {
+        self.sex_kmers = Some(Arc::new(kmers));
+        self.expected_sexes = expected_sexes;
+    }
+
+    pub fn sex_kmers(&self) -> &Option<Arc<sex::SexKmers>> {
+        return &self.sex_kmers;
+    }
+
+    /// Measure the conversion rate on the reads of the spike-in in the bisulfite module.
+    pub fn set_bisulfite_spike_in(&mut self, spike_in: bisulfite::SpikeIn) {
+        self.bisulfite_spike_in = Some(Arc::new(spike_in));
+    }
+
+    pub fn bisulfite_spike_in(&self) -> &Option<Arc<bisulfite::SpikeIn>> {
+        return &self.bisulfite_spike_in;
+    }
+
+    /// The modules in the registry are run besides the built-in modules.
+    pub fn set_registry(&mut self, registry: module::ModuleRegistry) {
+        self.registry = registry;
+    }
+
+    pub fn registry(&self) -> &module::ModuleRegistry {
+        return &self.registry;
+    }
+
+    fn new_fastqc(
+        &self,
+        fastq_path: &str,
+        detection: &Option<adapter_detection::AdapterDetection>,
+    ) -> fastqc::FastQC {
+        let mut qc = fastqc::FastQC::new(self);
+
+        if self.dup_engine == dup_sketch::DupEngine::Sketch {
+            qc.use_sketch_duplication(self.dup_length.unwrap_or(0));
+        }
+
+        if let (Some(module), Some(detection)) = (&mut qc.adapter_content, detection) {
+            module.add_detection(detection.clone());
+        }
+
+        if self.modules.iter().any(|m| m == "trimming") {
+            let mut module = trimming::TrimSimulation::new(
+                &self.adapters,
+                self.adapter_mismatches.unwrap_or(0),
+                self.adapter_min_overlap.unwrap_or(fastqc::DEFAULT_ADAPTER_MIN_OVERLAP),
+                &self.trim_params,
+            );
+            // The whole detected adapter, the adapter module only counts its beginning
+            if let Some(detection) = detection {
+                if let (Some(sequence), false) = (detection.sequence(), detection.is_known()) {
+                    module.add_adapter(fastqc::DETECTED_ADAPTER_NAME, sequence);
+                }
+            }
+            qc.trim_simulation = Some(module);
+        }
+
+        if let Some(module) = &mut qc.index_stats {
+            module.set_expected_indexes(barcode::select_expected(
+                &self.expected_indexes,
+                fastq_path,
+            ));
+        }
+
+        if let Some(umi_source) = &self.umi_source {
+            if self.modules.iter().any(|m| m == "umi") {
+                qc.umi_stats = Some(umi::UmiStats::new(
+                    umi_source.clone(),
+                    self.overrepresented_max_unique_seq_count.unwrap_or(100000),
+                    self.dup_length.unwrap_or(0),
+                    &fastqc::Limits::new(&self.limits),
+                ));
+            }
+        }
+
+        if let Some(index) = &self.screen_index {
+            if self.modules.iter().any(|m| m == "screen") {
+                qc.screen = Some(screen::ContaminationScreen::new(
+                    index.clone(),
+                    self.screen_sample_interval,
+                ));
+            }
+        }
+
+        if let Some(database) = &self.taxonomy_db {
+            if self.modules.iter().any(|m| m == "taxonomy") {
+                qc.taxonomy = Some(taxonomy::TaxonomicProfile::new(
+                    database.clone(),
+                    self.taxonomy_sample_interval,
+                ));
+            }
+        }
+
+        if let Some(kmers) = &self.sex_kmers {
+            if self.modules.iter().any(|m| m == "sex") {
+                qc.sex_check = Some(sex::SexCheck::new(
+                    kmers.clone(),
+                    sex::select_expected_sex(&self.expected_sexes, fastq_path),
+                ));
+            }
+        }
+
+        if self.modules.iter().any(|m| m == "bisulfite") {
+            qc.bisulfite = Some(bisulfite::BisulfiteStats::new(
+                self.bisulfite_spike_in.clone(),
+            ));
+        }
+
+        for module in self.registry.create_all() {
+            qc.add_module(module);
+        }
+
+        return qc;
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -71,18 +544,96 @@ impl MislabelingConfig {
 }
 
 impl QCResults {
+    pub fn filemeta(&self) -> &Option<hasher::Meta> {
+        return &self.filemeta;
+    }
+
     pub fn fastqc(&self) -> &Option<fastqc::FastQC> {
         return &self.fastqc;
     }
 
+    pub fn summary(&self) -> &Option<Vec<fastqc::ModuleSummary>> {
+        return &self.summary;
+    }
+
     pub fn vaf_matrix(&self) -> &Option<mislabeling::VAFMatrix> {
         return &self.vaf_matrix;
     }
 
+    pub fn filemeta_r2(&self) -> &Option<hasher::Meta> {
+        return &self.filemeta_r2;
+    }
+
+    pub fn fastqc_r2(&self) -> &Option<fastqc::FastQC> {
+        return &self.fastqc_r2;
+    }
+
+    pub fn summary_r2(&self) -> &Option<Vec<fastqc::ModuleSummary>> {
+        return &self.summary_r2;
+    }
+
+    pub fn paired(&self) -> &Option<paired::PairedMetrics> {
+        return &self.paired;
+    }
+
+    pub fn filtered_fastqc(&self) -> &Option<fastqc::FilteredFastQC> {
+        return &self.filtered_fastqc;
+    }
+
+    pub fn filtered_summary(&self) -> &Option<Vec<fastqc::ModuleSummary>> {
+        return &self.filtered_summary;
+    }
+
+    pub fn filtered_fastqc_r2(&self) -> &Option<fastqc::FilteredFastQC> {
+        return &self.filtered_fastqc_r2;
+    }
+
+    pub fn filtered_summary_r2(&self) -> &Option<Vec<fastqc::ModuleSummary>> {
+        return &self.filtered_summary_r2;
+    }
+
+    pub fn filter_stats(&self) -> &Option<filter::FilterStats> {
+        return &self.filter_stats;
+    }
+
+    pub fn alignment(&self) -> &Option<align::AlignmentStats> {
+        return &self.alignment;
+    }
+
+    /// The results of a single-end fastqc only, for the tests of the reports.
+    #[cfg(test)]
+    pub(crate) fn from_fastqc(fastqc: fastqc::FastQC) -> QCResults {
+        let summary = Some(fastqc.summary());
+        return QCResults {
+            filemeta: None,
+            fastqc: Some(fastqc),
+            summary: summary,
+            vaf_matrix: None,
+            filemeta_r2: None,
+            fastqc_r2: None,
+            summary_r2: None,
+            paired: None,
+            filtered_fastqc: None,
+            filtered_summary: None,
+            filtered_fastqc_r2: None,
+            filtered_summary_r2: None,
+            filter_stats: None,
+            alignment: None,
+        };
+    }
+
     pub fn set_filemeta(&mut self, filemeta: Option<hasher::Meta>) {
         self.filemeta = filemeta;
     }
 
+    pub fn set_filemeta_r2(&mut self, filemeta: Option<hasher::Meta>) {
+        self.filemeta_r2 = filemeta;
+    }
+
+    pub fn set_alignment(&mut self, alignment: Option<align::AlignmentStats>) {
+        self.alignment = alignment;
+    }
+
     pub fn run_qc_par(
         fastq_path: &str,
         n_threads: usize,
@@ -90,19 +641,28 @@ impl QCResults {
         fastqc_config: Arc<FastQCConfig>,
         mislabeling_config: Arc<MislabelingConfig>,
     ) -> QCResults {
+        let detection = if &which[..] == "fastqc" || &which[..] == "all" {
+            Arc::new(fastqc_config.detect_adapter(fastq_path))
+        } else {
+            Arc::new(None)
+        };
+        // The indexes are selected by the path, which is moved into the threads
+        let fastq_name = fastq_path.to_string();
+        // NOTE: The order of the filtered reads is only kept in a record set
+        let writer = if &which[..] == "fastqc" || &which[..] == "all" {
+            Arc::new(fastqc_config.filtered_writer(fastq_path).map(Mutex::new))
+        } else {
+            Arc::new(None)
+        };
+        let writer_arc = Arc::clone(&writer);
+
         match parse_path(Some(fastq_path), |parser| {
             let which_arc = Arc::clone(&which);
             let result: Result<Vec<_>, Error> =
                 parser.parallel_each(n_threads, move |record_sets| {
                     let which_step = &which_arc[..];
-                    let mut qc = fastqc::FastQC::new(
-                        &fastqc_config.contaminants,
-                        &fastqc_config.adapters,
-                        fastqc_config.overrepresented_max_unique_seq_count,
-                        fastqc_config.kmer_ignore_smapling_interval,
-                        fastqc_config.tile_continuous_sampling_boundary,
-                        fastqc_config.tile_ignore_smapling_interval,
-                    );
+                    let mut qc = fastqc_config.new_fastqc(&fastq_name, &detection);
+                    let mut filtered_qc = fastqc_config.new_filtered_qc(&fastq_name, &detection);
 
                     let mut vaf_matrix = mislabeling::VAFMatrix::new(
                         mislabeling_config.count,
@@ -110,9 +670,16 @@ impl QCResults {
                     );
 
                     for record_set in record_sets {
+                        let mut passed_reads: Vec<u8> = vec![];
                         for record in record_set.iter() {
                             if which_step == "fastqc" || which_step == "all" {
                                 qc.process_sequence(&record);
+                                if let Some(filtered_qc) = &mut filtered_qc {
+                                    if filtered_qc.process_sequence(&record) && writer_arc.is_some()
+                                    {
+                                        record.write(&mut passed_reads).unwrap();
+                                    }
+                                }
                             }
 
                             if which_step == "checkmate" || which_step == "all" {
@@ -120,12 +687,39 @@ impl QCResults {
                                     .process_sequence_unsafe(&mislabeling_config.patterns, &record);
                             }
                         }
+
+                        if let Some(writer) = writer_arc.as_ref() {
+                            writer
+                                .lock()
+                                .unwrap()
+                                .write_all(&passed_reads)
+                                .expect("Cannot write the filtered reads");
+                        }
                     }
 
+                    let (filtered_fastqc, filter_stats) = match filtered_qc {
+                        Some(filtered_qc) => {
+                            let (fastqc, stats) = filtered_qc.into_parts();
+                            (Some(fastqc), Some(stats))
+                        }
+                        None => (None, None),
+                    };
+
                     QCResults {
                         filemeta: None,
                         fastqc: Some(qc),
+                        summary: None,
                         vaf_matrix: Some(vaf_matrix),
+                        filemeta_r2: None,
+                        fastqc_r2: None,
+                        summary_r2: None,
+                        paired: None,
+                        filtered_fastqc: filtered_fastqc,
+                        filtered_summary: None,
+                        filtered_fastqc_r2: None,
+                        filtered_summary_r2: None,
+                        filter_stats: filter_stats,
+                        alignment: None,
                     }
                 });
 
@@ -133,12 +727,24 @@ impl QCResults {
                 Ok(qc_results) => {
                     let which_step = &which[..];
                     let mut merged_qc = qc_results[0].fastqc().to_owned().unwrap();
+                    let mut merged_filtered_qc = qc_results[0].filtered_fastqc().to_owned();
+                    let mut merged_filter_stats = qc_results[0].filter_stats().to_owned();
                     let mut merged_vaf_matrix = qc_results[0].vaf_matrix().to_owned().unwrap();
                     for i in 1..qc_results.len() {
                         if which_step == "fastqc" || which_step == "all" {
                             if let Some(fastqc) = qc_results[i].fastqc().to_owned() {
                                 merged_qc.merge(&[fastqc]);
                             }
+                            if let (Some(merged), Some(fastqc)) =
+                                (&mut merged_filtered_qc, qc_results[i].filtered_fastqc())
+                            {
+                                merged.merge(&[fastqc.clone()]);
+                            }
+                            if let (Some(merged), Some(stats)) =
+                                (&mut merged_filter_stats, qc_results[i].filter_stats())
+                            {
+                                merged.merge(stats);
+                            }
                         }
 
                         if which_step == "checkmate" || which_step == "all" {
@@ -148,13 +754,30 @@ impl QCResults {
                         }
                     }
 
-                    let fastqc = if which_step == "fastqc" || which_step == "all" {
-                        merged_qc.finish();
-                        let filename = Path::new(fastq_path).file_name().unwrap().to_str().unwrap();
-                        Some(merged_qc.update_name(filename))
-                    } else {
-                        None
-                    };
+                    let filename = Path::new(fastq_path).file_name().unwrap().to_str().unwrap();
+                    let (fastqc, filtered_fastqc, filter_stats) =
+                        if which_step == "fastqc" || which_step == "all" {
+                            merged_qc.finish();
+                            let filter_stats = merged_filter_stats.map(|mut stats| {
+                                stats.finish();
+                                stats
+                            });
+                            // An empty fastqc cannot be finished
+                            let any_passed =
+                                filter_stats.as_ref().map_or(false, |s| s.passed_reads() > 0);
+                            let filtered_fastqc =
+                                merged_filtered_qc.filter(|_| any_passed).map(|mut fastqc| {
+                                    fastqc.finish();
+                                    fastqc.update_name(filename)
+                                });
+                            (
+                                Some(merged_qc.update_name(filename)),
+                                filtered_fastqc,
+                                filter_stats,
+                            )
+                        } else {
+                            (None, None, None)
+                        };
 
                     let vaf_matrix = if which_step == "checkmate" || which_step == "all" {
                         merged_vaf_matrix.finish();
@@ -163,10 +786,24 @@ impl QCResults {
                         None
                     };
 
+                    let summary = fastqc.as_ref().map(|f| f.summary());
+                    let filtered_summary = filtered_fastqc.as_ref().map(|f| f.summary());
+
                     QCResults {
                         filemeta: None,
                         fastqc: fastqc,
+                        summary: summary,
                         vaf_matrix: vaf_matrix,
+                        filemeta_r2: None,
+                        fastqc_r2: None,
+                        summary_r2: None,
+                        paired: None,
+                        filtered_fastqc: filtered_fastqc,
+                        filtered_summary: filtered_summary,
+                        filtered_fastqc_r2: None,
+                        filtered_summary_r2: None,
+                        filter_stats: filter_stats,
+                        alignment: None,
                     }
                 }
                 Err(msg) => {
@@ -177,7 +814,16 @@ impl QCResults {
             Err(msg) => {
                 panic!("Cannot parse fastq file: {}", msg);
             }
-            Ok(o) => o,
+            Ok(o) => {
+                if let Some(writer) = writer.as_ref() {
+                    writer
+                        .lock()
+                        .unwrap()
+                        .flush()
+                        .expect("Cannot write the filtered reads");
+                }
+                o
+            }
         }
     }
 
@@ -187,15 +833,20 @@ impl QCResults {
         fastqc_config: &FastQCConfig,
         mislabeling_config: &MislabelingConfig,
     ) -> QCResults {
+        let detection = if which == "fastqc" || which == "all" {
+            fastqc_config.detect_adapter(fastq_path)
+        } else {
+            None
+        };
+
         match parse_path(Some(fastq_path), |parser| {
-            let mut qc = fastqc::FastQC::new(
-                &fastqc_config.contaminants,
-                &fastqc_config.adapters,
-                fastqc_config.overrepresented_max_unique_seq_count,
-                fastqc_config.kmer_ignore_smapling_interval,
-                fastqc_config.tile_continuous_sampling_boundary,
-                fastqc_config.tile_ignore_smapling_interval,
-            );
+            let mut qc = fastqc_config.new_fastqc(fastq_path, &detection);
+            let mut filtered_qc = fastqc_config.new_filtered_qc(fastq_path, &detection);
+            let mut writer = if which == "fastqc" || which == "all" {
+                fastqc_config.filtered_writer(fastq_path)
+            } else {
+                None
+            };
             let mut vaf_matrix = mislabeling::VAFMatrix::new(
                 mislabeling_config.count,
                 &mislabeling_config.count_vec,
@@ -204,6 +855,15 @@ impl QCResults {
                 .each(|record| {
                     if which == "fastqc" || which == "all" {
                         qc.process_sequence(&record);
+                        if let Some(filtered_qc) = &mut filtered_qc {
+                            if filtered_qc.process_sequence(&record) {
+                                if let Some(writer) = &mut writer {
+                                    record
+                                        .write(writer)
+                                        .expect("Cannot write the filtered reads");
+                                }
+                            }
+                        }
                     }
 
                     if which == "checkmate" || which == "all" {
@@ -214,12 +874,30 @@ impl QCResults {
                 })
                 .expect("Invalid fastq file");
 
-            let fastqc = if which == "fastqc" || which == "all" {
-                let filename = Path::new(fastq_path).file_name().unwrap().to_str().unwrap();
+            if let Some(writer) = &mut writer {
+                writer.flush().expect("Cannot write the filtered reads");
+            }
+
+            let filename = Path::new(fastq_path).file_name().unwrap().to_str().unwrap();
+            let (fastqc, filtered_fastqc, filter_stats) = if which == "fastqc" || which == "all" {
                 qc.finish();
-                Some(qc.update_name(filename))
+                let (filtered_fastqc, filter_stats) = match filtered_qc {
+                    Some(filtered_qc) => {
+                        let (mut fastqc, mut stats) = filtered_qc.into_parts();
+                        stats.finish();
+                        if stats.passed_reads() == 0 {
+                            // An empty fastqc cannot be finished
+                            (None, Some(stats))
+                        } else {
+                            fastqc.finish();
+                            (Some(fastqc.update_name(filename)), Some(stats))
+                        }
+                    }
+                    None => (None, None),
+                };
+                (Some(qc.update_name(filename)), filtered_fastqc, filter_stats)
             } else {
-                None
+                (None, None, None)
             };
 
             let vaf_matrix = if which == "checkmate" || which == "all" {
@@ -229,10 +907,24 @@ impl QCResults {
                 None
             };
 
+            let summary = fastqc.as_ref().map(|f| f.summary());
+            let filtered_summary = filtered_fastqc.as_ref().map(|f| f.summary());
+
             QCResults {
                 filemeta: None,
                 fastqc: fastqc,
+                summary: summary,
                 vaf_matrix: vaf_matrix,
+                filemeta_r2: None,
+                fastqc_r2: None,
+                summary_r2: None,
+                paired: None,
+                filtered_fastqc: filtered_fastqc,
+                filtered_summary: filtered_summary,
+                filtered_fastqc_r2: None,
+                filtered_summary_r2: None,
+                filter_stats: filter_stats,
+                alignment: None,
             }
         }) {
             Err(msg) => {
@@ -241,4 +933,169 @@ impl QCResults {
             Ok(o) => o,
         }
     }
+
+    /// Run qc on paired-end reads, R1 and R2 are read in lockstep in one thread.
+    ///
+    /// Each mate has its own fastqc results besides the pair-level metrics, checkmate counts
+    /// the reads of both mates.
+    ///
+    pub fn run_qc_paired(
+        r1_path: &str,
+        r2_path: &str,
+        which: &str,
+        fastqc_config: &FastQCConfig,
+        mislabeling_config: &MislabelingConfig,
+    ) -> QCResults {
+        let (detection_r1, detection_r2) = if which == "fastqc" || which == "all" {
+            fastqc_config.detect_adapters_paired(r1_path, r2_path)
+        } else {
+            (None, None)
+        };
+        let mut qc_r1 = fastqc_config.new_fastqc(r1_path, &detection_r1);
+        let mut qc_r2 = fastqc_config.new_fastqc(r2_path, &detection_r2);
+        let mut filtered_r1 = fastqc_config.new_filtered_qc(r1_path, &detection_r1);
+        let mut filtered_r2 = fastqc_config.new_filtered_qc(r2_path, &detection_r2);
+        // The UMI bases are only in R1
+        if let Some(umi::UmiSource::Read { .. }) = fastqc_config.umi_source {
+            qc_r2.umi_stats = None;
+            if let Some(filtered_r2) = &mut filtered_r2 {
+                filtered_r2.disable_umi();
+            }
+        }
+        let (mut writer_r1, mut writer_r2) = if which == "fastqc" || which == "all" {
+            (
+                fastqc_config.filtered_writer(r1_path),
+                fastqc_config.filtered_writer(r2_path),
+            )
+        } else {
+            (None, None)
+        };
+        let mut paired_metrics = paired::PairedMetrics::new(
+            fastqc_config
+                .overrepresented_max_unique_seq_count
+                .unwrap_or(100000),
+        );
+        let mut vaf_matrix =
+            mislabeling::VAFMatrix::new(mislabeling_config.count, &mislabeling_config.count_vec);
+
+        let result = parse_path(Some(r1_path), |parser1| {
+            parse_path(Some(r2_path), |parser2| {
+                each_zipped(parser1, parser2, |r1, r2| {
+                    if which == "fastqc" || which == "all" {
+                        if let Some(record) = &r1 {
+                            qc_r1.process_sequence(record);
+                        }
+                        if let Some(record) = &r2 {
+                            qc_r2.process_sequence(record);
+                        }
+                        paired_metrics.process_pair(r1.as_ref(), r2.as_ref());
+
+                        // Only the complete pairs are filtered
+                        if let (Some(f1), Some(f2), Some(record1), Some(record2)) =
+                            (&mut filtered_r1, &mut filtered_r2, &r1, &r2)
+                        {
+                            if f1.process_pair(f2, record1, record2) {
+                                for (writer, record) in
+                                    vec![(&mut writer_r1, record1), (&mut writer_r2, record2)]
+                                {
+                                    if let Some(writer) = writer {
+                                        record
+                                            .write(writer)
+                                            .expect("Cannot write the filtered reads");
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    if which == "checkmate" || which == "all" {
+                        for record in r1.iter().chain(r2.iter()) {
+                            vaf_matrix.process_sequence_unsafe(&mislabeling_config.patterns, record);
+                        }
+                    }
+
+                    return (r1.is_some(), r2.is_some());
+                })
+            })
+        });
+
+        match result {
+            Ok(Ok(Ok(_))) => {}
+            Err(msg) | Ok(Err(msg)) | Ok(Ok(Err(msg))) => {
+                panic!("Cannot parse fastq files: {}", msg);
+            }
+        }
+
+        for writer in vec![&mut writer_r1, &mut writer_r2] {
+            if let Some(writer) = writer {
+                writer.flush().expect("Cannot write the filtered reads");
+            }
+        }
+
+        let filename =
+            |path: &str| Path::new(path).file_name().unwrap().to_str().unwrap().to_string();
+        let (fastqc, fastqc_r2, paired) = if which == "fastqc" || which == "all" {
+            qc_r1.finish();
+            qc_r2.finish();
+            paired_metrics.finish();
+            (
+                Some(qc_r1.update_name(&filename(r1_path))),
+                Some(qc_r2.update_name(&filename(r2_path))),
+                Some(paired_metrics),
+            )
+        } else {
+            (None, None, None)
+        };
+
+        let (filtered_fastqc, filtered_fastqc_r2, filter_stats) =
+            match (&fastqc, filtered_r1, filtered_r2) {
+                (Some(_), Some(filtered_r1), Some(filtered_r2)) => {
+                    let (mut fastqc_r1, mut stats) = filtered_r1.into_parts();
+                    let (mut fastqc_r2, _) = filtered_r2.into_parts();
+                    stats.finish();
+                    if stats.passed_reads() == 0 {
+                        // An empty fastqc cannot be finished
+                        (None, None, Some(stats))
+                    } else {
+                        fastqc_r1.finish();
+                        fastqc_r2.finish();
+                        (
+                            Some(fastqc_r1.update_name(&filename(r1_path))),
+                            Some(fastqc_r2.update_name(&filename(r2_path))),
+                            Some(stats),
+                        )
+                    }
+                }
+                _ => (None, None, None),
+            };
+
+        let vaf_matrix = if which == "checkmate" || which == "all" {
+            vaf_matrix.finish();
+            Some(vaf_matrix)
+        } else {
+            None
+        };
+
+        let summary = fastqc.as_ref().map(|f| f.summary());
+        let summary_r2 = fastqc_r2.as_ref().map(|f| f.summary());
+        let filtered_summary = filtered_fastqc.as_ref().map(|f| f.summary());
+        let filtered_summary_r2 = filtered_fastqc_r2.as_ref().map(|f| f.summary());
+
+        return QCResults {
+            filemeta: None,
+            fastqc: fastqc,
+            summary: summary,
+            vaf_matrix: vaf_matrix,
+            filemeta_r2: None,
+            fastqc_r2: fastqc_r2,
+            summary_r2: summary_r2,
+            paired: paired,
+            filtered_fastqc: filtered_fastqc,
+            filtered_summary: filtered_summary,
+            filtered_fastqc_r2: filtered_fastqc_r2,
+            filtered_summary_r2: filtered_summary_r2,
+            filter_stats: filter_stats,
+            alignment: None,
+        };
+    }
 }
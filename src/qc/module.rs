@@ -0,0 +1,319 @@
+//! The common interface of all fastqc modules, and a registry for the modules from other crates.
+//!
+//! A module only needs to know how to process one read, merge with a peer which has processed
+//! other reads and finish the statistics. `QCResults::run_qc` and `QCResults::run_qc_par`
+//! take care of reading, threading and merging.
+use super::fastqc::{Limits, ModuleStatus};
+use fastq::Record;
+use serde::Serialize;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Result, Write};
+use std::sync::Arc;
+
+/// A borrowed view of a fastq record.
+///
+/// `fastq::Record` can't be a trait object, so all modules process this type instead.
+///
+pub struct ReadRecord<'a> {
+    head: &'a [u8],
+    seq: &'a [u8],
+    qual: &'a [u8],
+}
+
+impl<'a> ReadRecord<'a> {
+    pub fn new<R: Record>(record: &'a R) -> ReadRecord<'a> {
+        return ReadRecord {
+            head: record.head(),
+            seq: record.seq(),
+            qual: record.qual(),
+        };
+    }
+}
+
+impl<'a> Record for ReadRecord<'a> {
+    fn seq(&self) -> &[u8] {
+        return self.seq;
+    }
+
+    fn head(&self) -> &[u8] {
+        return self.head;
+    }
+
+    fn qual(&self) -> &[u8] {
+        return self.qual;
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> Result<usize> {
+        let mut written = 0;
+        written += writer.write(b"@")?;
+        written += writer.write(self.head)?;
+        written += writer.write(b"\n")?;
+        written += writer.write(self.seq)?;
+        written += writer.write(b"\n+\n")?;
+        written += writer.write(self.qual)?;
+        written += writer.write(b"\n")?;
+        return Ok(written);
+    }
+}
+
+/// The methods which every module gets for free when it is `Clone` and `Serialize`.
+pub trait QCModuleBase {
+    fn as_any(&self) -> &dyn Any;
+
+    fn clone_box(&self) -> Box<dyn QCModule>;
+
+    /// The results of the module, it's the value of `key()` in the JSON results.
+    fn to_json(&self) -> Value;
+}
+
+impl<T: QCModule + Clone + Serialize + 'static> QCModuleBase for T {
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
+
+    fn clone_box(&self) -> Box<dyn QCModule> {
+        return Box::new(self.clone());
+    }
+
+    fn to_json(&self) -> Value {
+        return match serde_json::to_value(self) {
+            Ok(value) => value,
+            Err(msg) => panic!("Cannot serialize the module {}: {}", self.key(), msg),
+        };
+    }
+}
+
+/// A fastqc module.
+///
+/// The lifecycle of a module:
+///
+/// 1. `process_sequence` is called for every read in one thread.
+/// 2. The modules of all threads are merged into one by `merge`.
+/// 3. `finish` is called once after all reads have been processed.
+/// 4. `calculate_status` decides the pass/warn/fail status based on the limits.
+///
+pub trait QCModule: QCModuleBase + fmt::Debug + Send + Sync {
+    /// A unique key of the module, e.g. per_base_seq_quality.
+    fn key(&self) -> &str;
+
+    /// The name in reports, e.g. Per base sequence quality.
+    fn name(&self) -> &str;
+
+    fn process_sequence(&mut self, record: &ReadRecord);
+
+    /// Merge the data of a peer, the peer is always the same type as self.
+    fn merge(&mut self, other: &dyn QCModule);
+
+    /// Some data values should be calculated after all sequences have been processed.
+    ///
+    /// `phred_offset` is guessed from all qualities, e.g. 33 for Sanger / Illumina 1.9.
+    ///
+    fn finish(&mut self, phred_offset: usize);
+
+    fn calculate_status(&mut self, limits: &Limits);
+
+    fn status(&self) -> ModuleStatus;
+}
+
+impl Clone for Box<dyn QCModule> {
+    fn clone(&self) -> Box<dyn QCModule> {
+        return self.clone_box();
+    }
+}
+
+/// Get the concrete type of a peer in `QCModule::merge`.
+///
+/// It panics when the peer is another type, which means the modules are mismatched.
+///
+pub fn downcast<'a, T: 'static>(module: &'a dyn QCModule) -> &'a T {
+    return match module.as_any().downcast_ref::<T>() {
+        Some(module) => module,
+        None => panic!(
+            "Cannot merge the module {} with a different type",
+            module.key()
+        ),
+    };
+}
+
+type ModuleFactory = Arc<dyn Fn() -> Box<dyn QCModule> + Send + Sync>;
+
+/// A collection of module factories, each thread creates its own modules from them.
+#[derive(Clone, Default)]
+pub struct ModuleRegistry {
+    factories: BTreeMap<String, ModuleFactory>,
+}
+
+impl ModuleRegistry {
+    pub fn new() -> ModuleRegistry {
+        return ModuleRegistry::default();
+    }
+
+    /// Register a module, the key must be the same as the key of the modules which the factory creates.
+    pub fn register<F>(&mut self, key: &str, factory: F)
+    where
+        F: Fn() -> Box<dyn QCModule> + Send + Sync + 'static,
+    {
+        if self.factories.contains_key(key) {
+            panic!("The module {} has been registered", key);
+        }
+
+        self.factories.insert(key.to_string(), Arc::new(factory));
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        return self.factories.keys().cloned().collect();
+    }
+
+    pub fn create(&self, key: &str) -> Option<Box<dyn QCModule>> {
+        return self.factories.get(key).map(|factory| factory());
+    }
+
+    /// Create all registered modules, ordered by their keys.
+    pub fn create_all(&self) -> Vec<Box<dyn QCModule>> {
+        return self.factories.values().map(|factory| factory()).collect();
+    }
+}
+
+impl fmt::Debug for ModuleRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return f
+            .debug_struct("ModuleRegistry")
+            .field("modules", &self.keys())
+            .finish();
+    }
+}
+
+#[cfg(test)]
+mod module_tests {
+    use super::*;
+    use crate::qc::fastqc::{all_modules, FastQC};
+    use crate::qc::FastQCConfig;
+    use fastq::OwnedRecord;
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, Clone)]
+    struct ReadCount {
+        count: usize,
+    }
+
+    impl QCModule for ReadCount {
+        fn key(&self) -> &str {
+            return "read_count";
+        }
+
+        fn name(&self) -> &str {
+            return "Read Count";
+        }
+
+        fn process_sequence(&mut self, _record: &ReadRecord) {
+            self.count += 1;
+        }
+
+        fn merge(&mut self, other: &dyn QCModule) {
+            self.count += downcast::<ReadCount>(other).count;
+        }
+
+        fn finish(&mut self, _phred_offset: usize) {}
+
+        fn calculate_status(&mut self, _limits: &Limits) {}
+
+        fn status(&self) -> ModuleStatus {
+            return ModuleStatus::Pass;
+        }
+    }
+
+    fn make_fastqc(registry: &ModuleRegistry, modules: &Vec<String>, n_reads: usize) -> FastQC {
+        let adapters = include_str!("../../data/adapter_list.txt").to_string();
+        let contaminants = include_str!("../../data/contaminant_list.txt").to_string();
+        let mut config = FastQCConfig::new(
+            adapters,
+            contaminants,
+            String::new(),
+            None,
+            Some(1),
+            None,
+            None,
+        );
+        config.set_modules(modules.clone()).unwrap();
+        let mut qc = FastQC::new(&config);
+        for module in registry.create_all() {
+            qc.add_module(module);
+        }
+
+        for i in 0..n_reads {
+            qc.process_sequence(&OwnedRecord {
+                head: format!("A00123:8:H5KJ2DSXY:1:1101:1000:{}", i).into_bytes(),
+                seq: b"ACGTACGTAC".to_vec(),
+                qual: b"IIIIIIIIII".to_vec(),
+                sep: None,
+            });
+        }
+
+        return qc;
+    }
+
+    #[test]
+    fn test_registered_module() {
+        let mut registry = ModuleRegistry::new();
+        registry.register("read_count", || Box::new(ReadCount { count: 0 }));
+
+        let mut qc = make_fastqc(&registry, &all_modules(), 3);
+        qc.merge(&[make_fastqc(&registry, &all_modules(), 2)]);
+        qc.finish();
+
+        assert_eq!(qc.basic_stats.total_reads(), 5);
+        assert_eq!(qc.extra_modules()[0].to_json()["count"], 5);
+
+        let summary = qc.summary();
+        assert_eq!(summary.last().unwrap().module(), "Read Count");
+
+        let results = serde_json::to_value(&qc).unwrap();
+        assert_eq!(results["extra_modules"]["read_count"]["count"], 5);
+    }
+
+    #[test]
+    fn test_selected_modules() {
+        let modules = vec!["gc".to_string(), "duplication".to_string()];
+        let mut qc = make_fastqc(&ModuleRegistry::new(), &modules, 3);
+        qc.finish();
+
+        // Duplication is computed from overrepresented sequences, which isn't reported
+        let keys: Vec<&str> = qc.modules().iter().map(|m| m.key()).collect();
+        assert_eq!(
+            keys,
+            vec!["basic_stats", "per_seq_gc_content", "seq_duplication_level"]
+        );
+
+        let results = serde_json::to_value(&qc).unwrap();
+        assert!(results.get("kmer_content").is_none());
+        assert!(results.get("overrepresented_seqs").is_none());
+        assert!(results.get("extra_modules").is_none());
+
+        let modules = vec!["duplication".to_string(), "overrepresented".to_string()];
+        let mut qc = make_fastqc(&ModuleRegistry::new(), &modules, 3);
+        qc.finish();
+        let keys: Vec<&str> = qc.modules().iter().map(|m| m.key()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "basic_stats",
+                "seq_duplication_level",
+                "overrepresented_seqs"
+            ]
+        );
+        let mut config = FastQCConfig::new(
+            String::new(),
+            String::new(),
+            String::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+        assert!(config.set_modules(vec!["gcc".to_string()]).is_err());
+        assert_eq!(config.modules(), &all_modules());
+    }
+}
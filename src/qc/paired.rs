@@ -0,0 +1,364 @@
+//! Pair-level metrics of paired-end reads, R1 and R2 are read in lockstep.
+//!
+//! Each mate also has its own `FastQC`, these metrics only cover what needs both mates:
+//! read-name synchronization, insert size from the mate overlap and pair-level duplication.
+use super::util::reverse_complement;
+use fastq::Record;
+use serde::{Deserialize, Serialize};
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::str::from_utf8;
+
+// The same as fastp, the mates overlap when at least 30bp are matched with at most 5 (and 20%)
+// mismatches.
+const OVERLAP_REQUIRE: usize = 30;
+const OVERLAP_DIFF_LIMIT: usize = 5;
+const OVERLAP_DIFF_PERCENT_LIMIT: f64 = 0.2;
+
+/// The first pair whose read names don't match, a missing mate means one file ended early.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Desync {
+    // 1-based
+    pair: usize,
+    r1: Option<String>,
+    r2: Option<String>,
+}
+
+impl Desync {
+    pub fn pair(&self) -> usize {
+        return self.pair;
+    }
+
+    pub fn r1(&self) -> &Option<String> {
+        return &self.r1;
+    }
+
+    pub fn r2(&self) -> &Option<String> {
+        return &self.r2;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PairedMetrics {
+    total_pairs: usize,
+    // The reads left in one file after the other file has ended
+    unpaired_r1: usize,
+    unpaired_r2: usize,
+    name_mismatches: usize,
+    first_desync: Option<Desync>,
+    overlapping_pairs: usize,
+    // insert_sizes[i] is the number of overlapping pairs whose insert size is i
+    insert_sizes: Vec<usize>,
+    mean_insert_size: f64,
+    median_insert_size: usize,
+    #[serde(skip_serializing, default)]
+    pairs: HashMap<u64, usize>,
+    #[serde(skip_serializing, default)]
+    frozen: bool,
+    // The number of distinct pairs we want to track, the same as overrepresented sequences
+    #[serde(skip_serializing, default)]
+    observation_cut_off: usize,
+    unique_pairs: usize,
+    count_at_unique_limit: usize,
+    percent_duplicate_pairs: f64,
+}
+
+impl PairedMetrics {
+    pub fn new(observation_cut_off: usize) -> PairedMetrics {
+        return PairedMetrics {
+            total_pairs: 0,
+            unpaired_r1: 0,
+            unpaired_r2: 0,
+            name_mismatches: 0,
+            first_desync: None,
+            overlapping_pairs: 0,
+            insert_sizes: vec![],
+            mean_insert_size: 0.0,
+            median_insert_size: 0,
+            pairs: HashMap::new(),
+            frozen: false,
+            observation_cut_off: observation_cut_off,
+            unique_pairs: 0,
+            count_at_unique_limit: 0,
+            percent_duplicate_pairs: 0.0,
+        };
+    }
+
+    /// The read name without the comment and the /1 or /2 suffix.
+    pub fn read_name(head: &[u8]) -> &[u8] {
+        let end = head
+            .iter()
+            .position(|c| c.is_ascii_whitespace())
+            .unwrap_or(head.len());
+        let name = &head[..end];
+        if name.ends_with(b"/1") || name.ends_with(b"/2") {
+            return &name[..name.len() - 2];
+        }
+
+        return name;
+    }
+
+    /// Estimate the insert size from the overlap of R1 and the reverse complement of R2.
+    ///
+    /// None means the mates don't overlap, i.e. the insert is longer than both reads.
+    ///
+    pub fn insert_size(seq1: &[u8], seq2: &[u8]) -> Option<usize> {
+        let rc2 = reverse_complement(seq2);
+        let len1 = seq1.len();
+        let len2 = rc2.len();
+
+        // R1 starts before R2 ends
+        let mut offset = 0;
+        while offset + OVERLAP_REQUIRE <= len1 {
+            let overlap_len = cmp::min(len1 - offset, len2);
+            if overlap_len < OVERLAP_REQUIRE {
+                break;
+            }
+
+            if PairedMetrics::is_overlap(&seq1[offset..offset + overlap_len], &rc2[..overlap_len]) {
+                return Some(offset + len2);
+            }
+            offset += 1;
+        }
+
+        // The insert is shorter than the reads, both mates run into the adapters
+        let mut offset = 1;
+        while offset + OVERLAP_REQUIRE <= len2 {
+            let overlap_len = cmp::min(len1, len2 - offset);
+            if overlap_len < OVERLAP_REQUIRE {
+                break;
+            }
+
+            // The insert starts at the offset of R2, R1 may be shorter than it
+            if PairedMetrics::is_overlap(&seq1[..overlap_len], &rc2[offset..offset + overlap_len]) {
+                return Some(len2 - offset);
+            }
+            offset += 1;
+        }
+
+        return None;
+    }
+
+    fn is_overlap(seq1: &[u8], seq2: &[u8]) -> bool {
+        let limit = cmp::min(
+            OVERLAP_DIFF_LIMIT,
+            (seq1.len() as f64 * OVERLAP_DIFF_PERCENT_LIMIT) as usize,
+        );
+
+        let mut diff = 0;
+        for i in 0..seq1.len() {
+            if seq1[i] != seq2[i] {
+                diff += 1;
+                if diff > limit {
+                    return false;
+                }
+            }
+        }
+
+        return true;
+    }
+
+    fn record_desync(&mut self, r1: Option<&[u8]>, r2: Option<&[u8]>) {
+        if self.first_desync.is_none() {
+            let to_string = |head: &[u8]| from_utf8(head).unwrap_or("").to_string();
+            self.first_desync = Some(Desync {
+                pair: self.total_pairs + self.unpaired_r1 + self.unpaired_r2,
+                r1: r1.map(to_string),
+                r2: r2.map(to_string),
+            });
+        }
+    }
+
+    /// Process the next reads of R1 and R2, None means the file has ended.
+    pub fn process_pair<R: Record>(&mut self, r1: Option<&R>, r2: Option<&R>) {
+        let (r1, r2) = match (r1, r2) {
+            (Some(r1), Some(r2)) => (r1, r2),
+            (Some(r1), None) => {
+                self.unpaired_r1 += 1;
+                self.record_desync(Some(r1.head()), None);
+                return;
+            }
+            (None, Some(r2)) => {
+                self.unpaired_r2 += 1;
+                self.record_desync(None, Some(r2.head()));
+                return;
+            }
+            (None, None) => return,
+        };
+
+        self.total_pairs += 1;
+
+        if PairedMetrics::read_name(r1.head()) != PairedMetrics::read_name(r2.head()) {
+            self.name_mismatches += 1;
+            self.record_desync(Some(r1.head()), Some(r2.head()));
+        }
+
+        if let Some(insert_size) = PairedMetrics::insert_size(r1.seq(), r2.seq()) {
+            self.overlapping_pairs += 1;
+            if insert_size >= self.insert_sizes.len() {
+                self.insert_sizes.resize(insert_size + 1, 0);
+            }
+            self.insert_sizes[insert_size] += 1;
+        }
+
+        // A pair is a duplicate only when both mates are the same
+        let mut hasher = DefaultHasher::new();
+        r1.seq().hash(&mut hasher);
+        r2.seq().hash(&mut hasher);
+        let key = hasher.finish();
+
+        if let Some(count) = self.pairs.get_mut(&key) {
+            *count += 1;
+            if !self.frozen {
+                self.count_at_unique_limit = self.total_pairs;
+            }
+        } else if !self.frozen {
+            self.pairs.insert(key, 1);
+            self.unique_pairs += 1;
+            self.count_at_unique_limit = self.total_pairs;
+            if self.unique_pairs == self.observation_cut_off {
+                self.frozen = true;
+            }
+        }
+    }
+
+    /// Some data values should be calculated after all pairs have been processed
+    pub fn finish(&mut self) {
+        let total: usize = self.insert_sizes.iter().sum();
+        if total > 0 {
+            let mut sum = 0;
+            let mut cumulative = 0;
+            let mut median = None;
+            for (insert_size, count) in self.insert_sizes.iter().enumerate() {
+                sum += insert_size * count;
+                cumulative += count;
+                if median.is_none() && cumulative * 2 >= total {
+                    median = Some(insert_size);
+                }
+            }
+
+            self.mean_insert_size = sum as f64 / total as f64;
+            self.median_insert_size = median.unwrap();
+        }
+
+        if self.count_at_unique_limit > 0 {
+            self.percent_duplicate_pairs =
+                (1.0 - self.unique_pairs as f64 / self.count_at_unique_limit as f64) * 100.0;
+        }
+
+        self.pairs.clear();
+    }
+
+    pub fn total_pairs(&self) -> usize {
+        return self.total_pairs;
+    }
+
+    pub fn unpaired_r1(&self) -> usize {
+        return self.unpaired_r1;
+    }
+
+    pub fn unpaired_r2(&self) -> usize {
+        return self.unpaired_r2;
+    }
+
+    pub fn name_mismatches(&self) -> usize {
+        return self.name_mismatches;
+    }
+
+    pub fn first_desync(&self) -> &Option<Desync> {
+        return &self.first_desync;
+    }
+
+    pub fn overlapping_pairs(&self) -> usize {
+        return self.overlapping_pairs;
+    }
+
+    pub fn insert_sizes(&self) -> &Vec<usize> {
+        return &self.insert_sizes;
+    }
+
+    pub fn mean_insert_size(&self) -> f64 {
+        return self.mean_insert_size;
+    }
+
+    pub fn median_insert_size(&self) -> usize {
+        return self.median_insert_size;
+    }
+
+    pub fn unique_pairs(&self) -> usize {
+        return self.unique_pairs;
+    }
+
+    pub fn percent_duplicate_pairs(&self) -> f64 {
+        return self.percent_duplicate_pairs;
+    }
+}
+
+#[cfg(test)]
+mod paired_tests {
+    use super::*;
+    use fastq::OwnedRecord;
+
+    const INSERT: &[u8] = b"GTCGCACTGATCTGGGTTAGGCGCGGAGCCGAGGGTTGCACCATTTTTCATTATTGAATGCCAAGATA";
+
+    fn make_record(head: &str, seq: &[u8]) -> OwnedRecord {
+        return OwnedRecord {
+            head: head.as_bytes().to_vec(),
+            seq: seq.to_vec(),
+            qual: vec![b'I'; seq.len()],
+            sep: None,
+        };
+    }
+
+    #[test]
+    fn test_insert_size() {
+        // A 68bp insert sequenced by 50bp reads
+        let r2 = reverse_complement(INSERT);
+        assert_eq!(
+            PairedMetrics::insert_size(&INSERT[..50], &r2[..50]),
+            Some(68)
+        );
+
+        // Both reads run into the adapters after a 40bp insert
+        let mut r1 = INSERT[..40].to_vec();
+        r1.extend_from_slice(b"AGATCGGAAGAGCACACGTC");
+        let mut r2 = reverse_complement(&INSERT[..40]);
+        r2.extend_from_slice(b"AGATCGGAAGAGCGTCGTGT");
+        assert_eq!(PairedMetrics::insert_size(&r1, &r2), Some(40));
+
+        // R1 is trimmed shorter than the insert, only R2 runs into the adapter
+        assert_eq!(PairedMetrics::insert_size(&INSERT[..30], &r2), Some(40));
+
+        assert_eq!(
+            PairedMetrics::insert_size(&INSERT[..50], &INSERT[..50]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_desync() {
+        let r2 = reverse_complement(INSERT);
+        let mut metrics = PairedMetrics::new(100000);
+        metrics.process_pair(
+            Some(&make_record("read1/1", &INSERT[..50])),
+            Some(&make_record("read1/2", &r2[..50])),
+        );
+        metrics.process_pair(
+            Some(&make_record("read2/1", &INSERT[..50])),
+            Some(&make_record("read3/2", &r2[..50])),
+        );
+        metrics.process_pair(Some(&make_record("read3/1", &INSERT[..50])), None);
+        metrics.finish();
+
+        assert_eq!(metrics.total_pairs(), 2);
+        assert_eq!(metrics.unpaired_r1(), 1);
+        assert_eq!(metrics.name_mismatches(), 1);
+        let desync = metrics.first_desync().as_ref().unwrap();
+        assert_eq!(desync.pair(), 2);
+        assert_eq!(desync.r2(), &Some("read3/2".to_string()));
+        assert_eq!(metrics.median_insert_size(), 68);
+        assert_eq!(metrics.percent_duplicate_pairs(), 50.0);
+    }
+}
@@ -0,0 +1,581 @@
+//! Screen reads against a set of genomes, like FastQ Screen.
+//!
+//! The overrepresented sequences only find the contamination which is abundant and in the
+//! contaminant list. Here the (w, k) minimizers of local FASTA files (e.g. human, mouse, E. coli,
+//! PhiX, UniVec, rRNA) are indexed before the qc, and a subsample of reads is classified against
+//! every genome:
+//!
+//! * a read hits a genome when at least half of its minimizers are in the genome;
+//! * a hit is unique when any of the minimizers occurs once in the genome, otherwise it's a
+//!   multi-mapping hit;
+//! * the hit is counted as one genome or multiple genomes by the other genomes which the read
+//!   hits, the same categories as FastQ Screen.
+//!
+//! The reads are sampled by their hashes, so the results are the same for any number of threads.
+//!
+//! Indexing a large genome (e.g. human) takes minutes, so the index can be built once by
+//! `build-screen` and loaded by `--screen-index`.
+use super::fastqc::{Limits, ModuleStatus};
+use super::module::{downcast, QCModule, ReadRecord};
+use super::util::{for_each_fasta_record, for_each_minimizer, is_sampled};
+use fastq::Record;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+const KMER_SIZE: usize = 21;
+// The number of consecutive k-mers of a minimizer window
+const WINDOW_SIZE: usize = 11;
+const MIN_HIT_FRACTION: f64 = 0.5;
+// The genomes of a minimizer are the bits of an u32
+pub const MAX_GENOMES: usize = 32;
+// One out of 10 reads is screened by default
+pub const DEFAULT_SAMPLE_INTERVAL: usize = 10;
+const INDEX_MAGIC: &[u8; 8] = b"PQCSCRIX";
+const INDEX_VERSION: u32 = 1;
+const MAX_GENOME_NAME_LENGTH: usize = 1024;
+
+/// How a read hits a genome.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hit {
+    Unmapped,
+    Unique,
+    MultiMapping,
+}
+
+/// The minimizers of all genomes, it's shared by the threads.
+#[derive(Default)]
+pub struct ScreenIndex {
+    genomes: Vec<String>,
+    // The genomes of each minimizer, and the genomes in which it occurs more than once
+    minimizers: HashMap<u64, (u32, u32)>,
+}
+
+impl fmt::Debug for ScreenIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return f
+            .debug_struct("ScreenIndex")
+            .field("genomes", &self.genomes)
+            .field("minimizers", &self.minimizers.len())
+            .finish();
+    }
+}
+
+impl ScreenIndex {
+    pub fn new() -> ScreenIndex {
+        return ScreenIndex::default();
+    }
+
+    /// Index the FASTA files of `<name>=<path>` or `<path>`, the name is the file name without
+    /// the extension by default.
+    pub fn read_fasta_files(genomes: &Vec<String>) -> Result<ScreenIndex, String> {
+        if genomes.len() > MAX_GENOMES {
+            return Err(format!(
+                "Too many genomes to screen: {}, the max is {}",
+                genomes.len(),
+                MAX_GENOMES
+            ));
+        }
+
+        let mut index = ScreenIndex::new();
+        for genome in genomes {
+            let (name, path) = match genome.find('=') {
+                Some(i) => (genome[..i].to_string(), &genome[i + 1..]),
+                None => (
+                    Path::new(genome)
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().to_string())
+                        .unwrap_or(genome.to_string()),
+                    &genome[..],
+                ),
+            };
+            if let Err(msg) = index.add_fasta(&name, path) {
+                return Err(format!("Cannot read the genome {} - {}", path, msg));
+            }
+        }
+
+        return Ok(index);
+    }
+
+    fn add_fasta(&mut self, name: &str, path: &str) -> std::io::Result<()> {
+        let mut counts: HashMap<u64, bool> = HashMap::new();
+        for_each_fasta_record(path, |_, seq| count_minimizers(seq, &mut counts))?;
+
+        self.add_counts(name, counts);
+        return Ok(());
+    }
+
+    /// Index a genome of the sequences, e.g. the chromosomes.
+    pub fn add_genome(&mut self, name: &str, sequences: &[&[u8]]) {
+        let mut counts: HashMap<u64, bool> = HashMap::new();
+        for seq in sequences {
+            count_minimizers(seq, &mut counts);
+        }
+        self.add_counts(name, counts);
+    }
+
+    fn add_counts(&mut self, name: &str, counts: HashMap<u64, bool>) {
+        if self.genomes.len() >= MAX_GENOMES {
+            panic!("Cannot index more than {} genomes", MAX_GENOMES);
+        }
+
+        let bit = 1u32 << self.genomes.len();
+        for (minimizer, repeated) in counts {
+            let entry = self.minimizers.entry(minimizer).or_insert((0, 0));
+            entry.0 |= bit;
+            if repeated {
+                entry.1 |= bit;
+            }
+        }
+        self.genomes.push(name.to_string());
+    }
+
+    pub fn genomes(&self) -> &Vec<String> {
+        return &self.genomes;
+    }
+
+    pub fn minimizers(&self) -> usize {
+        return self.minimizers.len();
+    }
+
+    /// Write the index in a binary format: the magic, version, k, w, the genome names and the
+    /// minimizers with their genomes, the integers are little-endian.
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_all(&INDEX_VERSION.to_le_bytes())?;
+        writer.write_all(&(KMER_SIZE as u32).to_le_bytes())?;
+        writer.write_all(&(WINDOW_SIZE as u32).to_le_bytes())?;
+        writer.write_all(&(self.genomes.len() as u32).to_le_bytes())?;
+        for genome in &self.genomes {
+            writer.write_all(&(genome.len() as u32).to_le_bytes())?;
+            writer.write_all(genome.as_bytes())?;
+        }
+        writer.write_all(&(self.minimizers.len() as u64).to_le_bytes())?;
+        for (minimizer, (genomes, repeated)) in &self.minimizers {
+            writer.write_all(&minimizer.to_le_bytes())?;
+            writer.write_all(&genomes.to_le_bytes())?;
+            writer.write_all(&repeated.to_le_bytes())?;
+        }
+
+        return writer.flush();
+    }
+
+    /// Read an index written by `write`, the sizes in it are checked against the file size.
+    pub fn read(path: &str) -> Result<ScreenIndex, String> {
+        let error = |msg: String| format!("Cannot read the screen index {} - {}", path, msg);
+        let file = File::open(path).map_err(|msg| error(msg.to_string()))?;
+        let file_size = file.metadata().map_err(|msg| error(msg.to_string()))?.len() as usize;
+        let mut reader = BufReader::new(file);
+        let mut read_bytes = |n: usize| -> Result<Vec<u8>, String> {
+            let mut buffer = vec![0; n];
+            reader
+                .read_exact(&mut buffer)
+                .map_err(|msg| error(msg.to_string()))?;
+            return Ok(buffer);
+        };
+        let to_u32 = |bytes: Vec<u8>| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let to_u64 = |bytes: Vec<u8>| {
+            let mut array = [0; 8];
+            array.copy_from_slice(&bytes);
+            u64::from_le_bytes(array)
+        };
+
+        if read_bytes(8)? != INDEX_MAGIC || to_u32(read_bytes(4)?) != INDEX_VERSION {
+            return Err(error("Not a screen index of this version".to_string()));
+        }
+        let (kmer_size, window_size) = (to_u32(read_bytes(4)?), to_u32(read_bytes(4)?));
+        if kmer_size as usize != KMER_SIZE || window_size as usize != WINDOW_SIZE {
+            return Err(error(format!(
+                "The minimizers are ({}, {}), not ({}, {})",
+                window_size, kmer_size, WINDOW_SIZE, KMER_SIZE
+            )));
+        }
+
+        let n_genomes = to_u32(read_bytes(4)?) as usize;
+        if n_genomes > MAX_GENOMES {
+            return Err(error(format!("Too many genomes: {}", n_genomes)));
+        }
+        let mut genomes = vec![];
+        for _ in 0..n_genomes {
+            let length = to_u32(read_bytes(4)?) as usize;
+            if length > MAX_GENOME_NAME_LENGTH {
+                return Err(error(format!(
+                    "Invalid length of a genome name: {}",
+                    length
+                )));
+            }
+            let name =
+                String::from_utf8(read_bytes(length)?).map_err(|msg| error(msg.to_string()))?;
+            genomes.push(name);
+        }
+
+        let n = to_u64(read_bytes(8)?) as usize;
+        if n > file_size / 16 {
+            return Err(error(format!("Invalid number of minimizers: {}", n)));
+        }
+        let mut minimizers = HashMap::with_capacity(n);
+        for _ in 0..n {
+            let minimizer = to_u64(read_bytes(8)?);
+            let genome_bits = to_u32(read_bytes(4)?);
+            minimizers.insert(minimizer, (genome_bits, to_u32(read_bytes(4)?)));
+        }
+
+        return Ok(ScreenIndex {
+            genomes: genomes,
+            minimizers: minimizers,
+        });
+    }
+
+    /// How the sequence hits each genome, in the order of `genomes`.
+    pub fn screen(&self, seq: &[u8]) -> Vec<Hit> {
+        let mut minimizers = vec![];
+        for_each_minimizer(seq, KMER_SIZE, WINDOW_SIZE, |minimizer| {
+            minimizers.push(minimizer)
+        });
+        minimizers.sort_unstable();
+        minimizers.dedup();
+
+        let mut hits = vec![0usize; self.genomes.len()];
+        let mut unique = vec![false; self.genomes.len()];
+        for minimizer in &minimizers {
+            if let Some((genomes, repeated)) = self.minimizers.get(minimizer) {
+                for i in 0..self.genomes.len() {
+                    if genomes & (1 << i) != 0 {
+                        hits[i] += 1;
+                        unique[i] |= repeated & (1 << i) == 0;
+                    }
+                }
+            }
+        }
+
+        let min_hits = (minimizers.len() as f64 * MIN_HIT_FRACTION).ceil().max(1.0) as usize;
+        return (0..self.genomes.len())
+            .map(|i| {
+                if hits[i] < min_hits {
+                    Hit::Unmapped
+                } else if unique[i] {
+                    Hit::Unique
+                } else {
+                    Hit::MultiMapping
+                }
+            })
+            .collect();
+    }
+}
+
+// Whether each minimizer occurs more than once
+fn count_minimizers(seq: &[u8], counts: &mut HashMap<u64, bool>) {
+    for_each_minimizer(seq, KMER_SIZE, WINDOW_SIZE, |minimizer| {
+        counts
+            .entry(minimizer)
+            .and_modify(|repeated| *repeated = true)
+            .or_insert(false);
+    });
+}
+
+/// The screened reads by how they hit a genome.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GenomeScreen {
+    genome: String,
+    unmapped: usize,
+    unique_one_genome: usize,
+    multi_mapping_one_genome: usize,
+    unique_multiple_genomes: usize,
+    multi_mapping_multiple_genomes: usize,
+    percent_unmapped: f64,
+    percent_unique_one_genome: f64,
+    percent_multi_mapping_one_genome: f64,
+    percent_unique_multiple_genomes: f64,
+    percent_multi_mapping_multiple_genomes: f64,
+}
+
+impl GenomeScreen {
+    fn new(genome: &str) -> GenomeScreen {
+        return GenomeScreen {
+            genome: genome.to_string(),
+            unmapped: 0,
+            unique_one_genome: 0,
+            multi_mapping_one_genome: 0,
+            unique_multiple_genomes: 0,
+            multi_mapping_multiple_genomes: 0,
+            percent_unmapped: 0.0,
+            percent_unique_one_genome: 0.0,
+            percent_multi_mapping_one_genome: 0.0,
+            percent_unique_multiple_genomes: 0.0,
+            percent_multi_mapping_multiple_genomes: 0.0,
+        };
+    }
+
+    fn count(&mut self, hit: Hit, multiple_genomes: bool) {
+        match (hit, multiple_genomes) {
+            (Hit::Unmapped, _) => self.unmapped += 1,
+            (Hit::Unique, false) => self.unique_one_genome += 1,
+            (Hit::MultiMapping, false) => self.multi_mapping_one_genome += 1,
+            (Hit::Unique, true) => self.unique_multiple_genomes += 1,
+            (Hit::MultiMapping, true) => self.multi_mapping_multiple_genomes += 1,
+        }
+    }
+
+    fn merge(&mut self, other: &GenomeScreen) {
+        self.unmapped += other.unmapped;
+        self.unique_one_genome += other.unique_one_genome;
+        self.multi_mapping_one_genome += other.multi_mapping_one_genome;
+        self.unique_multiple_genomes += other.unique_multiple_genomes;
+        self.multi_mapping_multiple_genomes += other.multi_mapping_multiple_genomes;
+    }
+
+    fn finish(&mut self, screened_reads: usize) {
+        let percentage = |count: usize| count as f64 / screened_reads as f64 * 100.0;
+        self.percent_unmapped = percentage(self.unmapped);
+        self.percent_unique_one_genome = percentage(self.unique_one_genome);
+        self.percent_multi_mapping_one_genome = percentage(self.multi_mapping_one_genome);
+        self.percent_unique_multiple_genomes = percentage(self.unique_multiple_genomes);
+        self.percent_multi_mapping_multiple_genomes =
+            percentage(self.multi_mapping_multiple_genomes);
+    }
+
+    pub fn genome(&self) -> &String {
+        return &self.genome;
+    }
+
+    pub fn percent_unmapped(&self) -> f64 {
+        return self.percent_unmapped;
+    }
+
+    pub fn percent_unique_one_genome(&self) -> f64 {
+        return self.percent_unique_one_genome;
+    }
+
+    pub fn percent_multi_mapping_one_genome(&self) -> f64 {
+        return self.percent_multi_mapping_one_genome;
+    }
+
+    pub fn percent_unique_multiple_genomes(&self) -> f64 {
+        return self.percent_unique_multiple_genomes;
+    }
+
+    pub fn percent_multi_mapping_multiple_genomes(&self) -> f64 {
+        return self.percent_multi_mapping_multiple_genomes;
+    }
+
+    /// The percentage of the screened reads which hit the genome.
+    pub fn percent_mapped(&self) -> f64 {
+        return 100.0 - self.percent_unmapped;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContaminationScreen {
+    // One out of `sample_interval` reads is screened
+    sample_interval: usize,
+    total_reads: usize,
+    screened_reads: usize,
+    hit_no_genomes: usize,
+    percent_hit_no_genomes: f64,
+    genomes: Vec<GenomeScreen>,
+    #[serde(skip)]
+    index: Arc<ScreenIndex>,
+    status: ModuleStatus,
+}
+
+impl ContaminationScreen {
+    pub fn new(index: Arc<ScreenIndex>, sample_interval: usize) -> ContaminationScreen {
+        return ContaminationScreen {
+            sample_interval: sample_interval.max(1),
+            total_reads: 0,
+            screened_reads: 0,
+            hit_no_genomes: 0,
+            percent_hit_no_genomes: 0.0,
+            genomes: index
+                .genomes()
+                .iter()
+                .map(|genome| GenomeScreen::new(genome))
+                .collect(),
+            index: index,
+            status: ModuleStatus::Pass,
+        };
+    }
+
+    pub fn sample_interval(&self) -> usize {
+        return self.sample_interval;
+    }
+
+    pub fn total_reads(&self) -> usize {
+        return self.total_reads;
+    }
+
+    pub fn screened_reads(&self) -> usize {
+        return self.screened_reads;
+    }
+
+    pub fn hit_no_genomes(&self) -> usize {
+        return self.hit_no_genomes;
+    }
+
+    pub fn percent_hit_no_genomes(&self) -> f64 {
+        return self.percent_hit_no_genomes;
+    }
+
+    pub fn genomes(&self) -> &Vec<GenomeScreen> {
+        return &self.genomes;
+    }
+}
+
+impl QCModule for ContaminationScreen {
+    fn key(&self) -> &str {
+        return "screen";
+    }
+
+    fn name(&self) -> &str {
+        return "Contamination screen";
+    }
+
+    fn process_sequence(&mut self, record: &ReadRecord) {
+        self.total_reads += 1;
+        if !is_sampled(record.head(), record.seq(), self.sample_interval) {
+            return;
+        }
+
+        self.screened_reads += 1;
+        let hits = self.index.screen(record.seq());
+        let mapped = hits.iter().filter(|hit| **hit != Hit::Unmapped).count();
+        if mapped == 0 {
+            self.hit_no_genomes += 1;
+        }
+        for (genome, hit) in self.genomes.iter_mut().zip(hits) {
+            genome.count(hit, mapped > 1);
+        }
+    }
+
+    fn merge(&mut self, other: &dyn QCModule) {
+        let other = downcast::<ContaminationScreen>(other);
+        let names = |module: &ContaminationScreen| -> Vec<String> {
+            return module.genomes.iter().map(|g| g.genome.clone()).collect();
+        };
+        if names(self) != names(other) || self.sample_interval != other.sample_interval {
+            panic!("Cannot merge the screens of different genomes or sample intervals");
+        }
+
+        self.total_reads += other.total_reads;
+        self.screened_reads += other.screened_reads;
+        self.hit_no_genomes += other.hit_no_genomes;
+        for (genome, other_genome) in self.genomes.iter_mut().zip(other.genomes.iter()) {
+            genome.merge(other_genome);
+        }
+    }
+
+    fn finish(&mut self, _phred_offset: usize) {
+        if self.screened_reads == 0 {
+            return;
+        }
+
+        self.percent_hit_no_genomes =
+            self.hit_no_genomes as f64 / self.screened_reads as f64 * 100.0;
+        for genome in self.genomes.iter_mut() {
+            genome.finish(self.screened_reads);
+        }
+    }
+
+    /// The screen is informational, the genomes may include the species of the sample.
+    fn calculate_status(&mut self, limits: &Limits) {
+        if self.screened_reads == 0 || limits.ignore("screen") {
+            self.status = ModuleStatus::Ignore;
+            return;
+        }
+
+        self.status = ModuleStatus::Pass;
+    }
+
+    fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
+
+#[cfg(test)]
+mod screen_tests {
+    use super::*;
+    use crate::qc::util::{mix64, reverse_complement};
+    use fastq::OwnedRecord;
+
+    // A pseudo-random genome, the minimizers of a random sequence occur once
+    fn genome(seed: u64, length: usize) -> Vec<u8> {
+        return (0..length as u64)
+            .map(|i| b"ACGT"[(mix64(seed ^ i) % 4) as usize])
+            .collect();
+    }
+
+    fn process(module: &mut ContaminationScreen, seq: &[u8]) {
+        let record = OwnedRecord {
+            head: b"read".to_vec(),
+            seq: seq.to_vec(),
+            qual: vec![b'I'; seq.len()],
+            sep: None,
+        };
+        module.process_sequence(&ReadRecord::new(&record));
+    }
+
+    #[test]
+    fn test_screen() {
+        let (a, b) = (genome(1, 5000), genome(2, 5000));
+        let repeat = [&a[1000..1100], &a[1000..1100]].concat();
+        let mut index = ScreenIndex::new();
+        index.add_genome("a", &[&a, &repeat]);
+        index.add_genome("b", &[&b, &a[2000..2200]]);
+
+        use Hit::*;
+        assert_eq!(index.screen(&a[100..250]), vec![Unique, Unmapped]);
+        assert_eq!(
+            index.screen(&reverse_complement(&b[100..250])),
+            vec![Unmapped, Unique]
+        );
+        assert_eq!(index.screen(&a[2020..2170]), vec![Unique, Unique]);
+        assert_eq!(index.screen(&a[1000..1100]), vec![MultiMapping, Unmapped]);
+        assert_eq!(index.screen(&genome(3, 150)), vec![Unmapped, Unmapped]);
+        assert_eq!(index.screen(b"ACGT"), vec![Unmapped, Unmapped]);
+
+        let index = Arc::new(index);
+        let mut module = ContaminationScreen::new(index.clone(), 1);
+        let mut other = ContaminationScreen::new(index, 1);
+        process(&mut module, &a[100..250]);
+        process(&mut module, &a[2020..2170]);
+        process(&mut other, &genome(3, 150));
+        process(&mut other, &b[300..450]);
+        module.merge(&other);
+        module.finish(33);
+
+        assert_eq!(module.screened_reads(), 4);
+        assert_eq!(module.hit_no_genomes(), 1);
+        let genomes = module.genomes();
+        assert_eq!(genomes[0].percent_unique_one_genome(), 25.0);
+        assert_eq!(genomes[0].percent_unique_multiple_genomes(), 25.0);
+        assert_eq!(genomes[1].percent_unique_one_genome(), 25.0);
+        assert_eq!(genomes[1].percent_unmapped(), 50.0);
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let a = genome(1, 5000);
+        let mut index = ScreenIndex::new();
+        index.add_genome("a", &[&a]);
+        index.add_genome("b", &[&genome(2, 5000)]);
+
+        let path = std::env::temp_dir().join("preqc_screen_tests.idx");
+        let path = path.to_str().unwrap();
+        index.write(path).unwrap();
+        let loaded = ScreenIndex::read(path).unwrap();
+        assert_eq!(loaded.genomes(), &vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(loaded.minimizers(), index.minimizers());
+        assert_eq!(loaded.screen(&a[100..250]), index.screen(&a[100..250]));
+
+        // A truncated index is an error rather than a partial index
+        let bytes = std::fs::read(path).unwrap();
+        std::fs::write(path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(ScreenIndex::read(path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,465 @@
+//! Infer the sex of the sample by the k-mers unique to chrY and chrX, to catch sample swaps.
+//!
+//! A read is counted on the chromosome which it has more k-mer hits of. The reads per k-mer of
+//! chrY are compared with chrX: a male (XY) has one copy of each, so the ratio is about 1, and a
+//! female (XX) has no chrY, so it's about 0. The sex is inferred by the Poisson likelihood of the
+//! chrY reads given the chrX reads, and the confidence is its posterior with equal priors.
+use super::barcode::{matches_sample, read_sample_sheet_rows, sample_sheet_sample};
+use super::fastqc::{Limits, ModuleStatus};
+use super::module::{downcast, QCModule, ReadRecord};
+use super::util::for_each_canonical_kmer;
+use fastq::Record;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// The k-mers of a read on the other chromosome may be hit by a sequencing error
+const MIN_READ_HITS: usize = 2;
+// Fewer reads on chrX and chrY can't tell the sex
+const MIN_SEX_READS: usize = 20;
+// The chrY reads of a female relative to a male, e.g. the reads from homologous regions which
+// are not masked in the k-mers
+const FEMALE_Y_BACKGROUND: f64 = 0.02;
+const MIN_CONFIDENCE: f64 = 0.95;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sex {
+    Male,
+    Female,
+    Undetermined,
+}
+
+impl fmt::Display for Sex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Sex::Male => "male",
+            Sex::Female => "female",
+            Sex::Undetermined => "undetermined",
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+/// The expected sex: male (M) or female (F), case-insensitive.
+impl FromStr for Sex {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s.trim().to_lowercase().as_str() {
+            "male" | "m" => Ok(Sex::Male),
+            "female" | "f" => Ok(Sex::Female),
+            _ => Err(format!("Invalid sex: {}, it should be male or female", s)),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SexChromosome {
+    X,
+    Y,
+}
+
+/// The k-mers unique to chrX or chrY, canonical so both strands are counted.
+#[derive(Default)]
+pub struct SexKmers {
+    kmer_size: usize,
+    kmers: HashMap<u64, SexChromosome>,
+    x_kmers: usize,
+    y_kmers: usize,
+}
+
+impl fmt::Debug for SexKmers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return f
+            .debug_struct("SexKmers")
+            .field("kmer_size", &self.kmer_size)
+            .field("x_kmers", &self.x_kmers)
+            .field("y_kmers", &self.y_kmers)
+            .finish();
+    }
+}
+
+impl SexKmers {
+    /// Read a k-mer table, see `SexKmers::parse` for the format.
+    pub fn read_table(path: &str) -> Result<SexKmers, String> {
+        return match fs::read_to_string(path) {
+            Ok(content) => SexKmers::parse(&content),
+            Err(msg) => Err(format!("Cannot read the sex k-mers {} - {}", path, msg)),
+        };
+    }
+
+    /// Parse the lines of `<kmer>\t<X or Y>`, all k-mers have the same length (up to 31). There
+    /// should be the k-mers of both chromosomes, chrX is the control of the depth.
+    pub fn parse(content: &str) -> Result<SexKmers, String> {
+        let mut sex_kmers = SexKmers::default();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let chromosome = match fields.get(1).map(|c| c.trim_start_matches("chr")) {
+                Some("X") | Some("x") => SexChromosome::X,
+                Some("Y") | Some("y") => SexChromosome::Y,
+                _ => return Err(format!("Invalid sex k-mer at line {}: {}", i + 1, line)),
+            };
+
+            let kmer = fields[0].as_bytes();
+            if sex_kmers.kmer_size == 0 {
+                sex_kmers.kmer_size = kmer.len();
+            }
+            if kmer.len() != sex_kmers.kmer_size || kmer.len() > 31 {
+                return Err(format!(
+                    "The k-mer at line {} should be {}bp (up to 31bp): {}",
+                    i + 1,
+                    sex_kmers.kmer_size.min(31),
+                    line
+                ));
+            }
+
+            let mut encoded = vec![];
+            for_each_canonical_kmer(kmer, kmer.len(), |kmer| encoded.push(kmer));
+            match encoded.first() {
+                Some(kmer) => sex_kmers.kmers.insert(*kmer, chromosome),
+                None => return Err(format!("Invalid bases at line {}: {}", i + 1, line)),
+            };
+        }
+
+        sex_kmers.x_kmers = sex_kmers
+            .kmers
+            .values()
+            .filter(|c| **c == SexChromosome::X)
+            .count();
+        sex_kmers.y_kmers = sex_kmers.kmers.len() - sex_kmers.x_kmers;
+        if sex_kmers.x_kmers == 0 || sex_kmers.y_kmers == 0 {
+            return Err("The sex k-mers should have both chrX and chrY k-mers".to_string());
+        }
+
+        return Ok(sex_kmers);
+    }
+
+    pub fn kmer_size(&self) -> usize {
+        return self.kmer_size;
+    }
+
+    pub fn x_kmers(&self) -> usize {
+        return self.x_kmers;
+    }
+
+    pub fn y_kmers(&self) -> usize {
+        return self.y_kmers;
+    }
+
+    /// The chromosome which the read has more k-mer hits of, None for a tie or fewer than
+    /// `MIN_READ_HITS` hits.
+    fn classify(&self, seq: &[u8]) -> Option<SexChromosome> {
+        let (mut x_hits, mut y_hits) = (0, 0);
+        for_each_canonical_kmer(seq, self.kmer_size, |kmer| match self.kmers.get(&kmer) {
+            Some(SexChromosome::X) => x_hits += 1,
+            Some(SexChromosome::Y) => y_hits += 1,
+            None => {}
+        });
+
+        if x_hits >= MIN_READ_HITS && x_hits > y_hits {
+            return Some(SexChromosome::X);
+        } else if y_hits >= MIN_READ_HITS && y_hits > x_hits {
+            return Some(SexChromosome::Y);
+        } else {
+            return None;
+        }
+    }
+}
+
+/// An expected sex of a sample in the sample sheet, or of all fastq files for None.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ExpectedSex {
+    sample: Option<String>,
+    sex: Sex,
+}
+
+impl ExpectedSex {
+    pub fn new(sample: Option<String>, sex: Sex) -> ExpectedSex {
+        return ExpectedSex {
+            sample: sample,
+            sex: sex,
+        };
+    }
+
+    pub fn sample(&self) -> &Option<String> {
+        return &self.sample;
+    }
+
+    pub fn sex(&self) -> Sex {
+        return self.sex;
+    }
+}
+
+/// Read the expected sex of the samples from the `Sex` (or `Gender`) column of an Illumina sample
+/// sheet, the samples without it are left out.
+pub fn read_expected_sexes(path: &str) -> Result<Vec<ExpectedSex>, String> {
+    let mut expected = vec![];
+    for row in read_sample_sheet_rows(path)? {
+        if let Some(sex) = row.get("sex").or(row.get("gender")) {
+            expected.push(ExpectedSex::new(sample_sheet_sample(&row), sex.parse()?));
+        }
+    }
+
+    return Ok(expected);
+}
+
+/// The expected sex of the fastq file, see `barcode::matches_sample`.
+pub fn select_expected_sex(expected: &Vec<ExpectedSex>, fastq_path: &str) -> Option<Sex> {
+    return expected
+        .iter()
+        .find(|expected| match &expected.sample {
+            Some(sample) => matches_sample(sample, fastq_path),
+            None => true,
+        })
+        .map(|expected| expected.sex);
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SexCheck {
+    total_reads: usize,
+    x_reads: usize,
+    y_reads: usize,
+    // Normalized by the sequencing depth
+    x_reads_per_million: f64,
+    y_reads_per_million: f64,
+    // The chrY reads per k-mer relative to chrX, about 1 for a male and 0 for a female
+    y_x_ratio: Option<f64>,
+    inferred_sex: Sex,
+    // The posterior of the more likely sex
+    confidence: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected_sex: Option<Sex>,
+    #[serde(skip)]
+    kmers: Arc<SexKmers>,
+    status: ModuleStatus,
+}
+
+impl SexCheck {
+    pub fn new(kmers: Arc<SexKmers>, expected_sex: Option<Sex>) -> SexCheck {
+        return SexCheck {
+            total_reads: 0,
+            x_reads: 0,
+            y_reads: 0,
+            x_reads_per_million: 0.0,
+            y_reads_per_million: 0.0,
+            y_x_ratio: None,
+            inferred_sex: Sex::Undetermined,
+            confidence: 0.0,
+            expected_sex: expected_sex,
+            kmers: kmers,
+            status: ModuleStatus::Pass,
+        };
+    }
+
+    pub fn total_reads(&self) -> usize {
+        return self.total_reads;
+    }
+
+    pub fn x_reads(&self) -> usize {
+        return self.x_reads;
+    }
+
+    pub fn y_reads(&self) -> usize {
+        return self.y_reads;
+    }
+
+    pub fn x_reads_per_million(&self) -> f64 {
+        return self.x_reads_per_million;
+    }
+
+    pub fn y_reads_per_million(&self) -> f64 {
+        return self.y_reads_per_million;
+    }
+
+    pub fn y_x_ratio(&self) -> Option<f64> {
+        return self.y_x_ratio;
+    }
+
+    pub fn inferred_sex(&self) -> Sex {
+        return self.inferred_sex;
+    }
+
+    pub fn confidence(&self) -> f64 {
+        return self.confidence;
+    }
+
+    pub fn expected_sex(&self) -> Option<Sex> {
+        return self.expected_sex;
+    }
+}
+
+impl QCModule for SexCheck {
+    fn key(&self) -> &str {
+        return "sex";
+    }
+
+    fn name(&self) -> &str {
+        return "Sex check";
+    }
+
+    fn process_sequence(&mut self, record: &ReadRecord) {
+        self.total_reads += 1;
+        match self.kmers.classify(record.seq()) {
+            Some(SexChromosome::X) => self.x_reads += 1,
+            Some(SexChromosome::Y) => self.y_reads += 1,
+            None => {}
+        }
+    }
+
+    fn merge(&mut self, other: &dyn QCModule) {
+        let other = downcast::<SexCheck>(other);
+        self.total_reads += other.total_reads;
+        self.x_reads += other.x_reads;
+        self.y_reads += other.y_reads;
+    }
+
+    fn finish(&mut self, _phred_offset: usize) {
+        if self.total_reads == 0 {
+            return;
+        }
+
+        let million_reads = self.total_reads as f64 / 1e6;
+        self.x_reads_per_million = self.x_reads as f64 / million_reads;
+        self.y_reads_per_million = self.y_reads as f64 / million_reads;
+
+        let (x_kmers, y_kmers) = (self.kmers.x_kmers as f64, self.kmers.y_kmers as f64);
+        if self.x_reads > 0 {
+            self.y_x_ratio =
+                Some((self.y_reads as f64 / y_kmers) / (self.x_reads as f64 / x_kmers));
+        }
+        if self.x_reads + self.y_reads < MIN_SEX_READS {
+            return;
+        }
+
+        // The expected chrY reads by the chrX reads, a female has two copies of chrX
+        let male_mean = (self.x_reads as f64 * y_kmers / x_kmers).max(1e-3);
+        let female_mean = (male_mean / 2.0 * FEMALE_Y_BACKGROUND).max(1e-3);
+        let log_likelihood = |mean: f64| self.y_reads as f64 * mean.ln() - mean;
+        let male_probability =
+            1.0 / (1.0 + (log_likelihood(female_mean) - log_likelihood(male_mean)).exp());
+
+        self.confidence = male_probability.max(1.0 - male_probability);
+        if male_probability >= MIN_CONFIDENCE {
+            self.inferred_sex = Sex::Male;
+        } else if 1.0 - male_probability >= MIN_CONFIDENCE {
+            self.inferred_sex = Sex::Female;
+        }
+    }
+
+    /// Fail when the inferred sex is not the expected one, and warn when the sex is undetermined.
+    fn calculate_status(&mut self, limits: &Limits) {
+        if self.total_reads == 0 || limits.ignore("sex") {
+            self.status = ModuleStatus::Ignore;
+            return;
+        }
+
+        self.status = match (self.expected_sex, self.inferred_sex) {
+            (_, Sex::Undetermined) => ModuleStatus::Warn,
+            (Some(expected), inferred) if expected != inferred => ModuleStatus::Fail,
+            _ => ModuleStatus::Pass,
+        };
+    }
+
+    fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
+
+#[cfg(test)]
+mod sex_tests {
+    use super::*;
+    use crate::qc::util::{mix64, reverse_complement};
+    use fastq::OwnedRecord;
+
+    // A pseudo-random chromosome
+    fn chromosome(seed: u64, length: usize) -> Vec<u8> {
+        return (0..length as u64)
+            .map(|i| b"ACGT"[(mix64(seed ^ i) % 4) as usize])
+            .collect();
+    }
+
+    fn sex_kmers(x: &[u8], y: &[u8]) -> SexKmers {
+        let mut table = String::from("# kmer\tchromosome\n");
+        for (seq, name) in &[(x, "X"), (y, "chrY")] {
+            for i in (0..seq.len() - 25).step_by(3) {
+                let kmer = String::from_utf8(seq[i..i + 25].to_vec()).unwrap();
+                table.push_str(&format!("{}\t{}\n", kmer, name));
+            }
+        }
+        return SexKmers::parse(&table).unwrap();
+    }
+
+    fn check(kmers: &Arc<SexKmers>, reads: &Vec<Vec<u8>>, expected: Option<Sex>) -> SexCheck {
+        let mut module = SexCheck::new(kmers.clone(), expected);
+        for (i, seq) in reads.iter().enumerate() {
+            let record = OwnedRecord {
+                head: format!("read{}", i).into_bytes(),
+                seq: seq.clone(),
+                qual: vec![b'I'; seq.len()],
+                sep: None,
+            };
+            module.process_sequence(&ReadRecord::new(&record));
+        }
+        module.finish(33);
+        module.calculate_status(&Limits::new(&"".to_string()));
+        return module;
+    }
+
+    #[test]
+    fn test_sex_check() {
+        let (x, y, autosome) = (
+            chromosome(1, 2000),
+            chromosome(2, 1000),
+            chromosome(3, 5000),
+        );
+        let kmers = Arc::new(sex_kmers(&x, &y));
+        assert_eq!((kmers.x_kmers(), kmers.y_kmers()), (659, 325));
+
+        // One read per 100bp of each copy, the reads of chrY are reverse complemented
+        let read = |seq: &[u8], i: usize| seq[i..i + 100].to_vec();
+        let autosome_reads: Vec<Vec<u8>> = (0..49).map(|i| read(&autosome, i * 100)).collect();
+        let x_reads: Vec<Vec<u8>> = (0..19).map(|i| read(&x, i * 100)).collect();
+        let y_reads: Vec<Vec<u8>> = (0..9)
+            .map(|i| reverse_complement(&read(&y, i * 100)))
+            .collect();
+
+        let male = [&autosome_reads[..], &x_reads[..], &y_reads[..]].concat();
+        let module = check(&kmers, &male, Some(Sex::Male));
+        assert_eq!((module.x_reads(), module.y_reads()), (19, 9));
+        assert!((module.y_x_ratio().unwrap() - 1.0).abs() < 0.1);
+        assert_eq!(module.inferred_sex(), Sex::Male);
+        assert!(module.confidence() > 0.99);
+        assert_eq!(module.status(), ModuleStatus::Pass);
+
+        let female = [&autosome_reads[..], &x_reads[..], &x_reads[..]].concat();
+        let module = check(&kmers, &female, Some(Sex::Male));
+        assert_eq!(module.inferred_sex(), Sex::Female);
+        assert_eq!(module.status(), ModuleStatus::Fail);
+
+        let module = check(&kmers, &autosome_reads, None);
+        assert_eq!(module.inferred_sex(), Sex::Undetermined);
+        assert_eq!(module.status(), ModuleStatus::Warn);
+
+        assert_eq!("F".parse::<Sex>(), Ok(Sex::Female));
+        assert!("unknown".parse::<Sex>().is_err());
+        assert!(SexKmers::parse("ACGTACGT\tX\n").is_err());
+        let expected = vec![
+            ExpectedSex::new(Some("S1".to_string()), Sex::Female),
+            ExpectedSex::new(Some("S2".to_string()), Sex::Male),
+        ];
+        assert_eq!(
+            select_expected_sex(&expected, "/data/S2_S2_L001_R1_001.fastq.gz"),
+            Some(Sex::Male)
+        );
+        assert_eq!(select_expected_sex(&expected, "S3.fq"), None);
+    }
+}
@@ -0,0 +1,736 @@
+//! Classify reads by a minimizer database of taxa, like Kraken.
+//!
+//! The database (see `build-db`) maps the minimizers of local FASTA files to their taxa, and a
+//! minimizer of several taxa is mapped to their lowest common ancestor (LCA). A sampled read is
+//! classified to the taxon whose lineage has the most hits of the read's minimizers, ties are
+//! resolved by the LCA. The classified reads are summed up at each rank (phylum, ..., species).
+//!
+//! The reads are sampled by their hashes, so the results are the same for any number of threads.
+use super::fastqc::{Limits, ModuleStatus};
+use super::module::{downcast, QCModule, ReadRecord};
+use super::util::{for_each_fasta_record, for_each_minimizer, is_sampled};
+use fastq::Record;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::str::from_utf8;
+use std::sync::Arc;
+
+const KMER_SIZE: usize = 31;
+const WINDOW_SIZE: usize = 15;
+// The same as --minimum-hit-groups of Kraken 2
+const MIN_HITS: usize = 2;
+// A lineage is never deeper, it stops a loop in a broken taxonomy
+const MAX_DEPTH: usize = 256;
+const DB_MAGIC: &[u8; 8] = b"PQCTAXDB";
+const DB_VERSION: u32 = 1;
+// One out of 10 reads is classified by default
+pub const DEFAULT_SAMPLE_INTERVAL: usize = 10;
+// The ranks which are reported when they are in the taxonomy
+pub const RANKS: [&str; 9] = [
+    "domain",
+    "superkingdom",
+    "kingdom",
+    "phylum",
+    "class",
+    "order",
+    "family",
+    "genus",
+    "species",
+];
+
+#[derive(Debug, Clone)]
+struct Taxon {
+    // None for the root
+    parent: Option<u32>,
+    rank: String,
+    name: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Taxonomy {
+    taxa: HashMap<u32, Taxon>,
+}
+
+impl Taxonomy {
+    /// Read a taxonomy table, see `Taxonomy::parse` for the format.
+    pub fn read_table(path: &str) -> Result<Taxonomy, String> {
+        return match fs::read_to_string(path) {
+            Ok(content) => Taxonomy::parse(&content),
+            Err(msg) => Err(format!("Cannot read the taxonomy {} - {}", path, msg)),
+        };
+    }
+
+    /// Parse the lines of `<taxid>\t<parent taxid>\t<rank>\t<name>`, e.g. converted from
+    /// nodes.dmp and names.dmp of NCBI. The parent of the root is itself or 0, and there should
+    /// be only one root.
+    pub fn parse(content: &str) -> Result<Taxonomy, String> {
+        let mut taxa = HashMap::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').map(|field| field.trim()).collect();
+            let ids = (fields.get(0), fields.get(1), fields.get(2), fields.get(3));
+            let (taxid, parent, rank, name) = match ids {
+                (Some(taxid), Some(parent), Some(rank), Some(name)) => {
+                    match (taxid.parse::<u32>(), parent.parse::<u32>()) {
+                        (Ok(taxid), Ok(parent)) => (taxid, parent, rank, name),
+                        _ => return Err(format!("Invalid taxid at line {}: {}", i + 1, line)),
+                    }
+                }
+                _ => return Err(format!("Invalid taxonomy at line {}: {}", i + 1, line)),
+            };
+
+            let taxon = Taxon {
+                parent: if parent == 0 || parent == taxid {
+                    None
+                } else {
+                    Some(parent)
+                },
+                rank: rank.to_string(),
+                name: name.to_string(),
+            };
+            taxa.insert(taxid, taxon);
+        }
+
+        let mut roots = 0;
+        for (taxid, taxon) in &taxa {
+            match taxon.parent {
+                Some(parent) if !taxa.contains_key(&parent) => {
+                    return Err(format!("Not found the parent {} of {}", parent, taxid));
+                }
+                Some(_) => {}
+                None => roots += 1,
+            }
+        }
+        if roots != 1 {
+            return Err(format!("The taxonomy should have one root, not {}", roots));
+        }
+
+        return Ok(Taxonomy { taxa: taxa });
+    }
+
+    fn to_table(&self) -> String {
+        let mut taxids: Vec<&u32> = self.taxa.keys().collect();
+        taxids.sort();
+        return taxids
+            .iter()
+            .map(|taxid| {
+                let taxon = &self.taxa[taxid];
+                format!(
+                    "{}\t{}\t{}\t{}\n",
+                    taxid,
+                    taxon.parent.unwrap_or(0),
+                    taxon.rank,
+                    taxon.name
+                )
+            })
+            .collect();
+    }
+
+    pub fn contains(&self, taxid: u32) -> bool {
+        return self.taxa.contains_key(&taxid);
+    }
+
+    pub fn name(&self, taxid: u32) -> Option<&String> {
+        return self.taxa.get(&taxid).map(|taxon| &taxon.name);
+    }
+
+    pub fn rank(&self, taxid: u32) -> Option<&String> {
+        return self.taxa.get(&taxid).map(|taxon| &taxon.rank);
+    }
+
+    /// The taxon and its ancestors up to the root.
+    pub fn lineage(&self, taxid: u32) -> Vec<u32> {
+        let mut lineage = vec![];
+        let mut current = Some(taxid);
+        while let Some(taxid) = current {
+            match self.taxa.get(&taxid) {
+                Some(taxon) if lineage.len() < MAX_DEPTH => {
+                    lineage.push(taxid);
+                    current = taxon.parent;
+                }
+                _ => break,
+            }
+        }
+
+        return lineage;
+    }
+
+    /// The lowest common ancestor, it's the root for the taxa which are not in the taxonomy.
+    pub fn lca(&self, a: u32, b: u32) -> u32 {
+        let ancestors: HashSet<u32> = self.lineage(b).into_iter().collect();
+        let lineage = self.lineage(a);
+        return match lineage.iter().find(|taxid| ancestors.contains(taxid)) {
+            Some(taxid) => *taxid,
+            None => *self.lineage(b).last().or(lineage.last()).unwrap_or(&a),
+        };
+    }
+
+    /// The ancestor of the taxon (or itself) at the rank.
+    pub fn ancestor_at(&self, taxid: u32, rank: &str) -> Option<u32> {
+        return self
+            .lineage(taxid)
+            .into_iter()
+            .find(|taxid| self.taxa[taxid].rank == rank);
+    }
+
+    /// The ranks of `RANKS` which are in the taxonomy.
+    pub fn ranks(&self) -> Vec<String> {
+        let ranks: HashSet<&str> = self.taxa.values().map(|t| t.rank.as_str()).collect();
+        return RANKS
+            .iter()
+            .filter(|rank| ranks.contains(*rank))
+            .map(|rank| rank.to_string())
+            .collect();
+    }
+}
+
+/// The taxid in a FASTA header like Kraken, e.g. `>NC_000913.3|kraken:taxid|511145`.
+pub fn header_taxid(header: &[u8]) -> Option<u32> {
+    let header = from_utf8(header).ok()?;
+    let start = header.find("kraken:taxid|")? + "kraken:taxid|".len();
+    let digits: String = header[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    return digits.parse::<u32>().ok();
+}
+
+/// The minimizers of the taxa, it's shared by the threads.
+#[derive(Default)]
+pub struct TaxonomyDatabase {
+    taxonomy: Taxonomy,
+    kmer_size: usize,
+    window_size: usize,
+    minimizers: HashMap<u64, u32>,
+}
+
+impl fmt::Debug for TaxonomyDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return f
+            .debug_struct("TaxonomyDatabase")
+            .field("taxa", &self.taxonomy.taxa.len())
+            .field("kmer_size", &self.kmer_size)
+            .field("window_size", &self.window_size)
+            .field("minimizers", &self.minimizers.len())
+            .finish();
+    }
+}
+
+impl TaxonomyDatabase {
+    pub fn new(taxonomy: Taxonomy) -> TaxonomyDatabase {
+        return TaxonomyDatabase {
+            taxonomy: taxonomy,
+            kmer_size: KMER_SIZE,
+            window_size: WINDOW_SIZE,
+            minimizers: HashMap::new(),
+        };
+    }
+
+    pub fn taxonomy(&self) -> &Taxonomy {
+        return &self.taxonomy;
+    }
+
+    pub fn minimizers(&self) -> usize {
+        return self.minimizers.len();
+    }
+
+    /// Map the minimizers of the sequence to the taxon, the taxon should be in the taxonomy.
+    pub fn add_sequence(&mut self, taxid: u32, seq: &[u8]) {
+        if !self.taxonomy.contains(taxid) {
+            panic!("Not found the taxid {} in the taxonomy", taxid);
+        }
+
+        let (taxonomy, minimizers) = (&self.taxonomy, &mut self.minimizers);
+        for_each_minimizer(seq, self.kmer_size, self.window_size, |minimizer| {
+            minimizers
+                .entry(minimizer)
+                .and_modify(|current| *current = taxonomy.lca(*current, taxid))
+                .or_insert(taxid);
+        });
+    }
+
+    /// Add the records of a FASTA file to the taxon, the taxid in the header takes precedence
+    /// (see `header_taxid`). It returns the number of records.
+    pub fn add_fasta(&mut self, taxid: Option<u32>, path: &str) -> Result<usize, String> {
+        let mut records = 0;
+        let mut error = None;
+        let result = for_each_fasta_record(path, |header, seq| {
+            if error.is_some() {
+                return;
+            }
+
+            match header_taxid(header).or(taxid) {
+                Some(taxid) if self.taxonomy.contains(taxid) => {
+                    self.add_sequence(taxid, seq);
+                    records += 1;
+                }
+                Some(taxid) => error = Some(format!("Not found the taxid {} in the taxonomy", taxid)),
+                None => {
+                    error = Some(format!(
+                        "No taxid for {}, give it by <taxid>=<path> or kraken:taxid|<taxid> in the header",
+                        String::from_utf8_lossy(header)
+                    ))
+                }
+            }
+        });
+
+        if let Err(msg) = result {
+            return Err(format!("Cannot read {} - {}", path, msg));
+        }
+        return match error {
+            Some(msg) => Err(format!("{} ({})", msg, path)),
+            None => Ok(records),
+        };
+    }
+
+    /// Write the database in a binary format: the magic, the version, k, w, the taxonomy table,
+    /// then the minimizers and their taxids, all integers are little-endian.
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let table = self.taxonomy.to_table();
+        writer.write_all(DB_MAGIC)?;
+        writer.write_all(&DB_VERSION.to_le_bytes())?;
+        writer.write_all(&(self.kmer_size as u32).to_le_bytes())?;
+        writer.write_all(&(self.window_size as u32).to_le_bytes())?;
+        writer.write_all(&(table.len() as u64).to_le_bytes())?;
+        writer.write_all(table.as_bytes())?;
+        writer.write_all(&(self.minimizers.len() as u64).to_le_bytes())?;
+        for (minimizer, taxid) in &self.minimizers {
+            writer.write_all(&minimizer.to_le_bytes())?;
+            writer.write_all(&taxid.to_le_bytes())?;
+        }
+
+        return writer.flush();
+    }
+
+    pub fn read(path: &str) -> Result<TaxonomyDatabase, String> {
+        let error = |msg: String| format!("Cannot read the taxonomy database {} - {}", path, msg);
+        let file = File::open(path).map_err(|msg| error(msg.to_string()))?;
+        let file_size = file.metadata().map_err(|msg| error(msg.to_string()))?.len() as usize;
+        let mut reader = BufReader::new(file);
+        let mut read_bytes = |n: usize| -> Result<Vec<u8>, String> {
+            let mut buffer = vec![0; n];
+            reader
+                .read_exact(&mut buffer)
+                .map_err(|msg| error(msg.to_string()))?;
+            return Ok(buffer);
+        };
+        let to_u32 = |bytes: Vec<u8>| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let to_u64 = |bytes: Vec<u8>| {
+            let mut array = [0; 8];
+            array.copy_from_slice(&bytes);
+            u64::from_le_bytes(array)
+        };
+
+        if read_bytes(8)? != DB_MAGIC || to_u32(read_bytes(4)?) != DB_VERSION {
+            return Err(error("Not a database of this version".to_string()));
+        }
+        // The k-mers are packed into an u64, and the sizes are checked before allocating
+        let kmer_size = to_u32(read_bytes(4)?) as usize;
+        let window_size = to_u32(read_bytes(4)?) as usize;
+        if kmer_size < 1 || kmer_size > 31 || window_size < 1 {
+            return Err(error(format!(
+                "Invalid k-mer size {} or window size {}",
+                kmer_size, window_size
+            )));
+        }
+        let table_length = to_u64(read_bytes(8)?) as usize;
+        if table_length > file_size {
+            return Err(error(format!(
+                "Invalid length of the taxonomy table: {}",
+                table_length
+            )));
+        }
+        let table =
+            String::from_utf8(read_bytes(table_length)?).map_err(|msg| error(msg.to_string()))?;
+        let taxonomy = Taxonomy::parse(&table).map_err(error)?;
+
+        let n = to_u64(read_bytes(8)?) as usize;
+        if n > file_size / 12 {
+            return Err(error(format!("Invalid number of minimizers: {}", n)));
+        }
+        let mut minimizers = HashMap::with_capacity(n);
+        for _ in 0..n {
+            let minimizer = to_u64(read_bytes(8)?);
+            minimizers.insert(minimizer, to_u32(read_bytes(4)?));
+        }
+
+        return Ok(TaxonomyDatabase {
+            taxonomy: taxonomy,
+            kmer_size: kmer_size,
+            window_size: window_size,
+            minimizers: minimizers,
+        });
+    }
+
+    /// The taxon of a read, None when it has fewer than `MIN_HITS` hits.
+    pub fn classify(&self, seq: &[u8]) -> Option<u32> {
+        let mut hits: HashMap<u32, usize> = HashMap::new();
+        for_each_minimizer(seq, self.kmer_size, self.window_size, |minimizer| {
+            if let Some(taxid) = self.minimizers.get(&minimizer) {
+                *hits.entry(*taxid).or_insert(0) += 1;
+            }
+        });
+        if hits.values().sum::<usize>() < MIN_HITS {
+            return None;
+        }
+
+        // The score of a taxon is the hits on its lineage
+        let mut best: Option<(u32, usize)> = None;
+        for taxid in hits.keys() {
+            let score: usize = self
+                .taxonomy
+                .lineage(*taxid)
+                .iter()
+                .map(|ancestor| hits.get(ancestor).unwrap_or(&0))
+                .sum();
+            best = match best {
+                Some((best_taxid, best_score)) if best_score > score => {
+                    Some((best_taxid, best_score))
+                }
+                Some((best_taxid, best_score)) if best_score == score => {
+                    Some((self.taxonomy.lca(best_taxid, *taxid), score))
+                }
+                _ => Some((*taxid, score)),
+            };
+        }
+
+        return best.map(|(taxid, _)| taxid);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TaxonFraction {
+    taxid: u32,
+    name: String,
+    reads: usize,
+    // Of the sampled reads
+    percentage: f64,
+}
+
+impl TaxonFraction {
+    pub fn taxid(&self) -> u32 {
+        return self.taxid;
+    }
+
+    pub fn name(&self) -> &String {
+        return &self.name;
+    }
+
+    pub fn reads(&self) -> usize {
+        return self.reads;
+    }
+
+    pub fn percentage(&self) -> f64 {
+        return self.percentage;
+    }
+}
+
+/// The classified reads at a rank, from the most reads.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RankProfile {
+    rank: String,
+    taxa: Vec<TaxonFraction>,
+    // The classified reads without a taxon at the rank, e.g. the reads only classified to the
+    // genus at the species rank
+    unresolved_reads: usize,
+    percent_unresolved: f64,
+}
+
+impl RankProfile {
+    pub fn rank(&self) -> &String {
+        return &self.rank;
+    }
+
+    pub fn taxa(&self) -> &Vec<TaxonFraction> {
+        return &self.taxa;
+    }
+
+    pub fn unresolved_reads(&self) -> usize {
+        return self.unresolved_reads;
+    }
+
+    pub fn percent_unresolved(&self) -> f64 {
+        return self.percent_unresolved;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TaxonomicProfile {
+    // One out of `sample_interval` reads is classified
+    sample_interval: usize,
+    total_reads: usize,
+    sampled_reads: usize,
+    classified_reads: usize,
+    percent_classified: f64,
+    ranks: Vec<RankProfile>,
+    // The sampled reads by the taxon which they are classified to
+    #[serde(skip)]
+    taxon_reads: HashMap<u32, usize>,
+    #[serde(skip)]
+    database: Arc<TaxonomyDatabase>,
+    status: ModuleStatus,
+}
+
+impl TaxonomicProfile {
+    pub fn new(database: Arc<TaxonomyDatabase>, sample_interval: usize) -> TaxonomicProfile {
+        return TaxonomicProfile {
+            sample_interval: sample_interval.max(1),
+            total_reads: 0,
+            sampled_reads: 0,
+            classified_reads: 0,
+            percent_classified: 0.0,
+            ranks: vec![],
+            taxon_reads: HashMap::new(),
+            database: database,
+            status: ModuleStatus::Pass,
+        };
+    }
+
+    pub fn sample_interval(&self) -> usize {
+        return self.sample_interval;
+    }
+
+    pub fn total_reads(&self) -> usize {
+        return self.total_reads;
+    }
+
+    pub fn sampled_reads(&self) -> usize {
+        return self.sampled_reads;
+    }
+
+    pub fn classified_reads(&self) -> usize {
+        return self.classified_reads;
+    }
+
+    pub fn percent_classified(&self) -> f64 {
+        return self.percent_classified;
+    }
+
+    pub fn ranks(&self) -> &Vec<RankProfile> {
+        return &self.ranks;
+    }
+}
+
+impl QCModule for TaxonomicProfile {
+    fn key(&self) -> &str {
+        return "taxonomy";
+    }
+
+    fn name(&self) -> &str {
+        return "Taxonomic profile";
+    }
+
+    fn process_sequence(&mut self, record: &ReadRecord) {
+        self.total_reads += 1;
+        if !is_sampled(record.head(), record.seq(), self.sample_interval) {
+            return;
+        }
+
+        self.sampled_reads += 1;
+        if let Some(taxid) = self.database.classify(record.seq()) {
+            self.classified_reads += 1;
+            *self.taxon_reads.entry(taxid).or_insert(0) += 1;
+        }
+    }
+
+    fn merge(&mut self, other: &dyn QCModule) {
+        let other = downcast::<TaxonomicProfile>(other);
+        if self.sample_interval != other.sample_interval {
+            panic!("Cannot merge the taxonomic profiles of different sample intervals");
+        }
+
+        self.total_reads += other.total_reads;
+        self.sampled_reads += other.sampled_reads;
+        self.classified_reads += other.classified_reads;
+        for (taxid, reads) in &other.taxon_reads {
+            *self.taxon_reads.entry(*taxid).or_insert(0) += reads;
+        }
+    }
+
+    fn finish(&mut self, _phred_offset: usize) {
+        if self.sampled_reads == 0 {
+            return;
+        }
+
+        let sampled_reads = self.sampled_reads as f64;
+        self.percent_classified = self.classified_reads as f64 / sampled_reads * 100.0;
+
+        let taxonomy = self.database.taxonomy();
+        self.ranks = taxonomy
+            .ranks()
+            .iter()
+            .map(|rank| {
+                let mut reads: HashMap<u32, usize> = HashMap::new();
+                let mut unresolved_reads = 0;
+                for (taxid, count) in &self.taxon_reads {
+                    match taxonomy.ancestor_at(*taxid, rank) {
+                        Some(ancestor) => *reads.entry(ancestor).or_insert(0) += count,
+                        None => unresolved_reads += count,
+                    }
+                }
+
+                let mut taxa: Vec<TaxonFraction> = reads
+                    .into_iter()
+                    .map(|(taxid, reads)| TaxonFraction {
+                        taxid: taxid,
+                        name: taxonomy.name(taxid).cloned().unwrap_or_default(),
+                        reads: reads,
+                        percentage: reads as f64 / sampled_reads * 100.0,
+                    })
+                    .collect();
+                taxa.sort_by(|a, b| b.reads.cmp(&a.reads).then(a.taxid.cmp(&b.taxid)));
+
+                RankProfile {
+                    rank: rank.clone(),
+                    taxa: taxa,
+                    unresolved_reads: unresolved_reads,
+                    percent_unresolved: unresolved_reads as f64 / sampled_reads * 100.0,
+                }
+            })
+            .collect();
+    }
+
+    /// The profile is informational, the expected taxa depend on the sample.
+    fn calculate_status(&mut self, limits: &Limits) {
+        if self.sampled_reads == 0 || limits.ignore("taxonomy") {
+            self.status = ModuleStatus::Ignore;
+            return;
+        }
+
+        self.status = ModuleStatus::Pass;
+    }
+
+    fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
+
+#[cfg(test)]
+mod taxonomy_tests {
+    use super::*;
+    use crate::qc::util::{mix64, reverse_complement};
+    use fastq::OwnedRecord;
+
+    const TAXONOMY: &str = "1\t1\tno rank\troot\n\
+                            2\t1\tsuperkingdom\tBacteria\n\
+                            561\t2\tgenus\tEscherichia\n\
+                            562\t561\tspecies\tEscherichia coli\n\
+                            1279\t2\tgenus\tStaphylococcus\n\
+                            1280\t1279\tspecies\tStaphylococcus aureus\n";
+
+    // A pseudo-random genome
+    fn genome(seed: u64, length: usize) -> Vec<u8> {
+        return (0..length as u64)
+            .map(|i| b"ACGT"[(mix64(seed ^ i) % 4) as usize])
+            .collect();
+    }
+
+    fn database() -> TaxonomyDatabase {
+        let shared = genome(3, 1000);
+        let mut database = TaxonomyDatabase::new(Taxonomy::parse(TAXONOMY).unwrap());
+        database.add_sequence(562, &genome(1, 5000));
+        database.add_sequence(562, &shared);
+        database.add_sequence(1280, &genome(2, 5000));
+        database.add_sequence(1280, &shared);
+        return database;
+    }
+
+    #[test]
+    fn test_taxonomy() {
+        let taxonomy = Taxonomy::parse(TAXONOMY).unwrap();
+        assert_eq!(taxonomy.lineage(562), vec![562, 561, 2, 1]);
+        assert_eq!(taxonomy.lca(562, 1280), 2);
+        assert_eq!(taxonomy.lca(562, 561), 561);
+        assert_eq!(taxonomy.ancestor_at(562, "genus"), Some(561));
+        assert_eq!(taxonomy.ancestor_at(561, "species"), None);
+        assert_eq!(taxonomy.ranks(), vec!["superkingdom", "genus", "species"]);
+
+        assert!(Taxonomy::parse("1\t1\tno rank\troot\n3\t4\tgenus\tX\n").is_err());
+        assert!(Taxonomy::parse("1\t0\tno rank\troot\n2\t0\tno rank\troot\n").is_err());
+        assert_eq!(
+            header_taxid(b"NC_000913.3|kraken:taxid|511145 E. coli"),
+            Some(511145)
+        );
+        assert_eq!(header_taxid(b"NC_000913.3 E. coli"), None);
+    }
+
+    #[test]
+    fn test_classify() {
+        let database = database();
+        let (coli, aureus, shared) = (genome(1, 5000), genome(2, 5000), genome(3, 1000));
+        assert_eq!(database.classify(&coli[100..250]), Some(562));
+        assert_eq!(
+            database.classify(&reverse_complement(&aureus[100..250])),
+            Some(1280)
+        );
+        assert_eq!(database.classify(&shared[100..250]), Some(2));
+        assert_eq!(database.classify(&genome(4, 150)), None);
+
+        let path = std::env::temp_dir().join("preqc_taxonomy_tests.db");
+        let path = path.to_str().unwrap();
+        database.write(path).unwrap();
+        let copy = TaxonomyDatabase::read(path).unwrap();
+        assert_eq!(copy.minimizers, database.minimizers);
+        assert_eq!(copy.classify(&coli[100..250]), Some(562));
+        assert!(TaxonomyDatabase::read("/nonexistent/taxonomy.db").is_err());
+
+        // A corrupted k, w or table length is an error rather than a panic or a huge allocation
+        let bytes = std::fs::read(path).unwrap();
+        for (offset, value) in &[(12, 0u32), (12, 32), (16, 0), (24, u32::MAX)] {
+            let mut corrupted = bytes.clone();
+            corrupted[*offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            std::fs::write(path, &corrupted).unwrap();
+            assert!(TaxonomyDatabase::read(path).is_err());
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_taxonomic_profile() {
+        let database = Arc::new(database());
+        let (coli, aureus, shared) = (genome(1, 5000), genome(2, 5000), genome(3, 1000));
+        let mut module = TaxonomicProfile::new(database.clone(), 1);
+        let mut other = TaxonomicProfile::new(database, 1);
+        for (i, seq) in [&coli[100..250], &coli[300..450], &shared[100..250]]
+            .iter()
+            .enumerate()
+        {
+            let module = if i % 2 == 0 { &mut module } else { &mut other };
+            let record = OwnedRecord {
+                head: b"read".to_vec(),
+                seq: seq.to_vec(),
+                qual: vec![b'I'; seq.len()],
+                sep: None,
+            };
+            module.process_sequence(&ReadRecord::new(&record));
+        }
+        let record = OwnedRecord {
+            head: b"read".to_vec(),
+            seq: [&aureus[100..250], &genome(4, 100)[..]].concat(),
+            qual: vec![b'I'; 250],
+            sep: None,
+        };
+        other.process_sequence(&ReadRecord::new(&record));
+        module.merge(&other);
+        module.finish(33);
+
+        assert_eq!(module.sampled_reads(), 4);
+        assert_eq!(module.classified_reads(), 4);
+        let ranks = module.ranks();
+        assert_eq!(ranks.len(), 3);
+        assert_eq!(ranks[0].taxa()[0].reads(), 4);
+        assert_eq!(ranks[2].rank(), "species");
+        assert_eq!(ranks[2].taxa()[0].name(), "Escherichia coli");
+        assert_eq!(ranks[2].taxa()[0].percentage(), 50.0);
+        assert_eq!(ranks[2].taxa()[1].taxid(), 1280);
+        assert_eq!(ranks[2].unresolved_reads(), 1);
+    }
+}
@@ -0,0 +1,473 @@
+//! Predict how much data survives a trimmer, without writing any reads.
+//!
+//! Each read goes through the usual steps of a trimmer: the Ns at both ends are trimmed, the read
+//! is clipped at the first adapter (the adapter list of the adapter module), then the 3' end is
+//! cut at the first window of low quality, the same as `SLIDINGWINDOW` of Trimmomatic. The reads
+//! shorter than the minimum length are dropped.
+//!
+//! The phred encoding is only known after all reads, so the quality step is simulated for both
+//! encodings and the right one is picked when finishing.
+use super::fastqc::{Adapter, Limits, ModuleStatus};
+use super::module::{downcast, QCModule, ReadRecord};
+use fastq::Record;
+use serde::{Deserialize, Serialize};
+
+// Sanger / Illumina 1.9 and Illumina 1.3
+const PHRED_OFFSETS: [usize; 2] = [33, 64];
+
+/// The parameters of the simulated trimmer, the defaults are the ones of the Trimmomatic manual.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TrimParams {
+    window_size: usize,
+    // 0 disables the quality trimming
+    window_quality: usize,
+    trim_n: bool,
+    min_length: usize,
+}
+
+impl Default for TrimParams {
+    fn default() -> TrimParams {
+        return TrimParams::new(4, 20, true, 36);
+    }
+}
+
+impl TrimParams {
+    pub fn new(
+        window_size: usize,
+        window_quality: usize,
+        trim_n: bool,
+        min_length: usize,
+    ) -> TrimParams {
+        if window_size == 0 {
+            panic!("The window size of quality trimming must be greater than 0");
+        }
+
+        return TrimParams {
+            window_size: window_size,
+            window_quality: window_quality,
+            trim_n: trim_n,
+            min_length: min_length,
+        };
+    }
+}
+
+/// The number of bases which are kept by the sliding window, the read is cut at the first window
+/// whose mean quality is lower than `quality`, and the good bases at the start of that window are
+/// kept.
+fn sliding_window_length(qual: &[u8], offset: usize, window_size: usize, quality: usize) -> usize {
+    if quality == 0 || qual.len() == 0 {
+        return qual.len();
+    }
+
+    let phred = |c: u8| (c as usize).saturating_sub(offset);
+    let window_size = window_size.min(qual.len());
+    let required = quality * window_size;
+    let mut total: usize = qual[..window_size].iter().map(|c| phred(*c)).sum();
+    for start in 0..(qual.len() - window_size + 1) {
+        if start > 0 {
+            total = total + phred(qual[start + window_size - 1]) - phred(qual[start - 1]);
+        }
+
+        if total < required {
+            return start
+                + qual[start..start + window_size]
+                    .iter()
+                    .take_while(|c| phred(**c) >= quality)
+                    .count();
+        }
+    }
+
+    return qual.len();
+}
+
+// The results of the quality step and after, for one phred encoding
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct TrimCounts {
+    quality_trimmed_reads: usize,
+    quality_trimmed_bases: usize,
+    // The number of reads by the length after trimming, from 0
+    lengths: Vec<usize>,
+}
+
+impl TrimCounts {
+    fn add_length(&mut self, length: usize, count: usize) {
+        if self.lengths.len() <= length {
+            self.lengths.resize(length + 1, 0);
+        }
+        self.lengths[length] += count;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrimSimulation {
+    window_size: usize,
+    window_quality: usize,
+    trim_n: bool,
+    min_length: usize,
+    // The same adapter matching as the adapter module
+    max_mismatches: usize,
+    min_overlap: usize,
+    total_reads: usize,
+    total_bases: usize,
+    n_trimmed_reads: usize,
+    n_trimmed_bases: usize,
+    adapter_trimmed_reads: usize,
+    adapter_trimmed_bases: usize,
+    quality_trimmed_reads: usize,
+    quality_trimmed_bases: usize,
+    // The reads shorter than `min_length` after trimming
+    dropped_reads: usize,
+    percent_dropped_reads: f64,
+    surviving_reads: usize,
+    surviving_bases: usize,
+    percent_surviving_reads: f64,
+    percent_surviving_bases: f64,
+    // The mean length of the surviving reads
+    mean_length: f64,
+    // The number of reads (dropped or not) by the length after trimming, from 0
+    length_distribution: Vec<usize>,
+    #[serde(skip_serializing, default)]
+    adapters: Vec<Adapter>,
+    // Indexed the same as `PHRED_OFFSETS`
+    #[serde(skip_serializing, default)]
+    counts: Vec<TrimCounts>,
+    status: ModuleStatus,
+}
+
+impl TrimSimulation {
+    pub fn new(
+        adapters_content: &String,
+        max_mismatches: usize,
+        min_overlap: usize,
+        params: &TrimParams,
+    ) -> TrimSimulation {
+        return TrimSimulation {
+            window_size: params.window_size,
+            window_quality: params.window_quality,
+            trim_n: params.trim_n,
+            min_length: params.min_length,
+            max_mismatches: max_mismatches,
+            min_overlap: min_overlap,
+            total_reads: 0,
+            total_bases: 0,
+            n_trimmed_reads: 0,
+            n_trimmed_bases: 0,
+            adapter_trimmed_reads: 0,
+            adapter_trimmed_bases: 0,
+            quality_trimmed_reads: 0,
+            quality_trimmed_bases: 0,
+            dropped_reads: 0,
+            percent_dropped_reads: 0.0,
+            surviving_reads: 0,
+            surviving_bases: 0,
+            percent_surviving_reads: 0.0,
+            percent_surviving_bases: 0.0,
+            mean_length: 0.0,
+            length_distribution: vec![],
+            adapters: Adapter::parse_adapters(adapters_content),
+            counts: vec![TrimCounts::default(); PHRED_OFFSETS.len()],
+            status: ModuleStatus::Pass,
+        };
+    }
+
+    /// Clip at an adapter besides the known adapters, e.g. the adapter detected before the qc.
+    /// It must be called before any sequence is processed.
+    pub fn add_adapter(&mut self, name: &str, sequence: &str) {
+        self.adapters
+            .push(Adapter::new(name.to_string(), sequence.to_string()));
+    }
+
+    fn params(&self) -> TrimParams {
+        return TrimParams::new(
+            self.window_size,
+            self.window_quality,
+            self.trim_n,
+            self.min_length,
+        );
+    }
+
+    pub fn min_length(&self) -> usize {
+        return self.min_length;
+    }
+
+    pub fn total_reads(&self) -> usize {
+        return self.total_reads;
+    }
+
+    pub fn total_bases(&self) -> usize {
+        return self.total_bases;
+    }
+
+    pub fn n_trimmed_reads(&self) -> usize {
+        return self.n_trimmed_reads;
+    }
+
+    pub fn n_trimmed_bases(&self) -> usize {
+        return self.n_trimmed_bases;
+    }
+
+    pub fn adapter_trimmed_reads(&self) -> usize {
+        return self.adapter_trimmed_reads;
+    }
+
+    pub fn adapter_trimmed_bases(&self) -> usize {
+        return self.adapter_trimmed_bases;
+    }
+
+    pub fn quality_trimmed_reads(&self) -> usize {
+        return self.quality_trimmed_reads;
+    }
+
+    pub fn quality_trimmed_bases(&self) -> usize {
+        return self.quality_trimmed_bases;
+    }
+
+    pub fn dropped_reads(&self) -> usize {
+        return self.dropped_reads;
+    }
+
+    pub fn percent_dropped_reads(&self) -> f64 {
+        return self.percent_dropped_reads;
+    }
+
+    pub fn surviving_reads(&self) -> usize {
+        return self.surviving_reads;
+    }
+
+    pub fn surviving_bases(&self) -> usize {
+        return self.surviving_bases;
+    }
+
+    pub fn percent_surviving_reads(&self) -> f64 {
+        return self.percent_surviving_reads;
+    }
+
+    pub fn percent_surviving_bases(&self) -> f64 {
+        return self.percent_surviving_bases;
+    }
+
+    pub fn mean_length(&self) -> f64 {
+        return self.mean_length;
+    }
+
+    pub fn length_distribution(&self) -> &Vec<usize> {
+        return &self.length_distribution;
+    }
+}
+
+impl QCModule for TrimSimulation {
+    fn key(&self) -> &str {
+        return "trim_simulation";
+    }
+
+    fn name(&self) -> &str {
+        return "Trimming simulation";
+    }
+
+    fn process_sequence(&mut self, record: &ReadRecord) {
+        let (seq, qual) = (record.seq(), record.qual());
+        self.total_reads += 1;
+        self.total_bases += seq.len();
+
+        let (mut start, mut end) = (0, seq.len());
+        if self.trim_n {
+            let is_n = |base: u8| base == b'N' || base == b'n';
+            while start < end && is_n(seq[start]) {
+                start += 1;
+            }
+            while end > start && is_n(seq[end - 1]) {
+                end -= 1;
+            }
+            if end - start < seq.len() {
+                self.n_trimmed_reads += 1;
+                self.n_trimmed_bases += seq.len() - (end - start);
+            }
+        }
+
+        let clip = self
+            .adapters
+            .iter()
+            .filter_map(|adapter| {
+                adapter.find(&seq[start..end], self.max_mismatches, self.min_overlap)
+            })
+            .map(|adapter_match| adapter_match.position())
+            .min();
+        if let Some(position) = clip {
+            self.adapter_trimmed_reads += 1;
+            self.adapter_trimmed_bases += end - start - position;
+            end = start + position;
+        }
+
+        for (i, offset) in PHRED_OFFSETS.iter().enumerate() {
+            let length = sliding_window_length(
+                &qual[start..end],
+                *offset,
+                self.window_size,
+                self.window_quality,
+            );
+            let counts = &mut self.counts[i];
+            if length < end - start {
+                counts.quality_trimmed_reads += 1;
+                counts.quality_trimmed_bases += end - start - length;
+            }
+            counts.add_length(length, 1);
+        }
+    }
+
+    fn merge(&mut self, other: &dyn QCModule) {
+        let other = downcast::<TrimSimulation>(other);
+        if self.params() != other.params()
+            || self.max_mismatches != other.max_mismatches
+            || self.min_overlap != other.min_overlap
+        {
+            panic!("Cannot merge trimming simulations with different parameters");
+        }
+
+        self.total_reads += other.total_reads;
+        self.total_bases += other.total_bases;
+        self.n_trimmed_reads += other.n_trimmed_reads;
+        self.n_trimmed_bases += other.n_trimmed_bases;
+        self.adapter_trimmed_reads += other.adapter_trimmed_reads;
+        self.adapter_trimmed_bases += other.adapter_trimmed_bases;
+        for (counts, other_counts) in self.counts.iter_mut().zip(other.counts.iter()) {
+            counts.quality_trimmed_reads += other_counts.quality_trimmed_reads;
+            counts.quality_trimmed_bases += other_counts.quality_trimmed_bases;
+            for (length, count) in other_counts.lengths.iter().enumerate() {
+                if *count > 0 {
+                    counts.add_length(length, *count);
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self, phred_offset: usize) {
+        let i = match PHRED_OFFSETS
+            .iter()
+            .position(|offset| *offset == phred_offset)
+        {
+            Some(i) => i,
+            None => panic!("Unknown phred offset {}", phred_offset),
+        };
+        let counts = &self.counts[i];
+        self.quality_trimmed_reads = counts.quality_trimmed_reads;
+        self.quality_trimmed_bases = counts.quality_trimmed_bases;
+        self.length_distribution = counts.lengths.clone();
+
+        self.dropped_reads = 0;
+        self.surviving_reads = 0;
+        self.surviving_bases = 0;
+        for (length, count) in self.length_distribution.iter().enumerate() {
+            if length < self.min_length || length == 0 {
+                self.dropped_reads += count;
+            } else {
+                self.surviving_reads += count;
+                self.surviving_bases += length * count;
+            }
+        }
+
+        if self.total_reads > 0 {
+            let total_reads = self.total_reads as f64;
+            self.percent_dropped_reads = self.dropped_reads as f64 / total_reads * 100.0;
+            self.percent_surviving_reads = self.surviving_reads as f64 / total_reads * 100.0;
+        }
+        if self.total_bases > 0 {
+            self.percent_surviving_bases =
+                self.surviving_bases as f64 / self.total_bases as f64 * 100.0;
+        }
+        if self.surviving_reads > 0 {
+            self.mean_length = self.surviving_bases as f64 / self.surviving_reads as f64;
+        }
+    }
+
+    /// Warn or fail if the percentage of bases surviving the trimming is lower than the limits.
+    fn calculate_status(&mut self, limits: &Limits) {
+        if self.total_reads == 0 || limits.ignore("trimming") {
+            self.status = ModuleStatus::Ignore;
+            return;
+        }
+
+        self.status = limits.lower_status("trimming", self.percent_surviving_bases);
+    }
+
+    fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
+
+#[cfg(test)]
+mod trimming_tests {
+    use super::*;
+    use fastq::OwnedRecord;
+
+    const ADAPTERS: &str = "Illumina Universal Adapter\tAGATCGGAAGAG\n";
+
+    fn process(module: &mut TrimSimulation, seq: &[u8], qual: &[u8]) {
+        let record = OwnedRecord {
+            head: b"read".to_vec(),
+            seq: seq.to_vec(),
+            qual: qual.to_vec(),
+            sep: None,
+        };
+        module.process_sequence(&ReadRecord::new(&record));
+    }
+
+    #[test]
+    fn test_sliding_window_length() {
+        // Q40 then Q2, the window is cut at the first low base
+        let qual = [&[b'I'; 10][..], &[b'#'; 10][..]].concat();
+        assert_eq!(sliding_window_length(&qual, 33, 4, 20), 10);
+        assert_eq!(sliding_window_length(&qual, 33, 4, 0), 20);
+        assert_eq!(sliding_window_length(&[b'I'; 3], 33, 4, 20), 3);
+        assert_eq!(sliding_window_length(&[b'#'; 3], 33, 4, 20), 0);
+    }
+
+    #[test]
+    fn test_trim_simulation() {
+        let insert = b"GTCGCACTGATCTGGGTTAGGCGCGGAGCCGAGGGTTGCACCATTTTTCA";
+        let mut module = TrimSimulation::new(
+            &ADAPTERS.to_string(),
+            0,
+            5,
+            &TrimParams::new(4, 20, true, 20),
+        );
+        let mut other = module.clone();
+
+        // 50bp insert with the adapter
+        let seq = [&insert[..], b"AGATCGGAAGAGCACA"].concat();
+        process(&mut module, &seq, &vec![b'I'; seq.len()]);
+        // Ns at both ends and low quality at the 3' end
+        let seq = [b"NN", &insert[..], b"N"].concat();
+        let qual = [&[b'I'; 33][..], &[b'#'; 20][..]].concat();
+        process(&mut other, &seq, &qual);
+        // Too short after trimming
+        process(
+            &mut other,
+            &insert[..30],
+            &[&[b'I'; 10][..], &[b'#'; 20][..]].concat(),
+        );
+        module.merge(&other);
+        module.finish(33);
+        module.calculate_status(&Limits::default());
+
+        assert_eq!(module.total_reads(), 3);
+        assert_eq!(module.total_bases(), 66 + 53 + 30);
+        assert_eq!(module.n_trimmed_reads(), 1);
+        assert_eq!(module.n_trimmed_bases(), 3);
+        assert_eq!(module.adapter_trimmed_reads(), 1);
+        assert_eq!(module.adapter_trimmed_bases(), 16);
+        assert_eq!(module.quality_trimmed_reads(), 2);
+        assert_eq!(module.length_distribution()[50], 1);
+        assert_eq!(module.length_distribution()[31], 1);
+        assert_eq!(module.length_distribution()[10], 1);
+        assert_eq!(module.dropped_reads(), 1);
+        assert_eq!(module.surviving_reads(), 2);
+        assert_eq!(module.surviving_bases(), 81);
+        assert_eq!(module.mean_length(), 40.5);
+
+        // Every quality is lower than 20 with Illumina 1.3, the reads are trimmed entirely
+        let mut module = other.clone();
+        module.finish(64);
+        assert_eq!(module.quality_trimmed_reads(), 2);
+        assert_eq!(module.length_distribution()[0], 2);
+    }
+}
@@ -0,0 +1,531 @@
+//! Extract UMIs from read names or the first bases of reads, and report their diversity.
+//!
+//! Besides the UMI composition, the duplication is computed again with the UMI prepended to the
+//! sequence: reads with the same sequence but different UMIs are different molecules, so it
+//! shows how much of `SeqDuplicationLevel` is PCR duplication.
+use super::fastqc::{
+    truncate_for_duplication, Limits, ModuleStatus, OverRepresentedSeqs, SeqDuplicationLevel,
+};
+use super::module::{downcast, QCModule, ReadRecord};
+use fastq::{OwnedRecord, Record};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::{from_utf8, FromStr};
+
+// Stop tallying new UMIs beyond this, the counts of the known ones are still exact
+const MAX_DISTINCT_UMIS: usize = 1000000;
+
+/// Where the UMI of a read is.
+#[derive(Debug, Clone)]
+pub enum UmiSource {
+    /// After the last `:` or `_` of the read name, e.g. `@A00123:8:FC1:1:1101:1000:2000:ACGTACGT`
+    /// (bcl2fastq) or `@READ1_ACGTACGT` (umi_tools).
+    Header,
+    /// The `umi` capture of a regex on the read header.
+    Regex(Regex),
+    /// The bases of the read at `offset`, the rest of the read is the insert.
+    Read { offset: usize, length: usize },
+}
+
+impl Default for UmiSource {
+    fn default() -> Self {
+        return UmiSource::Header;
+    }
+}
+
+/// `header`, `regex:<pattern>` or `read:[<offset>:]<length>`.
+impl FromStr for UmiSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "header" {
+            return Ok(UmiSource::Header);
+        }
+
+        if let Some(pattern) = s.strip_prefix("regex:") {
+            let regex = Regex::new(pattern).map_err(|msg| format!("Invalid UMI regex: {}", msg))?;
+            if !regex.capture_names().any(|name| name == Some("umi")) {
+                return Err(format!("No umi capture in the UMI regex: {}", pattern));
+            }
+            return Ok(UmiSource::Regex(regex));
+        }
+
+        if let Some(position) = s.strip_prefix("read:") {
+            let numbers: Result<Vec<usize>, _> = position.split(':').map(|n| n.parse()).collect();
+            return match numbers.as_ref().map(|n| n.as_slice()) {
+                Ok([length]) if *length > 0 => Ok(UmiSource::Read {
+                    offset: 0,
+                    length: *length,
+                }),
+                Ok([offset, length]) if *length > 0 => Ok(UmiSource::Read {
+                    offset: *offset,
+                    length: *length,
+                }),
+                _ => Err(format!("Invalid UMI position: {}", position)),
+            };
+        }
+
+        return Err(format!(
+            "Invalid UMI source: {}, expected header, regex:<pattern> or read:[<offset>:]<length>",
+            s
+        ));
+    }
+}
+
+impl fmt::Display for UmiSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            UmiSource::Header => write!(f, "header"),
+            UmiSource::Regex(regex) => write!(f, "regex:{}", regex.as_str()),
+            UmiSource::Read { offset, length } => write!(f, "read:{}:{}", offset, length),
+        };
+    }
+}
+
+impl UmiSource {
+    /// The UMI and the insert of a read, None if the read has no valid UMI.
+    fn extract<'a>(&self, record: &'a ReadRecord) -> Option<(&'a [u8], &'a [u8], &'a [u8])> {
+        let empty: &[u8] = &[];
+        let umi = match self {
+            UmiSource::Header => {
+                let name = record.head().split(|c| *c == b' ').next().unwrap_or(empty);
+                let start = name
+                    .iter()
+                    .rposition(|c| *c == b':' || *c == b'_')
+                    .map_or(0, |i| i + 1);
+                (&name[start..], empty)
+            }
+            UmiSource::Regex(regex) => {
+                let head = from_utf8(record.head()).ok()?;
+                let umi = regex.captures(head)?.name("umi")?;
+                (&record.head()[umi.start()..umi.end()], empty)
+            }
+            UmiSource::Read { offset, length } => {
+                if record.seq().len() < offset + length {
+                    return None;
+                }
+                let qual = record.qual().get(*offset..offset + length).unwrap_or(empty);
+                (&record.seq()[*offset..offset + length], qual)
+            }
+        };
+
+        // Dual UMIs are joined by '+', e.g. by BCL Convert
+        let valid = umi.0.len() > 0 && umi.0.iter().all(|c| b"ACGTN+".contains(c));
+        if !valid {
+            return None;
+        }
+
+        let insert = match self {
+            UmiSource::Read { offset, length } => &record.seq()[offset + length..],
+            _ => record.seq(),
+        };
+        return Some((umi.0, umi.1, insert));
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UmiStats {
+    source: String,
+    reads_with_umi: usize,
+    // The reads whose UMI can't be extracted, or is not made of ACGTN
+    unparsed_reads: usize,
+    umis_with_n: usize,
+    // The reads without a valid UMI or with N in the UMI
+    percent_invalid: f64,
+    length_distribution: BTreeMap<usize, usize>,
+    // Per UMI position, the percentages of A, C, G, T and N
+    base_composition: Vec<Vec<f64>>,
+    // Per UMI position, only for UMIs in the read
+    mean_qualities: Vec<f64>,
+    distinct_umis: usize,
+    // The chance that two reads carry the same UMI, i.e. that two molecules at the same locus
+    // collide, and the number of equally frequent UMIs which gives the same chance
+    collision_probability: f64,
+    effective_umis: f64,
+    // The duplication of UMI + sequence
+    umi_duplication: Option<SeqDuplicationLevel>,
+    #[serde(skip)]
+    umi_source: UmiSource,
+    #[serde(skip)]
+    umi_counts: HashMap<Vec<u8>, usize>,
+    // [A, C, G, T, N] per position
+    #[serde(skip)]
+    base_counts: Vec<[usize; 5]>,
+    #[serde(skip)]
+    quality_sums: Vec<usize>,
+    #[serde(skip)]
+    quality_counts: Vec<usize>,
+    #[serde(skip_serializing, default)]
+    umi_seqs: OverRepresentedSeqs,
+    #[serde(skip_serializing, default)]
+    dup_length: usize,
+    status: ModuleStatus,
+}
+
+impl UmiStats {
+    pub fn new(
+        umi_source: UmiSource,
+        overrepresented_max_unique_seq_count: usize,
+        dup_length: usize,
+        limits: &Limits,
+    ) -> UmiStats {
+        return UmiStats {
+            source: umi_source.to_string(),
+            reads_with_umi: 0,
+            unparsed_reads: 0,
+            umis_with_n: 0,
+            percent_invalid: 0.0,
+            length_distribution: BTreeMap::new(),
+            base_composition: vec![],
+            mean_qualities: vec![],
+            distinct_umis: 0,
+            collision_probability: 0.0,
+            effective_umis: 0.0,
+            umi_duplication: None,
+            umi_source: umi_source,
+            umi_counts: HashMap::new(),
+            base_counts: vec![],
+            quality_sums: vec![],
+            quality_counts: vec![],
+            // The keys are truncated by `dup_length` before they are counted, and they are not
+            // searched in the contaminants as they start with the UMIs
+            umi_seqs: OverRepresentedSeqs::new(
+                &String::new(),
+                overrepresented_max_unique_seq_count,
+                usize::MAX,
+                limits,
+            ),
+            dup_length: dup_length,
+            status: ModuleStatus::Pass,
+        };
+    }
+
+    pub fn source(&self) -> &String {
+        return &self.source;
+    }
+
+    pub fn reads_with_umi(&self) -> usize {
+        return self.reads_with_umi;
+    }
+
+    pub fn unparsed_reads(&self) -> usize {
+        return self.unparsed_reads;
+    }
+
+    pub fn umis_with_n(&self) -> usize {
+        return self.umis_with_n;
+    }
+
+    pub fn percent_invalid(&self) -> f64 {
+        return self.percent_invalid;
+    }
+
+    pub fn length_distribution(&self) -> &BTreeMap<usize, usize> {
+        return &self.length_distribution;
+    }
+
+    pub fn base_composition(&self) -> &Vec<Vec<f64>> {
+        return &self.base_composition;
+    }
+
+    pub fn mean_qualities(&self) -> &Vec<f64> {
+        return &self.mean_qualities;
+    }
+
+    pub fn distinct_umis(&self) -> usize {
+        return self.distinct_umis;
+    }
+
+    pub fn collision_probability(&self) -> f64 {
+        return self.collision_probability;
+    }
+
+    pub fn effective_umis(&self) -> f64 {
+        return self.effective_umis;
+    }
+
+    pub fn umi_duplication(&self) -> &Option<SeqDuplicationLevel> {
+        return &self.umi_duplication;
+    }
+
+    fn count_bases(&mut self, umi: &[u8], qual: &[u8]) {
+        if self.base_counts.len() < umi.len() {
+            self.base_counts.resize(umi.len(), [0; 5]);
+            self.quality_sums.resize(umi.len(), 0);
+            self.quality_counts.resize(umi.len(), 0);
+        }
+
+        for (i, base) in umi.iter().enumerate() {
+            match base {
+                b'A' => self.base_counts[i][0] += 1,
+                b'C' => self.base_counts[i][1] += 1,
+                b'G' => self.base_counts[i][2] += 1,
+                b'T' => self.base_counts[i][3] += 1,
+                b'N' => self.base_counts[i][4] += 1,
+                _ => {}
+            }
+        }
+
+        for (i, q) in qual.iter().enumerate() {
+            self.quality_sums[i] += *q as usize;
+            self.quality_counts[i] += 1;
+        }
+    }
+}
+
+impl QCModule for UmiStats {
+    fn key(&self) -> &str {
+        return "umi_stats";
+    }
+
+    fn name(&self) -> &str {
+        return "UMI statistics";
+    }
+
+    fn process_sequence(&mut self, record: &ReadRecord) {
+        let (umi, qual, insert) = match self.umi_source.extract(record) {
+            Some(umi) => umi,
+            None => {
+                self.unparsed_reads += 1;
+                return;
+            }
+        };
+
+        self.reads_with_umi += 1;
+        if umi.contains(&b'N') {
+            self.umis_with_n += 1;
+        }
+        *self.length_distribution.entry(umi.len()).or_insert(0) += 1;
+        self.count_bases(umi, qual);
+
+        match self.umi_counts.get_mut(umi) {
+            Some(count) => *count += 1,
+            None => {
+                if self.umi_counts.len() < MAX_DISTINCT_UMIS {
+                    self.umi_counts.insert(umi.to_vec(), 1);
+                }
+            }
+        }
+
+        let mut key = umi.to_vec();
+        key.push(b'_');
+        key.extend_from_slice(truncate_for_duplication(insert, self.dup_length));
+        self.umi_seqs.process_sequence(&OwnedRecord {
+            head: vec![],
+            seq: key,
+            qual: vec![],
+            sep: None,
+        });
+    }
+
+    fn merge(&mut self, other: &dyn QCModule) {
+        let other = downcast::<UmiStats>(other);
+        if self.source != other.source {
+            panic!(
+                "Cannot merge UMI statistics from different sources: {} and {}",
+                self.source, other.source
+            );
+        }
+
+        self.reads_with_umi += other.reads_with_umi;
+        self.unparsed_reads += other.unparsed_reads;
+        self.umis_with_n += other.umis_with_n;
+        for (length, count) in &other.length_distribution {
+            *self.length_distribution.entry(*length).or_insert(0) += count;
+        }
+
+        if self.base_counts.len() < other.base_counts.len() {
+            self.base_counts.resize(other.base_counts.len(), [0; 5]);
+            self.quality_sums.resize(other.base_counts.len(), 0);
+            self.quality_counts.resize(other.base_counts.len(), 0);
+        }
+        for i in 0..other.base_counts.len() {
+            for b in 0..5 {
+                self.base_counts[i][b] += other.base_counts[i][b];
+            }
+            self.quality_sums[i] += other.quality_sums[i];
+            self.quality_counts[i] += other.quality_counts[i];
+        }
+
+        for (umi, count) in &other.umi_counts {
+            match self.umi_counts.get_mut(umi) {
+                Some(total) => *total += count,
+                None => {
+                    if self.umi_counts.len() < MAX_DISTINCT_UMIS {
+                        self.umi_counts.insert(umi.clone(), *count);
+                    }
+                }
+            }
+        }
+
+        self.umi_seqs.merge(&other.umi_seqs);
+    }
+
+    fn finish(&mut self, phred_offset: usize) {
+        let total = self.reads_with_umi + self.unparsed_reads;
+        if total == 0 {
+            return;
+        }
+        self.percent_invalid =
+            (self.unparsed_reads + self.umis_with_n) as f64 / total as f64 * 100.0;
+
+        self.base_composition = self
+            .base_counts
+            .iter()
+            .map(|counts| {
+                let sum: usize = counts.iter().sum();
+                counts
+                    .iter()
+                    .map(|count| *count as f64 / sum.max(1) as f64 * 100.0)
+                    .collect()
+            })
+            .collect();
+
+        self.mean_qualities = self
+            .quality_sums
+            .iter()
+            .zip(self.quality_counts.iter())
+            .filter(|(_, count)| **count > 0)
+            .map(|(sum, count)| *sum as f64 / *count as f64 - phred_offset as f64)
+            .collect();
+
+        self.distinct_umis = self.umi_counts.len();
+        let tallied: usize = self.umi_counts.values().sum();
+        if tallied > 0 {
+            self.collision_probability = self
+                .umi_counts
+                .values()
+                .map(|count| (*count as f64 / tallied as f64).powi(2))
+                .sum();
+            self.effective_umis = 1.0 / self.collision_probability;
+        }
+
+        QCModule::finish(&mut self.umi_seqs, phred_offset);
+        self.umi_duplication = self
+            .umi_seqs
+            .duplication_level_module()
+            .map(|module| *module);
+    }
+
+    /// Warn or fail if too many reads have no valid UMI or N in the UMI, the UMI duplication is
+    /// graded by the duplication limits.
+    fn calculate_status(&mut self, limits: &Limits) {
+        if self.reads_with_umi + self.unparsed_reads == 0 || limits.ignore("umi") {
+            self.status = ModuleStatus::Ignore;
+            return;
+        }
+
+        self.status = limits.upper_status("umi", self.percent_invalid);
+        if let Some(module) = &mut self.umi_duplication {
+            QCModule::calculate_status(module, limits);
+        }
+    }
+
+    fn status(&self) -> ModuleStatus {
+        return self.status;
+    }
+}
+
+#[cfg(test)]
+mod umi_tests {
+    use super::*;
+
+    fn process(module: &mut UmiStats, head: &str, seq: &[u8]) {
+        let record = OwnedRecord {
+            head: head.as_bytes().to_vec(),
+            seq: seq.to_vec(),
+            qual: vec![b'I'; seq.len()],
+            sep: None,
+        };
+        module.process_sequence(&ReadRecord::new(&record));
+    }
+
+    #[test]
+    fn test_umi_source() {
+        assert!(matches!(
+            UmiSource::from_str("read:8"),
+            Ok(UmiSource::Read {
+                offset: 0,
+                length: 8
+            })
+        ));
+        assert!(matches!(
+            UmiSource::from_str("read:2:6"),
+            Ok(UmiSource::Read {
+                offset: 2,
+                length: 6
+            })
+        ));
+        assert!(UmiSource::from_str("read:0").is_err());
+        assert!(UmiSource::from_str("regex:(?P<barcode>\\w+)").is_err());
+        assert_eq!(
+            UmiSource::from_str("regex:UMI=(?P<umi>\\w+)")
+                .unwrap()
+                .to_string(),
+            "regex:UMI=(?P<umi>\\w+)"
+        );
+    }
+
+    #[test]
+    fn test_umi_stats() {
+        let limits = Limits::default();
+        let mut module = UmiStats::new(UmiSource::Header, 100000, 0, &limits);
+        let mut other = UmiStats::new(UmiSource::Header, 100000, 0, &limits);
+        // The same sequence with two UMIs, i.e. two molecules
+        for _ in 0..4 {
+            process(
+                &mut module,
+                "A00123:8:FC1:1:1101:1000:2000:ACGT 1:N:0:1",
+                b"GGGGCCCC",
+            );
+            process(&mut other, "READ1_TTGA", b"GGGGCCCC");
+        }
+        process(&mut other, "READ2_TNGA", b"AAAACCCC");
+        process(&mut other, "A00123:8:FC1:1:1101:1000:2000", b"AAAACCCC");
+        module.merge(&other);
+        module.finish(33);
+        module.calculate_status(&limits);
+
+        assert_eq!(module.reads_with_umi(), 9);
+        assert_eq!(module.unparsed_reads(), 1);
+        assert_eq!(module.umis_with_n(), 1);
+        assert_eq!(module.percent_invalid(), 20.0);
+        assert_eq!(module.length_distribution()[&4], 9);
+        assert_eq!(module.distinct_umis(), 3);
+        assert_eq!(
+            module.base_composition()[1],
+            vec![
+                0.0,
+                4.0 / 9.0 * 100.0,
+                0.0,
+                4.0 / 9.0 * 100.0,
+                1.0 / 9.0 * 100.0
+            ]
+        );
+        assert_eq!(module.collision_probability(), (16.0 + 16.0 + 1.0) / 81.0);
+        assert_eq!(
+            module
+                .umi_duplication()
+                .as_ref()
+                .unwrap()
+                .percent_diff_seq(),
+            3.0 / 9.0 * 100.0
+        );
+        assert_eq!(module.status(), ModuleStatus::Fail);
+
+        let mut module = UmiStats::new(
+            UmiSource::from_str("read:2:3").unwrap(),
+            100000,
+            0,
+            &limits,
+        );
+        process(&mut module, "read1", b"NNACGTTTT");
+        process(&mut module, "read2", b"NNACG");
+        process(&mut module, "read3", b"NNA");
+        module.finish(33);
+        assert_eq!(module.reads_with_umi(), 2);
+        assert_eq!(module.mean_qualities(), &vec![40.0, 40.0, 40.0]);
+    }
+}
@@ -1 +1,157 @@
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+/// The reverse complement of a DNA sequence, any base other than ACGT becomes N.
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    return seq.iter().rev().map(|base| complement(*base)).collect();
+}
+
+/// The complement of a base, N for any base other than ACGT.
+pub fn complement(base: u8) -> u8 {
+    return match base {
+        b'A' | b'a' => b'T',
+        b'C' | b'c' => b'G',
+        b'G' | b'g' => b'C',
+        b'T' | b't' => b'A',
+        _ => b'N',
+    };
+}
+
+/// The index of a base in ACGT, None for any other base.
+pub fn base_index(base: u8) -> Option<usize> {
+    return match base {
+        b'A' | b'a' => Some(0),
+        b'C' | b'c' => Some(1),
+        b'G' | b'g' => Some(2),
+        b'T' | b't' => Some(3),
+        _ => None,
+    };
+}
+
+/// The finalizer of SplitMix64, it spreads the bits of an integer over the whole hash.
+pub fn mix64(value: u64) -> u64 {
+    let mut hash = value;
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    return hash ^ (hash >> 31);
+}
+
+/// FNV-1a with `mix64`, the high bits of FNV alone are not random enough for the HyperLogLog.
+pub fn hash_seq(seq: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for base in seq {
+        hash ^= *base as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    return mix64(hash);
+}
+
+/// Whether a read is one of the reads sampled by `interval`, e.g. one out of 10 reads. The reads
+/// are sampled by their hashes, so the sample doesn't depend on the order of reads or threads.
+pub fn is_sampled(head: &[u8], seq: &[u8], interval: usize) -> bool {
+    if interval <= 1 {
+        return true;
+    }
+
+    let hash = hash_seq(head) ^ hash_seq(seq);
+    return hash % interval as u64 == 0;
+}
+
+/// Call `f` with the (w, k) minimizers of a sequence in order, the same minimizer of consecutive
+/// windows is only reported once.
+///
+/// The canonical k-mers (k <= 31) are hashed, so both strands have the same minimizers. The
+/// k-mers with any base other than ACGT are skipped.
+///
+pub fn for_each_minimizer<F: FnMut(u64)>(
+    seq: &[u8],
+    kmer_size: usize,
+    window_size: usize,
+    mut f: F,
+) {
+    let mask = (1u64 << (2 * kmer_size)) - 1;
+    let shift = 2 * (kmer_size - 1);
+    let (mut forward, mut reverse, mut valid) = (0u64, 0u64, 0);
+    let mut window: VecDeque<u64> = VecDeque::with_capacity(window_size);
+    let mut last = None;
+    for base in seq {
+        let index = match base_index(*base) {
+            Some(index) => index as u64,
+            None => {
+                valid = 0;
+                window.clear();
+                continue;
+            }
+        };
+
+        forward = ((forward << 2) | index) & mask;
+        reverse = (reverse >> 2) | ((3 - index) << shift);
+        valid += 1;
+        if valid < kmer_size {
+            continue;
+        }
+
+        if window.len() == window_size {
+            window.pop_front();
+        }
+        window.push_back(mix64(forward.min(reverse)));
+        if window.len() == window_size {
+            let minimizer = *window.iter().min().unwrap();
+            if last != Some(minimizer) {
+                f(minimizer);
+                last = Some(minimizer);
+            }
+        }
+    }
+}
+
+/// Call `f` with the canonical k-mers (k <= 31) of a sequence in 2-bit encoding, so both strands
+/// have the same k-mers. The k-mers with any base other than ACGT are skipped.
+pub fn for_each_canonical_kmer<F: FnMut(u64)>(seq: &[u8], kmer_size: usize, mut f: F) {
+    let mask = (1u64 << (2 * kmer_size)) - 1;
+    let shift = 2 * (kmer_size - 1);
+    let (mut forward, mut reverse, mut valid) = (0u64, 0u64, 0);
+    for base in seq {
+        let index = match base_index(*base) {
+            Some(index) => index as u64,
+            None => {
+                valid = 0;
+                continue;
+            }
+        };
+
+        forward = ((forward << 2) | index) & mask;
+        reverse = (reverse >> 2) | ((3 - index) << shift);
+        valid += 1;
+        if valid >= kmer_size {
+            f(forward.min(reverse));
+        }
+    }
+}
+
+/// Call `f` with the header (without '>') and the sequence of each record in a FASTA file.
+pub fn for_each_fasta_record<F: FnMut(&[u8], &[u8])>(path: &str, mut f: F) -> io::Result<()> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut header: Option<Vec<u8>> = None;
+    let mut seq: Vec<u8> = vec![];
+    for line in reader.split(b'\n') {
+        let line = line?;
+        if line.starts_with(b">") {
+            if let Some(header) = &header {
+                f(header, &seq);
+            }
+            header = Some(line[1..].iter().filter(|c| **c != b'\r').cloned().collect());
+            seq.clear();
+        } else {
+            seq.extend(line.iter().filter(|c| !c.is_ascii_whitespace()));
+        }
+    }
+    if let Some(header) = &header {
+        f(header, &seq);
+    }
+
+    return Ok(());
+}
@@ -0,0 +1,603 @@
+//! Render a finished `FastQC` into the `fastqc_data.txt` and `summary.txt` layout of FastQC,
+//! so that the tools which parse FastQC's outputs (e.g. MultiQC) can read them without any change.
+use crate::qc::dup_sketch::SketchDuplicationLevel;
+use crate::qc::fastqc::{
+    AdapterContent, FastQC, KmerContent, ModuleStatus, OverRepresentedSeqs, PerBaseNContent,
+    PerBaseSeqContent, PerBaseSeqQuality, PerSeqGCContent, PerSeqQualityScore, PerTileQualityScore,
+    SeqDuplicationLevel, SeqLenDistribution,
+};
+use crate::qc::module::QCModule;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// The version of FastQC whose layout we follow.
+const FASTQC_VERSION: &str = "0.11.9";
+
+/// Format a float as Java does, e.g. 1.0 instead of 1, because some parsers expect a decimal point.
+fn format_double(value: f64) -> String {
+    if value.is_finite() && value.fract() == 0.0 {
+        return format!("{:.1}", value);
+    }
+
+    return format!("{}", value);
+}
+
+fn begin_module<W: Write>(writer: &mut W, name: &str, status: ModuleStatus) -> io::Result<()> {
+    return writeln!(writer, ">>{}\t{}", name, status.name());
+}
+
+fn end_module<W: Write>(writer: &mut W) -> io::Result<()> {
+    return writeln!(writer, ">>END_MODULE");
+}
+
+fn write_basic_stats<W: Write>(fastqc: &FastQC, writer: &mut W) -> io::Result<()> {
+    let basic_stats = &fastqc.basic_stats;
+    let file_type = if basic_stats.file_type().len() > 0 {
+        basic_stats.file_type()
+    } else {
+        "Conventional base calls".to_string()
+    };
+
+    let seq_length = if basic_stats.min_length() == basic_stats.max_length() {
+        format!("{}", basic_stats.min_length())
+    } else {
+        format!("{}-{}", basic_stats.min_length(), basic_stats.max_length())
+    };
+
+    begin_module(writer, "Basic Statistics", basic_stats.status())?;
+    writeln!(writer, "#Measure\tValue")?;
+    writeln!(writer, "Filename\t{}", basic_stats.file_name())?;
+    writeln!(writer, "File type\t{}", file_type)?;
+    writeln!(writer, "Encoding\t{}", basic_stats.phred().name())?;
+    writeln!(writer, "Total Sequences\t{}", basic_stats.total_reads())?;
+    writeln!(writer, "Sequences flagged as poor quality\t0")?;
+    writeln!(writer, "Sequence length\t{}", seq_length)?;
+    writeln!(
+        writer,
+        "%GC\t{}",
+        (basic_stats.gc_percentage() * 100.0) as usize
+    )?;
+    return end_module(writer);
+}
+
+fn write_per_base_seq_quality<W: Write>(
+    module: &PerBaseSeqQuality,
+    writer: &mut W,
+) -> io::Result<()> {
+    begin_module(writer, "Per base sequence quality", module.status())?;
+    writeln!(
+        writer,
+        "#Base\tMean\tMedian\tLower Quartile\tUpper Quartile\t10th Percentile\t90th Percentile"
+    )?;
+    for i in 0..module.xlabels().len() {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            module.xlabels()[i],
+            format_double(module.mean()[i]),
+            format_double(module.median()[i]),
+            format_double(module.lower_quartile()[i]),
+            format_double(module.upper_quartile()[i]),
+            format_double(module.lowest()[i]),
+            format_double(module.highest()[i])
+        )?;
+    }
+    return end_module(writer);
+}
+
+fn write_per_tile_quality_score<W: Write>(
+    module: &PerTileQualityScore,
+    writer: &mut W,
+) -> io::Result<()> {
+    begin_module(writer, "Per tile sequence quality", module.status())?;
+    writeln!(writer, "#Tile\tBase\tMean")?;
+    for t in 0..module.tiles().len() {
+        for i in 0..module.x_labels().len() {
+            writeln!(
+                writer,
+                "{}\t{}\t{}",
+                module.tiles()[t],
+                module.x_labels()[i],
+                format_double(module.means()[t][i])
+            )?;
+        }
+    }
+    return end_module(writer);
+}
+
+fn write_per_seq_quality_score<W: Write>(
+    module: &PerSeqQualityScore,
+    writer: &mut W,
+) -> io::Result<()> {
+    begin_module(writer, "Per sequence quality scores", module.status())?;
+    writeln!(writer, "#Quality\tCount")?;
+    for i in 0..module.x_category_quality().len() {
+        writeln!(
+            writer,
+            "{}\t{}",
+            module.x_category_quality()[i],
+            format_double(module.y_category_count()[i] as f64)
+        )?;
+    }
+    return end_module(writer);
+}
+
+fn write_per_base_seq_content<W: Write>(
+    module: &PerBaseSeqContent,
+    writer: &mut W,
+) -> io::Result<()> {
+    // percentages: [T, C, A, G]
+    let percentages = module.percentages();
+    begin_module(writer, "Per base sequence content", module.status())?;
+    writeln!(writer, "#Base\tG\tA\tT\tC")?;
+    for i in 0..module.x_category().len() {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}",
+            module.x_category()[i],
+            format_double(percentages[3][i]),
+            format_double(percentages[2][i]),
+            format_double(percentages[0][i]),
+            format_double(percentages[1][i])
+        )?;
+    }
+    return end_module(writer);
+}
+
+fn write_per_seq_gc_content<W: Write>(module: &PerSeqGCContent, writer: &mut W) -> io::Result<()> {
+    begin_module(writer, "Per sequence GC content", module.status())?;
+    writeln!(writer, "#GC Content\tCount")?;
+    for i in 0..module.x_category().len() {
+        writeln!(
+            writer,
+            "{}\t{}",
+            module.x_category()[i],
+            format_double(module.y_gc_distribution()[i])
+        )?;
+    }
+    return end_module(writer);
+}
+
+fn write_per_base_n_content<W: Write>(module: &PerBaseNContent, writer: &mut W) -> io::Result<()> {
+    begin_module(writer, "Per base N content", module.status())?;
+    writeln!(writer, "#Base\tN-Count")?;
+    for i in 0..module.x_categories().len() {
+        writeln!(
+            writer,
+            "{}\t{}",
+            module.x_categories()[i],
+            format_double(module.percentages()[i])
+        )?;
+    }
+    return end_module(writer);
+}
+
+fn write_seq_len_distribution<W: Write>(
+    module: &SeqLenDistribution,
+    writer: &mut W,
+) -> io::Result<()> {
+    begin_module(writer, "Sequence Length Distribution", module.status())?;
+    writeln!(writer, "#Length\tCount")?;
+    for i in 0..module.x_categories().len() {
+        writeln!(
+            writer,
+            "{}\t{}",
+            module.x_categories()[i],
+            format_double(module.graph_counts()[i])
+        )?;
+    }
+    return end_module(writer);
+}
+
+fn write_seq_duplication_level<W: Write>(
+    module: &SeqDuplicationLevel,
+    writer: &mut W,
+) -> io::Result<()> {
+    return write_duplication_levels(
+        module.status(),
+        module.percent_diff_seq(),
+        module.labels(),
+        module.dedup_percentages(),
+        module.total_percentages(),
+        writer,
+    );
+}
+
+/// The sketch engine has the same levels as FastQC, so it's written in the same section.
+fn write_sketch_duplication_level<W: Write>(
+    module: &SketchDuplicationLevel,
+    writer: &mut W,
+) -> io::Result<()> {
+    return write_duplication_levels(
+        module.status(),
+        module.percent_diff_seq(),
+        module.labels(),
+        module.dedup_percentages(),
+        module.total_percentages(),
+        writer,
+    );
+}
+
+fn write_duplication_levels<W: Write>(
+    status: ModuleStatus,
+    percent_diff_seq: f64,
+    labels: &Vec<String>,
+    dedup_percentages: &Vec<f64>,
+    total_percentages: &Vec<f64>,
+    writer: &mut W,
+) -> io::Result<()> {
+    begin_module(writer, "Sequence Duplication Levels", status)?;
+    writeln!(
+        writer,
+        "#Total Deduplicated Percentage\t{}",
+        format_double(percent_diff_seq)
+    )?;
+    writeln!(
+        writer,
+        "#Duplication Level\tPercentage of deduplicated\tPercentage of total"
+    )?;
+    for i in 0..labels.len() {
+        writeln!(
+            writer,
+            "{}\t{}\t{}",
+            labels[i],
+            format_double(dedup_percentages[i]),
+            format_double(total_percentages[i])
+        )?;
+    }
+    return end_module(writer);
+}
+
+fn write_overrepresented_seqs<W: Write>(
+    module: &OverRepresentedSeqs,
+    writer: &mut W,
+) -> io::Result<()> {
+    begin_module(writer, "Overrepresented sequences", module.status())?;
+    if module.overrepresented_seqs().len() > 0 {
+        writeln!(writer, "#Sequence\tCount\tPercentage\tPossible Source")?;
+        for os in module.overrepresented_seqs() {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                os.seq(),
+                os.count(),
+                format_double(os.percentage()),
+                os.contaminant_hit()
+            )?;
+        }
+    }
+    return end_module(writer);
+}
+
+fn write_adapter_content<W: Write>(module: &AdapterContent, writer: &mut W) -> io::Result<()> {
+    begin_module(writer, "Adapter Content", module.status())?;
+    writeln!(writer, "#Position\t{}", module.labels().join("\t"))?;
+    for g in 0..module.x_labels().len() {
+        let values: Vec<String> = module
+            .enrichments()
+            .iter()
+            .map(|e| format_double(e[g]))
+            .collect();
+        writeln!(writer, "{}\t{}", module.x_labels()[g], values.join("\t"))?;
+    }
+    return end_module(writer);
+}
+
+fn write_kmer_content<W: Write>(module: &KmerContent, writer: &mut W) -> io::Result<()> {
+    begin_module(writer, "Kmer Content", module.status())?;
+    if module.enriched_kmers().len() > 0 {
+        writeln!(
+            writer,
+            "#Sequence\tCount\tPValue\tObs/Exp Max\tMax Obs/Exp Position"
+        )?;
+        for kmer in module.enriched_kmers() {
+            // max_position starts from 1, a kmer never seen at any position has none
+            let index = match kmer.max_position().checked_sub(1) {
+                Some(index) => index,
+                None => continue,
+            };
+            let position = match module.x_categories().get(index) {
+                Some(category) => category.clone(),
+                None => format!("{}", kmer.max_position()),
+            };
+
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}",
+                kmer.sequence(),
+                kmer.count(),
+                format_double(kmer.lowest_pvalue()),
+                format_double(kmer.max_obs_exp()),
+                position
+            )?;
+        }
+    }
+    return end_module(writer);
+}
+
+/// Write all modules in the layout of fastqc_data.txt, the modules which are ignored in the limits are omitted.
+///
+/// NOTE: You must call the finish method of `FastQC` before writing.
+///
+pub fn write_fastqc_data<W: Write>(fastqc: &FastQC, writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "##FastQC\t{}", FASTQC_VERSION)?;
+
+    write_basic_stats(fastqc, writer)?;
+
+    if let Some(module) = &fastqc.per_base_seq_quality {
+        if module.status() != ModuleStatus::Ignore {
+            write_per_base_seq_quality(module, writer)?;
+        }
+    }
+
+    if let Some(module) = &fastqc.per_tile_quality_score {
+        if module.status() != ModuleStatus::Ignore {
+            write_per_tile_quality_score(module, writer)?;
+        }
+    }
+
+    if let Some(module) = &fastqc.per_seq_quality_score {
+        if module.status() != ModuleStatus::Ignore {
+            write_per_seq_quality_score(module, writer)?;
+        }
+    }
+
+    if let Some(module) = &fastqc.per_base_seq_content {
+        if module.status() != ModuleStatus::Ignore {
+            write_per_base_seq_content(module, writer)?;
+        }
+    }
+
+    if let Some(module) = &fastqc.per_seq_gc_content {
+        if module.status() != ModuleStatus::Ignore {
+            write_per_seq_gc_content(module, writer)?;
+        }
+    }
+
+    if let Some(module) = &fastqc.per_base_n_content {
+        if module.status() != ModuleStatus::Ignore {
+            write_per_base_n_content(module, writer)?;
+        }
+    }
+
+    if let Some(module) = &fastqc.seq_len_distribution {
+        if module.status() != ModuleStatus::Ignore {
+            write_seq_len_distribution(module, writer)?;
+        }
+    }
+
+    if let Some(module) = &fastqc.seq_duplication_level {
+        if module.status() != ModuleStatus::Ignore {
+            write_seq_duplication_level(module, writer)?;
+        }
+    } else if let Some(module) = &fastqc.sketch_duplication_level {
+        if module.status() != ModuleStatus::Ignore {
+            write_sketch_duplication_level(module, writer)?;
+        }
+    }
+
+    if let Some(module) = &fastqc.overrepresented_seqs {
+        if module.status() != ModuleStatus::Ignore {
+            write_overrepresented_seqs(module, writer)?;
+        }
+    }
+
+    if let Some(module) = &fastqc.adapter_content {
+        if module.status() != ModuleStatus::Ignore {
+            write_adapter_content(module, writer)?;
+        }
+    }
+
+    if let Some(module) = &fastqc.kmer_content {
+        if module.status() != ModuleStatus::Ignore {
+            write_kmer_content(module, writer)?;
+        }
+    }
+
+    return Ok(());
+}
+
+/// The status of the modules of FastQC in the order of FastQC, the same modules as the sections
+/// of fastqc_data.txt. The other modules (e.g. lane and index) are not in the outputs of FastQC.
+fn fastqc_statuses(fastqc: &FastQC) -> Vec<(&'static str, ModuleStatus)> {
+    fn status<M: QCModule>(module: &Option<M>) -> Option<ModuleStatus> {
+        return module.as_ref().map(|module| module.status());
+    }
+
+    let statuses = vec![
+        ("Basic Statistics", Some(fastqc.basic_stats.status())),
+        (
+            "Per base sequence quality",
+            status(&fastqc.per_base_seq_quality),
+        ),
+        (
+            "Per tile sequence quality",
+            status(&fastqc.per_tile_quality_score),
+        ),
+        (
+            "Per sequence quality scores",
+            status(&fastqc.per_seq_quality_score),
+        ),
+        (
+            "Per base sequence content",
+            status(&fastqc.per_base_seq_content),
+        ),
+        (
+            "Per sequence GC content",
+            status(&fastqc.per_seq_gc_content),
+        ),
+        ("Per base N content", status(&fastqc.per_base_n_content)),
+        (
+            "Sequence Length Distribution",
+            status(&fastqc.seq_len_distribution),
+        ),
+        (
+            "Sequence Duplication Levels",
+            status(&fastqc.seq_duplication_level).or(status(&fastqc.sketch_duplication_level)),
+        ),
+        (
+            "Overrepresented sequences",
+            status(&fastqc.overrepresented_seqs),
+        ),
+        ("Adapter Content", status(&fastqc.adapter_content)),
+        ("Kmer Content", status(&fastqc.kmer_content)),
+    ];
+
+    return statuses
+        .into_iter()
+        .filter_map(|(name, status)| match status {
+            Some(ModuleStatus::Ignore) | None => None,
+            Some(status) => Some((name, status)),
+        })
+        .collect();
+}
+
+/// Write the status of each module of FastQC in the layout of summary.txt, e.g. `PASS	Basic Statistics	test.fq.gz`.
+pub fn write_summary<W: Write>(fastqc: &FastQC, writer: &mut W) -> io::Result<()> {
+    let filename = fastqc.basic_stats.file_name();
+    for (module, status) in fastqc_statuses(fastqc) {
+        writeln!(
+            writer,
+            "{}\t{}\t{}",
+            status.name().to_uppercase(),
+            module,
+            filename
+        )?;
+    }
+
+    return Ok(());
+}
+
+/// Write fastqc_data.txt and summary.txt into `<outdir>/<basename>_fastqc`, the same layout as the output of FastQC.
+///
+/// It returns the directory which contains these files.
+///
+pub fn export(fastqc: &FastQC, outdir: &Path, basename: &str) -> io::Result<PathBuf> {
+    let dir = outdir.join(format!("{}_fastqc", basename));
+    fs::create_dir_all(&dir)?;
+
+    let mut data_writer = BufWriter::new(File::create(dir.join("fastqc_data.txt"))?);
+    write_fastqc_data(fastqc, &mut data_writer)?;
+    data_writer.flush()?;
+
+    let mut summary_writer = BufWriter::new(File::create(dir.join("summary.txt"))?);
+    write_summary(fastqc, &mut summary_writer)?;
+    summary_writer.flush()?;
+
+    return Ok(dir);
+}
+
+#[cfg(test)]
+pub(crate) mod fastqc_data_tests {
+    use super::*;
+    use crate::qc::fastqc::all_modules;
+    use crate::qc::FastQCConfig;
+    use fastq::OwnedRecord;
+
+    pub(crate) fn make_fastqc() -> FastQC {
+        let mut qc = new_fastqc();
+        process_reads(&mut qc);
+        qc.finish();
+        return qc.update_name("test.fq.gz");
+    }
+
+    pub(crate) fn new_fastqc() -> FastQC {
+        // A module besides the ones of FastQC, which is not in the outputs of FastQC
+        let mut modules = all_modules();
+        modules.push("lane".to_string());
+        let adapters = include_str!("../../data/adapter_list.txt").to_string();
+        let contaminants = include_str!("../../data/contaminant_list.txt").to_string();
+        let mut config = FastQCConfig::new(
+            adapters,
+            contaminants,
+            String::new(),
+            None,
+            Some(1),
+            None,
+            None,
+        );
+        config.set_modules(modules).unwrap();
+        return FastQC::new(&config);
+    }
+
+    pub(crate) fn process_reads(qc: &mut FastQC) {
+        let seqs = [
+            "GTCGCACTGATCTGGGTTAGGCGCGGAGCCGAGGGTTGCACCATTTTTCATTATTGAATGCCAAGATA",
+            "ACGTTGCAAGGCTTACGGATCCAAGTGGCATCAGATCGGAAGAGCACACGTCTGAACTCCAGTCACAT",
+        ];
+        for i in 0..200 {
+            let seq = seqs[i % 2];
+            qc.process_sequence(&OwnedRecord {
+                head: format!("A00123:8:H5KJ2DSXY:1:{}:1000:{}", 1101 + i % 2, i).into_bytes(),
+                seq: seq.as_bytes().to_vec(),
+                qual: [b"5".repeat(10), b"I".repeat(seq.len() - 10)].concat(),
+                sep: None,
+            });
+        }
+    }
+
+    #[test]
+    fn test_write_fastqc_data() {
+        let qc = make_fastqc();
+        let mut buffer: Vec<u8> = vec![];
+        write_fastqc_data(&qc, &mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+
+        assert!(text.starts_with("##FastQC\t0.11.9\n>>Basic Statistics\tpass\n"));
+        assert!(text.contains("Filename\ttest.fq.gz\n"));
+        assert!(text.contains("Encoding\tSanger / Illumina 1.9\n"));
+        assert!(text.contains("Total Sequences\t200\n"));
+        assert!(text.contains("#Base\tG\tA\tT\tC\n"));
+        // Each module must be closed
+        let starts = text
+            .lines()
+            .filter(|l| l.starts_with(">>") && *l != ">>END_MODULE")
+            .count();
+        assert_eq!(starts, text.matches(">>END_MODULE").count());
+    }
+
+    #[test]
+    fn test_write_summary() {
+        let qc = make_fastqc();
+        let mut buffer: Vec<u8> = vec![];
+        write_summary(&qc, &mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+
+        assert!(text.starts_with("PASS\tBasic Statistics\ttest.fq.gz\n"));
+        // Only the modules of FastQC, each of them has a section in fastqc_data.txt
+        let mut data: Vec<u8> = vec![];
+        write_fastqc_data(&qc, &mut data).unwrap();
+        let data = String::from_utf8(data).unwrap();
+        let sections: Vec<&str> = data
+            .lines()
+            .filter(|l| l.starts_with(">>") && *l != ">>END_MODULE")
+            .map(|l| l[2..].split('\t').next().unwrap())
+            .collect();
+        let modules: Vec<&str> = text
+            .lines()
+            .map(|l| l.split('\t').nth(1).unwrap())
+            .collect();
+        assert_eq!(modules, sections);
+        assert!(modules.len() <= 12);
+    }
+
+    #[test]
+    fn test_write_sketch_duplication() {
+        let mut qc = new_fastqc();
+        qc.use_sketch_duplication(0);
+        process_reads(&mut qc);
+        qc.finish();
+
+        let mut buffer: Vec<u8> = vec![];
+        write_fastqc_data(&qc, &mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert!(text.contains(">>Sequence Duplication Levels\t"));
+        assert!(text.contains("#Total Deduplicated Percentage\t"));
+
+        let mut buffer: Vec<u8> = vec![];
+        write_summary(&qc, &mut buffer).unwrap();
+        let summary = String::from_utf8(buffer).unwrap();
+        assert!(summary.contains("\tSequence Duplication Levels\t"));
+    }
+}
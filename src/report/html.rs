@@ -0,0 +1,1642 @@
+//! Render the qc results into a single self-contained HTML file.
+//!
+//! All charts are inline SVG and the stylesheet is embedded, so the report can be opened
+//! without network access or any external JS/CSS.
+use super::svg::{self, escape, Series, PALETTE};
+use crate::qc::adapter_detection::AdapterDetection;
+use crate::qc::align::AlignmentStats;
+use crate::qc::barcode::IndexStats;
+use crate::qc::bisulfite::BisulfiteStats;
+use crate::qc::complexity::LibraryComplexity;
+use crate::qc::dup_sketch::SketchDuplicationLevel;
+use crate::qc::fastqc::{FastQC, ModuleStatus};
+use crate::qc::filter::FilterStats;
+use crate::qc::hasher::Meta;
+use crate::qc::lane::PerLaneStats;
+use crate::qc::long_read::LongReadStats;
+use crate::qc::low_complexity::LowComplexity;
+use crate::qc::mislabeling::VAFMatrix;
+use crate::qc::module::QCModule;
+use crate::qc::paired::PairedMetrics;
+use crate::qc::screen::ContaminationScreen;
+use crate::qc::sex::SexCheck;
+use crate::qc::taxonomy::TaxonomicProfile;
+use crate::qc::trimming::TrimSimulation;
+use crate::qc::umi::UmiStats;
+use crate::qc::QCResults;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 0; color: #222; }
+header { background: #2c3e50; color: #fff; padding: 12px 24px; }
+nav { position: fixed; top: 60px; left: 0; width: 240px; padding: 0 12px; font-size: 13px; }
+nav ul { list-style: none; padding: 0; }
+nav li { margin: 4px 0; }
+main { margin-left: 270px; padding: 12px 24px; }
+section { margin-bottom: 36px; }
+table { border-collapse: collapse; font-size: 13px; }
+th, td { border: 1px solid #ccc; padding: 3px 8px; text-align: left; }
+th { background: #f0f0f0; }
+.status { display: inline-block; width: 44px; text-align: center; border-radius: 3px; color: #fff; font-size: 11px; }
+.pass { background: #2ca02c; }
+.warn { background: #ff7f0e; }
+.fail { background: #d62728; }
+.ignore { background: #7f7f7f; }
+.seq { font-family: monospace; }
+";
+
+fn badge(status: ModuleStatus) -> String {
+    return format!(
+        "<span class=\"status {}\">{}</span>",
+        status.name(),
+        status.name().to_uppercase()
+    );
+}
+
+fn anchor(title: &str) -> String {
+    return title
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+}
+
+/// The sections of a report, the navigation is built from their titles.
+struct Page {
+    titles: Vec<String>,
+    body: String,
+    // Appended to the titles to tell the sections of R2 from R1's
+    suffix: String,
+}
+
+fn section(page: &mut Page, title: &str, status: Option<ModuleStatus>, body: &str) {
+    let status = match status {
+        Some(status) => badge(status),
+        None => String::new(),
+    };
+    let title = format!("{}{}", title, page.suffix);
+    let title = title.as_str();
+
+    page.titles.push(title.to_string());
+    write!(
+        page.body,
+        "<section id=\"{}\"><h2>{} {}</h2>{}</section>",
+        anchor(title),
+        status,
+        escape(title),
+        body
+    )
+    .unwrap();
+}
+
+fn table(header: &[&str], rows: &Vec<Vec<String>>) -> String {
+    let mut html = String::from("<table><tr>");
+    for h in header {
+        write!(html, "<th>{}</th>", escape(h)).unwrap();
+    }
+    html.push_str("</tr>");
+
+    for row in rows {
+        html.push_str("<tr>");
+        for cell in row {
+            write!(html, "<td>{}</td>", cell).unwrap();
+        }
+        html.push_str("</tr>");
+    }
+    html.push_str("</table>");
+
+    return html;
+}
+
+fn file_meta_section(page: &mut Page, filemeta: &Option<Meta>) {
+    let body = match filemeta {
+        Some(meta) => table(
+            &["Measure", "Value"],
+            &vec![
+                vec!["Checksum".to_string(), escape(&meta.md5sum())],
+                vec![
+                    "File size (bytes)".to_string(),
+                    format!("{}", meta.filesize()),
+                ],
+            ],
+        ),
+        None => "<p>Not computed.</p>".to_string(),
+    };
+
+    section(page, "File Metadata", None, &body);
+}
+
+fn summary_section(page: &mut Page, fastqc: &FastQC) {
+    let rows: Vec<Vec<String>> = fastqc
+        .summary()
+        .iter()
+        .map(|s| {
+            vec![
+                badge(s.status()),
+                format!(
+                    "<a href=\"#{}\">{}</a>",
+                    anchor(&format!("{}{}", s.module(), page.suffix)),
+                    escape(&s.module())
+                ),
+            ]
+        })
+        .collect();
+
+    section(page, "Summary", None, &table(&["Status", "Module"], &rows));
+}
+
+fn basic_stats_section(page: &mut Page, fastqc: &FastQC) {
+    let basic_stats = &fastqc.basic_stats;
+    let seq_length = if basic_stats.min_length() == basic_stats.max_length() {
+        format!("{}", basic_stats.min_length())
+    } else {
+        format!("{}-{}", basic_stats.min_length(), basic_stats.max_length())
+    };
+
+    let rows = vec![
+        vec!["Filename".to_string(), escape(&basic_stats.file_name())],
+        vec!["Encoding".to_string(), escape(&basic_stats.phred().name())],
+        vec![
+            "Total Sequences".to_string(),
+            format!("{}", basic_stats.total_reads()),
+        ],
+        vec![
+            "Total Bases".to_string(),
+            format!("{}", basic_stats.total_bases()),
+        ],
+        vec!["Sequence length".to_string(), seq_length],
+        vec![
+            "%GC".to_string(),
+            format!("{:.1}", basic_stats.gc_percentage() * 100.0),
+        ],
+    ];
+
+    section(
+        page,
+        "Basic Statistics",
+        Some(basic_stats.status()),
+        &table(&["Measure", "Value"], &rows),
+    );
+}
+
+fn to_f64(values: &Vec<usize>) -> Vec<f64> {
+    return values.iter().map(|v| *v as f64).collect();
+}
+
+fn to_labels<T: ToString>(values: &Vec<T>) -> Vec<String> {
+    return values.iter().map(|v| v.to_string()).collect();
+}
+
+/// The module when it's enabled and not ignored in the limits.
+fn visible<T: QCModule>(module: &Option<T>) -> Option<&T> {
+    return module
+        .as_ref()
+        .filter(|module| module.status() != ModuleStatus::Ignore);
+}
+
+fn fastqc_sections(page: &mut Page, fastqc: &FastQC) {
+    basic_stats_section(page, fastqc);
+
+    if let Some(module) = visible(&fastqc.per_base_seq_quality) {
+        let chart = svg::quality_boxplot(
+            module.xlabels(),
+            module.lowest(),
+            module.lower_quartile(),
+            module.median(),
+            module.upper_quartile(),
+            module.highest(),
+            module.mean(),
+        );
+        section(
+            page,
+            "Per base sequence quality",
+            Some(module.status()),
+            &chart,
+        );
+    }
+
+    if let Some(module) = visible(&fastqc.per_tile_quality_score) {
+        let body = if module.tiles().len() > 0 {
+            let mut body = svg::heatmap(
+                module.x_labels(),
+                &to_labels(module.tiles()),
+                module.means(),
+                10.0,
+                "Position in read (bp)",
+                "Tile",
+            );
+            if module.unparsed_reads() > 0 {
+                body.push_str(&format!(
+                    "<p>{} sampled reads are skipped since the tile is not available in the header.</p>",
+                    module.unparsed_reads()
+                ));
+            }
+            body
+        } else {
+            "<p>No tile information is available.</p>".to_string()
+        };
+        section(
+            page,
+            "Per tile sequence quality",
+            Some(module.status()),
+            &body,
+        );
+    }
+
+    if let Some(module) = visible(&fastqc.per_seq_quality_score) {
+        let chart = svg::line_chart(
+            &to_labels(module.x_category_quality()),
+            &[Series::new(
+                "Average Quality per read",
+                &to_f64(module.y_category_count()),
+                PALETTE[1],
+            )],
+            "Mean Sequence Quality (Phred Score)",
+            "Count",
+            None,
+        );
+        section(
+            page,
+            "Per sequence quality scores",
+            Some(module.status()),
+            &chart,
+        );
+    }
+
+    if let Some(module) = visible(&fastqc.per_base_seq_content) {
+        // percentages: [T, C, A, G]
+        let names = ["%T", "%C", "%A", "%G"];
+        let colors = [PALETTE[1], PALETTE[0], PALETTE[2], "#000000"];
+        let series: Vec<Series> = (0..4)
+            .map(|i| Series::new(names[i], &module.percentages()[i], colors[i]))
+            .collect();
+        let chart = svg::line_chart(
+            module.x_category(),
+            &series,
+            "Position in read (bp)",
+            "Percentage (%)",
+            Some((0.0, 100.0)),
+        );
+        section(
+            page,
+            "Per base sequence content",
+            Some(module.status()),
+            &chart,
+        );
+    }
+
+    if let Some(module) = visible(&fastqc.per_seq_gc_content) {
+        let chart = svg::line_chart(
+            &to_labels(module.x_category()),
+            &[
+                Series::new("GC count per read", module.y_gc_distribution(), PALETTE[1]),
+                Series::new(
+                    "Theoretical Distribution",
+                    module.y_theo_distribution(),
+                    PALETTE[0],
+                ),
+            ],
+            "Mean GC content (%)",
+            "Count",
+            None,
+        );
+        section(
+            page,
+            "Per sequence GC content",
+            Some(module.status()),
+            &chart,
+        );
+    }
+
+    if let Some(module) = visible(&fastqc.per_base_n_content) {
+        let chart = svg::line_chart(
+            module.x_categories(),
+            &[Series::new("%N", module.percentages(), PALETTE[1])],
+            "Position in read (bp)",
+            "Percentage (%)",
+            Some((0.0, 100.0)),
+        );
+        section(page, "Per base N content", Some(module.status()), &chart);
+    }
+
+    if let Some(module) = visible(&fastqc.seq_len_distribution) {
+        let chart = svg::line_chart(
+            module.x_categories(),
+            &[Series::new(
+                "Sequence Length",
+                module.graph_counts(),
+                PALETTE[1],
+            )],
+            "Sequence Length (bp)",
+            "Count",
+            None,
+        );
+        section(
+            page,
+            "Sequence Length Distribution",
+            Some(module.status()),
+            &chart,
+        );
+    }
+
+    if let Some(module) = visible(&fastqc.seq_duplication_level) {
+        let mut body = format!(
+            "<p>Percent of seqs remaining if deduplicated: {:.2}%</p>",
+            module.percent_diff_seq()
+        );
+        if let Some(overrepresented) = &fastqc.overrepresented_seqs {
+            body.push_str(&dup_length_note(overrepresented.dup_length()));
+        }
+        body.push_str(&svg::line_chart(
+            module.labels(),
+            &[
+                Series::new(
+                    "% Deduplicated sequences",
+                    module.dedup_percentages(),
+                    PALETTE[1],
+                ),
+                Series::new("% Total sequences", module.total_percentages(), PALETTE[0]),
+            ],
+            "Sequence Duplication Level",
+            "Percentage (%)",
+            Some((0.0, 100.0)),
+        ));
+        section(
+            page,
+            "Sequence Duplication Levels",
+            Some(module.status()),
+            &body,
+        );
+    }
+
+    if let Some(module) = visible(&fastqc.sketch_duplication_level) {
+        sketch_duplication_section(page, module);
+    }
+
+    if let Some(module) = visible(&fastqc.library_complexity) {
+        complexity_section(page, module);
+    }
+
+    if let Some(module) = visible(&fastqc.overrepresented_seqs) {
+        let mut body = dup_length_note(module.dup_length());
+        body.push_str(&if module.overrepresented_seqs().len() > 0 {
+            let rows: Vec<Vec<String>> = module
+                .overrepresented_seqs()
+                .iter()
+                .map(|os| {
+                    vec![
+                        format!("<span class=\"seq\">{}</span>", escape(&os.seq())),
+                        format!("{}", os.count()),
+                        format!("{:.4}", os.percentage()),
+                        escape(&os.contaminant_hit()),
+                    ]
+                })
+                .collect();
+            table(
+                &["Sequence", "Count", "Percentage", "Possible Source"],
+                &rows,
+            )
+        } else {
+            "<p>No overrepresented sequences.</p>".to_string()
+        });
+        section(
+            page,
+            "Overrepresented sequences",
+            Some(module.status()),
+            &body,
+        );
+    }
+
+    if let Some(module) = visible(&fastqc.adapter_content) {
+        let series: Vec<Series> = module
+            .labels()
+            .iter()
+            .enumerate()
+            .map(|(i, label)| {
+                Series::new(label, &module.enrichments()[i], PALETTE[i % PALETTE.len()])
+            })
+            .collect();
+        let chart = svg::line_chart(
+            module.x_labels(),
+            &series,
+            "Position in read (bp)",
+            "Percentage (%)",
+            Some((0.0, 100.0)),
+        );
+        let rows: Vec<Vec<String>> = module
+            .hits()
+            .iter()
+            .map(|hits| {
+                vec![
+                    escape(hits.name()),
+                    format!("{}", hits.full()),
+                    format!("{}", hits.mismatched()),
+                    format!("{}", hits.partial()),
+                ]
+            })
+            .collect();
+        let mut body = format!(
+            "<p>Reads with any adapter: {} of {}, up to {} mismatches in a full adapter, partial adapters of at least {}bp at the 3' end.</p>",
+            module.reads_with_adapter(),
+            module.total_count(),
+            module.max_mismatches(),
+            module.min_overlap()
+        );
+        if let Some(detection) = module.detection() {
+            body.push_str(&detection_note(detection));
+        }
+        body.push_str(&table(
+            &["Adapter", "Full", "Full with mismatches", "Partial (3')"],
+            &rows,
+        ));
+        body.push_str(&chart);
+        section(page, "Adapter Content", Some(module.status()), &body);
+    }
+
+    if let Some(module) = visible(&fastqc.kmer_content) {
+        let mut body = if module.min_kmer_size() == module.max_kmer_size() {
+            format!("<p>Kmer size: {}</p>", module.min_kmer_size())
+        } else {
+            format!(
+                "<p>Kmer size: {}..{}</p>",
+                module.min_kmer_size(),
+                module.max_kmer_size()
+            )
+        };
+        body.push_str(&if module.enriched_kmers().len() > 0 {
+            // Only the top six kmers are drawn, the same as FastQC
+            let series: Vec<Series> = module
+                .enriched_kmers()
+                .iter()
+                .take(6)
+                .enumerate()
+                .map(|(i, kmer)| {
+                    Series::new(&kmer.sequence(), &module.enrichments()[i], PALETTE[i])
+                })
+                .collect();
+            let mut body = svg::line_chart(
+                module.x_labels(),
+                &series,
+                "Position in read (bp)",
+                "Obs/Exp",
+                None,
+            );
+
+            let rows: Vec<Vec<String>> = module
+                .enriched_kmers()
+                .iter()
+                .filter_map(|kmer| {
+                    // max_position starts from 1, a kmer never seen at any position has none
+                    let index = kmer.max_position().checked_sub(1)?;
+                    let position = match module.x_categories().get(index) {
+                        Some(category) => category.clone(),
+                        None => format!("{}", kmer.max_position()),
+                    };
+                    Some(vec![
+                        format!("<span class=\"seq\">{}</span>", escape(&kmer.sequence())),
+                        format!("{}", kmer.count()),
+                        format!("{:.3e}", kmer.lowest_pvalue()),
+                        format!("{:.2}", kmer.max_obs_exp()),
+                        escape(&position),
+                    ])
+                })
+                .collect();
+            body.push_str(&table(
+                &[
+                    "Sequence",
+                    "Count",
+                    "PValue",
+                    "Obs/Exp Max",
+                    "Max Obs/Exp Position",
+                ],
+                &rows,
+            ));
+            body
+        } else {
+            "<p>No overrepresented Kmers.</p>".to_string()
+        });
+        section(page, "Kmer Content", Some(module.status()), &body);
+    }
+
+    if let Some(module) = visible(&fastqc.long_read_stats) {
+        long_read_section(page, module);
+    }
+
+    if let Some(module) = visible(&fastqc.per_lane_stats) {
+        lane_section(page, module);
+    }
+
+    if let Some(module) = visible(&fastqc.index_stats) {
+        index_section(page, module);
+    }
+
+    if let Some(module) = visible(&fastqc.umi_stats) {
+        umi_section(page, module);
+    }
+
+    if let Some(module) = visible(&fastqc.low_complexity) {
+        low_complexity_section(page, module);
+    }
+
+    if let Some(module) = visible(&fastqc.trim_simulation) {
+        trimming_section(page, module);
+    }
+
+    if let Some(module) = visible(&fastqc.screen) {
+        screen_section(page, module);
+    }
+
+    if let Some(module) = visible(&fastqc.taxonomy) {
+        taxonomy_section(page, module);
+    }
+
+    if let Some(module) = visible(&fastqc.sex_check) {
+        sex_section(page, module);
+    }
+
+    if let Some(module) = visible(&fastqc.bisulfite) {
+        bisulfite_section(page, module);
+    }
+}
+
+fn lane_section(page: &mut Page, module: &PerLaneStats) {
+    let text = |field: &Option<String>| escape(field.as_ref().map_or("NA", |f| f.as_str()));
+    let mut rows: Vec<Vec<String>> = module
+        .lanes()
+        .iter()
+        .map(|lane| {
+            vec![
+                text(lane.instrument()),
+                text(lane.run()),
+                text(lane.flowcell()),
+                format!("{}", lane.lane()),
+                format!("{}", lane.reads()),
+                format!("{:.2}", lane.percent_q30()),
+                format!("{:.2}", lane.percent_gc()),
+                format!("{:.3}", lane.percent_n()),
+                format!("{:.2}", lane.mean_quality()),
+                format!("{} {}", badge(lane.status()), lane.flags().join(", ")),
+            ]
+        })
+        .collect();
+    if let Some(overall) = module.overall() {
+        rows.push(vec![
+            "All lanes".to_string(),
+            "".to_string(),
+            "".to_string(),
+            "".to_string(),
+            format!("{}", overall.reads()),
+            format!("{:.2}", overall.percent_q30()),
+            format!("{:.2}", overall.percent_gc()),
+            format!("{:.3}", overall.percent_n()),
+            format!("{:.2}", overall.mean_quality()),
+            "".to_string(),
+        ]);
+    }
+
+    let mut body = table(
+        &[
+            "Instrument",
+            "Run",
+            "Flowcell",
+            "Lane",
+            "Reads",
+            "Q30 (%)",
+            "GC (%)",
+            "N (%)",
+            "Mean quality",
+            "Deviation",
+        ],
+        &rows,
+    );
+    if module.unparsed_reads() > 0 {
+        body.push_str(&format!(
+            "<p>{} reads are not counted since the lane is not available in the header.</p>",
+            module.unparsed_reads()
+        ));
+    }
+    section(page, "Per lane statistics", Some(module.status()), &body);
+}
+
+fn index_section(page: &mut Page, module: &IndexStats) {
+    let percent = |value: Option<f64>| value.map_or("NA".to_string(), |v| format!("{:.2}", v));
+    let mut body = table(
+        &["Metric", "Value"],
+        &vec![
+            vec![
+                "Reads with an index".to_string(),
+                format!("{}", module.reads_with_index()),
+            ],
+            vec![
+                "Distinct barcodes".to_string(),
+                format!("{}", module.distinct_barcodes()),
+            ],
+            vec![
+                "Expected index (%)".to_string(),
+                percent(module.percent_expected()),
+            ],
+            vec![
+                "Index hopping (%)".to_string(),
+                percent(module.percent_hopped()),
+            ],
+            vec![
+                "Index with N (%)".to_string(),
+                format!("{:.2}", module.percent_with_n()),
+            ],
+        ],
+    );
+
+    let rows: Vec<Vec<String>> = module
+        .top_barcodes()
+        .iter()
+        .map(|barcode| {
+            vec![
+                escape(barcode.barcode()),
+                format!("{}", barcode.count()),
+                format!("{:.2}", barcode.percent()),
+                if barcode.expected() { "Yes" } else { "No" }.to_string(),
+            ]
+        })
+        .collect();
+    body.push_str("<p>Top barcodes:</p>");
+    body.push_str(&table(
+        &["Barcode", "Count", "Percentage", "Expected"],
+        &rows,
+    ));
+    if module.expected_indexes().len() == 0 {
+        body.push_str("<p>No index is expected, see --expected-index and --sample-sheet.</p>");
+    }
+    if module.unparsed_reads() > 0 {
+        body.push_str(&format!(
+            "<p>{} reads are not counted since the index is not available in the header.</p>",
+            module.unparsed_reads()
+        ));
+    }
+    section(page, "Index composition", Some(module.status()), &body);
+}
+
+fn low_complexity_section(page: &mut Page, module: &LowComplexity) {
+    let mut body = format!(
+        "<p>{} reads ({:.2}%) have a DUST score above {}, {:.2}% of reads have a poly-X tail of at least {}bp.</p>",
+        module.low_complexity_reads(),
+        module.percent_low_complexity(),
+        module.dust_threshold(),
+        module.percent_poly_x(),
+        module.poly_x_min_length()
+    );
+
+    let total_reads = module.total_reads() as f64;
+    let distribution: Vec<f64> = module
+        .dust_distribution()
+        .iter()
+        .map(|count| *count as f64 / total_reads * 100.0)
+        .collect();
+    let labels: Vec<String> = (0..distribution.len()).map(|i| i.to_string()).collect();
+    body.push_str(&svg::line_chart(
+        &labels,
+        &[Series::new("% Reads", &distribution, PALETTE[1])],
+        "DUST score",
+        "Percentage (%)",
+        None,
+    ));
+
+    let tails = module.poly_x_tails();
+    let max_length = tails.iter().map(|t| t.lengths().len()).max().unwrap_or(0);
+    if max_length > module.poly_x_min_length() {
+        let labels: Vec<String> = (module.poly_x_min_length()..max_length)
+            .map(|i| i.to_string())
+            .collect();
+        let series: Vec<Series> = tails
+            .iter()
+            .enumerate()
+            .map(|(i, tail)| {
+                let values: Vec<f64> = (module.poly_x_min_length()..max_length)
+                    .map(|length| {
+                        *tail.lengths().get(length).unwrap_or(&0) as f64 / total_reads * 100.0
+                    })
+                    .collect();
+                Series::new(&format!("poly-{}", tail.base()), &values, PALETTE[i])
+            })
+            .collect();
+        body.push_str(&svg::line_chart(
+            &labels,
+            &series,
+            "Length of the 3' tail (bp)",
+            "Percentage (%)",
+            None,
+        ));
+    }
+
+    let rows: Vec<Vec<String>> = tails
+        .iter()
+        .map(|tail| {
+            vec![
+                format!("poly-{}", tail.base()),
+                format!("{}", tail.reads()),
+                format!("{:.2}", tail.percentage()),
+                format!("{:.1}", tail.mean_length()),
+            ]
+        })
+        .collect();
+    body.push_str(&table(
+        &["Tail", "Reads", "Percentage (%)", "Mean length (bp)"],
+        &rows,
+    ));
+    section(
+        page,
+        "Low complexity sequences",
+        Some(module.status()),
+        &body,
+    );
+}
+
+fn trimming_section(page: &mut Page, module: &TrimSimulation) {
+    let rows = vec![
+        vec![
+            "Ns at both ends".to_string(),
+            format!("{}", module.n_trimmed_reads()),
+            format!("{}", module.n_trimmed_bases()),
+        ],
+        vec![
+            "Adapter".to_string(),
+            format!("{}", module.adapter_trimmed_reads()),
+            format!("{}", module.adapter_trimmed_bases()),
+        ],
+        vec![
+            "Sliding window quality".to_string(),
+            format!("{}", module.quality_trimmed_reads()),
+            format!("{}", module.quality_trimmed_bases()),
+        ],
+    ];
+    let mut body = format!(
+        "<p>Predicted surviving reads: {} ({:.2}%), surviving bases: {} ({:.2}%), mean length: {:.1}bp.</p><p>{} reads ({:.2}%) are shorter than {}bp after trimming and dropped.</p>",
+        module.surviving_reads(),
+        module.percent_surviving_reads(),
+        module.surviving_bases(),
+        module.percent_surviving_bases(),
+        module.mean_length(),
+        module.dropped_reads(),
+        module.percent_dropped_reads(),
+        module.min_length()
+    );
+    body.push_str(&table(&["Trimmed by", "Reads", "Bases"], &rows));
+
+    let distribution = module.length_distribution();
+    if distribution.len() > 0 {
+        let labels: Vec<String> = (0..distribution.len()).map(|i| i.to_string()).collect();
+        let values: Vec<f64> = distribution
+            .iter()
+            .map(|count| *count as f64 / module.total_reads() as f64 * 100.0)
+            .collect();
+        body.push_str(&svg::line_chart(
+            &labels,
+            &[Series::new("% Reads", &values, PALETTE[1])],
+            "Length after trimming (bp)",
+            "Percentage (%)",
+            None,
+        ));
+    }
+    section(page, "Trimming simulation", Some(module.status()), &body);
+}
+
+fn screen_section(page: &mut Page, module: &ContaminationScreen) {
+    let rows: Vec<Vec<String>> = module
+        .genomes()
+        .iter()
+        .map(|genome| {
+            vec![
+                escape(genome.genome()),
+                format!("{:.2}", genome.percent_mapped()),
+                format!("{:.2}", genome.percent_unique_one_genome()),
+                format!("{:.2}", genome.percent_multi_mapping_one_genome()),
+                format!("{:.2}", genome.percent_unique_multiple_genomes()),
+                format!("{:.2}", genome.percent_multi_mapping_multiple_genomes()),
+            ]
+        })
+        .collect();
+    let mut body = format!(
+        "<p>{} of {} reads are screened (one out of {}), {:.2}% hit no genomes.</p>",
+        module.screened_reads(),
+        module.total_reads(),
+        module.sample_interval(),
+        module.percent_hit_no_genomes()
+    );
+    body.push_str(&table(
+        &[
+            "Genome",
+            "Mapped (%)",
+            "Unique, one genome (%)",
+            "Multi-mapping, one genome (%)",
+            "Unique, multiple genomes (%)",
+            "Multi-mapping, multiple genomes (%)",
+        ],
+        &rows,
+    ));
+    section(page, "Contamination screen", Some(module.status()), &body);
+}
+
+fn taxonomy_section(page: &mut Page, module: &TaxonomicProfile) {
+    // Only the most abundant taxa at each rank, the rest are in the json
+    let top_taxa = 5;
+    let mut rows: Vec<Vec<String>> = vec![];
+    for rank in module.ranks() {
+        for taxon in rank.taxa().iter().take(top_taxa) {
+            rows.push(vec![
+                escape(rank.rank()),
+                format!("{} ({})", escape(taxon.name()), taxon.taxid()),
+                taxon.reads().to_string(),
+                format!("{:.2}", taxon.percentage()),
+            ]);
+        }
+        if rank.unresolved_reads() > 0 {
+            rows.push(vec![
+                escape(rank.rank()),
+                "Unresolved at this rank".to_string(),
+                rank.unresolved_reads().to_string(),
+                format!("{:.2}", rank.percent_unresolved()),
+            ]);
+        }
+    }
+    let mut body = format!(
+        "<p>{} of {} reads are sampled (one out of {}), {:.2}% of them are classified.</p>",
+        module.sampled_reads(),
+        module.total_reads(),
+        module.sample_interval(),
+        module.percent_classified()
+    );
+    body.push_str(&table(&["Rank", "Taxon", "Reads", "Reads (%)"], &rows));
+    section(page, "Taxonomic profile", Some(module.status()), &body);
+}
+
+fn sex_section(page: &mut Page, module: &SexCheck) {
+    let rows = vec![
+        vec![
+            "Expected sex".to_string(),
+            module
+                .expected_sex()
+                .map_or("NA".to_string(), |sex| sex.to_string()),
+        ],
+        vec![
+            "Inferred sex".to_string(),
+            module.inferred_sex().to_string(),
+        ],
+        vec![
+            "Confidence".to_string(),
+            format!("{:.4}", module.confidence()),
+        ],
+        vec![
+            "chrX reads (per million)".to_string(),
+            format!("{} ({:.1})", module.x_reads(), module.x_reads_per_million()),
+        ],
+        vec![
+            "chrY reads (per million)".to_string(),
+            format!("{} ({:.1})", module.y_reads(), module.y_reads_per_million()),
+        ],
+        vec![
+            "chrY/chrX ratio".to_string(),
+            module
+                .y_x_ratio()
+                .map_or("NA".to_string(), |ratio| format!("{:.3}", ratio)),
+        ],
+    ];
+    let mut body = String::from(
+        "<p>The chrY/chrX ratio of the reads per k-mer is about 1 for a male and 0 for a female.</p>",
+    );
+    body.push_str(&table(&["Metric", "Value"], &rows));
+    section(page, "Sex check", Some(module.status()), &body);
+}
+
+fn bisulfite_section(page: &mut Page, module: &BisulfiteStats) {
+    let mut rows = vec![
+        vec![
+            "Library type".to_string(),
+            module.library_type().to_string(),
+        ],
+        vec![
+            "OT/OB reads (C to T)".to_string(),
+            format!("{}", module.converted_reads()),
+        ],
+        vec![
+            "CTOT/CTOB reads (G to A)".to_string(),
+            format!("{}", module.complementary_reads()),
+        ],
+        vec![
+            "Unconverted reads".to_string(),
+            format!(
+                "{} ({:.2}%)",
+                module.unconverted_reads(),
+                module.percent_unconverted_reads()
+            ),
+        ],
+    ];
+    if module.spike_in().is_some() {
+        rows.push(vec![
+            "Spike-in reads".to_string(),
+            format!("{}", module.spike_in_reads()),
+        ]);
+        rows.push(vec![
+            "Conversion rate in CHH context".to_string(),
+            module
+                .conversion_rate()
+                .map_or("NA".to_string(), |rate| format!("{:.2}%", rate)),
+        ]);
+    } else {
+        rows.push(vec![
+            "Conversion rate in CHH context (upper bound, by the reads)".to_string(),
+            module
+                .conversion_rate_upper_bound()
+                .map_or("NA".to_string(), |rate| format!("{:.2}%", rate)),
+        ]);
+    }
+    let mut body = table(&["Metric", "Value"], &rows);
+
+    let m_bias = module.m_bias_relative();
+    if m_bias.len() > 0 {
+        let labels: Vec<String> = (1..m_bias.len() + 1).map(|i| i.to_string()).collect();
+        body.push_str("<p>M-bias: the CpG level by the CG and TG in the reads relative to all positions. The genomic TG is a constant background, so it's not the methylation and only the shape matters. The ends which deviate from 1 should be trimmed before calling methylation.</p>");
+        body.push_str(&svg::line_chart(
+            &labels,
+            &[Series::new("Relative CpG level", m_bias, PALETTE[0])],
+            "Position in read (bp)",
+            "Relative level",
+            None,
+        ));
+    }
+    section(page, "Bisulfite conversion", Some(module.status()), &body);
+}
+
+fn umi_section(page: &mut Page, module: &UmiStats) {
+    let mut rows = vec![
+        vec!["Source".to_string(), escape(module.source())],
+        vec![
+            "Reads with a UMI".to_string(),
+            format!("{}", module.reads_with_umi()),
+        ],
+        vec![
+            "Reads without a valid UMI".to_string(),
+            format!("{}", module.unparsed_reads()),
+        ],
+        vec![
+            "UMIs with N".to_string(),
+            format!("{}", module.umis_with_n()),
+        ],
+        vec![
+            "Distinct UMIs".to_string(),
+            format!("{}", module.distinct_umis()),
+        ],
+        vec![
+            "Effective UMIs".to_string(),
+            format!("{:.1}", module.effective_umis()),
+        ],
+        vec![
+            "Collision probability".to_string(),
+            format!("{:.3e}", module.collision_probability()),
+        ],
+    ];
+    for (length, count) in module.length_distribution() {
+        rows.push(vec![format!("Length {}bp", length), format!("{}", count)]);
+    }
+    let mut body = table(&["Metric", "Value"], &rows);
+
+    let composition = module.base_composition();
+    if composition.len() > 0 {
+        let labels: Vec<String> = (1..composition.len() + 1).map(|i| i.to_string()).collect();
+        let names = ["%A", "%C", "%G", "%T", "%N"];
+        let series: Vec<Series> = (0..5)
+            .map(|b| {
+                let values: Vec<f64> = composition.iter().map(|p| p[b]).collect();
+                Series::new(names[b], &values, PALETTE[b])
+            })
+            .collect();
+        body.push_str(&svg::line_chart(
+            &labels,
+            &series,
+            "Position in UMI (bp)",
+            "Percentage (%)",
+            Some((0.0, 100.0)),
+        ));
+    }
+
+    if module.mean_qualities().len() > 0 {
+        let labels: Vec<String> = (1..module.mean_qualities().len() + 1)
+            .map(|i| i.to_string())
+            .collect();
+        body.push_str(&svg::line_chart(
+            &labels,
+            &[Series::new(
+                "Mean quality",
+                module.mean_qualities(),
+                PALETTE[1],
+            )],
+            "Position in UMI (bp)",
+            "Quality",
+            None,
+        ));
+    }
+
+    if let Some(duplication) = module.umi_duplication() {
+        body.push_str(&format!(
+            "<p>Percent of UMI + sequence remaining if deduplicated: {:.2}%</p>",
+            duplication.percent_diff_seq()
+        ));
+    }
+    section(page, "UMI statistics", Some(module.status()), &body);
+}
+
+fn long_read_section(page: &mut Page, module: &LongReadStats) {
+    let mut rows = vec![
+        vec![
+            "Total Reads".to_string(),
+            format!("{}", module.total_reads()),
+        ],
+        vec![
+            "Total Bases".to_string(),
+            format!("{}", module.total_bases()),
+        ],
+        vec![
+            "Mean Length".to_string(),
+            format!("{:.1}", module.mean_length()),
+        ],
+        vec!["Longest".to_string(), format!("{}", module.longest())],
+        vec!["N50".to_string(), format!("{}", module.n50())],
+        vec!["N90".to_string(), format!("{}", module.n90())],
+    ];
+    for y in module.length_yield() {
+        rows.push(vec![
+            format!("Length >= {}bp", y.cutoff()),
+            format!("{} reads, {} bases", y.reads(), y.bases()),
+        ]);
+    }
+    for y in module.quality_yield() {
+        rows.push(vec![
+            format!("Mean quality >= Q{}", y.cutoff()),
+            format!("{} reads, {} bases", y.reads(), y.bases()),
+        ]);
+    }
+
+    let mut body = table(&["Measure", "Value"], &rows);
+    body.push_str(&svg::line_chart(
+        &to_labels(module.length_bins()),
+        &[Series::new(
+            "Reads",
+            &to_f64(module.length_counts()),
+            PALETTE[1],
+        )],
+        "Read length (bp, log scale)",
+        "Count",
+        None,
+    ));
+    body.push_str(&svg::line_chart(
+        &to_labels(module.mean_quality_bins()),
+        &[Series::new(
+            "Reads",
+            &to_f64(module.mean_quality_counts()),
+            PALETTE[1],
+        )],
+        "Mean read quality (Phred score)",
+        "Count",
+        None,
+    ));
+    body.push_str(&svg::line_chart(
+        module.position_labels(),
+        &[Series::new(
+            "Mean quality",
+            module.position_mean_quality(),
+            PALETTE[1],
+        )],
+        "Position in read (% of read length)",
+        "Phred score",
+        None,
+    ));
+    body.push_str(&svg::line_chart(
+        module.position_labels(),
+        &[
+            Series::new("%GC", module.position_gc(), PALETTE[0]),
+            Series::new("%N", module.position_n(), PALETTE[3]),
+        ],
+        "Position in read (% of read length)",
+        "Percentage (%)",
+        Some((0.0, 100.0)),
+    ));
+
+    section(page, "Long read statistics", Some(module.status()), &body);
+}
+
+fn detection_note(detection: &AdapterDetection) -> String {
+    let sequence = match detection.sequence() {
+        Some(sequence) => sequence,
+        None => {
+            return format!(
+                "<p>No adapter is detected in the first {} reads ({}).</p>",
+                detection.sampled_reads(),
+                detection.method()
+            )
+        }
+    };
+
+    let closest = match (detection.closest_adapter(), detection.closest_mismatches()) {
+        (Some(name), Some(mismatches)) => {
+            format!(
+                ", the closest known adapter is {} with {} mismatches",
+                escape(name),
+                mismatches
+            )
+        }
+        _ => "".to_string(),
+    };
+    return format!(
+        "<p>Detected adapter ({}): <code>{}</code>, supported by {} of the first {} reads ({:.2}%){}.</p>",
+        detection.method(),
+        escape(sequence),
+        detection.support(),
+        detection.sampled_reads(),
+        detection.percent_support(),
+        closest
+    );
+}
+
+fn complexity_section(page: &mut Page, module: &LibraryComplexity) {
+    let points = module.extrapolation();
+    let mut body = format!(
+        "<p>Estimated library size (Chao1 lower bound): {:.0} distinct molecules.</p>",
+        module.estimated_library_size()
+    );
+    if module.method() == "good_toulmin" {
+        body.push_str("<p>No stable rational function was found, the Good-Toulmin estimate is only given up to twice the depth.</p>");
+    }
+    if module.input_reads() > module.total_reads() {
+        body.push_str(&format!(
+            "<p>Estimated from the first {} of {} reads, before the unique sequence limit of overrepresented sequences, the depth is relative to them. Use the sketch engine of duplication for the whole file.</p>",
+            module.total_reads(),
+            module.input_reads()
+        ));
+    }
+
+    let labels: Vec<String> = points.iter().map(|p| format!("{}x", p.fold())).collect();
+    let values: Vec<f64> = points.iter().map(|p| p.distinct_reads()).collect();
+    body.push_str(&svg::line_chart(
+        &labels,
+        &[Series::new("Expected distinct reads", &values, PALETTE[1])],
+        "Sequencing depth",
+        "Distinct reads",
+        None,
+    ));
+    let rows: Vec<Vec<String>> = points
+        .iter()
+        .map(|p| {
+            vec![
+                format!("{}x", p.fold()),
+                format!("{}", p.reads()),
+                format!("{:.0}", p.distinct_reads()),
+            ]
+        })
+        .collect();
+    body.push_str(&table(
+        &["Depth", "Reads", "Expected distinct reads"],
+        &rows,
+    ));
+    section(page, "Library complexity", Some(module.status()), &body);
+}
+
+fn sketch_duplication_section(page: &mut Page, module: &SketchDuplicationLevel) {
+    let mut body = format!(
+        "<p>Percent of seqs remaining if deduplicated: {:.2}%</p><p>Estimated distinct reads: {:.0} of {} reads.</p>",
+        module.percent_diff_seq(),
+        module.estimated_distinct_reads(),
+        module.total_reads()
+    );
+    if module.sample_level() > 0 {
+        body.push_str(&format!(
+            "<p>The duplication levels are computed from 1 out of {} distinct sequences.</p>",
+            1usize << module.sample_level()
+        ));
+    }
+    body.push_str(&dup_length_note(module.dup_length()));
+    body.push_str(&svg::line_chart(
+        module.labels(),
+        &[
+            Series::new(
+                "% Deduplicated sequences",
+                module.dedup_percentages(),
+                PALETTE[1],
+            ),
+            Series::new("% Total sequences", module.total_percentages(), PALETTE[0]),
+        ],
+        "Sequence Duplication Level",
+        "Percentage (%)",
+        Some((0.0, 100.0)),
+    ));
+    section(
+        page,
+        "Sequence Duplication Levels (sketch)",
+        Some(module.status()),
+        &body,
+    );
+}
+
+fn dup_length_note(dup_length: usize) -> String {
+    if dup_length == 0 {
+        return "<p>Reads longer than 75bp were truncated to 50bp.</p>".to_string();
+    }
+
+    return format!("<p>Reads were truncated to {}bp.</p>", dup_length);
+}
+
+fn paired_section(page: &mut Page, paired: &PairedMetrics) {
+    let percentage = |count: usize| {
+        if paired.total_pairs() > 0 {
+            count as f64 / paired.total_pairs() as f64 * 100.0
+        } else {
+            0.0
+        }
+    };
+    let first_desync = match paired.first_desync() {
+        Some(desync) => format!(
+            "Pair {}: {} / {}",
+            desync.pair(),
+            escape(desync.r1().as_ref().map_or("<missing>", |n| n.as_str())),
+            escape(desync.r2().as_ref().map_or("<missing>", |n| n.as_str()))
+        ),
+        None => "None".to_string(),
+    };
+
+    let rows = vec![
+        vec![
+            "Total Pairs".to_string(),
+            format!("{}", paired.total_pairs()),
+        ],
+        vec![
+            "Unpaired R1 / R2".to_string(),
+            format!("{} / {}", paired.unpaired_r1(), paired.unpaired_r2()),
+        ],
+        vec![
+            "Read name mismatches".to_string(),
+            format!("{}", paired.name_mismatches()),
+        ],
+        vec!["First desync".to_string(), first_desync],
+        vec![
+            "Overlapping pairs".to_string(),
+            format!(
+                "{} ({:.2}%)",
+                paired.overlapping_pairs(),
+                percentage(paired.overlapping_pairs())
+            ),
+        ],
+        vec![
+            "Insert size (mean / median)".to_string(),
+            format!(
+                "{:.1} / {}",
+                paired.mean_insert_size(),
+                paired.median_insert_size()
+            ),
+        ],
+        vec![
+            "Duplicate pairs".to_string(),
+            format!("{:.2}%", paired.percent_duplicate_pairs()),
+        ],
+    ];
+
+    let mut body = table(&["Measure", "Value"], &rows);
+    if paired.overlapping_pairs() > 0 {
+        let x_labels: Vec<String> = (0..paired.insert_sizes().len())
+            .map(|i| i.to_string())
+            .collect();
+        body.push_str(&svg::line_chart(
+            &x_labels,
+            &[Series::new(
+                "Overlapping pairs",
+                &to_f64(paired.insert_sizes()),
+                PALETTE[1],
+            )],
+            "Insert size (bp)",
+            "Count",
+            None,
+        ));
+    }
+
+    section(page, "Paired-end", None, &body);
+}
+
+fn alignment_section(page: &mut Page, alignment: &AlignmentStats) {
+    let mut rows = vec![
+        vec!["Index".to_string(), escape(alignment.index())],
+        vec![
+            "Aligned reads".to_string(),
+            format!("{}", alignment.total_reads()),
+        ],
+        vec![
+            "Mapped reads".to_string(),
+            format!(
+                "{} ({:.2}%)",
+                alignment.mapped_reads(),
+                alignment.percent_mapped()
+            ),
+        ],
+        vec![
+            "Mismatch rate".to_string(),
+            format!("{:.2}%", alignment.mismatch_rate()),
+        ],
+    ];
+    if alignment.total_pairs() > 0 {
+        rows.push(vec![
+            "Proper pairs".to_string(),
+            format!(
+                "{} ({:.2}%)",
+                alignment.proper_pairs(),
+                alignment.percent_proper_pairs()
+            ),
+        ]);
+        rows.push(vec![
+            "Insert size (mean / median)".to_string(),
+            format!(
+                "{:.1} / {}",
+                alignment.mean_insert_size(),
+                alignment.median_insert_size()
+            ),
+        ]);
+    }
+    let mut body = table(&["Measure", "Value"], &rows);
+
+    if alignment.mapped_reads() > 0 {
+        body.push_str(&svg::line_chart(
+            &to_labels(&(0..alignment.mapq_distribution().len()).collect()),
+            &[Series::new(
+                "Mapped reads",
+                &to_f64(alignment.mapq_distribution()),
+                PALETTE[1],
+            )],
+            "MAPQ",
+            "Count",
+            None,
+        ));
+        body.push_str(&svg::line_chart(
+            &to_labels(&(1..=alignment.mismatch_rate_per_cycle().len()).collect()),
+            &[Series::new(
+                "Mismatch rate",
+                alignment.mismatch_rate_per_cycle(),
+                PALETTE[0],
+            )],
+            "Position in read (bp)",
+            "Mismatches (%)",
+            None,
+        ));
+    }
+    if alignment.proper_pairs() > 0 {
+        body.push_str(&svg::line_chart(
+            &to_labels(&(0..alignment.insert_sizes().len()).collect()),
+            &[Series::new(
+                "Proper pairs",
+                &to_f64(alignment.insert_sizes()),
+                PALETTE[1],
+            )],
+            "Insert size (bp)",
+            "Count",
+            None,
+        ));
+    }
+
+    let contigs: Vec<Vec<String>> = alignment
+        .contigs()
+        .iter()
+        .filter(|contig| contig.reads() > 0)
+        .map(|contig| {
+            vec![
+                escape(contig.name()),
+                format!("{}", contig.length()),
+                format!("{}", contig.reads()),
+                format!("{:.2}", contig.percent_reads()),
+                format!("{:.2}", contig.percent_length()),
+            ]
+        })
+        .collect();
+    if contigs.len() > 0 {
+        body.push_str(&table(
+            &[
+                "Contig",
+                "Length",
+                "Reads",
+                "Mapped reads (%)",
+                "Expected by length (%)",
+            ],
+            &contigs,
+        ));
+    }
+    section(page, "Alignment", None, &body);
+}
+
+fn filter_section(page: &mut Page, stats: &FilterStats, results: &QCResults) {
+    let percentage = |count: usize| {
+        if stats.total_reads() > 0 {
+            count as f64 / stats.total_reads() as f64 * 100.0
+        } else {
+            0.0
+        }
+    };
+    let params = stats.params();
+    let mut rows = vec![
+        (
+            format!("Shorter than {}bp", params.min_length()),
+            stats.too_short(),
+        ),
+        (
+            format!("More than {} Ns", params.max_n()),
+            stats.too_many_n(),
+        ),
+    ];
+    if params.min_mean_quality() > 0 {
+        rows.push((
+            format!("Mean quality lower than {}", params.min_mean_quality()),
+            stats.low_quality(),
+        ));
+    }
+    if params.low_complexity() {
+        rows.push(("Low complexity".to_string(), stats.low_complexity()));
+    }
+    if params.adapter() {
+        rows.push(("With adapter".to_string(), stats.with_adapter()));
+    }
+    rows.push(("Passed".to_string(), stats.passed_reads()));
+    let rows: Vec<Vec<String>> = rows
+        .into_iter()
+        .map(|(reason, count)| {
+            vec![
+                escape(&reason),
+                format!("{}", count),
+                format!("{:.2}", percentage(count)),
+            ]
+        })
+        .collect();
+    let unit = if results.paired().is_some() {
+        "Pairs"
+    } else {
+        "Reads"
+    };
+    let mut body = table(&["Filter", unit, "Percentage (%)"], &rows);
+
+    let mut comparison = vec![];
+    for (label, before, after) in &[
+        ("", results.fastqc(), results.filtered_fastqc()),
+        (" (R2)", results.fastqc_r2(), results.filtered_fastqc_r2()),
+    ] {
+        if let (Some(before), Some(after)) = (before, after) {
+            let (before, after) = (&before.basic_stats, &after.basic_stats);
+            comparison.push(vec![
+                format!("Total Sequences{}", label),
+                format!("{}", before.total_reads()),
+                format!("{}", after.total_reads()),
+            ]);
+            comparison.push(vec![
+                format!("Total Bases{}", label),
+                format!("{}", before.total_bases()),
+                format!("{}", after.total_bases()),
+            ]);
+            comparison.push(vec![
+                format!("%GC{}", label),
+                format!("{:.1}", before.gc_percentage() * 100.0),
+                format!("{:.1}", after.gc_percentage() * 100.0),
+            ]);
+        }
+    }
+    if !comparison.is_empty() {
+        body.push_str(&table(&["Measure", "Before", "After"], &comparison));
+    }
+    section(page, "Read filter", None, &body);
+}
+
+fn vaf_section(page: &mut Page, vaf_matrix: &VAFMatrix) {
+    let mut rows: Vec<Vec<String>> = vec![];
+    let total = vaf_matrix.vaf().len();
+    for i in 0..total {
+        if let Some(vaf) = vaf_matrix.vaf()[i] {
+            rows.push(vec![
+                format!("{}", i),
+                format!("{}", vaf_matrix.reference()[i].unwrap_or(0)),
+                format!("{}", vaf_matrix.alternative()[i].unwrap_or(0)),
+                format!("{:.4}", vaf),
+            ]);
+        }
+    }
+
+    let mut body = format!("<p>{} of {} SNP sites are covered.</p>", rows.len(), total);
+    if rows.len() > 0 {
+        body.push_str(&table(&["Index", "Reference", "Alternative", "VAF"], &rows));
+    }
+
+    section(page, "Variant Allele Frequency (checkmate)", None, &body);
+}
+
+/// Render the results into a HTML page.
+///
+/// NOTE: The fastqc results must be finished before rendering.
+///
+pub fn render(results: &QCResults, title: &str) -> String {
+    let mut page = Page {
+        titles: vec![],
+        body: String::new(),
+        suffix: String::new(),
+    };
+    file_meta_section(&mut page, results.filemeta());
+    if let Some(fastqc) = results.fastqc() {
+        summary_section(&mut page, fastqc);
+        fastqc_sections(&mut page, fastqc);
+    }
+    if let Some(paired) = results.paired() {
+        paired_section(&mut page, paired);
+    }
+    if let Some(fastqc) = results.fastqc_r2() {
+        page.suffix = " (R2)".to_string();
+        file_meta_section(&mut page, results.filemeta_r2());
+        summary_section(&mut page, fastqc);
+        fastqc_sections(&mut page, fastqc);
+        page.suffix = String::new();
+    }
+    if let Some(alignment) = results.alignment() {
+        alignment_section(&mut page, alignment);
+    }
+    if let Some(stats) = results.filter_stats() {
+        filter_section(&mut page, stats, results);
+    }
+    if let Some(fastqc) = results.filtered_fastqc() {
+        page.suffix = " (filtered)".to_string();
+        summary_section(&mut page, fastqc);
+        fastqc_sections(&mut page, fastqc);
+        page.suffix = String::new();
+    }
+    if let Some(fastqc) = results.filtered_fastqc_r2() {
+        page.suffix = " (R2, filtered)".to_string();
+        summary_section(&mut page, fastqc);
+        fastqc_sections(&mut page, fastqc);
+        page.suffix = String::new();
+    }
+    if let Some(vaf_matrix) = results.vaf_matrix() {
+        vaf_section(&mut page, vaf_matrix);
+    }
+
+    let mut nav = String::from("<nav><ul>");
+    for title in &page.titles {
+        write!(
+            nav,
+            "<li><a href=\"#{}\">{}</a></li>",
+            anchor(title),
+            escape(title)
+        )
+        .unwrap();
+    }
+    nav.push_str("</ul></nav>");
+
+    return format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title><style>{style}</style></head><body><header><h1>{title}</h1></header>{nav}<main>{body}</main></body></html>\n",
+        title = escape(title),
+        style = STYLE,
+        nav = nav,
+        body = page.body
+    );
+}
+
+/// Write the HTML report into `<outdir>/<basename>.html` and return its path.
+pub fn export(results: &QCResults, outdir: &Path, basename: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(outdir)?;
+    let filepath = outdir.join(format!("{}.html", basename));
+    let mut f = File::create(&filepath)?;
+    f.write_all(render(results, basename).as_bytes())?;
+
+    return Ok(filepath);
+}
+
+#[cfg(test)]
+mod html_tests {
+    use super::*;
+    use crate::report::fastqc_data::fastqc_data_tests::make_fastqc;
+
+    #[test]
+    fn test_render() {
+        let qc = make_fastqc().update_name("<a&b>.fq.gz");
+        let results = QCResults::from_fastqc(qc);
+        let html = render(&results, "sample \"1\"");
+
+        assert!(html.starts_with("<!DOCTYPE html>\n<html>"));
+        assert!(html.ends_with("</html>\n"));
+        assert!(html.contains("<title>sample &quot;1&quot;</title>"));
+        assert!(html.contains("&lt;a&amp;b&gt;.fq.gz"));
+        assert!(!html.contains("<a&b>"));
+        // Each section is listed in the navigation
+        assert!(
+            html.contains("<a href=\"#per-base-sequence-quality\">Per base sequence quality</a>")
+        );
+        assert!(html.contains("id=\"per-base-sequence-quality\""));
+        assert_eq!(html.matches("<svg").count(), html.matches("</svg>").count());
+    }
+
+    #[test]
+    fn test_render_saved_results() {
+        // The report subcommand renders the JSON of the metrics, so nothing may be lost on the way
+        let results = QCResults::from_fastqc(make_fastqc());
+        let json = serde_json::to_string(&results).unwrap();
+        let saved: QCResults = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(render(&saved, "sample"), render(&results, "sample"));
+    }
+}
@@ -0,0 +1,5 @@
+//! Exporters which render the qc results into the formats of other tools.
+pub mod fastqc_data;
+pub mod html;
+pub mod multiqc;
+pub mod svg;
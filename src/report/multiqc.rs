@@ -0,0 +1,503 @@
+//! Render the qc results into the custom content files of MultiQC (`*_mqc.tsv` and `*_mqc.json`).
+//!
+//! All samples share the same section ids, so MultiQC merges them into one plot per section.
+use crate::qc::fastqc::FastQC;
+use crate::qc::mislabeling::VAFMatrix;
+use crate::qc::paired::PairedMetrics;
+use crate::qc::QCResults;
+use serde_json::{json, Map, Value};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// The thresholds of VAF which decide the genotype of a SNP site.
+const HOM_REF_MAX_VAF: f32 = 0.1;
+const HOM_ALT_MIN_VAF: f32 = 0.9;
+
+/// The columns of general statistics: (id, title, description, suffix, format).
+const GENERAL_STATS_COLUMNS: [(&str, &str, &str, &str, &str); 5] = [
+    ("total_reads", "Reads", "Total sequences", "", "{:,.0f}"),
+    ("percent_gc", "GC", "Average % GC content", "%", "{:,.1f}"),
+    (
+        "q30_rate",
+        "Q30",
+        "% bases with quality >= 30",
+        "%",
+        "{:,.1f}",
+    ),
+    (
+        "percent_duplicates",
+        "Dups",
+        "% duplicate reads",
+        "%",
+        "{:,.1f}",
+    ),
+    (
+        "percent_adapter",
+        "Adapter",
+        "Max % reads with adapter",
+        "%",
+        "{:,.1f}",
+    ),
+];
+
+/// The x value of a base group, e.g. 10-14 -> 12, so that MultiQC can plot them as numbers.
+fn group_position(label: &str) -> String {
+    let bounds: Vec<f64> = label
+        .split('-')
+        .filter_map(|v| v.trim().parse::<f64>().ok())
+        .collect();
+
+    return match bounds.len() {
+        0 => label.to_string(),
+        1 => format!("{}", bounds[0]),
+        _ => format!("{}", (bounds[0] + bounds[1]) / 2.0),
+    };
+}
+
+fn line_data<T: ToString>(labels: &[T], values: &[f64]) -> Value {
+    let mut data = Map::new();
+    for i in 0..labels.len() {
+        data.insert(group_position(&labels[i].to_string()), json!(values[i]));
+    }
+
+    return Value::Object(data);
+}
+
+fn section(id: &str, name: &str, description: &str, plot_type: &str, pconfig: Value) -> Value {
+    return json!({
+        "id": format!("preqc_{}", id),
+        "section_name": name,
+        "description": description,
+        "plot_type": plot_type,
+        "pconfig": pconfig,
+    });
+}
+
+fn line_section(
+    id: &str,
+    name: &str,
+    description: &str,
+    x_title: &str,
+    y_title: &str,
+    sample: &str,
+    data: Value,
+) -> Value {
+    let mut section = section(
+        id,
+        name,
+        description,
+        "linegraph",
+        json!({
+            "id": format!("preqc_{}_plot", id),
+            "title": format!("preqc-pack: {}", name),
+            "xlab": x_title,
+            "ylab": y_title,
+        }),
+    );
+    section["data"] = json!({ sample: data });
+
+    return section;
+}
+
+/// The largest percentage of reads which contain any adapter at any position.
+fn max_adapter_percentage(fastqc: &FastQC) -> Option<f64> {
+    let mut max_percentage: f64 = 0.0;
+    let adapter_content = match &fastqc.adapter_content {
+        Some(module) => module,
+        None => return None,
+    };
+
+    for enrichment in adapter_content.enrichments() {
+        for value in enrichment {
+            if value.is_finite() {
+                max_percentage = max_percentage.max(*value);
+            }
+        }
+    }
+
+    return Some(max_percentage);
+}
+
+/// The values of general statistics in the same order as `GENERAL_STATS_COLUMNS`, a value is None when the module is not available.
+pub fn general_stats(fastqc: &FastQC) -> Vec<Option<f64>> {
+    let basic_stats = &fastqc.basic_stats;
+    let q30_rate = if basic_stats.total_bases() > 0 {
+        Some(basic_stats.q30_bases() as f64 / basic_stats.total_bases() as f64 * 100.0)
+    } else {
+        None
+    };
+    // The duplication is computed from the sketches instead when it's used
+    let percent_duplicates = match (
+        &fastqc.seq_duplication_level,
+        &fastqc.sketch_duplication_level,
+    ) {
+        (Some(module), _) => Some(100.0 - module.percent_diff_seq()),
+        (None, Some(sketch)) => Some(100.0 - sketch.percent_diff_seq()),
+        (None, None) => None,
+    };
+
+    return vec![
+        Some(basic_stats.total_reads() as f64),
+        Some(basic_stats.gc_percentage() * 100.0),
+        q30_rate,
+        percent_duplicates,
+        max_adapter_percentage(fastqc),
+    ];
+}
+
+/// Write the general statistics in the layout of `*_mqc.tsv`, the plot config is in the commented YAML header.
+pub fn write_general_stats<W: Write>(
+    fastqc: &FastQC,
+    sample: &str,
+    writer: &mut W,
+) -> io::Result<()> {
+    writeln!(writer, "# id: 'preqc_general_stats'")?;
+    writeln!(writer, "# plot_type: 'generalstats'")?;
+    writeln!(writer, "# pconfig:")?;
+    for (id, title, description, suffix, format) in GENERAL_STATS_COLUMNS.iter() {
+        writeln!(writer, "#     - {}:", id)?;
+        writeln!(writer, "#         title: '{}'", title)?;
+        writeln!(writer, "#         description: '{}'", description)?;
+        writeln!(writer, "#         format: '{}'", format)?;
+        if suffix.len() > 0 {
+            writeln!(writer, "#         suffix: '{}'", suffix)?;
+            writeln!(writer, "#         max: 100")?;
+            writeln!(writer, "#         min: 0")?;
+        }
+    }
+
+    let ids: Vec<&str> = GENERAL_STATS_COLUMNS.iter().map(|c| c.0).collect();
+    writeln!(writer, "Sample\t{}", ids.join("\t"))?;
+
+    let values: Vec<String> = general_stats(fastqc)
+        .iter()
+        .map(|v| match v {
+            Some(v) => format!("{}", v),
+            None => "".to_string(),
+        })
+        .collect();
+    writeln!(writer, "{}\t{}", sample, values.join("\t"))?;
+
+    return Ok(());
+}
+
+/// The sections of the main fastqc modules.
+pub fn fastqc_sections(fastqc: &FastQC, sample: &str) -> Vec<(String, Value)> {
+    let mut sections: Vec<(String, Value)> = vec![];
+
+    if let Some(module) = &fastqc.per_base_seq_quality {
+        sections.push((
+            "per_base_quality".to_string(),
+            line_section(
+                "per_base_quality",
+                "Per Base Sequence Quality",
+                "The mean quality value across each base position in the read.",
+                "Position (bp)",
+                "Phred Score",
+                sample,
+                line_data(module.xlabels(), module.mean()),
+            ),
+        ));
+    }
+
+    if let Some(module) = &fastqc.per_seq_quality_score {
+        let counts: Vec<f64> = module
+            .y_category_count()
+            .iter()
+            .map(|v| *v as f64)
+            .collect();
+        sections.push((
+            "per_seq_quality".to_string(),
+            line_section(
+                "per_seq_quality",
+                "Per Sequence Quality Scores",
+                "The number of reads with average quality scores.",
+                "Mean Sequence Quality (Phred Score)",
+                "Count",
+                sample,
+                line_data(module.x_category_quality(), &counts),
+            ),
+        ));
+    }
+
+    if let Some(module) = &fastqc.per_seq_gc_content {
+        sections.push((
+            "per_seq_gc_content".to_string(),
+            line_section(
+                "per_seq_gc_content",
+                "Per Sequence GC Content",
+                "The average GC content of reads.",
+                "% GC",
+                "Count",
+                sample,
+                line_data(module.x_category(), module.y_gc_distribution()),
+            ),
+        ));
+    }
+
+    if let Some(module) = &fastqc.per_base_n_content {
+        sections.push((
+            "per_base_n_content".to_string(),
+            line_section(
+                "per_base_n_content",
+                "Per Base N Content",
+                "The percentage of base calls at each position for which an N was called.",
+                "Position (bp)",
+                "Percentage N-Count",
+                sample,
+                line_data(module.x_categories(), module.percentages()),
+            ),
+        ));
+    }
+
+    if let Some(module) = &fastqc.seq_len_distribution {
+        sections.push((
+            "seq_len_distribution".to_string(),
+            line_section(
+                "seq_len_distribution",
+                "Sequence Length Distribution",
+                "The distribution of read lengths.",
+                "Sequence Length (bp)",
+                "Read Count",
+                sample,
+                line_data(module.x_categories(), module.graph_counts()),
+            ),
+        ));
+    }
+
+    if let Some(module) = &fastqc.seq_duplication_level {
+        let mut section = section(
+            "seq_duplication_level",
+            "Sequence Duplication Levels",
+            "The percentage of reads in each duplication level.",
+            "bargraph",
+            json!({
+                "id": "preqc_seq_duplication_level_plot",
+                "title": "preqc-pack: Sequence Duplication Levels",
+                "ylab": "% Total sequences",
+                "cpswitch": false,
+            }),
+        );
+        let mut data = Map::new();
+        for i in 0..module.labels().len() {
+            data.insert(
+                module.labels()[i].clone(),
+                json!(module.total_percentages()[i]),
+            );
+        }
+        section["categories"] = json!(module.labels());
+        section["data"] = json!({ sample: data });
+        sections.push(("seq_duplication_level".to_string(), section));
+    }
+
+    // Each adapter is a line, the same as the adapter content of MultiQC's fastqc module
+    if let Some(module) = &fastqc.adapter_content {
+        let mut data = Map::new();
+        for (i, label) in module.labels().iter().enumerate() {
+            data.insert(
+                format!("{} - {}", sample, label),
+                line_data(module.x_labels(), &module.enrichments()[i]),
+            );
+        }
+        let mut adapter_section = section(
+            "adapter_content",
+            "Adapter Content",
+            "The cumulative percentage of reads which contain each adapter at each position.",
+            "linegraph",
+            json!({
+                "id": "preqc_adapter_content_plot",
+                "title": "preqc-pack: Adapter Content",
+                "xlab": "Position (bp)",
+                "ylab": "% of Sequences",
+                "ymin": 0,
+                "ymax": 100,
+            }),
+        );
+        adapter_section["data"] = Value::Object(data);
+        sections.push(("adapter_content".to_string(), adapter_section));
+    }
+
+    return sections;
+}
+
+/// The genotypes of the covered SNP sites and the number of sites which are not covered.
+pub fn vaf_summary(vaf_matrix: &VAFMatrix) -> Vec<(&'static str, usize)> {
+    let mut hom_ref = 0;
+    let mut het = 0;
+    let mut hom_alt = 0;
+    let mut not_covered = 0;
+    for i in 0..vaf_matrix.vaf().len() {
+        // Only the sites in the indexes have a reference count
+        if vaf_matrix.reference()[i].is_none() {
+            continue;
+        }
+
+        match vaf_matrix.vaf()[i] {
+            Some(vaf) if vaf < HOM_REF_MAX_VAF => hom_ref += 1,
+            Some(vaf) if vaf > HOM_ALT_MIN_VAF => hom_alt += 1,
+            Some(_) => het += 1,
+            None => not_covered += 1,
+        }
+    }
+
+    return vec![
+        ("Homozygous reference", hom_ref),
+        ("Heterozygous", het),
+        ("Homozygous alternative", hom_alt),
+        ("Not covered", not_covered),
+    ];
+}
+
+pub fn vaf_section(vaf_matrix: &VAFMatrix, sample: &str) -> Value {
+    let summary = vaf_summary(vaf_matrix);
+    let mut section = section(
+        "checkmate_vaf",
+        "Checkmate VAF Summary",
+        "The genotypes of SNP sites inferred from the variant allele frequency.",
+        "bargraph",
+        json!({
+            "id": "preqc_checkmate_vaf_plot",
+            "title": "preqc-pack: Checkmate VAF Summary",
+            "ylab": "SNP sites",
+        }),
+    );
+
+    let mut data = Map::new();
+    for (category, count) in &summary {
+        data.insert(category.to_string(), json!(count));
+    }
+    let categories: Vec<&str> = summary.iter().map(|s| s.0).collect();
+    section["categories"] = json!(categories);
+    section["data"] = json!({ sample: data });
+
+    return section;
+}
+
+/// The insert sizes of overlapping pairs.
+pub fn insert_size_section(paired: &PairedMetrics, sample: &str) -> Value {
+    let labels: Vec<usize> = (0..paired.insert_sizes().len()).collect();
+    let counts: Vec<f64> = paired.insert_sizes().iter().map(|c| *c as f64).collect();
+
+    return line_section(
+        "insert_size",
+        "Insert Size",
+        "The insert sizes of the pairs whose mates overlap.",
+        "Insert size (bp)",
+        "Count",
+        sample,
+        line_data(&labels, &counts),
+    );
+}
+
+fn write_json(filepath: &Path, value: &Value) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(filepath)?);
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    return writer.flush();
+}
+
+/// Write the custom content files of one fastqc results, e.g. R2 of paired-end reads.
+pub fn export_fastqc(fastqc: &FastQC, outdir: &Path, sample: &str) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(outdir)?;
+    let mut files: Vec<PathBuf> = vec![];
+
+    let filepath = outdir.join(format!("{}_general_stats_mqc.tsv", sample));
+    let mut writer = BufWriter::new(File::create(&filepath)?);
+    write_general_stats(fastqc, sample, &mut writer)?;
+    writer.flush()?;
+    files.push(filepath);
+
+    for (name, section) in fastqc_sections(fastqc, sample) {
+        let filepath = outdir.join(format!("{}_{}_mqc.json", sample, name));
+        write_json(&filepath, &section)?;
+        files.push(filepath);
+    }
+
+    return Ok(files);
+}
+
+/// Write all custom content files into `outdir` and return their paths.
+///
+/// The files are named as `<basename>_<section>_mqc.json` (or `.tsv`), the sample name in MultiQC is the basename.
+///
+pub fn export(results: &QCResults, outdir: &Path, basename: &str) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(outdir)?;
+    let mut files: Vec<PathBuf> = vec![];
+
+    if let Some(fastqc) = results.fastqc() {
+        files.extend(export_fastqc(fastqc, outdir, basename)?);
+    }
+
+    if let Some(paired) = results.paired() {
+        let filepath = outdir.join(format!("{}_insert_size_mqc.json", basename));
+        write_json(&filepath, &insert_size_section(paired, basename))?;
+        files.push(filepath);
+    }
+
+    if let Some(vaf_matrix) = results.vaf_matrix() {
+        let filepath = outdir.join(format!("{}_checkmate_vaf_mqc.json", basename));
+        write_json(&filepath, &vaf_section(vaf_matrix, basename))?;
+        files.push(filepath);
+    }
+
+    return Ok(files);
+}
+
+#[cfg(test)]
+mod multiqc_tests {
+    use super::*;
+    use crate::report::fastqc_data::fastqc_data_tests::{make_fastqc, new_fastqc, process_reads};
+
+    #[test]
+    fn test_fastqc_sections() {
+        let qc = make_fastqc();
+        let sections = fastqc_sections(&qc, "sample");
+        assert!(sections.iter().any(|(name, _)| name == "per_base_quality"));
+
+        for (name, section) in &sections {
+            // The files are read back by MultiQC, so they must survive a round trip
+            let text = serde_json::to_string_pretty(section).unwrap();
+            let value: Value = serde_json::from_str(&text).unwrap();
+            assert_eq!(value["id"], json!(format!("preqc_{}", name)));
+            for key in &["section_name", "description", "plot_type", "pconfig"] {
+                assert!(value.get(key).is_some(), "{} has no {}", name, key);
+            }
+            if value["plot_type"] == json!("linegraph") {
+                // One line per sample, or per adapter of a sample
+                let data = value["data"].as_object().unwrap();
+                assert!(data.len() > 0, "{} has no data", name);
+                assert!(data.keys().all(|k| k.starts_with("sample")));
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_general_stats() {
+        let qc = make_fastqc();
+        let mut buffer: Vec<u8> = vec![];
+        write_general_stats(&qc, "sample", &mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+
+        assert!(text.starts_with("# id: 'preqc_general_stats'\n"));
+        let rows: Vec<&str> = text.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(
+            rows[0],
+            "Sample\ttotal_reads\tpercent_gc\tq30_rate\tpercent_duplicates\tpercent_adapter"
+        );
+        assert!(rows[1].starts_with("sample\t200\t"));
+        assert_eq!(rows[1].split('\t').count(), GENERAL_STATS_COLUMNS.len() + 1);
+    }
+
+    #[test]
+    fn test_general_stats_sketch_duplication() {
+        let mut qc = new_fastqc();
+        qc.use_sketch_duplication(0);
+        process_reads(&mut qc);
+        qc.finish();
+
+        assert!(qc.seq_duplication_level.is_none());
+        let sketch = qc.sketch_duplication_level.as_ref().unwrap();
+        let stats = general_stats(&qc);
+        assert_eq!(stats[3], Some(100.0 - sketch.percent_diff_seq()));
+    }
+}
@@ -0,0 +1,458 @@
+//! Minimal SVG charts for the HTML report, they don't depend on any external JS or CSS.
+use std::fmt::Write;
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 360.0;
+const MARGIN_LEFT: f64 = 60.0;
+const MARGIN_RIGHT: f64 = 180.0;
+const MARGIN_TOP: f64 = 20.0;
+const MARGIN_BOTTOM: f64 = 60.0;
+// We don't want the labels of x axis overlap each other
+const MAX_X_LABELS: usize = 25;
+
+pub const PALETTE: [&str; 8] = [
+    "#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+];
+
+#[derive(Debug, Clone)]
+pub struct Series {
+    name: String,
+    values: Vec<f64>,
+    color: String,
+}
+
+impl Series {
+    pub fn new(name: &str, values: &Vec<f64>, color: &str) -> Series {
+        return Series {
+            name: name.to_string(),
+            values: values.clone(),
+            color: color.to_string(),
+        };
+    }
+}
+
+/// Escape the characters which have a special meaning in HTML and SVG.
+pub fn escape(text: &str) -> String {
+    return text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;");
+}
+
+fn plot_width() -> f64 {
+    return WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+}
+
+fn plot_height() -> f64 {
+    return HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+}
+
+/// The x coordinate of the center of a category.
+fn x_pos(index: usize, count: usize) -> f64 {
+    let step = plot_width() / cmp_max(count, 1) as f64;
+    return MARGIN_LEFT + step * (index as f64 + 0.5);
+}
+
+fn y_pos(value: f64, y_min: f64, y_max: f64) -> f64 {
+    let range = if y_max > y_min { y_max - y_min } else { 1.0 };
+    let clamped = value.max(y_min).min(y_max);
+    return MARGIN_TOP + plot_height() * (1.0 - (clamped - y_min) / range);
+}
+
+fn cmp_max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// A "nice" step for about 5 ticks between y_min and y_max.
+fn tick_step(y_min: f64, y_max: f64) -> f64 {
+    let range = y_max - y_min;
+    if range <= 0.0 || !range.is_finite() {
+        return 1.0;
+    }
+
+    let raw = range / 5.0;
+    let magnitude = 10_f64.powf(raw.log10().floor());
+    let residual = raw / magnitude;
+    let nice = if residual > 5.0 {
+        10.0
+    } else if residual > 2.0 {
+        5.0
+    } else if residual > 1.0 {
+        2.0
+    } else {
+        1.0
+    };
+
+    return nice * magnitude;
+}
+
+fn format_tick(value: f64, step: f64) -> String {
+    if step >= 1.0 {
+        return format!("{:.0}", value);
+    } else if step >= 0.1 {
+        return format!("{:.1}", value);
+    }
+
+    return format!("{:.2}", value);
+}
+
+/// The range of all finite values, it's [0, 1] when there is no value.
+pub fn value_range(series: &[Series]) -> (f64, f64) {
+    let mut y_min = f64::INFINITY;
+    let mut y_max = f64::NEG_INFINITY;
+    for s in series {
+        for v in &s.values {
+            if v.is_finite() {
+                y_min = y_min.min(*v);
+                y_max = y_max.max(*v);
+            }
+        }
+    }
+
+    if !y_min.is_finite() || !y_max.is_finite() {
+        return (0.0, 1.0);
+    }
+
+    if y_min == y_max {
+        return (y_min.min(0.0), y_max + 1.0);
+    }
+
+    return (y_min, y_max);
+}
+
+fn open_svg(svg: &mut String) {
+    write!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {w} {h}\" width=\"{w}\" height=\"{h}\" font-family=\"sans-serif\" font-size=\"11\">",
+        w = WIDTH,
+        h = HEIGHT
+    )
+    .unwrap();
+}
+
+fn draw_axes(
+    svg: &mut String,
+    x_labels: &[String],
+    y_min: f64,
+    y_max: f64,
+    x_title: &str,
+    y_title: &str,
+) {
+    let step = tick_step(y_min, y_max);
+    let mut tick = (y_min / step).ceil() * step;
+    while tick <= y_max + step * 1e-9 {
+        let y = y_pos(tick, y_min, y_max);
+        write!(
+            svg,
+            "<line x1=\"{x1:.1}\" y1=\"{y:.1}\" x2=\"{x2:.1}\" y2=\"{y:.1}\" stroke=\"#e0e0e0\"/><text x=\"{tx:.1}\" y=\"{ty:.1}\" text-anchor=\"end\">{label}</text>",
+            x1 = MARGIN_LEFT,
+            x2 = MARGIN_LEFT + plot_width(),
+            y = y,
+            tx = MARGIN_LEFT - 6.0,
+            ty = y + 4.0,
+            label = format_tick(tick, step)
+        )
+        .unwrap();
+        tick += step;
+    }
+
+    let count = x_labels.len();
+    let every = cmp_max((count + MAX_X_LABELS - 1) / MAX_X_LABELS, 1);
+    for i in (0..count).step_by(every) {
+        let x = x_pos(i, count);
+        let y = MARGIN_TOP + plot_height() + 12.0;
+        write!(
+            svg,
+            "<text x=\"{x:.1}\" y=\"{y:.1}\" text-anchor=\"end\" transform=\"rotate(-45 {x:.1} {y:.1})\">{label}</text>",
+            x = x,
+            y = y,
+            label = escape(&x_labels[i])
+        )
+        .unwrap();
+    }
+
+    write!(
+        svg,
+        "<rect x=\"{x:.1}\" y=\"{y:.1}\" width=\"{w:.1}\" height=\"{h:.1}\" fill=\"none\" stroke=\"#333\"/>",
+        x = MARGIN_LEFT,
+        y = MARGIN_TOP,
+        w = plot_width(),
+        h = plot_height()
+    )
+    .unwrap();
+    write!(
+        svg,
+        "<text x=\"{x:.1}\" y=\"{y:.1}\" text-anchor=\"middle\">{title}</text>",
+        x = MARGIN_LEFT + plot_width() / 2.0,
+        y = HEIGHT - 6.0,
+        title = escape(x_title)
+    )
+    .unwrap();
+    write!(
+        svg,
+        "<text x=\"14\" y=\"{y:.1}\" text-anchor=\"middle\" transform=\"rotate(-90 14 {y:.1})\">{title}</text>",
+        y = MARGIN_TOP + plot_height() / 2.0,
+        title = escape(y_title)
+    )
+    .unwrap();
+}
+
+fn draw_legend(svg: &mut String, series: &[Series]) {
+    for (i, s) in series.iter().enumerate() {
+        let y = MARGIN_TOP + 10.0 + 18.0 * i as f64;
+        let x = MARGIN_LEFT + plot_width() + 12.0;
+        write!(
+            svg,
+            "<rect x=\"{x:.1}\" y=\"{ry:.1}\" width=\"12\" height=\"4\" fill=\"{color}\"/><text x=\"{tx:.1}\" y=\"{ty:.1}\">{name}</text>",
+            x = x,
+            ry = y - 4.0,
+            color = s.color,
+            tx = x + 18.0,
+            ty = y,
+            name = escape(&s.name)
+        )
+        .unwrap();
+    }
+}
+
+fn draw_polyline(svg: &mut String, values: &[f64], color: &str, y_min: f64, y_max: f64) {
+    let count = values.len();
+    let mut points: Vec<String> = vec![];
+    for (i, v) in values.iter().enumerate() {
+        if v.is_finite() {
+            points.push(format!(
+                "{:.1},{:.1}",
+                x_pos(i, count),
+                y_pos(*v, y_min, y_max)
+            ));
+        }
+    }
+
+    write!(
+        svg,
+        "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>",
+        color,
+        points.join(" ")
+    )
+    .unwrap();
+}
+
+/// A line chart which shares the categories of x axis among all series.
+///
+/// The range of y axis is decided by the values when `y_range` is None.
+///
+pub fn line_chart(
+    x_labels: &[String],
+    series: &[Series],
+    x_title: &str,
+    y_title: &str,
+    y_range: Option<(f64, f64)>,
+) -> String {
+    let (y_min, y_max) = match y_range {
+        Some(range) => range,
+        None => value_range(series),
+    };
+
+    let mut svg = String::new();
+    open_svg(&mut svg);
+    draw_axes(&mut svg, x_labels, y_min, y_max, x_title, y_title);
+    for s in series {
+        draw_polyline(&mut svg, &s.values, &s.color, y_min, y_max);
+    }
+    draw_legend(&mut svg, series);
+    svg.push_str("</svg>");
+
+    return svg;
+}
+
+/// The box plot of qualities at each base group, the same as the one of FastQC.
+///
+/// The background is green above Q28, orange between Q20 and Q28, red below Q20.
+///
+pub fn quality_boxplot(
+    x_labels: &[String],
+    lowest: &[f64],
+    lower_quartile: &[f64],
+    median: &[f64],
+    upper_quartile: &[f64],
+    highest: &[f64],
+    mean: &[f64],
+) -> String {
+    let mut y_max: f64 = 40.0;
+    for v in highest {
+        y_max = y_max.max(*v);
+    }
+    let y_min = 0.0;
+
+    let mut svg = String::new();
+    open_svg(&mut svg);
+
+    let bands = [
+        (28.0, y_max, "#e6f5e6"),
+        (20.0, 28.0, "#fbf0dc"),
+        (0.0, 20.0, "#f8e0e0"),
+    ];
+    for (low, high, color) in bands.iter() {
+        let top = y_pos(*high, y_min, y_max);
+        let bottom = y_pos(*low, y_min, y_max);
+        write!(
+            svg,
+            "<rect x=\"{x:.1}\" y=\"{y:.1}\" width=\"{w:.1}\" height=\"{h:.1}\" fill=\"{c}\"/>",
+            x = MARGIN_LEFT,
+            y = top,
+            w = plot_width(),
+            h = bottom - top,
+            c = color
+        )
+        .unwrap();
+    }
+
+    draw_axes(
+        &mut svg,
+        x_labels,
+        y_min,
+        y_max,
+        "Position in read (bp)",
+        "Quality",
+    );
+
+    let count = x_labels.len();
+    let box_width = (plot_width() / cmp_max(count, 1) as f64) * 0.6;
+    for i in 0..count {
+        let x = x_pos(i, count);
+        let q1 = y_pos(lower_quartile[i], y_min, y_max);
+        let q3 = y_pos(upper_quartile[i], y_min, y_max);
+        write!(
+            svg,
+            "<line x1=\"{x:.1}\" y1=\"{lo:.1}\" x2=\"{x:.1}\" y2=\"{hi:.1}\" stroke=\"#333\"/><rect x=\"{bx:.1}\" y=\"{by:.1}\" width=\"{bw:.1}\" height=\"{bh:.1}\" fill=\"#ffd700\" stroke=\"#333\"/><line x1=\"{bx:.1}\" y1=\"{my:.1}\" x2=\"{bx2:.1}\" y2=\"{my:.1}\" stroke=\"#d62728\" stroke-width=\"1.5\"/>",
+            x = x,
+            lo = y_pos(lowest[i], y_min, y_max),
+            hi = y_pos(highest[i], y_min, y_max),
+            bx = x - box_width / 2.0,
+            bx2 = x + box_width / 2.0,
+            by = q3,
+            bw = box_width,
+            bh = (q1 - q3).max(0.5),
+            my = y_pos(median[i], y_min, y_max)
+        )
+        .unwrap();
+    }
+
+    draw_polyline(&mut svg, mean, "#1f77b4", y_min, y_max);
+    draw_legend(
+        &mut svg,
+        &[
+            Series::new("Mean", &vec![], "#1f77b4"),
+            Series::new("Median", &vec![], "#d62728"),
+        ],
+    );
+    svg.push_str("</svg>");
+
+    return svg;
+}
+
+/// A heatmap of the deviations from the average, blue means no deviation and red means `max_deviation` or worse.
+pub fn heatmap(
+    x_labels: &[String],
+    y_labels: &[String],
+    values: &Vec<Vec<f64>>,
+    max_deviation: f64,
+    x_title: &str,
+    y_title: &str,
+) -> String {
+    let mut svg = String::new();
+    open_svg(&mut svg);
+
+    let cols = x_labels.len();
+    let rows = y_labels.len();
+    let cell_width = plot_width() / cmp_max(cols, 1) as f64;
+    let cell_height = plot_height() / cmp_max(rows, 1) as f64;
+
+    for r in 0..rows {
+        for c in 0..cols {
+            // Only a lower quality is bad
+            let deviation = (0.0 - values[r][c]).max(0.0).min(max_deviation);
+            let hue = 240.0 * (1.0 - deviation / max_deviation);
+            write!(
+                svg,
+                "<rect x=\"{x:.1}\" y=\"{y:.1}\" width=\"{w:.2}\" height=\"{h:.2}\" fill=\"hsl({hue:.0},80%,50%)\"><title>{tile} / {pos}: {v:.2}</title></rect>",
+                x = MARGIN_LEFT + cell_width * c as f64,
+                y = MARGIN_TOP + cell_height * r as f64,
+                w = cell_width,
+                h = cell_height,
+                hue = hue,
+                tile = escape(&y_labels[r]),
+                pos = escape(&x_labels[c]),
+                v = values[r][c]
+            )
+            .unwrap();
+        }
+    }
+
+    // Reuse the axes of x, the labels of y are the tiles
+    draw_axes(&mut svg, x_labels, 0.0, 0.0, x_title, y_title);
+    let every = cmp_max((rows + MAX_X_LABELS - 1) / MAX_X_LABELS, 1);
+    for r in (0..rows).step_by(every) {
+        write!(
+            svg,
+            "<text x=\"{x:.1}\" y=\"{y:.1}\" text-anchor=\"end\">{label}</text>",
+            x = MARGIN_LEFT - 6.0,
+            y = MARGIN_TOP + cell_height * (r as f64 + 0.5) + 4.0,
+            label = escape(&y_labels[r])
+        )
+        .unwrap();
+    }
+    svg.push_str("</svg>");
+
+    return svg;
+}
+
+#[cfg(test)]
+mod svg_tests {
+    use super::*;
+
+    #[test]
+    fn test_line_chart() {
+        let labels: Vec<String> = (1..=4).map(|i| i.to_string()).collect();
+        let series = vec![
+            Series::new("<R1>", &vec![1.0, 2.0, 3.0, 4.0], PALETTE[0]),
+            Series::new("R2 & R1", &vec![4.0, f64::NAN, 2.0, 1.0], PALETTE[1]),
+        ];
+        let svg = line_chart(&labels, &series, "Position", "Quality", None);
+
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.ends_with("</svg>"));
+        assert_eq!(svg.matches("<polyline").count(), 2);
+        assert!(svg.contains(">&lt;R1&gt;</text>"));
+        assert!(svg.contains(">R2 &amp; R1</text>"));
+        // The missing value is skipped
+        let points = svg.split("points=\"").nth(2).unwrap();
+        assert_eq!(points.split('"').next().unwrap().split(' ').count(), 3);
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let x_labels = vec!["1".to_string(), "2".to_string()];
+        let y_labels = vec!["<1101>".to_string()];
+        let svg = heatmap(
+            &x_labels,
+            &y_labels,
+            &vec![vec![0.0, -10.0]],
+            5.0,
+            "Position",
+            "Tile",
+        );
+
+        assert!(svg.ends_with("</svg>"));
+        assert!(svg.contains("fill=\"hsl(240,80%,50%)\""));
+        assert!(svg.contains("fill=\"hsl(0,80%,50%)\""));
+        assert!(svg.contains("&lt;1101&gt;"));
+        assert!(!svg.contains("<1101>"));
+    }
+}